@@ -0,0 +1,38 @@
+// The C API of moolang, to compile moolang code and call it from C, or any language calling C functions,
+// see `capi.rs`. Link with the `moolang` shared library, e.g. `libmoolang.so`, which `cargo build` builds.
+// Functions returning an `int` return 0, or -1 on failure, whose error `moo_last_error` then returns.
+
+#ifndef MOOLANG_H
+#define MOOLANG_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+// Compiles moolang code into memory, and keeps it there for it to be called
+typedef struct moo_engine moo_engine;
+
+// Creates an engine, to free with `moo_engine_free`
+moo_engine *moo_engine_new(void);
+
+// Frees an engine, along with the code it compiled, does nothing if `engine` is null
+void moo_engine_free(moo_engine *engine);
+
+// Compiles a module from its source, whose functions `moo_run` can then call
+int moo_compile(moo_engine *engine, const char *source);
+
+// Calls the module level function `name`, which takes `count` arguments of type `int`, at most 6,
+// and returns an `int`, written to `result`
+int moo_run(const moo_engine *engine, const char *name, const int64_t *args, size_t count, int64_t *result);
+
+// The error of the last function which failed on this thread, or null, valid until the next one fails
+const char *moo_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,555 @@
+// A compact bytecode for a stack machine, the `vm` backend: programs are compiled from the IR,
+// see `ir`, into functions of instructions popping their operands off a stack and pushing their result.
+// Every value of the IR is kept in a local slot of its function, loaded onto the stack where it's used.
+// Programs can be written to a file with `build --emit bytecode`, and run from it by `run`
+// without compiling them again, see `read` for the layout of the file.
+
+pub mod vm;
+
+use core::fmt;
+use std::collections::HashMap;
+use std::error::Error;
+
+use crate::frontend::builtins::Builtin;
+use crate::frontend::overflow::Overflow;
+use crate::frontend::tokenizer::Span;
+use crate::frontend::types::{IntTy, Ty};
+use crate::ir::{self, BinaryOp, Conversion, Format, Inst, Printed};
+use crate::errors::Coded;
+use crate::messages::{message, Message};
+
+/// The first bytes of every bytecode file
+pub const MAGIC: &[u8; 4] = b"MOO\0";
+
+/// The version of the layout of bytecode files, increased whenever it changes
+pub const VERSION: u8 = 5;
+
+/// The extension of bytecode files
+pub const EXTENSION: &str = "moob";
+
+#[derive(Debug)]
+pub struct BytecodeError {
+    message: Message,
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "BytecodeError: {}", self.message)
+    }
+}
+
+impl Error for BytecodeError {}
+
+impl Coded for BytecodeError {
+    fn code(&self) -> Option<&'static str> {
+        self.message.code()
+    }
+}
+
+/// A program compiled to bytecode
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    /// The strings the functions push, by index
+    pub strings: Vec<String>,
+    pub functions: Vec<Function>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    /// The name of the function, `<enclosing function>::lambda<n>` for lambdas
+    pub name: String,
+    /// Whether the function is a module level one, which can be called by name
+    pub exported: bool,
+    /// The number of arguments, besides the closure
+    pub params: u32,
+    /// The number of values the function captures, stored in its closure
+    pub captures: u32,
+    /// The number of local slots the function uses
+    pub locals: u32,
+    /// How the value the function returns is printed, for that of `main`
+    pub returns: Printed,
+    pub code: Vec<Op>,
+    /// Where the code every instruction was compiled from is in the source, by instruction,
+    /// none for programs read from a file, whose source isn't known
+    pub spans: Vec<Span>,
+}
+
+/// An instruction of the stack machine
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    /// Pushes an integer, or the bits of a float
+    Int(i64),
+    /// Pushes a string of the program, by index
+    String(u32),
+    /// Pushes an argument of the function
+    Arg(u32),
+    /// Pushes a value captured by the function
+    Capture(u32),
+    /// Pushes the value of a local slot
+    Local(u32),
+    /// Pops a value into a local slot
+    Store(u32),
+    Binary(BinaryOp),
+    /// Arithmetic on values of an integer type, `int` for `None`, see `ir::Inst::Checked`
+    Checked(Overflow, BinaryOp, Option<IntTy>),
+    Wrap(IntTy),
+    Convert(Conversion),
+    Format(Format),
+    /// Pops two strings and pushes them concatenated
+    Concat,
+    /// Pops the captured values, pushed in order, and pushes a closure of a function of the program, by index
+    Closure { function: u32, captures: u32 },
+    /// Pops the arguments, pushed in order, and calls a function of the program, by index
+    Call { function: u32, args: u32 },
+    /// Pops the arguments, then the closure called with them
+    CallIndirect { args: u32 },
+    /// Pops the arguments of a builtin, pushed in order, and calls it
+    Builtin(Builtin),
+    /// Returns the value on top of the stack
+    Return,
+}
+
+/// Compiles a program of the IR to bytecode. The program has no values defined outside of it, see `ir::Inst::Load`,
+/// nor extern functions, see `compile::check_externs`, nor retains and releases, the values of the stack machine
+/// being reference counted by Rust
+pub fn compile(program: &ir::Program) -> Program {
+    let indices: HashMap<_, _> = program.functions
+        .iter()
+        .enumerate()
+        .filter_map(|(index, function)| function.global.map(|name| (name, index as u32)))
+        .collect();
+    let mut strings = Vec::new();
+    let functions = program.functions
+        .iter()
+        .map(|function| {
+            let mut code = Vec::new();
+            let mut spans = Vec::new();
+            for (at, inst) in function.insts.iter().enumerate() {
+                // in order, e.g. the closure called before the arguments
+                code.extend(inst.operands().into_iter().map(|value| Op::Local(value.0)));
+                code.push(match inst {
+                    Inst::Const(value) => Op::Int(*value),
+                    Inst::String(string) => {
+                        strings.push(string.clone());
+                        Op::String(strings.len() as u32 - 1)
+                    }
+                    Inst::Param(index) => Op::Arg(*index as u32),
+                    Inst::Capture(index) => Op::Capture(*index as u32),
+                    Inst::Binary(op, ..) => Op::Binary(*op),
+                    Inst::Checked(overflow, op, int, ..) => Op::Checked(*overflow, *op, *int),
+                    Inst::Wrap(int, _) => Op::Wrap(*int),
+                    Inst::Convert(conversion, _) => Op::Convert(*conversion),
+                    Inst::Format(format, _) => Op::Format(*format),
+                    Inst::Concat(..) => Op::Concat,
+                    Inst::Closure(index, captures) => Op::Closure { function: *index as u32, captures: captures.len() as u32 },
+                    Inst::Global(name) => Op::Closure { function: indices[name], captures: 0 },
+                    Inst::Call(name, args) => Op::Call { function: indices[name], args: args.len() as u32 },
+                    Inst::CallIndirect(_, args) => Op::CallIndirect { args: args.len() as u32 },
+                    Inst::Builtin(builtin, _) => Op::Builtin(*builtin),
+                    Inst::Load(name) => unreachable!("`{}` is defined outside of the program", name),
+                    Inst::CallExtern(index, _) => unreachable!("`{}` is an extern function, which only compiled code calls", program.externs[*index].symbol),
+                    Inst::Retain(_) | Inst::Release(_) => unreachable!("only compiled code counts references"),
+                });
+                code.push(Op::Store(at as u32));
+                spans.resize(code.len(), function.spans[at]);
+            }
+            code.push(Op::Local(function.result.0));
+            code.push(Op::Return);
+            spans.resize(code.len(), function.span);
+            Function {
+                name: function.name.clone(),
+                exported: function.global.is_some(),
+                params: function.params as u32,
+                captures: function.captures as u32,
+                locals: function.insts.len() as u32,
+                returns: match &function.ty {
+                    Ty::Function(_, returns) => Printed::of(returns),
+                    _ => Printed::Number(Format::Int),
+                },
+                code,
+                spans,
+            }
+        })
+        .collect();
+    Program { strings, functions }
+}
+
+impl Program {
+    /// The index of the module level function `name`
+    pub fn function(&self, name: &str) -> Option<usize> {
+        self.functions.iter().position(|function| function.exported && function.name == name)
+    }
+
+    /// Writes the program in the layout `read` reads
+    pub fn write(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        write_uint(&mut bytes, self.strings.len() as u64);
+        for string in self.strings.iter() {
+            write_str(&mut bytes, string);
+        }
+        write_uint(&mut bytes, self.functions.len() as u64);
+        for function in self.functions.iter() {
+            write_str(&mut bytes, &function.name);
+            bytes.push(function.exported as u8);
+            write_uint(&mut bytes, function.params as u64);
+            write_uint(&mut bytes, function.captures as u64);
+            write_uint(&mut bytes, function.locals as u64);
+            bytes.push(Printed::ALL.iter().position(|printed| *printed == function.returns).unwrap() as u8);
+            write_uint(&mut bytes, function.code.len() as u64);
+            for op in function.code.iter() {
+                write_op(&mut bytes, op);
+            }
+        }
+        bytes
+    }
+}
+
+/// Reads a program written by `Program::write`, checking that it's well formed.
+/// The file starts with `MAGIC` and the `VERSION`, followed by the strings, then the functions,
+/// each with its name, whether it's exported on a byte, its numbers of arguments, captures and locals,
+/// how the value it returns is printed, the index in `Printed::ALL` on a byte, and its instructions. Instructions are an opcode byte followed by their operands.
+/// Numbers are LEB128 encoded, and strings are their length followed by their UTF-8 bytes.
+pub fn read(bytes: &[u8]) -> Result<Program, BytecodeError> {
+    if !bytes.starts_with(MAGIC) {
+        return Err(BytecodeError {
+            message: message!("E0415"),
+        });
+    }
+    let mut reader = Reader { bytes, at: MAGIC.len() };
+    let version = reader.byte()?;
+    if version != VERSION {
+        return Err(BytecodeError {
+            message: message!("E0416", version = version, supported = VERSION),
+        });
+    }
+    let strings = (0..reader.uint()?)
+        .map(|_| reader.str())
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut functions = Vec::new();
+    for _ in 0..reader.uint()? {
+        let name = reader.str()?;
+        let exported = reader.byte()? != 0;
+        let params = reader.u32()?;
+        let captures = reader.u32()?;
+        let locals = reader.u32()?;
+        let returns = *Printed::ALL.get(reader.byte()? as usize).ok_or_else(|| malformed("unknown type of returned value"))?;
+        let code = (0..reader.uint()?)
+            .map(|_| reader.op())
+            .collect::<Result<Vec<_>, _>>()?;
+        functions.push(Function { name, exported, params, captures, locals, returns, code, spans: Vec::new() });
+    }
+    if reader.at != bytes.len() {
+        return Err(malformed("trailing bytes after the last function"));
+    }
+    let program = Program { strings, functions };
+    verify(&program).map_err(|error| malformed(&error))?;
+    Ok(program)
+}
+
+/// Checks that the operands of the instructions exist, and that every function returns,
+/// so that running the program can't read outside of it
+fn verify(program: &Program) -> Result<(), String> {
+    for function in program.functions.iter() {
+        let in_range = |index: u32, count: usize, what: &str| match (index as usize) < count {
+            true => Ok(()),
+            false => Err(format!("`{}` uses {} {} of {}", function.name, what, index, count)),
+        };
+        for op in function.code.iter() {
+            match *op {
+                Op::String(index) => in_range(index, program.strings.len(), "string")?,
+                Op::Arg(index) => in_range(index, function.params as usize, "argument")?,
+                Op::Capture(index) => in_range(index, function.captures as usize, "capture")?,
+                Op::Local(index) | Op::Store(index) => in_range(index, function.locals as usize, "local")?,
+                Op::Closure { function: index, captures } => {
+                    in_range(index, program.functions.len(), "function")?;
+                    if program.functions[index as usize].captures != captures {
+                        return Err(format!("`{}` creates a closure of `{}` with {} captures", function.name, program.functions[index as usize].name, captures));
+                    }
+                }
+                Op::Call { function: index, args } => {
+                    in_range(index, program.functions.len(), "function")?;
+                    if program.functions[index as usize].params != args {
+                        return Err(format!("`{}` calls `{}` with {} arguments", function.name, program.functions[index as usize].name, args));
+                    }
+                }
+                _ => (),
+            }
+        }
+        if function.code.last() != Some(&Op::Return) {
+            return Err(format!("`{}` doesn't end with a return", function.name));
+        }
+    }
+    Ok(())
+}
+
+fn malformed(error: &str) -> BytecodeError {
+    BytecodeError {
+        message: message!("E0417", error = error),
+    }
+}
+
+/// The opcodes, the first byte of every instruction
+mod opcode {
+    pub const INT: u8 = 0;
+    pub const STRING: u8 = 1;
+    pub const ARG: u8 = 2;
+    pub const CAPTURE: u8 = 3;
+    pub const LOCAL: u8 = 4;
+    pub const STORE: u8 = 5;
+    pub const ADD: u8 = 6;
+    pub const SUB: u8 = 7;
+    pub const MUL: u8 = 8;
+    pub const DIV: u8 = 9;
+    pub const REM: u8 = 10;
+    pub const UDIV: u8 = 11;
+    pub const UREM: u8 = 12;
+    /// followed by the index of the type in `IntTy::ALL`
+    pub const WRAP: u8 = 13;
+    pub const SINT_TO_FLOAT: u8 = 14;
+    pub const UINT_TO_FLOAT: u8 = 15;
+    pub const FLOAT_TO_SINT: u8 = 16;
+    pub const FLOAT_TO_UINT: u8 = 17;
+    pub const FORMAT_INT: u8 = 18;
+    pub const FORMAT_UINT: u8 = 19;
+    pub const FORMAT_FLOAT: u8 = 20;
+    pub const CONCAT: u8 = 21;
+    pub const CLOSURE: u8 = 22;
+    pub const CALL: u8 = 23;
+    pub const CALL_INDIRECT: u8 = 24;
+    pub const RETURN: u8 = 25;
+    /// followed by the opcode of the arithmetic, then 0 for `int` or 1 plus the index of the type in `IntTy::ALL`
+    pub const CHECKED: u8 = 26;
+    /// followed like `CHECKED`
+    pub const SATURATING: u8 = 27;
+    pub const POW: u8 = 28;
+    pub const UPOW: u8 = 29;
+    pub const FPOW: u8 = 30;
+    /// followed by the index of the builtin in `Builtin::ALL`
+    pub const BUILTIN: u8 = 31;
+}
+
+fn write_op(bytes: &mut Vec<u8>, op: &Op) {
+    match *op {
+        Op::Int(value) => {
+            bytes.push(opcode::INT);
+            write_int(bytes, value);
+        }
+        Op::String(index) => write_with(bytes, opcode::STRING, &[index]),
+        Op::Arg(index) => write_with(bytes, opcode::ARG, &[index]),
+        Op::Capture(index) => write_with(bytes, opcode::CAPTURE, &[index]),
+        Op::Local(index) => write_with(bytes, opcode::LOCAL, &[index]),
+        Op::Store(index) => write_with(bytes, opcode::STORE, &[index]),
+        Op::Binary(op) => bytes.push(binary_opcode(op)),
+        Op::Checked(overflow, op, int) => {
+            bytes.push(match overflow {
+                Overflow::Saturate => opcode::SATURATING,
+                Overflow::Wrap | Overflow::Trap => opcode::CHECKED,
+            });
+            bytes.push(binary_opcode(op));
+            bytes.push(int.map_or(0, |int| int_index(int) + 1));
+        }
+        Op::Wrap(int) => {
+            bytes.push(opcode::WRAP);
+            bytes.push(int_index(int));
+        }
+        Op::Convert(conversion) => bytes.push(match conversion {
+            Conversion::SignedToFloat => opcode::SINT_TO_FLOAT,
+            Conversion::UnsignedToFloat => opcode::UINT_TO_FLOAT,
+            Conversion::FloatToSigned => opcode::FLOAT_TO_SINT,
+            Conversion::FloatToUnsigned => opcode::FLOAT_TO_UINT,
+        }),
+        Op::Format(format) => bytes.push(match format {
+            Format::Int => opcode::FORMAT_INT,
+            Format::Uint => opcode::FORMAT_UINT,
+            Format::Float => opcode::FORMAT_FLOAT,
+        }),
+        Op::Concat => bytes.push(opcode::CONCAT),
+        Op::Closure { function, captures } => write_with(bytes, opcode::CLOSURE, &[function, captures]),
+        Op::Call { function, args } => write_with(bytes, opcode::CALL, &[function, args]),
+        Op::CallIndirect { args } => write_with(bytes, opcode::CALL_INDIRECT, &[args]),
+        Op::Builtin(builtin) => {
+            bytes.push(opcode::BUILTIN);
+            bytes.push(Builtin::ALL.iter().position(|other| *other == builtin).expect("a builtin") as u8);
+        }
+        Op::Return => bytes.push(opcode::RETURN),
+    }
+}
+
+fn binary_opcode(op: BinaryOp) -> u8 {
+    match op {
+        BinaryOp::Add => opcode::ADD,
+        BinaryOp::Sub => opcode::SUB,
+        BinaryOp::Mul => opcode::MUL,
+        BinaryOp::Div => opcode::DIV,
+        BinaryOp::Rem => opcode::REM,
+        BinaryOp::UDiv => opcode::UDIV,
+        BinaryOp::URem => opcode::UREM,
+        BinaryOp::Pow => opcode::POW,
+        BinaryOp::UPow => opcode::UPOW,
+        BinaryOp::FPow => opcode::FPOW,
+    }
+}
+
+/// The index of an integer type in `IntTy::ALL`
+fn int_index(int: IntTy) -> u8 {
+    IntTy::ALL.iter().position(|other| *other == int).expect("an integer type") as u8
+}
+
+fn write_with(bytes: &mut Vec<u8>, opcode: u8, operands: &[u32]) {
+    bytes.push(opcode);
+    operands.iter().for_each(|operand| write_uint(bytes, *operand as u64));
+}
+
+/// Writes an unsigned LEB128 number
+fn write_uint(bytes: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+/// Writes a signed LEB128 number
+fn write_int(bytes: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn write_str(bytes: &mut Vec<u8>, string: &str) {
+    write_uint(bytes, string.len() as u64);
+    bytes.extend_from_slice(string.as_bytes());
+}
+
+/// Reads the parts of a bytecode file in order
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, BytecodeError> {
+        let byte = *self.bytes.get(self.at).ok_or_else(|| malformed("unexpected end of file"))?;
+        self.at += 1;
+        Ok(byte)
+    }
+
+    fn uint(&mut self) -> Result<u64, BytecodeError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(malformed("number too large"))
+    }
+
+    fn u32(&mut self) -> Result<u32, BytecodeError> {
+        u32::try_from(self.uint()?).map_err(|_| malformed("number too large"))
+    }
+
+    fn int(&mut self) -> Result<i64, BytecodeError> {
+        let mut value = 0i64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= i64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                // extend the sign of the last byte
+                if shift + 7 < 64 && byte & 0x40 != 0 {
+                    value |= -1 << (shift + 7);
+                }
+                return Ok(value);
+            }
+        }
+        Err(malformed("number too large"))
+    }
+
+    fn str(&mut self) -> Result<String, BytecodeError> {
+        let len = self.uint()? as usize;
+        let bytes = self.at.checked_add(len)
+            .and_then(|end| self.bytes.get(self.at..end))
+            .ok_or_else(|| malformed("unexpected end of file"))?;
+        self.at += len;
+        String::from_utf8(bytes.to_vec()).map_err(|_| malformed("invalid UTF-8 in a string"))
+    }
+
+    fn op(&mut self) -> Result<Op, BytecodeError> {
+        Ok(match self.byte()? {
+            opcode::INT => Op::Int(self.int()?),
+            opcode::STRING => Op::String(self.u32()?),
+            opcode::ARG => Op::Arg(self.u32()?),
+            opcode::CAPTURE => Op::Capture(self.u32()?),
+            opcode::LOCAL => Op::Local(self.u32()?),
+            opcode::STORE => Op::Store(self.u32()?),
+            opcode @ (opcode::ADD..=opcode::UREM | opcode::POW..=opcode::FPOW) => Op::Binary(binary_op(opcode).expect("an arithmetic opcode")),
+            opcode @ (opcode::CHECKED | opcode::SATURATING) => {
+                let overflow = match opcode {
+                    opcode::SATURATING => Overflow::Saturate,
+                    _ => Overflow::Trap,
+                };
+                let op = binary_op(self.byte()?)
+                    .filter(|op| *op != BinaryOp::FPow)
+                    .ok_or_else(|| malformed("checked arithmetic of an unknown operation"))?;
+                let int = match self.byte()? {
+                    0 => None,
+                    index => Some(int_ty(index - 1)?),
+                };
+                Op::Checked(overflow, op, int)
+            }
+            opcode::WRAP => Op::Wrap(int_ty(self.byte()?)?),
+            opcode::SINT_TO_FLOAT => Op::Convert(Conversion::SignedToFloat),
+            opcode::UINT_TO_FLOAT => Op::Convert(Conversion::UnsignedToFloat),
+            opcode::FLOAT_TO_SINT => Op::Convert(Conversion::FloatToSigned),
+            opcode::FLOAT_TO_UINT => Op::Convert(Conversion::FloatToUnsigned),
+            opcode::FORMAT_INT => Op::Format(Format::Int),
+            opcode::FORMAT_UINT => Op::Format(Format::Uint),
+            opcode::FORMAT_FLOAT => Op::Format(Format::Float),
+            opcode::CONCAT => Op::Concat,
+            opcode::CLOSURE => Op::Closure { function: self.u32()?, captures: self.u32()? },
+            opcode::CALL => Op::Call { function: self.u32()?, args: self.u32()? },
+            opcode::CALL_INDIRECT => Op::CallIndirect { args: self.u32()? },
+            opcode::BUILTIN => {
+                let index = self.byte()?;
+                Op::Builtin(Builtin::ALL.get(index as usize).copied().ok_or_else(|| malformed("unknown builtin"))?)
+            }
+            opcode::RETURN => Op::Return,
+            opcode => return Err(malformed(&format!("unknown opcode {}", opcode))),
+        })
+    }
+}
+
+/// The arithmetic of an opcode, if it's one of arithmetic
+fn binary_op(opcode: u8) -> Option<BinaryOp> {
+    Some(match opcode {
+        opcode::ADD => BinaryOp::Add,
+        opcode::SUB => BinaryOp::Sub,
+        opcode::MUL => BinaryOp::Mul,
+        opcode::DIV => BinaryOp::Div,
+        opcode::REM => BinaryOp::Rem,
+        opcode::UDIV => BinaryOp::UDiv,
+        opcode::UREM => BinaryOp::URem,
+        opcode::POW => BinaryOp::Pow,
+        opcode::UPOW => BinaryOp::UPow,
+        opcode::FPOW => BinaryOp::FPow,
+        _ => return None,
+    })
+}
+
+/// An integer type by its index in `IntTy::ALL`
+fn int_ty(index: u8) -> Result<IntTy, BytecodeError> {
+    IntTy::ALL.get(index as usize).copied().ok_or_else(|| malformed("unknown integer type"))
+}
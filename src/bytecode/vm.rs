@@ -0,0 +1,293 @@
+// The stack machine running bytecode, see `bytecode`.
+// Calls don't recurse on the stack of the compiler: every call pushes a frame,
+// holding the arguments and locals of the function, and the base of its operands on the shared stack.
+// It follows the semantics of the code the JIT generates, like `interp`, reporting traps as errors.
+
+use core::fmt;
+use std::error::Error;
+use std::rc::Rc;
+
+use crate::bytecode::{Op, Program};
+use crate::frontend::builtins::{self, Builtin};
+use crate::interrupt;
+use crate::ir::{self, BinaryOp, Conversion};
+use crate::errors::Coded;
+use crate::messages::{message, Message};
+
+/// How deep calls can nest, compiled code overflowing its stack around there
+pub const MAX_DEPTH: usize = 100_000;
+
+#[derive(Debug)]
+pub struct VmError {
+    message: Message,
+    /// The function running, by index in the program
+    pub function: usize,
+    /// The instruction running, by index in the code of the function, none if calling the function failed
+    pub at: Option<usize>,
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "VmError: {}", self.message)
+    }
+}
+
+impl Error for VmError {}
+
+impl Coded for VmError {
+    fn code(&self) -> Option<&'static str> {
+        self.message.code()
+    }
+}
+
+/// A value of the program being run
+#[derive(Debug, Clone)]
+pub enum Value {
+    /// An integer, or the bits of a float, like in compiled code
+    Int(i64),
+    String(Rc<str>),
+    Array(Rc<[Value]>),
+    Closure(Rc<Closure>),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(value) => write!(f, "{}", value),
+            Value::String(string) => write!(f, "{}", string),
+            Value::Array(elements) => write!(f, "[{}]", elements.iter().map(Value::to_string).collect::<Vec<_>>().join(", ")),
+            Value::Closure(closure) => write!(f, "<fn {}>", closure.function),
+        }
+    }
+}
+
+/// A function of the program, by index, along with the values it captured
+#[derive(Debug)]
+pub struct Closure {
+    function: u32,
+    captures: Vec<Value>,
+}
+
+/// A call being run
+struct Frame {
+    function: usize,
+    /// The index of the next instruction
+    pc: usize,
+    args: Vec<Value>,
+    closure: Option<Rc<Closure>>,
+    locals: Vec<Value>,
+    /// Where the operands of the function start on the stack
+    base: usize,
+}
+
+/// Calls the function of the program at `function` with `args`, and returns the value it returns
+pub fn run(program: &Program, function: usize, args: Vec<Value>) -> Result<Value, VmError> {
+    let mut stack: Vec<Value> = Vec::new();
+    let mut frames = vec![frame(program, function, args, None, 0)];
+    loop {
+        let frame = frames.last_mut().expect("a frame");
+        let (function, at) = (frame.function, frame.pc);
+        let error = |message: Message| VmError { message, function, at: Some(at) };
+        let op = program.functions[frame.function].code[frame.pc];
+        frame.pc += 1;
+
+        // the operands are popped in reverse
+        macro_rules! pop {
+            () => {
+                match stack.len() > frame.base {
+                    true => stack.pop().expect("an operand"),
+                    false => return Err(error(message!("E0417", error = "an instruction has no operand"))),
+                }
+            };
+            (int) => {
+                match pop!() {
+                    Value::Int(value) => value,
+                    value => return Err(error(message!("E0419", expected = "a number", found = value))),
+                }
+            };
+            (string) => {
+                match pop!() {
+                    Value::String(value) => value,
+                    value => return Err(error(message!("E0419", expected = "a string", found = value))),
+                }
+            };
+        }
+
+        match op {
+            Op::Int(value) => stack.push(Value::Int(value)),
+            Op::String(index) => stack.push(Value::String(program.strings[index as usize].as_str().into())),
+            Op::Arg(index) => stack.push(frame.args[index as usize].clone()),
+            Op::Capture(index) => {
+                let capture = frame.closure.as_ref().and_then(|closure| closure.captures.get(index as usize));
+                match capture {
+                    Some(capture) => stack.push(capture.clone()),
+                    None => return Err(error(message!("E0417", error = "a function called directly reads a capture"))),
+                }
+            }
+            Op::Local(index) => stack.push(frame.locals[index as usize].clone()),
+            Op::Store(index) => frame.locals[index as usize] = pop!(),
+
+            Op::Binary(op) => {
+                let rhs = pop!(int);
+                let lhs = pop!(int);
+                let value = match op {
+                    BinaryOp::Add => Some(lhs.wrapping_add(rhs)),
+                    BinaryOp::Sub => Some(lhs.wrapping_sub(rhs)),
+                    BinaryOp::Mul => Some(lhs.wrapping_mul(rhs)),
+                    BinaryOp::Div => lhs.checked_div(rhs),
+                    // the remainder of the smallest `int` by -1 doesn't overflow in compiled code
+                    BinaryOp::Rem => (rhs != 0).then(|| lhs.wrapping_rem(rhs)),
+                    BinaryOp::UDiv => (lhs as u64).checked_div(rhs as u64).map(|value| value as i64),
+                    BinaryOp::URem => (lhs as u64).checked_rem(rhs as u64).map(|value| value as i64),
+                    BinaryOp::Pow | BinaryOp::UPow => ir::power(op, lhs, rhs),
+                    BinaryOp::FPow => Some(ir::float_power(lhs, rhs)),
+                };
+                match value {
+                    Some(value) => stack.push(Value::Int(value)),
+                    // zero to a negative power divides by zero
+                    None if rhs == 0 || op.is_power() => return Err(error(message!("E0413"))),
+                    None => return Err(error(message!("E0414", lhs = lhs, rhs = rhs))),
+                }
+            }
+
+            Op::Checked(overflow, op, int) => {
+                let rhs = pop!(int);
+                let lhs = pop!(int);
+                match ir::checked(overflow, op, int, lhs, rhs) {
+                    Ok(value) => stack.push(Value::Int(value)),
+                    Err(message) => return Err(error(message)),
+                }
+            }
+
+            Op::Wrap(int) => {
+                let value = pop!(int);
+                stack.push(Value::Int(int.wrap(value)));
+            }
+
+            // floats are kept as their bits, and converting them to integers saturates
+            Op::Convert(conversion) => {
+                let value = pop!(int);
+                let float = f64::from_bits(value as u64);
+                stack.push(Value::Int(match conversion {
+                    Conversion::SignedToFloat => (value as f64).to_bits() as i64,
+                    Conversion::UnsignedToFloat => (value as u64 as f64).to_bits() as i64,
+                    Conversion::FloatToSigned => float as i64,
+                    Conversion::FloatToUnsigned => float as u64 as i64,
+                }));
+            }
+
+            Op::Format(format) => {
+                let value = pop!(int);
+                stack.push(Value::String(format.format(value).into()));
+            }
+
+            Op::Concat => {
+                let rhs = pop!(string);
+                let lhs = pop!(string);
+                stack.push(Value::String(format!("{}{}", lhs, rhs).into()));
+            }
+
+            Op::Closure { function, captures } => {
+                let mut values = (0..captures).map(|_| Ok(pop!())).collect::<Result<Vec<_>, VmError>>()?;
+                values.reverse();
+                stack.push(Value::Closure(Rc::new(Closure { function, captures: values })));
+            }
+
+            Op::Call { function, args } => {
+                let mut values = (0..args).map(|_| Ok(pop!())).collect::<Result<Vec<_>, VmError>>()?;
+                values.reverse();
+                call(&mut frames, program, function as usize, values, None, stack.len())?;
+            }
+
+            Op::CallIndirect { args } => {
+                let mut values = (0..args).map(|_| Ok(pop!())).collect::<Result<Vec<_>, VmError>>()?;
+                values.reverse();
+                let closure = match pop!() {
+                    Value::Closure(closure) => closure,
+                    value => return Err(error(message!("E0419", expected = "a function", found = value))),
+                };
+                let function = closure.function as usize;
+                if program.functions[function].params as usize != values.len() {
+                    return Err(error(message!("E0312", name = program.functions[function].name, expected = program.functions[function].params, given = values.len())));
+                }
+                call(&mut frames, program, function, values, Some(closure), stack.len())?;
+            }
+
+            Op::Builtin(builtin) if builtin.is_math() => {
+                let mut args = (0..builtin.arity()).map(|_| Ok(pop!(int))).collect::<Result<Vec<_>, VmError>>()?;
+                args.reverse();
+                stack.push(Value::Int(ir::math(builtin, &args)));
+            }
+
+            Op::Builtin(Builtin::ReadInt) => match builtins::read_int() {
+                Ok(value) => stack.push(Value::Int(value)),
+                Err(message) => return Err(error(message)),
+            },
+
+            Op::Builtin(Builtin::ReadLine) => stack.push(Value::String(builtins::read_line().into())),
+
+            Op::Builtin(builtin @ (Builtin::Len | Builtin::At)) => {
+                let index = match builtin {
+                    Builtin::At => Some(pop!(int)),
+                    _ => None,
+                };
+                let elements = match pop!() {
+                    Value::Array(elements) => elements,
+                    value => return Err(error(message!("E0419", expected = "an array", found = value))),
+                };
+                match index {
+                    Some(index) => match builtins::index(index, elements.len()) {
+                        Ok(offset) => stack.push(elements[offset].clone()),
+                        Err(message) => return Err(error(message)),
+                    },
+                    None => stack.push(Value::Int(elements.len() as i64)),
+                }
+            }
+
+            // `unit` is represented by 0
+            Op::Builtin(builtin) => {
+                let string = pop!(string);
+                builtins::print(&string, builtin == Builtin::Println);
+                stack.push(Value::Int(0));
+            }
+
+            Op::Return => {
+                let value = pop!();
+                stack.truncate(frame.base);
+                frames.pop();
+                if frames.is_empty() {
+                    return Ok(value);
+                }
+                stack.push(value);
+            }
+        }
+    }
+}
+
+/// Starts running a function, checking for Ctrl-C like compiled code on entry to every function
+fn call(frames: &mut Vec<Frame>, program: &Program, function: usize, args: Vec<Value>, closure: Option<Rc<Closure>>, base: usize) -> Result<(), VmError> {
+    if interrupt::interrupted() {
+        interrupt::exit();
+    }
+    if frames.len() >= MAX_DEPTH {
+        return Err(VmError {
+            message: message!("E0418", depth = MAX_DEPTH),
+            function,
+            at: None,
+        });
+    }
+    frames.push(frame(program, function, args, closure, base));
+    Ok(())
+}
+
+fn frame(program: &Program, function: usize, args: Vec<Value>, closure: Option<Rc<Closure>>, base: usize) -> Frame {
+    Frame {
+        function,
+        pc: 0,
+        args,
+        closure,
+        locals: vec![Value::Int(0); program.functions[function].locals as usize],
+        base,
+    }
+}
+
@@ -0,0 +1,132 @@
+// On-disk cache of what `build` generates, under `target/cache` at the root of the project, see `manifest`.
+// Executables are linked from an object file per module, cached under the hash of the module once checked,
+// see `module_key`: building again only generates the code of the modules whose key changed.
+// The program is still checked as a whole on every build, its modules being merged before it is,
+// e.g. generic functions are instantiated for the types the importers call them with, into the module defining them.
+// The other artifacts are a single file for the whole program, e.g. bytecode numbering the functions of every module,
+// cached under the contents of every module read, see `key`, and loaded without checking the program again.
+//
+// Programs outside of a project, or with plugins registered, aren't cached, nor the whole program artifacts
+// of programs with warnings, which wouldn't be reported again.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "aot")]
+use crate::frontend::ast::AST;
+#[cfg(feature = "aot")]
+use crate::frontend::symbol::Symbol;
+#[cfg(feature = "aot")]
+use crate::frontend::types::Ty;
+use crate::frontend::{edition, optimize, overflow, plugin};
+use crate::gc;
+use crate::manifest;
+
+/// The first line of cached artifacts, followed by what the artifact was built from, see `Entry`
+const HEADER: &str = "moo-cache 3";
+
+/// The directory of the cache of a project
+pub struct Cache {
+    dir: PathBuf,
+}
+
+/// A cached artifact
+pub struct Entry {
+    /// The functions compiled, for the summary
+    pub functions: usize,
+    pub bytes: Vec<u8>,
+}
+
+/// The cache of the project `entry` is in, if it is in one and the programs compiled can be cached
+/// * `entry` - the first entry file of the program
+pub fn open(entry: &Path) -> Option<Cache> {
+    if plugin::registered() {
+        return None;
+    }
+    let dir = entry.parent().unwrap_or(Path::new(""));
+    let manifest = manifest::find(dir)?;
+    let root = manifest.parent().unwrap_or(Path::new(""));
+    Some(Cache { dir: root.join("target").join("cache") })
+}
+
+/// The key of the artifact of a program, hashing the contents of all its `files` and the settings of the compilation,
+/// none if one can't be read, e.g. code given on the command line
+/// * `settings` - what the artifact depends on besides the sources, e.g. its kind and target
+pub fn key<'a>(files: impl IntoIterator<Item = &'a PathBuf>, settings: &str) -> Option<u64> {
+    let mut hash = Fnv::of_settings(settings);
+    for file in files {
+        hash.write(file.to_string_lossy().as_bytes());
+        hash.write(&fs::read(file).ok()?);
+    }
+    Some(hash.0)
+}
+
+/// The key of the object of a module, hashing its syntax tree once checked, locations included,
+/// the types of the functions of other modules it calls and the settings of the compilation.
+/// A module whose imports changed keeps its key as long as what it calls of them keeps its type
+/// * `path` - the source file of the module, which its debug information refers to
+/// * `imports` - the functions of other modules the module calls, with their types, see `object::compile_object`
+/// * `settings` - what the object depends on besides the module, e.g. its target
+#[cfg(feature = "aot")]
+pub fn module_key(path: &Path, module: &AST, imports: &[(Symbol, Ty)], settings: &str) -> u64 {
+    let mut hash = Fnv::of_settings(settings);
+    hash.write(path.to_string_lossy().as_bytes());
+    hash.write(&serde_json::to_vec(module).expect("syntax trees serialize"));
+    for (name, ty) in imports {
+        hash.write(format!("{}: {}", name, ty).as_bytes());
+    }
+    hash.0
+}
+
+impl Cache {
+    /// The artifact cached for `key`, if any
+    pub fn get(&self, key: u64) -> Option<Entry> {
+        let bytes = fs::read(self.path(key)).ok()?;
+        let end = bytes.iter().position(|byte| *byte == b'\n')?;
+        let header = std::str::from_utf8(&bytes[..end]).ok()?;
+        let mut fields = header.strip_prefix(HEADER)?.split_whitespace();
+        let functions = fields.next()?.parse().ok()?;
+        Some(Entry { functions, bytes: bytes[end + 1..].to_vec() })
+    }
+
+    /// Caches the artifact of `key`. The cache being an optimization, failing to write it isn't an error
+    pub fn put(&self, key: u64, entry: &Entry) {
+        let mut bytes = format!("{} {}\n", HEADER, entry.functions).into_bytes();
+        bytes.extend_from_slice(&entry.bytes);
+        let written = fs::create_dir_all(&self.dir).and_then(|_| fs::write(self.path(key), bytes));
+        if let Err(error) = written {
+            tracing::debug!(dir = %self.dir.display(), %error, "cannot write the cache");
+        }
+    }
+
+    fn path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}", key))
+    }
+}
+
+/// The 64-bit FNV-1a hash, which unlike the hasher of the standard library is the same across builds of the compiler
+struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Fnv(0xcbf29ce484222325)
+    }
+}
+
+impl Fnv {
+    /// The hash of the version of the compiler and the settings of the compilation, which every key starts with
+    fn of_settings(settings: &str) -> Self {
+        let mut hash = Fnv::default();
+        hash.write(env!("CARGO_PKG_VERSION").as_bytes());
+        hash.write(settings.as_bytes());
+        hash.write(format!("{:?} {:?} {:?} {:?}", gc::current(), optimize::current(), overflow::current(), edition::current()).as_bytes());
+        hash
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        // the length separates the parts hashed
+        for byte in (bytes.len() as u64).to_le_bytes().iter().chain(bytes) {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+}
@@ -0,0 +1,136 @@
+// The C API of the library, to embed moolang into programs written in other languages than Rust,
+// e.g. C, or Python through ctypes, which load the `cdylib` of the crate, declared by `include/moolang.h`.
+// It wraps an `Engine`, and the functions it runs take and return integers only.
+// Functions failing return -1, and keep their error for `moo_last_error` to return, per thread.
+
+use core::fmt;
+use std::cell::RefCell;
+use std::error::Error;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::fmt::Display;
+
+use crate::engine::Engine;
+use crate::errors::Coded;
+use crate::messages::{message, Message};
+
+/// The most arguments `moo_run` passes
+const MAX_ARGS: usize = 6;
+
+#[derive(Debug)]
+pub struct CapiError {
+    message: Message,
+}
+
+impl fmt::Display for CapiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CapiError: {}", self.message)
+    }
+}
+
+impl Error for CapiError {}
+
+impl Coded for CapiError {
+    fn code(&self) -> Option<&'static str> {
+        self.message.code()
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Keeps `error` for `moo_last_error`, returning -1
+fn fail(error: impl Display) -> c_int {
+    // errors can't hold a null byte, which would end them early in C
+    let error = error.to_string().replace('\0', "\\0");
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(CString::new(error).expect("no null bytes")));
+    -1
+}
+
+/// Creates an engine, to free with `moo_engine_free`
+#[no_mangle]
+pub extern "C" fn moo_engine_new() -> *mut Engine {
+    Box::into_raw(Box::new(Engine::new()))
+}
+
+/// Frees an engine, along with the code it compiled, see `JIT::free`
+///
+/// # Safety
+/// `engine` comes from `moo_engine_new`, or is null, and isn't used after
+#[no_mangle]
+pub unsafe extern "C" fn moo_engine_free(engine: *mut Engine) {
+    if !engine.is_null() {
+        drop(Box::from_raw(engine));
+    }
+}
+
+/// Reads a string given to the API
+///
+/// # Safety
+/// `string` is null terminated
+unsafe fn read<'a>(string: *const c_char, what: &str) -> Result<&'a str, CapiError> {
+    CStr::from_ptr(string).to_str().map_err(|error| CapiError {
+        message: message!("E0431", what = what, error = error),
+    })
+}
+
+/// Compiles a module from its source, see `Engine::compile_str`. Returns 0, or -1 if it doesn't compile
+///
+/// # Safety
+/// `engine` comes from `moo_engine_new`, `source` is a null terminated string
+#[no_mangle]
+pub unsafe extern "C" fn moo_compile(engine: *mut Engine, source: *const c_char) -> c_int {
+    let source = match read(source, "source") {
+        Ok(source) => source,
+        Err(error) => return fail(error),
+    };
+    match (*engine).compile_str(source) {
+        Ok(()) => 0,
+        Err(errors) => fail(errors),
+    }
+}
+
+/// Calls the module level function `name` compiled before, which takes `count` arguments of type `int`,
+/// at most `MAX_ARGS`, and returns an `int`, written to `result`. Returns 0, or -1 if there's no such function
+///
+/// # Safety
+/// `engine` comes from `moo_engine_new`, `name` is a null terminated string, `args` points to `count` integers
+/// or is null without any, and `result` points to an integer
+#[no_mangle]
+pub unsafe extern "C" fn moo_run(engine: *const Engine, name: *const c_char, args: *const i64, count: usize, result: *mut i64) -> c_int {
+    let engine = &*engine;
+    let name = match read(name, "function name") {
+        Ok(name) => name,
+        Err(error) => return fail(error),
+    };
+    let args = match count {
+        0 => &[][..],
+        _ => std::slice::from_raw_parts(args, count),
+    };
+    let returned = match *args {
+        [] => engine.get_function::<fn() -> i64>(name).map(|function| function.call()),
+        [a] => engine.get_function::<fn(i64) -> i64>(name).map(|function| function.call(a)),
+        [a, b] => engine.get_function::<fn(i64, i64) -> i64>(name).map(|function| function.call(a, b)),
+        [a, b, c] => engine.get_function::<fn(i64, i64, i64) -> i64>(name).map(|function| function.call(a, b, c)),
+        [a, b, c, d] => engine.get_function::<fn(i64, i64, i64, i64) -> i64>(name).map(|function| function.call(a, b, c, d)),
+        [a, b, c, d, e] => engine.get_function::<fn(i64, i64, i64, i64, i64) -> i64>(name).map(|function| function.call(a, b, c, d, e)),
+        [a, b, c, d, e, f] => engine.get_function::<fn(i64, i64, i64, i64, i64, i64) -> i64>(name).map(|function| function.call(a, b, c, d, e, f)),
+        _ => return fail(CapiError {
+            message: message!("E0430", max = MAX_ARGS, count = count),
+        }),
+    };
+    match returned {
+        Ok(value) => {
+            *result = value;
+            0
+        }
+        Err(error) => fail(error),
+    }
+}
+
+/// The error of the last function of the API which failed on this thread, or null if none did.
+/// The string lives until the next function failing on this thread
+#[no_mangle]
+pub extern "C" fn moo_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(std::ptr::null(), |error| error.as_ptr()))
+}
@@ -1,5 +0,0 @@
-
-use crate::frontend::ast::{AST, Type};
-
-
-
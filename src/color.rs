@@ -0,0 +1,70 @@
+// Whether the diagnostics, and the debug output of the compiler, are colored.
+// The text is written with the ANSI escape codes of the colors through anstream, which strips them
+// when stderr can't show them, but the text formatted into strings, e.g. for the errors of an `Engine`,
+// is only colored if the colors are enabled, see `style`.
+
+use core::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use anstream::{AutoStream, ColorChoice};
+use owo_colors::Style;
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Color {
+    /// Color the output when stderr is a terminal which supports colors, and `NO_COLOR` isn't set
+    #[default]
+    Auto,
+    /// Always color the output
+    Always,
+    /// Never color the output
+    Never,
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Color::Auto => write!(f, "auto"),
+            Color::Always => write!(f, "always"),
+            Color::Never => write!(f, "never"),
+        }
+    }
+}
+
+static COLOR: AtomicU8 = AtomicU8::new(Color::Auto as u8);
+
+/// Sets whether the output is colored from now on, that written through anstream included
+pub fn set(color: Color) {
+    COLOR.store(color as u8, Ordering::Relaxed);
+    match color {
+        Color::Auto => ColorChoice::Auto,
+        Color::Always => ColorChoice::Always,
+        Color::Never => ColorChoice::Never,
+    }.write_global();
+}
+
+/// Whether the output is colored, decided for the terminal unless set otherwise
+pub fn current() -> Color {
+    match COLOR.load(Ordering::Relaxed) {
+        0 => Color::Auto,
+        1 => Color::Always,
+        _ => Color::Never,
+    }
+}
+
+/// Whether the text written to stderr is colored, for `auto` as anstream decides,
+/// which honors `NO_COLOR` and `CLICOLOR_FORCE`
+pub fn enabled() -> bool {
+    match current() {
+        Color::Auto => AutoStream::choice(&std::io::stderr()) != ColorChoice::Never,
+        Color::Always => true,
+        Color::Never => false,
+    }
+}
+
+/// Returns `style` if the colors are enabled, a plain style writing no escape codes otherwise
+pub fn style(style: Style) -> Style {
+    match enabled() {
+        true => style,
+        false => Style::new(),
+    }
+}
@@ -1,25 +1,1186 @@
+use core::fmt;
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 use std::result::Result;
-use anstream::println;
+use anstream::{print, println};
 
 
-use crate::errors::LocalizedError;
-use crate::frontend::tokenizer::tokenize;
-use crate::frontend::ast;
+use crate::errors::{self, Coded, Diagnostics, ErrorFormat, LocalizableError, LocalizedError, LocalizedSourcedError, Severity, Suggestion};
+use crate::messages::{message, Message};
+use crate::frontend::tokenizer::{tokenize, Location, Operator};
+use crate::frontend::arena::Node;
+use crate::frontend::ast::{to_source, AST, Associativity, Type, TypeName, GRAMMAR, PRECEDENCE};
+#[cfg(feature = "fmt")]
+use crate::frontend::ast::{self, to_formatted_source};
+use crate::frontend::modules::{load_all_imports_with_files, load_imports, merge, parse_file, read_source, source_files};
+use crate::frontend::resolve::{binding_name, resolve};
+#[cfg(feature = "aot")]
+use crate::frontend::resolve::free_variables;
+use crate::frontend::unused::{unused_functions, unused_variables};
+use crate::frontend::generics::monomorphize_with_types;
+use crate::frontend::plugin::run_plugins;
+use crate::frontend::symbol::Symbol;
+use crate::frontend::types::{binding_types, Aliases, Ty};
+#[cfg(feature = "aot")]
+use crate::frontend::types::lambda_type;
+use crate::bytecode::{self, vm};
+use crate::interp::{self, Interpreter};
+use crate::{interrupt, ir};
+use crate::ir::Printed;
+use crate::{cache, sources};
+use crate::sources::SourceFile;
+#[cfg(feature = "aot")]
+use crate::sources::FileId;
+#[cfg(feature = "jit")]
+use crate::jit::JIT;
+#[cfg(feature = "jit")]
+use crate::gc::{self, Gc};
+#[cfg(feature = "jit")]
+use crate::runtime;
+#[cfg(feature = "aot")]
+use crate::object::{compile_listings, compile_object, link};
 
-pub fn compile_lines<I, S>(lines: I) -> Result<(), LocalizedError> 
-where I: Iterator<Item = S>, S: AsRef<str>
-{
-    let mut tokenizer = tokenize(lines);
+#[derive(Debug)]
+pub struct RunError {
+    message: Message,
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RunError: {}", self.message)
+    }
+}
+
+impl Error for RunError {}
+
+impl Coded for RunError {
+    fn code(&self) -> Option<&'static str> {
+        self.message.code()
+    }
+}
+
+/// What a compilation did, summed up on one line with `--verbose`
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// The source files read, imported modules included
+    pub modules: usize,
+    /// The functions the backend compiled, lambdas and generic instances included
+    pub functions: usize,
+    /// The warnings reported
+    pub warnings: usize,
+    /// The time compiling took, running the program aside
+    pub elapsed: Duration,
+    /// The exit code of the program run, the value returned by a `main` taking its arguments, see `check_entry_point`
+    pub exit_code: i32,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plural = |count: usize| if count == 1 { "" } else { "s" };
+        write!(f, "compiled {} module{}, {} function{} in {}ms; {} warning{}",
+            self.modules, plural(self.modules),
+            self.functions, plural(self.functions),
+            self.elapsed.as_millis(),
+            self.warnings, plural(self.warnings))
+    }
+}
+
+/// A phase of compiling and running a program, timed by `time`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Splitting the source into tokens, see `tokenize`
+    Tokenize,
+    /// Parsing the tokens into syntax trees, and loading the modules imported
+    Parse,
+    /// Resolving the names, checking the types and instantiating the generic functions, see `check_module`
+    Check,
+    /// Generating the code of the functions, or the bytecode or the tree the other backends run
+    Codegen,
+    /// Resolving the calls between the functions the JIT compiled, and making their code executable
+    Finalize,
+    /// Linking an executable, see `build`
+    Link,
+    /// Running the program
+    Run,
+}
+
+impl Phase {
+    pub const ALL: &'static [Phase] = &[Phase::Tokenize, Phase::Parse, Phase::Check, Phase::Codegen, Phase::Finalize, Phase::Link, Phase::Run];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Tokenize => "tokenize",
+            Phase::Parse => "parse",
+            Phase::Check => "check",
+            Phase::Codegen => "codegen",
+            Phase::Finalize => "finalize",
+            Phase::Link => "link",
+            Phase::Run => "run",
+        }
+    }
+}
+
+/// The time spent in each phase, printed with `--time-phases`, see `take_timings`
+#[derive(Debug, Default)]
+pub struct Timings {
+    /// The phases which ran, in the order of `Phase::ALL`, with the time spent in them
+    pub phases: Vec<(Phase, Duration)>,
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (phase, elapsed) in self.phases.iter() {
+            writeln!(f, "{:<10}{:>10.3}ms", phase.name(), elapsed.as_secs_f64() * 1000.0)?;
+        }
+        let total = self.phases.iter().map(|(_, elapsed)| *elapsed).sum::<Duration>();
+        write!(f, "{:<10}{:>10.3}ms", "total", total.as_secs_f64() * 1000.0)
+    }
+}
+
+#[derive(Default)]
+struct Clock {
+    /// The time spent in each phase of `Phase::ALL` so far, if it ran
+    spent: [Option<Duration>; Phase::ALL.len()],
+    /// The phases running, the innermost last, with when they started or their innermost phase ended
+    running: Vec<(Phase, Instant)>,
+}
+
+thread_local! {
+    static CLOCK: RefCell<Clock> = RefCell::new(Clock::default());
+}
+
+/// Runs `f` as part of `phase`, adding the time it takes to the time spent in the phase.
+/// Phases nest, the time spent in the inner phase only counting for it, e.g. tokenizing during parsing
+pub fn time<T>(phase: Phase, f: impl FnOnce() -> T) -> T {
+    // lines are tokenized one at a time
+    let span = match phase {
+        Phase::Tokenize => tracing::trace_span!("phase", name = phase.name()),
+        _ => tracing::debug_span!("phase", name = phase.name()),
+    };
+    let _entered = span.enter();
+    let add = |clock: &mut Clock, phase: Phase, since: Instant| {
+        let spent = &mut clock.spent[phase as usize];
+        *spent = Some(spent.unwrap_or_default() + since.elapsed());
+    };
+    CLOCK.with(|clock| {
+        let mut clock = clock.borrow_mut();
+        if let Some((outer, since)) = clock.running.last().copied() {
+            add(&mut clock, outer, since);
+        }
+        clock.running.push((phase, Instant::now()));
+    });
+    let result = f();
+    CLOCK.with(|clock| {
+        let mut clock = clock.borrow_mut();
+        let (phase, since) = clock.running.pop().expect("a phase running");
+        add(&mut clock, phase, since);
+        if let Some((_, since)) = clock.running.last_mut() {
+            *since = Instant::now();
+        }
+    });
+    result
+}
+
+/// Returns the time spent in each phase since the last call
+pub fn take_timings() -> Timings {
+    let spent = CLOCK.with(|clock| std::mem::take(&mut clock.borrow_mut().spent));
+    Timings {
+        phases: Phase::ALL.iter()
+            .zip(spent)
+            .filter_map(|(phase, spent)| spent.map(|spent| (*phase, spent)))
+            .collect(),
+    }
+}
+
+/// The number of threads compiling functions into machine code, 0 for one per core
+static JOBS: AtomicUsize = AtomicUsize::new(0);
+
+/// Sets the number of threads compiling functions into machine code, 0 for one per core of the machine
+pub fn set_jobs(jobs: usize) {
+    JOBS.store(jobs, Ordering::Relaxed);
+}
+
+/// The number of threads compiling functions into machine code, one per core unless set otherwise
+pub fn jobs() -> usize {
+    match JOBS.load(Ordering::Relaxed) {
+        0 => std::thread::available_parallelism().map_or(1, usize::from),
+        jobs => jobs,
+    }
+}
+
+/// What generates the code of a program and runs it
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default)]
+pub enum Backend {
+    /// Compiles to machine code in memory with cranelift
+    #[default]
+    Jit,
+    /// Evaluates the syntax tree directly, without generating code, see `interp`
+    Interp,
+    /// Compiles to bytecode, run by a stack machine, see `bytecode`
+    Vm,
+}
+
+/// What `build` writes
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default)]
+pub enum Artifact {
+    /// An executable, running the `main` function of the program and printing the value it returns
+    #[default]
+    Exe,
+    /// A relocatable object file, `.o`, to link into other programs
+    Obj,
+    /// The Cranelift IR of every function, as generated then once optimized
+    Clif,
+    /// The assembly of every function: the machine instructions Cranelift selected, before encoding
+    Asm,
+    /// The IR of every function, see `ir`, optimized as set by `-O`
+    Ir,
+    /// Bytecode, `.moob`, which `run` runs without the JIT, see `bytecode`
+    Bytecode,
+}
+
+impl Artifact {
+    /// The extension of the file written by default, text being printed instead
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            Artifact::Exe => Some(std::env::consts::EXE_EXTENSION),
+            Artifact::Obj => Some("o"),
+            Artifact::Bytecode => Some(bytecode::EXTENSION),
+            Artifact::Clif | Artifact::Asm | Artifact::Ir => None,
+        }
+    }
+}
+
+/// The format of what the `emit` command prints
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default)]
+pub enum Format {
+    /// For people to read, or the format of the tool consuming it, e.g. a Makefile rule for dependencies
+    #[default]
+    Text,
+    /// Like `text`, with the values the compiler works with, e.g. `Operator(Let)` for the token `let`.
+    /// Stages without such values print text.
+    Typed,
+    /// A JSON document, for other programs to consume
+    Json,
+}
+
+/// Compiles the files at `paths`, or the source files in them for directories,
+/// along with the modules they import, into one program
+/// * `run` - the arguments to run the program with once compiled, if it is run, see `compile_module`
+pub fn compile_files(paths: &[PathBuf], run: Option<&[String]>, backend: Backend) -> Result<Summary, Diagnostics> {
+    let mut summary = Summary {
+        modules: 0,
+        functions: 0,
+        warnings: 0,
+        elapsed: Duration::ZERO,
+        exit_code: 0,
+    };
+    let started = Instant::now();
+    let loaded = load_program_with_files(paths);
+    // the warnings come before what the program prints, or the errors
+    summary.warnings = report_warnings();
+    let (ast, paths, imported) = loaded?;
+    // a module imported by several modules is read once by each
+    summary.modules = paths.iter().chain(imported.iter()).collect::<HashSet<_>>().len();
+    // the errors are reported in the file their span is from, the first file only standing for
+    // the spans of no registered source, e.g. those of generated code, see `with_source`
+    compile_module(ast, &paths[0], run, backend, started, &mut summary).map_err(|errors| errors
+        .into_iter()
+        .map(|err| err.with_source(&paths[0]))
+        .collect::<Diagnostics>())?;
+    Ok(summary)
+}
+
+/// Checks the files at `paths` like `compile_files` compiles them, without generating code or running it:
+/// the errors found are those of compiling the program, but for those of the backends, e.g. of extern functions.
+/// The functions of the summary are those lowered into the IR
+pub fn check_files(paths: &[PathBuf]) -> Result<Summary, Diagnostics> {
+    let mut summary = Summary::default();
+    let started = Instant::now();
+    let loaded = load_program_with_files(paths);
+    summary.warnings = report_warnings();
+    let (ast, paths, imported) = loaded?;
+    summary.modules = paths.iter().chain(imported.iter()).collect::<HashSet<_>>().len();
+    let checked = check_module(ast, &paths[0], &mut summary)
+        // the errors of calls are found lowering them
+        .and_then(|ast| ir::compile(&ast, &HashMap::new(), &HashMap::new()));
+    // the first file only stands for the spans of no registered source, like in `compile_files`
+    let program = checked.map_err(|errors| errors
+        .into_iter()
+        .map(|err| err.with_source(&paths[0]))
+        .collect::<Diagnostics>())?;
+    summary.functions = program.functions.len();
+    summary.elapsed = started.elapsed();
+    Ok(summary)
+}
+
+/// The number of times `fix_files` checks the program, fixing what the fixes before let the checks find
+const FIX_PASSES: usize = 8;
+
+/// Applies the machine-applicable suggestions of the errors and warnings of the program at `paths` to its
+/// source files, e.g. inserting a missing `;` or replacing a deprecated construct, see `errors::Applicability`.
+/// The program is checked again after each round of fixes, a syntax error hiding the warnings of the rest,
+/// until a round leaves as many errors as there were before it, which more of its fixes wouldn't change.
+/// Returns the number of fixes applied to each file changed, in the order they were first changed
+pub fn fix_files(paths: &[PathBuf]) -> std::io::Result<Vec<(PathBuf, usize)>> {
+    let mut fixed: Vec<(PathBuf, usize)> = Vec::new();
+    let mut previous = usize::MAX;
+    for _ in 0..FIX_PASSES {
+        let (errors, fixes) = errors::collect_fixes(|| match check_files(paths) {
+            Ok(_) => 0,
+            Err(errors) => {
+                errors::report(&errors);
+                errors.0.len()
+            }
+        });
+        if errors > 0 && errors >= previous {
+            break;
+        }
+        previous = errors;
+        let mut files = BTreeMap::<PathBuf, Vec<Suggestion>>::new();
+        for (path, fix) in fixes {
+            files.entry(path).or_default().push(fix);
+        }
+        let mut changed = false;
+        // code given on the command line isn't written anywhere
+        for (path, fixes) in files.into_iter().filter(|(path, _)| !sources::is_registered(path)) {
+            // the fixes are for the source compiled, which the source map has
+            let (source, applied) = apply_fixes(&*sources::load(&path)?, fixes);
+            if applied == 0 {
+                continue;
+            }
+            std::fs::write(&path, source)?;
+            sources::reload(&path);
+            changed = true;
+            match fixed.iter_mut().find(|(fixed, _)| *fixed == path) {
+                Some((_, count)) => *count += applied,
+                None => fixed.push((path, applied)),
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    Ok(fixed)
+}
+
+/// Applies `fixes` to `file`, but those overlapping a fix applied and those whose code isn't at their span,
+/// which weren't made for this source. Returns the source fixed and the number of fixes applied
+fn apply_fixes(file: &SourceFile, mut fixes: Vec<Suggestion>) -> (String, usize) {
+    // from the end of the source, for the offsets of those left to apply to stay right
+    fixes.sort_by_key(|fix| Reverse((fix.span.start.offset, fix.span.end.offset)));
+    fixes.dedup();
+    let mut source = file.text.to_string();
+    let mut applied = 0;
+    let mut limit = source.len();
+    for fix in fixes {
+        let range = fix.span.start.offset..fix.span.end.offset;
+        if range.end > limit || source.get(range.clone()) != Some(fix.code.as_str()) {
+            continue;
+        }
+        source.replace_range(range.clone(), &fix.replacement);
+        limit = range.start;
+        applied += 1;
+    }
+    (source, applied)
+}
+
+/// Formats the source file at `path` in the layout of `to_source`, keeping its comments and the blank lines
+/// between its statements, see `to_formatted_source`. Deprecated constructs are written as what replaces them
+#[cfg(feature = "fmt")]
+pub fn format_file(path: &Path) -> Result<String, Diagnostics> {
+    let source = read_source(path)?;
+    let mut tokenizer = tokenize(source.lines()).in_file(&source).keep_comments();
+    let ast = time(Phase::Parse, || ast::parse(&mut tokenizer));
+    // the deprecations are those formatting rewrites
+    ast::take_warnings();
+    let ast = ast.map_err(|errors| errors.into_iter().map(|error| error.with_source(path)).collect::<Diagnostics>())?;
+    Ok(to_formatted_source(&ast, tokenizer.take_comments()))
+}
+
+/// Formats the source files at `paths`, or in the directories among them, see `format_file`,
+/// writing those which change unless `check`. Nothing is written if any of them has errors.
+/// Returns the files which weren't formatted, with the first line which changed
+#[cfg(feature = "fmt")]
+pub fn format_files(paths: &[PathBuf], check: bool) -> Result<Vec<(PathBuf, usize)>, Diagnostics> {
+    let mut formatted = Vec::new();
+    let mut errors = Vec::new();
+    for path in source_files(paths)? {
+        match format_file(&path) {
+            Ok(text) => formatted.push((path, text)),
+            Err(diagnostics) => errors.extend(diagnostics.0),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(Diagnostics(errors));
+    }
+    let mut changed = Vec::new();
+    for (path, text) in formatted {
+        let source = read_source(&path)?;
+        if *source.text == text {
+            continue;
+        }
+        let line = source.text.lines().zip(text.lines()).take_while(|(before, after)| before == after).count() + 1;
+        if !check {
+            std::fs::write(&path, &text).map_err(|error| RunError {
+                message: message!("E0409", path = path.display(), error = error),
+            }.with_location(Location::default()).with_source(&path))?;
+            sources::reload(&path);
+        }
+        changed.push((path, line));
+    }
+    Ok(changed)
+}
+
+/// Compiles the code given on the command line with `-e` and runs it, its errors being reported in `<eval>`.
+/// Unless it defines a `main` function, its statements but the bindings are the body of one,
+/// `fn(args: [string]) -> int`, returning 0, so that a one-liner doesn't need to define it
+/// * `args` - the arguments of the program, as `args` to the statements
+pub fn eval_source(source: &str, args: &[String], backend: Backend) -> Result<Summary, Diagnostics> {
+    let mut summary = Summary::default();
+    let started = Instant::now();
+    let path = sources::eval_name();
+    sources::register(&path, source);
+    let loaded = parse_file(&path).and_then(|module| load_imports(module, &path));
+    summary.warnings = report_warnings();
+    let module = loaded?;
+    summary.modules = 1;
+    let module = match defines_main(&module) {
+        true => module,
+        false => eval_main(module),
+    };
+    compile_module(module, &path, Some(args), backend, started, &mut summary).map_err(|errors| errors
+        .into_iter()
+        .map(|err| err.with_source(&path))
+        .collect::<Diagnostics>())?;
+    Ok(summary)
+}
+
+/// Whether a module binds a `main`, of any type
+fn defines_main(module: &AST) -> bool {
+    match &**module {
+        Type::Module(statements) => statements.iter().any(|statement| matches!(
+            &**statement,
+            Type::Expression(Operator::Let, name, _) if binding_name(name).is_some_and(|name| name == "main"),
+        )),
+        _ => unreachable!("expected a module"),
+    }
+}
+
+/// Moves the statements of a module which aren't bindings into the body of a `main` function, see `eval_source`
+fn eval_main(module: AST) -> AST {
+    let span = module.span();
+    let statements = match module.type_() {
+        Type::Module(statements) => statements,
+        _ => unreachable!("parse returns a module"),
+    };
+    let (mut bindings, body): (Vec<_>, Vec<_>) = statements
+        .into_iter()
+        .partition(|statement| matches!(**statement, Type::Expression(Operator::Let, ..)));
+    let mut body = body.into_iter()
+        .map(|statement| {
+            let span = statement.span();
+            Type::Discard(Node::new(statement)).wrap_span(span)
+        })
+        .collect::<Vec<_>>();
+    body.push(Type::Literal(Symbol::intern("0")).wrap_span(span));
+    let args = Type::TypedLiteral(Symbol::intern("args"), TypeName::Array(Box::new(TypeName::Named("string".to_owned())))).wrap_span(span);
+    let main = Type::Lambda(TypeName::Named("int".to_owned()), vec![args], Node::new(Type::Block(body).wrap_span(span))).wrap_span(span);
+    bindings.push(Type::Expression(
+        Operator::Let,
+        Node::new(Type::Literal(Symbol::intern("main")).wrap_span(span)),
+        Node::new(main),
+    ).wrap_span(span));
+    Type::Module(bindings).wrap_span(span)
+}
+
+/// Compiles the files at `paths` like `compile_files`, into an `artifact` written to `output`,
+/// by default named after the first entry file, in the current directory, or printed for text
+/// * `target` - the target triple of the machine to compile for, the host by default
+/// * `debug_info` - whether to write debug information into executables and object files, see `debuginfo`
+///
+/// In a project, what is generated is cached, see `cache`: executables are linked from an object per module,
+/// generated again for the modules which changed only, and the other artifacts are built again once any module changed
+pub fn build_files(paths: &[PathBuf], artifact: Artifact, target: Option<&str>, output: Option<&Path>, debug_info: bool) -> Result<Summary, Diagnostics> {
+    let mut summary = Summary::default();
+    let started = Instant::now();
+    let loaded = load_program_with_files(paths);
+    summary.warnings = report_warnings();
+    let (ast, paths, imported) = loaded?;
+    summary.modules = paths.iter().chain(imported.iter()).collect::<HashSet<_>>().len();
+    let name = paths[0].file_stem().unwrap_or_default().to_string_lossy().into_owned();
+    let output = output
+        .map(Path::to_path_buf)
+        .or_else(|| artifact.extension().map(|extension| PathBuf::from(&name).with_extension(extension)));
+    let location = *ast.location();
+    let cache = cache::open(&paths[0]);
+    let settings = format!("{:?} {:?} {} {} {}", artifact, target, debug_info, name, paths[0].display());
+    let built = match artifact {
+        #[cfg(feature = "aot")]
+        Artifact::Exe => check_module_with_types(ast, &paths[0], &mut summary)
+            .and_then(|(ast, types)| {
+                let main_args = check_entry_point(&ast).map_err(|err| vec![err])?;
+                let returns = Printed::of(&main_returns(&types));
+                let objects = time(Phase::Codegen, || build_objects(ast, &paths[0], target, debug_info, cache.as_ref(), &settings, &mut summary))?;
+                Ok((objects, main_args, returns))
+            }),
+        _ => {
+            let key = cache.as_ref().and_then(|_| cache::key(paths.iter().chain(imported.iter()), &settings));
+            match cache.as_ref().zip(key).and_then(|(cache, key)| cache.get(key)) {
+                Some(entry) => {
+                    tracing::debug!("cache hit");
+                    summary.functions = entry.functions;
+                    Ok(vec![entry.bytes])
+                }
+                None => check_module(ast, &paths[0], &mut summary).and_then(|ast| {
+                    let bytes = time(Phase::Codegen, || build_artifact(&ast, &paths[0], &name, artifact, target, debug_info, &mut summary))?;
+                    if let (Some(cache), Some(key), 0) = (&cache, key, summary.warnings) {
+                        cache.put(key, &cache::Entry { functions: summary.functions, bytes: bytes.clone() });
+                    }
+                    Ok(vec![bytes])
+                }),
+            }
+            .map(|bytes| (bytes, false, Printed::Number(ir::Format::Int)))
+        }
+    };
+    built
+        .and_then(|(parts, main_args, returns)| write_build(location, &parts, &name, artifact, output.as_deref(), (main_args, returns), debug_info))
+        .map_err(|errors| errors
+            .into_iter()
+            .map(|err| err.with_source(&paths[0]))
+            .collect::<Diagnostics>())?;
+    summary.elapsed = started.elapsed();
+    Ok(summary)
+}
+
+/// Generates an object file for every module of a checked program, see `modules_of`, to link together,
+/// those whose key is in the `cache` loaded from it instead, see `cache::module_key`
+/// * `entry` - the first entry file of the program
+/// * `settings` - what the objects depend on besides their modules, see `cache::module_key`
+#[cfg(feature = "aot")]
+fn build_objects(ast: AST, entry: &Path, target: Option<&str>, debug_info: bool, cache: Option<&cache::Cache>, settings: &str, summary: &mut Summary) -> Result<Vec<Vec<u8>>, Vec<LocalizedError>> {
+    let mut objects = Vec::new();
+    let mut errors = Vec::new();
+    for (path, module, imports) in modules_of(ast, entry) {
+        let key = cache.map(|_| cache::module_key(&path, &module, &imports, settings));
+        if let Some(cached) = cache.zip(key).and_then(|(cache, key)| cache.get(key)) {
+            tracing::debug!(module = %path.display(), "cache hit");
+            summary.functions += cached.functions;
+            objects.push(cached.bytes);
+            continue;
+        }
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        match compile_object(&module, &path, &imports, &name, target, debug_info) {
+            Ok((bytes, functions)) => {
+                summary.functions += functions;
+                if let (Some(cache), Some(key)) = (cache, key) {
+                    cache.put(key, &cache::Entry { functions, bytes: bytes.clone() });
+                }
+                objects.push(bytes);
+            }
+            // the errors of every module are reported at once
+            Err(module_errors) => errors.extend(module_errors),
+        }
+    }
+    match errors.is_empty() {
+        true => Ok(objects),
+        false => Err(errors),
+    }
+}
+
+/// A module of a program, split out of it: its source file, its bindings and the functions of the other modules it calls
+#[cfg(feature = "aot")]
+type Part = (PathBuf, AST, Vec<(Symbol, Ty)>);
+
+/// Splits a checked program into its modules, by the source file of their module level bindings, in the order
+/// of their first binding. The instantiations of generic functions are in the module defining the function,
+/// the extern functions declared in every module calling them.
+/// * `entry` - the source file of the bindings of no file, e.g. generated ones
+#[cfg(feature = "aot")]
+fn modules_of(ast: AST, entry: &Path) -> Vec<Part> {
+    let location = *ast.location();
+    let statements = match ast.type_() {
+        Type::Module(statements) => statements,
+        _ => unreachable!("expected a module"),
+    };
+    let (externs, statements): (Vec<_>, Vec<_>) = statements.into_iter()
+        .partition(|statement| matches!(&**statement, Type::Expression(Operator::Let, _, value) if matches!(***value, Type::Extern(..))));
+    let externs = externs.into_iter()
+        .filter_map(|statement| Some((binding_of(&statement)?.0, statement)))
+        .collect::<HashMap<_, _>>();
+    let mut modules: Vec<(FileId, Vec<AST>)> = Vec::new();
+    let mut functions = HashMap::new();
+    for statement in statements {
+        let file = statement.location().file;
+        if let Some((name, lambda)) = binding_of(&statement) {
+            functions.insert(name, (file, lambda_type(lambda, &Aliases::default())));
+        }
+        match modules.iter_mut().find(|(module, _)| *module == file) {
+            Some((_, module)) => module.push(statement),
+            None => modules.push((file, vec![statement])),
+        }
+    }
+    modules.into_iter()
+        .map(|(file, mut statements)| {
+            let mut called = statements.iter()
+                .filter_map(|statement| binding_of(statement).map(|(_, lambda)| free_variables(lambda)))
+                .flatten()
+                .collect::<Vec<_>>();
+            called.sort_by_key(|name| name.to_string());
+            called.dedup();
+            let imports = called.iter()
+                .filter_map(|name| functions.get(name).filter(|(defined, _)| *defined != file).map(|(_, ty)| (*name, ty.clone())))
+                .collect();
+            statements.extend(called.iter().filter_map(|name| externs.get(name).cloned()));
+            let path = sources::file(file).map_or_else(|| entry.to_path_buf(), |source| source.name.clone());
+            (path, Type::Module(statements).wrap(location), imports)
+        })
+        .collect()
+}
+
+/// The name and the value of a module level binding
+#[cfg(feature = "aot")]
+fn binding_of(statement: &AST) -> Option<(Symbol, &AST)> {
+    match &**statement {
+        Type::Expression(Operator::Let, name, value) => binding_name(name).map(|name| (name, &**value)),
+        _ => None,
+    }
+}
 
-    let ast = ast::parse(&mut tokenizer);
+/// Generates the code of a checked module into the bytes of an `artifact`, written by `write_build`
+/// * `source` - the source file of the module, which the errors of the code running are reported in
+/// * `debug_info` - whether to write debug information against the source, see `debuginfo`
+#[cfg(feature = "aot")]
+fn build_artifact(ast: &AST, source: &Path, name: &str, artifact: Artifact, target: Option<&str>, debug_info: bool, summary: &mut Summary) -> Result<Vec<u8>, Vec<LocalizedError>> {
+    Ok(match artifact {
+        Artifact::Ir => compile_ir(ast, summary)?,
+        Artifact::Bytecode => compile_bytecode(ast, summary)?.write(),
+        Artifact::Exe | Artifact::Obj => {
+            let (bytes, functions) = compile_object(ast, source, &[], name, target, debug_info)?;
+            summary.functions = functions;
+            bytes
+        }
+        Artifact::Clif | Artifact::Asm => {
+            let listings = compile_listings(ast, source, name, target)?;
+            summary.functions = listings.len();
+            listings.iter()
+                .map(|listing| match artifact {
+                    Artifact::Asm => format!("; {}\n{}", listing.name, listing.asm),
+                    _ => format!("; {}\n{}; {}, optimized\n{}", listing.name, listing.clif, listing.name, listing.optimized_clif),
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+                .into_bytes()
+        }
+    })
+}
+
+/// Without the object backend, there is no code generator to build with, only the IR and bytecode can be written
+#[cfg(not(feature = "aot"))]
+fn build_artifact(ast: &AST, _: &Path, _: &str, artifact: Artifact, _: Option<&str>, _: bool, summary: &mut Summary) -> Result<Vec<u8>, Vec<LocalizedError>> {
+    match artifact {
+        Artifact::Ir => compile_ir(ast, summary),
+        Artifact::Bytecode => Ok(compile_bytecode(ast, summary)?.write()),
+        _ => Err(vec![RunError {
+            message: message!("E0408"),
+        }.with_location(*ast.location())]),
+    }
+}
+
+/// Writes the bytes of an `artifact` to `output`, linking executables, or prints them without
+/// * `location` - the location of the module, which the errors of writing are reported at
+/// * `parts` - the bytes of the artifact, those of the object of every module for executables, see `build_objects`
+/// * `(main_args, returns)` - whether `main` takes the arguments of the program, see `check_entry_point`,
+///   and how the value it returns is printed
+#[cfg_attr(not(feature = "aot"), allow(unused_variables))]
+fn write_build(location: Location, parts: &[Vec<u8>], name: &str, artifact: Artifact, output: Option<&Path>, (main_args, returns): (bool, Printed), debug_info: bool) -> Result<(), Vec<LocalizedError>> {
+    #[cfg(feature = "aot")]
+    if let (Artifact::Exe, Some(output)) = (artifact, output) {
+        return time(Phase::Link, || link(parts, name, output, main_args, returns, debug_info)).map_err(|err| vec![err.with_location(location)]);
+    }
+    write_artifact(location, &parts.concat(), output)
+}
+
+/// The IR of a checked module, printed
+fn compile_ir(ast: &AST, summary: &mut Summary) -> Result<Vec<u8>, Vec<LocalizedError>> {
+    let program = ir::compile(ast, &HashMap::new(), &HashMap::new())?;
+    summary.functions = program.functions.len();
+    Ok(program.to_string().into_bytes())
+}
+
+/// The bytecode of a checked module, along with the IR it was compiled from
+fn compile_bytecode(ast: &AST, summary: &mut Summary) -> Result<bytecode::Program, Vec<LocalizedError>> {
+    let program = ir::compile(ast, &HashMap::new(), &HashMap::new())?;
+    check_externs(&program)?;
+    summary.functions = program.functions.len();
+    Ok(bytecode::compile(&program))
+}
+
+/// Checks that the program declares no extern functions, which the stack machine can't call
+fn check_externs(program: &ir::Program) -> Result<(), Vec<LocalizedError>> {
+    match program.externs.first() {
+        Some(function) => Err(vec![RunError {
+            message: message!("E0427", name = function.symbol),
+        }.with_span(function.span)]),
+        None => Ok(()),
+    }
+}
+
+/// Writes the bytes of an artifact to `output`, or prints them without
+fn write_artifact(location: Location, bytes: &[u8], output: Option<&Path>) -> Result<(), Vec<LocalizedError>> {
+    let Some(output) = output else {
+        print!("{}", String::from_utf8_lossy(bytes));
+        return Ok(());
+    };
+    std::fs::write(output, bytes).map_err(|error| vec![RunError {
+        message: message!("E0409", path = output.display(), error = error),
+    }.with_location(location)])
+}
+
+/// Prints the warnings reported so far, returning how many there were, those denied aside
+pub fn report_warnings() -> usize {
+    let warnings = errors::take_warnings();
+    for warning in warnings.iter() {
+        errors::report(warning);
+        // a blank line separates the warnings from what comes next
+        if errors::format() == ErrorFormat::Human {
+            anstream::eprintln!();
+        }
+    }
+    warnings.iter().filter(|warning| errors::severity(*warning) == Severity::Warning).count()
+}
+
+/// Parses the source files at `paths` and the modules they import, and merges them into one module.
+/// Returns the module along with the source files, directories being expanded.
+pub fn load_program(paths: &[PathBuf]) -> Result<(AST, Vec<PathBuf>), Diagnostics> {
+    load_program_with_files(paths).map(|(ast, paths, _)| (ast, paths))
+}
+
+/// Like `load_program`, also returning the paths of the imported module files
+fn load_program_with_files(paths: &[PathBuf]) -> Result<(AST, Vec<PathBuf>, Vec<PathBuf>), Diagnostics> {
+    let paths = source_files(paths)?;
+    let files = paths.iter()
+        .map(|path| parse_file(path).map(|ast| (path.clone(), ast)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let (files, imported) = load_all_imports_with_files(files)?;
+    Ok((merge(files)?, paths, imported))
+}
+
+/// Compiles a module whose imports have been loaded.
+/// Codegen reports the errors of every function, the phases before stop at the first error.
+/// Without the `jit` feature, stops after type checking.
+/// On Ctrl-C, stops at the end of the current phase with the errors found so far.
+/// * `path` - the source file the warnings of the plugins are reported against
+/// * `run` - the arguments of the program, if the `main` function of the compiled module is to be called.
+///   A `main` without arguments doesn't get them, the value it returns is printed as its type is, e.g. the text of a string,
+///   that of a `main` taking them is the exit code of the program, see `check_entry_point`
+/// * `backend` - what generates the code and runs it
+/// * `started` - when compiling started, to time it in the `summary` of what was done
+pub fn compile_module(ast: AST, path: &Path, run: Option<&[String]>, backend: Backend, started: Instant, summary: &mut Summary) -> Result<(), Vec<LocalizedError>> {
+    let (ast, types) = check_module_with_types(ast, path, summary)?;
+    // whether to run, with the arguments for `main` if it takes them
+    let run = match run {
+        Some(args) => Some(check_entry_point(&ast).map_err(|err| vec![err])?.then_some(args)),
+        None => None,
+    };
+    // what `main` returns, printed the way its type is
+    let returns = main_returns(&types);
+
+    match backend {
+        Backend::Jit => run_jit(&ast, path, run, &returns, started, summary),
+        Backend::Interp => run_interp(&ast, run, &returns, started, summary),
+        Backend::Vm => run_vm(&ast, run, started, summary),
+    }
+}
+
+/// Runs the phases of the compilation before code generation on a module whose imports have been loaded,
+/// see `compile_module`, returning the module ready for code generation
+pub fn check_module(ast: AST, path: &Path, summary: &mut Summary) -> Result<AST, Vec<LocalizedError>> {
+    check_module_with_types(ast, path, summary).map(|(ast, _)| ast)
+}
+
+/// Like `check_module`, also returning the types of the module level bindings, see `monomorphize_with_types`
+pub fn check_module_with_types(ast: AST, path: &Path, summary: &mut Summary) -> Result<(AST, HashMap<Symbol, Ty>), Vec<LocalizedError>> {
+    time(Phase::Check, || check_module_timed(ast, path, summary))
+}
+
+fn check_module_timed(ast: AST, path: &Path, summary: &mut Summary) -> Result<(AST, HashMap<Symbol, Ty>), Vec<LocalizedError>> {
+    resolve(&ast)?;
+    unused_variables(&ast).into_iter().for_each(|warning| errors::warn(warning.with_source(path)));
+    unused_functions(&ast).into_iter().for_each(|warning| errors::warn(warning.with_source(path)));
+    if interrupt::interrupted() {
+        return Err(Vec::new());
+    }
+    let (ast, types) = monomorphize_with_types(ast).map_err(|err| vec![err])?;
+    if interrupt::interrupted() {
+        return Err(Vec::new());
+    }
+    let lints = run_plugins(&ast);
+    lints.warnings.into_iter().for_each(|warning| errors::warn(warning.with_source(path)));
+    summary.warnings += report_warnings();
+    if !lints.errors.is_empty() {
+        return Err(lints.errors);
+    }
+    // the denied warnings were reported as errors
+    if errors::take_denied() > 0 {
+        return Err(Vec::new());
+    }
+    Ok((ast, types))
+}
+
+/// The type of the value `main` returns, given the types of the module level bindings
+fn main_returns(types: &HashMap<Symbol, Ty>) -> Ty {
+    match types.get(&Symbol::intern("main")) {
+        Some(Ty::Function(_, returns)) => (**returns).clone(),
+        _ => Ty::Int,
+    }
+}
+
+/// Compiles the module with the JIT, then runs it if `run`, see `compile_module`
+/// * `returns` - the type of the value `main` returns
+#[cfg(feature = "jit")]
+fn run_jit(ast: &AST, path: &Path, run: Option<Option<&[String]>>, returns: &Ty, started: Instant, summary: &mut Summary) -> Result<(), Vec<LocalizedError>> {
+    // the compiled functions live as long as the JIT
+    let mut jit = JIT::default();
+    let functions = time(Phase::Codegen, || jit.compile(ast, path))?;
+    summary.functions = jit.defined();
+    summary.elapsed = started.elapsed();
+    match run {
+        Some(Some(args)) => {
+            let main: extern "C" fn(*const u8, *const u8) -> i64 = unsafe { std::mem::transmute(functions["main"]) };
+            let args = runtime::arguments(args);
+            summary.exit_code = time(Phase::Run, || main(std::ptr::null(), args)) as i32;
+            if gc::current() == Gc::Rc {
+                unsafe { runtime::__moo_release(args.cast_mut()) };
+            }
+        }
+        Some(None) => {
+            let main: extern "C" fn(*const u8) -> i64 = unsafe { std::mem::transmute(functions["main"]) };
+            let value = time(Phase::Run, || main(std::ptr::null()));
+            match Printed::of(returns) {
+                Printed::Number(format) => println!("{}", format.format(value)),
+                Printed::String => println!("{}", unsafe { runtime::read(value as *const u8) }),
+            }
+            // `main` returned its reference to the object, see `ir::rc`
+            if gc::current() == Gc::Rc && ir::rc::is_object(returns) {
+                unsafe { runtime::__moo_release(value as *mut u8) };
+            }
+        }
+        None => (),
+    }
+    // every object the program allocated is released by the time `main` returns, see `ir::rc`,
+    // or unreachable once it returned
+    if gc::current() == Gc::Tracing {
+        runtime::collect();
+    }
+    debug_assert_eq!(runtime::live(), 0, "the program leaked objects");
+    Ok(())
+}
+
+/// Loads the module into the interpreter, then runs it if `run`, see `compile_module`
+/// * `returns` - the type of the value `main` returns
+fn run_interp(ast: &AST, run: Option<Option<&[String]>>, returns: &Ty, started: Instant, summary: &mut Summary) -> Result<(), Vec<LocalizedError>> {
+    let interpreter = time(Phase::Codegen, || Interpreter::new(ast)).map_err(|err| vec![err])?;
+    summary.functions = interpreter.functions();
+    summary.elapsed = started.elapsed();
+    let Some(args) = run else {
+        return Ok(());
+    };
+    let Some(args) = args else {
+        match (time(Phase::Run, || interpreter.call("main", Vec::new())).map_err(|err| vec![err])?, Printed::of(returns)) {
+            (interp::Value::Int(value), Printed::Number(format)) => println!("{}", format.format(value)),
+            (value, _) => println!("{}", value),
+        }
+        return Ok(());
+    };
+    let args = interp::Value::Array(args.iter().map(|arg| interp::Value::String(arg.as_str().into())).collect());
+    match time(Phase::Run, || interpreter.call("main", vec![args])).map_err(|err| vec![err])? {
+        interp::Value::Int(value) => summary.exit_code = value as i32,
+        value => println!("{}", value),
+    }
+    Ok(())
+}
+
+/// Compiles the module to bytecode, then runs it in the stack machine if `run`, see `compile_module`
+fn run_vm(ast: &AST, run: Option<Option<&[String]>>, started: Instant, summary: &mut Summary) -> Result<(), Vec<LocalizedError>> {
+    let (ir, program) = time(Phase::Codegen, || {
+        let ir = ir::compile(ast, &HashMap::new(), &HashMap::new())?;
+        check_externs(&ir)?;
+        let program = bytecode::compile(&ir);
+        Ok::<_, Vec<LocalizedError>>((ir, program))
+    })?;
+    summary.functions = program.functions.len();
+    summary.elapsed = started.elapsed();
+    if let Some(args) = run {
+        let main = program.function("main").expect("an entry point");
+        // errors are reported at the code of the instruction they happened in, or at the function called
+        summary.exit_code = time(Phase::Run, || run_main(&program, main, args)).map_err(|err| {
+            let span = err.at
+                .and_then(|at| program.functions[err.function].spans.get(at))
+                .map_or(ir.functions[err.function].span, |span| *span);
+            vec![err.with_span(span)]
+        })?;
+    }
+    Ok(())
+}
+
+/// Runs the `main` function of a bytecode file written by `build --emit bytecode`, like `compile_module`,
+/// returning the exit code of the program
+/// * `args` - the arguments of the program, for a `main` taking them
+pub fn run_bytecode(path: &Path, args: &[String]) -> Result<i32, Box<dyn Error>> {
+    let bytes = std::fs::read(path).map_err(|error| RunError {
+        message: message!("E0420", path = path.display(), error = error),
+    })?;
+    let program = bytecode::read(&bytes)?;
+    let main = program.function("main").ok_or_else(|| RunError {
+        message: message!("E0407"),
+    })?;
+    // the types of the arguments aren't part of the bytecode
+    let args = match program.functions[main].params {
+        0 => None,
+        1 => Some(args),
+        _ => return Err(Box::new(RunError {
+            message: message!("E0406"),
+        })),
+    };
+    // there is no source to show the errors in
+    let exit_code = time(Phase::Run, || run_main(&program, main, args)).map_err(|err| {
+        let function = &program.functions[err.function].name;
+        format!("{}, in `{}`", err, function)
+    })?;
+    Ok(exit_code)
+}
+
+/// Runs `main` in the stack machine, with the arguments of the program if it takes them,
+/// returning the value it returns as the exit code, or printing it and returning 0 without
+fn run_main(program: &bytecode::Program, main: usize, args: Option<&[String]>) -> Result<i32, vm::VmError> {
+    let Some(args) = args else {
+        match (vm::run(program, main, Vec::new())?, program.functions[main].returns) {
+            (vm::Value::Int(value), Printed::Number(format)) => println!("{}", format.format(value)),
+            (value, _) => println!("{}", value),
+        }
+        return Ok(0);
+    };
+    let args = vm::Value::Array(args.iter().map(|arg| vm::Value::String(arg.as_str().into())).collect());
+    match vm::run(program, main, vec![args])? {
+        vm::Value::Int(value) => Ok(value as i32),
+        value => {
+            println!("{}", value);
+            Ok(0)
+        }
+    }
+}
+
+/// Without the JIT, programs are only type checked
+#[cfg(not(feature = "jit"))]
+fn run_jit(ast: &AST, _: &Path, run: Option<Option<&[String]>>, _: &Ty, started: Instant, summary: &mut Summary) -> Result<(), Vec<LocalizedError>> {
+    summary.elapsed = started.elapsed();
+    match run {
+        Some(_) => Err(vec![jit_required().with_location(*ast.location())]),
+        None => Ok(()),
+    }
+}
+
+/// The error for running code without the `jit` feature
+#[cfg(not(feature = "jit"))]
+pub fn jit_required() -> RunError {
+    RunError {
+        message: message!("E0405"),
+    }
+}
+
+/// The error for running the subcommand `command` of a `moolang` built without its `feature`
+#[cfg(not(all(feature = "repl", feature = "fmt", feature = "lsp")))]
+pub fn feature_required(command: &str, feature: &str) -> RunError {
+    RunError {
+        message: message!("E0435", command = command, feature = feature),
+    }
+}
+
+/// Checks that the module defines a `main` function to run: without arguments, or taking those of the program
+/// as `args: [string]` and returning an `int`, the exit code of the program.
+/// Returns whether `main` takes the arguments of the program.
+pub fn check_entry_point(module: &AST) -> Result<bool, LocalizedError> {
+    let statements = match &**module {
+        Type::Module(statements) => statements,
+        _ => unreachable!("expected a module"),
+    };
+    let main = statements.iter().find_map(|statement| match &**statement {
+        Type::Expression(Operator::Let, name, value) if binding_name(name).is_some_and(|name| name == "main") => Some(value),
+        _ => None,
+    });
+    match main.map(|main| (main, &***main)) {
+        Some((_, Type::Lambda(_, args, _))) if args.is_empty() => Ok(false),
+        Some((_, Type::Lambda(TypeName::Named(ret), args, _))) if ret == "int" && args.len() == 1 && matches!(
+            &*args[0],
+            Type::TypedLiteral(_, TypeName::Array(element)) if **element == TypeName::Named("string".to_owned()),
+        ) => Ok(true),
+        Some((main, _)) => Err(RunError {
+            message: message!("E0406"),
+        }.with_span(main.span())),
+        None => Err(RunError {
+            message: message!("E0407"),
+        }.with_location(*module.location())),
+    }
+}
 
-    if let Some(error) = tokenizer.error() {
-        return Err(error);
-    } else if let Err(error) = ast {
-        return Err(error);
-    } 
+/// Runs only the tokenizer and prints every token with its location
+/// * `format` - `Text` prints a token per line, `Typed` the same with the `Type` of the tokens,
+///   `Json` an object with the `files`, each with its `path` and `tokens`.
+///   The tokens before an invalid one are printed before the error.
+pub fn emit_tokens(paths: &[PathBuf], format: Format) -> Result<(), LocalizedSourcedError> {
+    let paths = source_files(paths)?;
+    let mut files = Vec::new();
+    for path in paths.iter() {
+        if paths.len() > 1 && !matches!(format, Format::Json) {
+            println!("{}:", path.display());
+        }
 
-    println!("{:#?}", ast.unwrap());
+        let mut tokens = Vec::new();
+        let mut invalid = None;
+        let source = read_source(path)?;
+        for token in tokenize(source.lines()).in_file(&source) {
+            let token = match token {
+                Ok(token) => token,
+                Err(error) => {
+                    invalid = Some(error);
+                    break;
+                }
+            };
+            match format {
+                Format::Text => println!("{}", token),
+                Format::Typed => println!("{:>4}:{:<4} {:?}", token.location.line, token.location.column, token.type_),
+                Format::Json => tokens.push(token),
+            }
+        }
+        if let Format::Json = format {
+            files.push(serde_json::json!({ "path": path.display().to_string(), "tokens": tokens }));
+        }
 
+        if let Some(error) = invalid {
+            if let Format::Json = format {
+                println!("{}", serde_json::json!({ "files": files }));
+            }
+            return Err(error.with_source(path));
+        }
+    }
+    if let Format::Json = format {
+        println!("{}", serde_json::json!({ "files": files }));
+    }
     Ok(())
 }
+
+/// Parses every source file on its own and prints it back in the canonical layout, see `to_source`
+pub fn emit_source(paths: &[PathBuf]) -> Result<(), Diagnostics> {
+    let paths = source_files(paths)?;
+    for path in paths.iter() {
+        if paths.len() > 1 {
+            println!("{}:", path.display());
+        }
+        print!("{}", to_source(&parse_file(path)?));
+    }
+    Ok(())
+}
+
+/// Parses every source file on its own and prints their trees as JSON:
+/// an object with the `files`, each with its `path` and `ast`, see `AST` for its shape
+pub fn emit_ast_json(paths: &[PathBuf]) -> Result<(), Diagnostics> {
+    let paths = source_files(paths)?;
+    let files = paths.iter()
+        .map(|path| Ok(serde_json::json!({ "path": path.display().to_string(), "ast": parse_file(path)? })))
+        .collect::<Result<Vec<_>, Diagnostics>>()?;
+    println!("{}", serde_json::json!({ "files": files }));
+    Ok(())
+}
+
+/// Prints the files the program is read from, the entry files then the modules they import,
+/// for build tools to know which files to track
+/// * `format` - `Text` prints a Makefile rule making the entry file without its extension
+///   depend on them, `Json` an object with the target and the files
+pub fn emit_deps(paths: &[PathBuf], format: Format) -> Result<(), Diagnostics> {
+    let paths = source_files(paths)?;
+    let files = paths.iter()
+        .map(|path| parse_file(path).map(|ast| (path.clone(), ast)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let (_, imported) = load_all_imports_with_files(files)?;
+    let mut files: Vec<&PathBuf> = Vec::new();
+    // the embedded standard library isn't read from a file
+    for path in paths.iter().chain(imported.iter()).filter(|path| !sources::is_registered(path)) {
+        if !files.contains(&path) {
+            files.push(path);
+        }
+    }
+    let target = paths[0].with_extension("");
+
+    match format {
+        Format::Text | Format::Typed => {
+            let escape = |path: &Path| path.display().to_string().replace(' ', "\\ ");
+            println!("{}: {}", escape(&target), files.iter().map(|path| escape(path)).collect::<Vec<_>>().join(" "));
+            // a rule for every file, so make doesn't fail once one is deleted
+            for path in files.iter() {
+                println!();
+                println!("{}:", escape(path));
+            }
+        }
+        Format::Json => println!("{}", serde_json::json!({
+            "target": target.display().to_string(),
+            "files": files.iter().map(|path| path.display().to_string()).collect::<Vec<_>>(),
+        })),
+    }
+    Ok(())
+}
+
+/// Prints the declared type of every module level binding of the program
+pub fn emit_types(paths: &[PathBuf]) -> Result<(), Diagnostics> {
+    let (ast, paths) = load_program(paths)?;
+    resolve(&ast).map_err(|errors| errors.into_iter().map(|error| error.with_source(&paths[0])).collect::<Diagnostics>())?;
+
+    for (name, params, ty) in binding_types(&ast, &Aliases::default()) {
+        let params = match params.is_empty() {
+            true => String::new(),
+            false => format!("<{}>", params.iter().map(|param| param.as_str()).collect::<Vec<_>>().join(", ")),
+        };
+        match ty {
+            Some(ty) => println!("{}{}: {}", name, params, ty),
+            None => println!("{}{}: _", name, params),
+        }
+    }
+    Ok(())
+}
+
+/// Prints the operator precedence table and the grammar, as used by the parser
+pub fn emit_grammar() {
+    println!("Binary operators, from the loosest to the tightest binding:");
+    for (i, level) in PRECEDENCE.iter().enumerate() {
+        let operators = level.operators.iter().map(|op| op.as_str()).collect::<Vec<_>>().join("  ");
+        let associativity = match level.associativity {
+            Associativity::Left => "left",
+            Associativity::Right => "right",
+        };
+        println!("  {}. {:<12} {} associative", i + 1, operators, associativity);
+    }
+
+    println!();
+    println!("Grammar:");
+    let width = GRAMMAR.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    for (name, production) in GRAMMAR {
+        println!("  {:width$} = {} ;", name, production, width = width);
+    }
+    println!("  {:width$} = binary_1 ;", "binary", width = width);
+    for (i, level) in PRECEDENCE.iter().enumerate() {
+        let name = format!("binary_{}", i + 1);
+        let operand = match i + 1 < PRECEDENCE.len() {
+            true => format!("binary_{}", i + 2),
+            false => "cast".to_owned(),
+        };
+        let operators = level.operators.iter().map(|op| format!("\"{}\"", op.as_str())).collect::<Vec<_>>().join(" | ");
+        match level.associativity {
+            Associativity::Left => println!("  {:width$} = {} {{ ( {} ) {} }} ;", name, operand, operators, operand, width = width),
+            Associativity::Right => println!("  {:width$} = {} [ ( {} ) {} ] ;", name, operand, operators, name, width = width),
+        }
+    }
+}
@@ -0,0 +1,327 @@
+// Debug information for the code compiled ahead of time, see `object`, so debuggers like gdb and lldb
+// can step through the source of a program and show the values of its variables.
+// Cranelift is given the index of the instruction of the IR it generates code for as the source location,
+// and the values bound to variables as value labels, which it maps to ranges of machine code.
+// The instructions are mapped back to the syntax tree they were lowered from, see `ir::Function::spans`,
+// and everything is written into the object file as DWARF, along with the call frame information
+// debuggers unwind the stack with.
+// The IR doesn't know the types of values, so every variable is described as a 64 bits integer.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use cranelift::codegen::gimli::write::{
+    Address, AttributeValue, DwarfUnit, EndianVec, Expression, FileId, FrameTable, LineProgram, LineString,
+    Location as DwarfLocation, LocationList, Range, RangeList, Relocation, RelocateWriter, RelocationTarget, Sections, UnitEntryId, Writer,
+};
+use cranelift::codegen::gimli::{self, constants, Encoding, Format, LineEncoding, Register, RunTimeEndian};
+use cranelift::codegen::ir::ValueLabel;
+use cranelift::codegen::isa::unwind::UnwindInfo;
+use cranelift::codegen::isa::TargetIsa;
+use cranelift::codegen::{CompiledCode, LabelValueLoc};
+use cranelift_module::FuncId;
+use cranelift_object::object::write::{Relocation as ObjectRelocation, StandardSegment};
+use cranelift_object::object::{BinaryFormat, RelocationEncoding, RelocationFlags, RelocationKind, SectionKind};
+use cranelift_object::ObjectProduct;
+
+use crate::frontend::tokenizer::Location;
+use crate::ir::{self, Inst};
+
+/// What debuggers are told about a function compiled to machine code
+pub struct FunctionDebugInfo {
+    id: FuncId,
+    /// The name of the function, `<enclosing function>::lambda<n>` for lambdas
+    name: String,
+    /// The symbol of a module level function
+    symbol: Option<String>,
+    /// Where the function is defined in the source
+    location: Location,
+    /// The size of the machine code in bytes
+    size: u32,
+    /// Where the source of the machine code is, by the offset of the code it's the source of from then on
+    lines: Vec<(u32, Location)>,
+    variables: Vec<Variable>,
+    unwind: Option<UnwindInfo>,
+}
+
+/// A variable of a function, with where its value is over ranges of the machine code
+struct Variable {
+    name: String,
+    param: bool,
+    ranges: Vec<(u32, u32, Expression)>,
+}
+
+impl FunctionDebugInfo {
+    /// The debug information of the machine code of a function, compiled with its source locations
+    /// and value labels set, see `Codegen::record_debug_info`
+    pub fn new(function: &ir::Function, id: FuncId, code: &CompiledCode, isa: &dyn TargetIsa) -> Self {
+        let lines = code.buffer
+            .get_srclocs_sorted()
+            .iter()
+            .filter(|srcloc| !srcloc.loc.is_default())
+            .map(|srcloc| (srcloc.start, function.spans[srcloc.loc.bits() as usize].start))
+            .collect();
+
+        // a variable assigned to has a label for every value bound to it
+        let mut variables: Vec<Variable> = Vec::new();
+        for (label, (name, value)) in function.variables.iter().enumerate() {
+            let ranges = code.value_labels_ranges
+                .get(&ValueLabel::from_u32(label as u32))
+                .into_iter()
+                .flatten()
+                .filter(|range| range.start < range.end)
+                .filter_map(|range| location(range.loc, isa).map(|expression| (range.start, range.end, expression)));
+            let name = name.to_string();
+            match variables.iter_mut().find(|variable| variable.name == name) {
+                Some(variable) => variable.ranges.extend(ranges),
+                None => variables.push(Variable {
+                    param: matches!(function.insts[value.index()], Inst::Param(_)),
+                    ranges: ranges.collect(),
+                    name,
+                }),
+            }
+        }
+
+        Self {
+            id,
+            name: function.name.clone(),
+            symbol: function.global.map(|name| crate::jit::symbol(&name)),
+            location: function.span.start,
+            size: code.code_info().total_size,
+            lines,
+            variables,
+            unwind: code.create_unwind_info(isa).ok().flatten(),
+        }
+    }
+}
+
+/// Where a value is, as a DWARF expression, if the register has a DWARF number
+fn location(loc: LabelValueLoc, isa: &dyn TargetIsa) -> Option<Expression> {
+    let mut expression = Expression::new();
+    match loc {
+        LabelValueLoc::Reg(reg) => expression.op_reg(Register(isa.map_regalloc_reg_to_dwarf(reg).ok()?)),
+        // the frame base of every function is the canonical frame address
+        LabelValueLoc::CFAOffset(offset) => expression.op_fbreg(offset),
+    }
+    Some(expression)
+}
+
+/// A DWARF section being written, with the relocations of the addresses in it
+#[derive(Clone)]
+struct Section {
+    data: EndianVec<RunTimeEndian>,
+    relocations: Vec<Relocation>,
+}
+
+impl RelocateWriter for Section {
+    type Writer = EndianVec<RunTimeEndian>;
+
+    fn writer(&self) -> &Self::Writer {
+        &self.data
+    }
+
+    fn writer_mut(&mut self) -> &mut Self::Writer {
+        &mut self.data
+    }
+
+    fn relocate(&mut self, relocation: Relocation) {
+        self.relocations.push(relocation);
+    }
+}
+
+/// The DWARF sections describing the functions compiled into an object file, to write into it
+pub struct Dwarf {
+    sections: Sections<Section>,
+    /// The functions, which the addresses in the sections are relocated against by index
+    functions: Vec<FuncId>,
+}
+
+impl Dwarf {
+    /// Describes the functions compiled for `isa` from the source file at `source`
+    pub fn new(functions: &[FunctionDebugInfo], source: &Path, isa: &dyn TargetIsa) -> Result<Self, gimli::write::Error> {
+        let endian = match isa.endianness() {
+            cranelift::codegen::ir::Endianness::Little => RunTimeEndian::Little,
+            cranelift::codegen::ir::Endianness::Big => RunTimeEndian::Big,
+        };
+        let mut sections = Sections::new(Section { data: EndianVec::new(endian), relocations: Vec::new() });
+        let encoding = Encoding {
+            format: Format::Dwarf32,
+            version: 4,
+            address_size: isa.pointer_bytes(),
+        };
+        unit(functions, source, encoding).write(&mut sections)?;
+
+        if let Some(cie) = isa.create_systemv_cie() {
+            let mut frames = FrameTable::default();
+            let cie = frames.add_cie(cie);
+            for (index, function) in functions.iter().enumerate() {
+                if let Some(UnwindInfo::SystemV(unwind)) = &function.unwind {
+                    frames.add_fde(cie, unwind.to_fde(Address::Symbol { symbol: index, addend: 0 }));
+                }
+            }
+            frames.write_debug_frame(&mut sections.debug_frame)?;
+        }
+        Ok(Self {
+            sections,
+            functions: functions.iter().map(|function| function.id).collect(),
+        })
+    }
+
+    /// Writes the sections into the object file the functions were compiled into,
+    /// the addresses of the functions being relocations against their symbols
+    pub fn write(mut self, product: &mut ObjectProduct) -> Result<(), String> {
+        // Mach-O debug sections aren't linked, the offsets into other sections are written as they are
+        let object = &mut product.object;
+        let macho = object.format() == BinaryFormat::MachO;
+        let coff = object.format() == BinaryFormat::Coff;
+        let mut ids = HashMap::new();
+        self.sections.for_each_mut(|id, section| {
+            if section.data.len() == 0 {
+                return Ok(());
+            }
+            if macho {
+                for relocation in section.relocations.iter().filter(|relocation| matches!(relocation.target, RelocationTarget::Section(_))) {
+                    section.data.write_udata_at(relocation.offset, relocation.addend as u64, relocation.size)?;
+                }
+                section.relocations.retain(|relocation| matches!(relocation.target, RelocationTarget::Symbol(_)));
+            }
+            let name = match macho {
+                true => id.name().replacen('.', "__", 1),
+                false => id.name().to_string(),
+            };
+            let section_id = object.add_section(object.segment_name(StandardSegment::Debug).to_vec(), name.into_bytes(), SectionKind::Debug);
+            object.append_section_data(section_id, section.data.slice(), 1);
+            ids.insert(id, section_id);
+            Ok(())
+        }).map_err(|error: gimli::write::Error| error.to_string())?;
+
+        self.sections.for_each(|id, section| {
+            let Some(section_id) = ids.get(&id) else {
+                return Ok(());
+            };
+            for relocation in section.relocations.iter() {
+                // offsets into other sections are relative to the section in COFF, and absolute in ELF
+                let (symbol, kind) = match relocation.target {
+                    RelocationTarget::Symbol(index) => (product.function_symbol(self.functions[index]), RelocationKind::Absolute),
+                    RelocationTarget::Section(target) if coff => (product.object.section_symbol(ids[&target]), RelocationKind::SectionOffset),
+                    RelocationTarget::Section(target) => (product.object.section_symbol(ids[&target]), RelocationKind::Absolute),
+                };
+                product.object.add_relocation(*section_id, ObjectRelocation {
+                    offset: relocation.offset as u64,
+                    symbol,
+                    addend: relocation.addend,
+                    flags: RelocationFlags::Generic {
+                        kind,
+                        encoding: RelocationEncoding::Generic,
+                        size: relocation.size * 8,
+                    },
+                })?;
+            }
+            Ok(())
+        }).map_err(|error: cranelift_object::object::write::Error| error.to_string())
+    }
+}
+
+/// The compilation unit of the functions, with their lines and variables
+fn unit(functions: &[FunctionDebugInfo], source: &Path, encoding: Encoding) -> DwarfUnit {
+    let mut dwarf = DwarfUnit::new(encoding);
+    let directory = std::env::current_dir().unwrap_or_default();
+    let name = source.to_string_lossy().into_owned().into_bytes();
+    let directory = directory.to_string_lossy().into_owned().into_bytes();
+    let mut lines = LineProgram::new(
+        encoding,
+        LineEncoding::default(),
+        LineString::new(directory.clone(), encoding, &mut dwarf.line_strings),
+        LineString::new(name.clone(), encoding, &mut dwarf.line_strings),
+        None,
+    );
+    let file = lines.add_file(LineString::new(name.clone(), encoding, &mut dwarf.line_strings), lines.default_directory(), None);
+
+    let root = dwarf.unit.root();
+    let ranges = functions.iter()
+        .enumerate()
+        .map(|(index, function)| Range::StartLength {
+            begin: Address::Symbol { symbol: index, addend: 0 },
+            length: u64::from(function.size),
+        })
+        .collect();
+    let ranges = dwarf.unit.ranges.add(RangeList(ranges));
+    let producer = format!("moolang {}", env!("CARGO_PKG_VERSION")).into_bytes();
+    let entry = dwarf.unit.get_mut(root);
+    entry.set(constants::DW_AT_producer, AttributeValue::String(producer));
+    // debuggers evaluate expressions in the language of the unit, C's arithmetic being moolang's
+    entry.set(constants::DW_AT_language, AttributeValue::Language(constants::DW_LANG_C99));
+    entry.set(constants::DW_AT_name, AttributeValue::String(name));
+    entry.set(constants::DW_AT_comp_dir, AttributeValue::String(directory));
+    entry.set(constants::DW_AT_low_pc, AttributeValue::Address(Address::Constant(0)));
+    entry.set(constants::DW_AT_ranges, AttributeValue::RangeListRef(ranges));
+
+    let int = dwarf.unit.add(root, constants::DW_TAG_base_type);
+    let entry = dwarf.unit.get_mut(int);
+    entry.set(constants::DW_AT_name, AttributeValue::String(b"int".to_vec()));
+    entry.set(constants::DW_AT_encoding, AttributeValue::Encoding(constants::DW_ATE_signed));
+    entry.set(constants::DW_AT_byte_size, AttributeValue::Data1(8));
+
+    for (index, function) in functions.iter().enumerate() {
+        subprogram(&mut dwarf, function, index, file, int);
+
+        // the rows of a line program are in increasing order of address
+        lines.begin_sequence(Some(Address::Symbol { symbol: index, addend: 0 }));
+        for (offset, location) in function.lines.iter() {
+            let row = lines.row();
+            row.address_offset = u64::from(*offset);
+            row.file = file;
+            row.line = location.line as u64;
+            row.column = location.column as u64 + 1;
+            lines.generate_row();
+        }
+        lines.end_sequence(u64::from(function.size));
+    }
+    dwarf.unit.line_program = lines;
+    dwarf
+}
+
+/// Adds the entry of a function, and of its variables, to the unit
+/// * `index` - the index of the function, which its address is relocated against
+fn subprogram(dwarf: &mut DwarfUnit, function: &FunctionDebugInfo, index: usize, file: FileId, int: UnitEntryId) {
+    let root = dwarf.unit.root();
+    let id = dwarf.unit.add(root, constants::DW_TAG_subprogram);
+    let entry = dwarf.unit.get_mut(id);
+    entry.set(constants::DW_AT_name, AttributeValue::String(function.name.clone().into_bytes()));
+    if let Some(symbol) = &function.symbol {
+        entry.set(constants::DW_AT_linkage_name, AttributeValue::String(symbol.clone().into_bytes()));
+        entry.set(constants::DW_AT_external, AttributeValue::Flag(true));
+    }
+    entry.set(constants::DW_AT_decl_file, AttributeValue::FileIndex(Some(file)));
+    entry.set(constants::DW_AT_decl_line, AttributeValue::Udata(function.location.line as u64));
+    entry.set(constants::DW_AT_low_pc, AttributeValue::Address(Address::Symbol { symbol: index, addend: 0 }));
+    entry.set(constants::DW_AT_high_pc, AttributeValue::Udata(u64::from(function.size)));
+    let mut frame_base = Expression::new();
+    frame_base.op(constants::DW_OP_call_frame_cfa);
+    entry.set(constants::DW_AT_frame_base, AttributeValue::Exprloc(frame_base));
+    entry.set(constants::DW_AT_type, AttributeValue::UnitRef(int));
+
+    // variables without ranges have been optimized out
+    for variable in function.variables.iter() {
+        let tag = match variable.param {
+            true => constants::DW_TAG_formal_parameter,
+            false => constants::DW_TAG_variable,
+        };
+        let locations = variable.ranges
+            .iter()
+            .map(|(start, end, expression)| DwarfLocation::StartEnd {
+                begin: Address::Symbol { symbol: index, addend: i64::from(*start) },
+                end: Address::Symbol { symbol: index, addend: i64::from(*end) },
+                data: expression.clone(),
+            })
+            .collect::<Vec<_>>();
+        let locations = (!locations.is_empty()).then(|| dwarf.unit.locations.add(LocationList(locations)));
+        let variable_id = dwarf.unit.add(id, tag);
+        let entry = dwarf.unit.get_mut(variable_id);
+        entry.set(constants::DW_AT_name, AttributeValue::String(variable.name.clone().into_bytes()));
+        entry.set(constants::DW_AT_type, AttributeValue::UnitRef(int));
+        if let Some(locations) = locations {
+            entry.set(constants::DW_AT_location, AttributeValue::LocationListRef(locations));
+        }
+    }
+}
@@ -0,0 +1,3 @@
+// How diagnostics are shown to people, the errors themselves being in `errors`.
+
+pub mod render;
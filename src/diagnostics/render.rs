@@ -0,0 +1,207 @@
+// Renders the snippet of code a diagnostic is about: the line of its span, numbered, between the lines around it,
+// with carets under the code of its span, and the lines of the other spans of its labels, with dashes
+// under their code followed by their message.
+//
+// The snippet is rendered from whatever the source is now, which may not be what was compiled,
+// e.g. a file edited since: spans past the end of a line or of the source point at their end,
+// and those in the middle of a character at its start, rather than failing to render.
+// Tabs are expanded for the carets to line up with the code whatever the terminal, and lines too long
+// to read are cut around the span, the code left out marked with `…`.
+
+use core::fmt;
+use owo_colors::{AnsiColors, OwoColorize as _, Style};
+
+use crate::color;
+use crate::errors::Label;
+use crate::frontend::tokenizer::Span;
+use crate::sources::SourceFile;
+
+/// What a tab is expanded to
+const TAB: &str = "    ";
+
+/// The number of columns of code shown, beyond which lines are cut
+const MAX_COLUMNS: usize = 100;
+
+/// The columns of code shown before the span, when lines are cut
+const CONTEXT: usize = 20;
+
+/// Writes the snippet of `source` showing `span` and the spans of `labels`,
+/// between borders `width` wide. The line of `span` is shown between the lines around it, those of the labels
+/// on their own, lines left out in between marked with `┆`
+/// * `color` - of the line number and the carets, the color of the severity of the diagnostic
+pub fn snippet(f: &mut impl fmt::Write, source: &SourceFile, span: Span, labels: &[Label], color: AnsiColors, width: usize) -> fmt::Result {
+    let primary = marks(source, span, None, true);
+    let number = primary[0].line;
+    let mut marks = primary.into_iter()
+        .chain(labels.iter().flat_map(|label| marks(source, label.span, Some(&label.message), false)))
+        .collect::<Vec<_>>();
+    marks.sort_by_key(|mark| (mark.line, mark.start));
+    let mut shown = marks.iter().map(|mark| mark.line).collect::<Vec<_>>();
+    shown.extend((number > 1).then(|| number - 1).into_iter().chain((number < source.line_count()).then(|| number + 1)));
+    shown.sort();
+    shown.dedup();
+
+    let styles = |primary: bool| match primary {
+        true => (color::style(Style::new().color(color)), color::style(Style::new().color(color).bold())),
+        false => (color::style(Style::new().blue()), color::style(Style::new())),
+    };
+    let pad = shown.iter().map(|number| number.to_string().len()).max().unwrap_or(1) + 1;
+    // the columns shown of the lines without marks, those of the line of the span for them to line up with it
+    let context = window(columns(source.line(number)), &marks.iter().filter(|mark| mark.line == number).collect::<Vec<_>>());
+
+    writeln!(f, "{}─┬{}", "─".repeat(pad), "─".repeat(width))?;
+    for (i, line) in shown.iter().copied().enumerate() {
+        if i > 0 && shown[i - 1] + 1 < line {
+            writeln!(f, "{:pad$} ┆", "", pad = pad)?;
+        }
+        let on_line = marks.iter().filter(|mark| mark.line == line).collect::<Vec<_>>();
+        if on_line.is_empty() {
+            writeln!(f, "{:pad$} │ {}", line, cut(source.line(line), &context), pad = pad)?;
+            // a blank line separates the line before the span from it
+            if line + 1 == number {
+                writeln!(f, "{:pad$} │", "", pad = pad)?;
+            }
+            continue;
+        }
+        let code = expand(source.line(line));
+        let shown = window(columns(source.line(line)), &on_line);
+        let is_primary = on_line.iter().any(|mark| mark.primary);
+        // only the code of the span is highlighted
+        let (before, highlighted, after) = match on_line.iter().find(|mark| mark.primary) {
+            Some(mark) => (slice(&code, shown.start, mark.start), slice(&code, mark.start, mark.end), slice(&code, mark.end, shown.end)),
+            None => (slice(&code, shown.start, shown.end), String::new(), String::new()),
+        };
+        let number = line.style(styles(is_primary).0);
+        writeln!(f, "{:pad$} │ {}{}{}{}{}", number, shown.before, before, highlighted.style(styles(true).1), after, shown.after, pad = pad)?;
+
+        // the marks of the line side by side, the message of the last after it and those of the others under them
+        let offset = shown.before.chars().count();
+        let column = |column: usize| offset + column.clamp(shown.start, shown.end) - shown.start;
+        let (mut carets, mut width) = (String::new(), 0);
+        for mark in on_line.iter() {
+            // overlapping marks are drawn from where the one before ends
+            let start = column(mark.start).max(width);
+            let length = column(mark.end).saturating_sub(start).max(1);
+            let caret = if mark.primary { "^" } else { "-" };
+            carets.push_str(&" ".repeat(start - width));
+            carets.push_str(&caret.repeat(length).style(styles(mark.primary).0).to_string());
+            width = start + length;
+        }
+        let mut messages = on_line.iter().filter_map(|mark| mark.message.map(|message| (column(mark.start), message, mark.primary))).collect::<Vec<_>>();
+        let last = messages.pop();
+        match last {
+            Some((_, message, primary)) => writeln!(f, "{:pad$} │ {} {}", "", carets, message.style(styles(primary).0), pad = pad)?,
+            None => writeln!(f, "{:pad$} │ {}", "", carets, pad = pad)?,
+        }
+        for (column, message, primary) in messages.into_iter().rev() {
+            writeln!(f, "{:pad$} │ {}{}", "", " ".repeat(column), message.style(styles(primary).0), pad = pad)?;
+        }
+    }
+    write!(f, "{}─┴{}", "─".repeat(pad), "─".repeat(width))
+}
+
+/// The code of a span on one of its lines, in the columns of the line with its tabs expanded
+struct Mark<'a> {
+    line: usize,
+    start: usize,
+    end: usize,
+    message: Option<&'a str>,
+    /// Whether it's the span of the diagnostic rather than of one of its labels
+    primary: bool,
+}
+
+/// The line of `offset` in `source`, and its byte offset in that line, at a character boundary.
+/// Past the end of the source, e.g. of a file edited since, it is the end of the last line
+fn position(source: &SourceFile, offset: usize) -> (usize, usize) {
+    let last = source.line_count().max(1);
+    let (number, offset) = match source.position(offset) {
+        (number, _) if number > last => (last, source.line(last).len()),
+        position => position,
+    };
+    (number, boundary(source.line(number), offset))
+}
+
+/// The marks of `span` in `source`: the code it covers on its first line, and on its last, where it shows `message`,
+/// for a span over several lines
+fn marks<'a>(source: &SourceFile, span: Span, message: Option<&'a str>, primary: bool) -> Vec<Mark<'a>> {
+    let (number, start) = position(source, span.start.offset);
+    let (end_number, end) = position(source, span.end.offset.max(span.start.offset));
+    let columns = |number: usize, offset: usize| columns(&source.line(number)[..offset]);
+    if end_number == number {
+        let (start, end) = (columns(number, start), columns(number, end.max(start)));
+        return vec![Mark { line: number, start, end, message, primary }];
+    }
+    // the last line is marked from its code on, not from its indentation
+    let last = source.line(end_number);
+    let indent = last.len() - last.trim_start().len();
+    vec![
+        Mark { line: number, start: columns(number, start), end: columns(number, source.line(number).len()), message: None, primary },
+        Mark { line: end_number, start: columns(end_number, indent.min(end)), end: columns(end_number, end), message, primary },
+    ]
+}
+
+/// The columns of code shown from a line, and what marks the code left out before and after them
+struct Window {
+    start: usize,
+    end: usize,
+    before: &'static str,
+    after: &'static str,
+}
+
+/// The columns shown from a line `length` columns long, to show its `marks`, as much of them as fits
+/// when they are too long themselves
+fn window(length: usize, marks: &[&Mark]) -> Window {
+    if length <= MAX_COLUMNS {
+        return Window { start: 0, end: MAX_COLUMNS, before: "", after: "" };
+    }
+    let start = marks.iter().map(|mark| mark.start).min().unwrap_or(0);
+    let end = marks.iter().map(|mark| mark.end).max().unwrap_or(start);
+    let first = match end - start + CONTEXT <= MAX_COLUMNS {
+        true => start.saturating_sub(CONTEXT),
+        false => start,
+    };
+    let first = first.min(length - MAX_COLUMNS);
+    let last = first + MAX_COLUMNS;
+    Window {
+        start: first,
+        end: last,
+        before: if first > 0 { "…" } else { "" },
+        after: if last < length { "…" } else { "" },
+    }
+}
+
+/// The columns of another line of the snippet in `window`, for the lines to stay aligned
+fn cut(line: &str, window: &Window) -> String {
+    let code = expand(line);
+    let length = code.chars().count();
+    match window.start == 0 && window.end >= length {
+        true => code,
+        false => {
+            let before = if window.start > 0 && length > 0 { "…" } else { "" };
+            let after = if window.end < length { "…" } else { "" };
+            format!("{}{}{}", before, slice(&code, window.start, window.end), after)
+        }
+    }
+}
+
+/// The line with its tabs expanded
+fn expand(line: &str) -> String {
+    line.replace('\t', TAB)
+}
+
+/// The number of columns `code` takes once its tabs are expanded
+fn columns(code: &str) -> usize {
+    code.chars().map(|c| if c == '\t' { TAB.len() } else { 1 }).sum()
+}
+
+/// The columns `start` to `end` of `code`, its tabs expanded, as many as there are
+fn slice(code: &str, start: usize, end: usize) -> String {
+    code.chars().skip(start).take(end.saturating_sub(start)).collect()
+}
+
+/// The byte offset `offset` in `line` moved back to the start of the character it's in,
+/// or to the end of the line if it's past it
+fn boundary(line: &str, offset: usize) -> usize {
+    let offset = offset.min(line.len());
+    (0..=offset).rev().find(|offset| line.is_char_boundary(*offset)).unwrap_or(0)
+}
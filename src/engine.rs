@@ -0,0 +1,249 @@
+// Embedding moolang into Rust programs, as a scripting language.
+// An `Engine` compiles sources with the JIT into the memory of the host program, which then calls
+// the module level functions they define through `Function`s. The Rust signature a function is asked for
+// is checked against the type the checker inferred for it, so the host can't call it with the wrong arguments.
+//
+// The code runs like under `moolang run`, but for the signals: the host installs its own handlers if any,
+// so a trap, e.g. dividing by zero, kills the process unless `traps::install` was called.
+
+use core::fmt;
+use std::collections::HashMap;
+use std::error::Error;
+use std::marker::PhantomData;
+use std::mem::ManuallyDrop;
+
+use crate::compile::{check_module_with_types, Summary};
+use crate::errors::{Coded, Diagnostics, LocalizedError};
+use crate::frontend::modules::{load_imports, parse_file};
+use crate::frontend::symbol::Symbol;
+use crate::frontend::types::{IntTy, Ty};
+use crate::jit::JIT;
+use crate::messages::{message, Message};
+use crate::{runtime, sources};
+
+#[derive(Debug)]
+pub struct EngineError {
+    message: Message,
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "EngineError: {}", self.message)
+    }
+}
+
+impl Error for EngineError {}
+
+impl Coded for EngineError {
+    fn code(&self) -> Option<&'static str> {
+        self.message.code()
+    }
+}
+
+/// Compiles moolang sources for a Rust program to call, e.g.
+/// ```
+/// let mut engine = moolang::Engine::new();
+/// engine.compile_str("let double = fn(x: int) -> int { x * 2 };").unwrap();
+/// let double = engine.get_function::<fn(i64) -> i64>("double").unwrap();
+/// assert_eq!(double.call(21), 42);
+/// ```
+#[derive(Default)]
+pub struct Engine {
+    /// Freed along with the engine, see `Drop`
+    jit: ManuallyDrop<JIT>,
+    /// The address of every module level function compiled so far
+    functions: HashMap<String, *const u8>,
+    /// The types of the module level bindings compiled so far
+    types: HashMap<Symbol, Ty>,
+    /// The number of sources compiled so far, to name them
+    sources: usize,
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compiles a module, whose functions can then be looked up with `get_function`.
+    /// The module can import others, e.g. of the standard library, but not see the functions
+    /// of the sources compiled before it, nor define functions of the same names.
+    /// The errors are reported in the source as `<engine:n>`, for the `n`th source compiled.
+    pub fn compile_str(&mut self, source: &str) -> Result<(), Diagnostics> {
+        self.sources += 1;
+        let path = sources::engine_name(self.sources);
+        sources::register(&path, source);
+        let with_source = |errors: Vec<LocalizedError>| errors.into_iter().map(|error| error.with_source(&path)).collect::<Diagnostics>();
+        let module = load_imports(parse_file(&path)?, &path)?;
+        let (module, types) = check_module_with_types(module, &path, &mut Summary::default()).map_err(with_source)?;
+        self.functions = self.jit.compile(&module, &path).map_err(with_source)?;
+        self.types.extend(types);
+        Ok(())
+    }
+
+    /// Looks up a module level function compiled before, by its name, e.g. `math.sqrt` for one of a module imported.
+    /// `F` is the Rust signature to call it with, e.g. `fn(i64, f64) -> String`, see `Value`,
+    /// which must be that of the function.
+    pub fn get_function<F: Signature>(&self, name: &str) -> Result<Function<'_, F>, EngineError> {
+        let (Some(address), Some(ty)) = (self.functions.get(name), self.types.get(&Symbol::intern(name))) else {
+            return Err(EngineError {
+                message: message!("E0428", name = name),
+            });
+        };
+        let expected = F::ty();
+        if *ty != expected {
+            return Err(EngineError {
+                message: message!("E0429", name = name, found = ty, expected = expected),
+            });
+        }
+        Ok(Function {
+            address: *address,
+            signature: PhantomData,
+        })
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        // Safety: the jit isn't used after, and the functions borrowing the engine are gone
+        unsafe { ManuallyDrop::take(&mut self.jit) }.free();
+    }
+}
+
+/// A compiled function, to call with the signature `F`, see `Engine::get_function`.
+/// The code lives as long as the engine.
+pub struct Function<'a, F> {
+    address: *const u8,
+    signature: PhantomData<(&'a Engine, F)>,
+}
+
+/// A Rust type whose values can be passed to compiled code and back,
+/// as the 8 bytes compiled code keeps every value in
+pub trait Value {
+    /// The moolang type of the values
+    fn ty() -> Ty;
+    fn into_raw(self) -> i64;
+    /// Takes the value compiled code returned, along with its reference if it is an object, see `ir::rc`
+    ///
+    /// # Safety
+    /// `raw` is a value of type `ty()` compiled code gave
+    unsafe fn from_raw(raw: i64) -> Self;
+    /// Drops the reference `into_raw` gave to a value which is an object, once compiled code is done with it
+    ///
+    /// # Safety
+    /// `raw` is a value `into_raw` gave, not used after
+    unsafe fn release(_raw: i64) {}
+}
+
+impl Value for i64 {
+    fn ty() -> Ty {
+        Ty::Int
+    }
+    fn into_raw(self) -> i64 {
+        self
+    }
+    unsafe fn from_raw(raw: i64) -> Self {
+        raw
+    }
+}
+
+// sized integers are kept extended to 64 bits, by their sign if they have one
+macro_rules! sized_values {
+    ($($rust:ty => $int:ident),*) => {
+        $(impl Value for $rust {
+            fn ty() -> Ty {
+                Ty::Sized(IntTy::$int)
+            }
+            fn into_raw(self) -> i64 {
+                self as i64
+            }
+            unsafe fn from_raw(raw: i64) -> Self {
+                raw as $rust
+            }
+        })*
+    };
+}
+
+sized_values!(i8 => I8, i16 => I16, i32 => I32, u8 => U8, u16 => U16, u32 => U32, u64 => U64);
+
+impl Value for f64 {
+    fn ty() -> Ty {
+        Ty::Float
+    }
+    fn into_raw(self) -> i64 {
+        self.to_bits() as i64
+    }
+    unsafe fn from_raw(raw: i64) -> Self {
+        f64::from_bits(raw as u64)
+    }
+}
+
+/// Strings are copied into the memory of compiled code, and back
+impl Value for String {
+    fn ty() -> Ty {
+        Ty::String
+    }
+    fn into_raw(self) -> i64 {
+        runtime::string(&self) as i64
+    }
+    unsafe fn from_raw(raw: i64) -> Self {
+        let string = runtime::read(raw as *const u8).to_owned();
+        Self::release(raw);
+        string
+    }
+    unsafe fn release(raw: i64) {
+        runtime::__moo_release(raw as *mut u8);
+    }
+}
+
+impl Value for () {
+    fn ty() -> Ty {
+        Ty::Unit
+    }
+    fn into_raw(self) -> i64 {
+        0
+    }
+    unsafe fn from_raw(_: i64) -> Self {}
+}
+
+/// The Rust signature of a compiled function, a `fn` pointer type of `Value`s
+pub trait Signature {
+    /// The moolang type of the functions
+    fn ty() -> Ty;
+}
+
+/// The type the `i64` every value is passed as, for every argument
+macro_rules! raw {
+    ($arg:ident) => { i64 };
+}
+
+macro_rules! signatures {
+    ($($arg:ident),*) => {
+        impl<$($arg: Value,)* R: Value> Signature for fn($($arg),*) -> R {
+            fn ty() -> Ty {
+                Ty::Function(vec![$($arg::ty()),*], Box::new(R::ty()))
+            }
+        }
+
+        impl<$($arg: Value,)* R: Value> Function<'_, fn($($arg),*) -> R> {
+            /// Calls the function, a module level one having no closure environment
+            #[allow(non_snake_case)]
+            pub fn call(&self, $($arg: $arg),*) -> R {
+                // `get_function` checked the signature
+                let function: extern "C" fn(*const u8, $(raw!($arg)),*) -> i64 = unsafe { std::mem::transmute(self.address) };
+                $(let $arg = $arg.into_raw();)*
+                let result = function(std::ptr::null(), $($arg),*);
+                // compiled code borrows the arguments
+                $(unsafe { $arg::release($arg) };)*
+                unsafe { R::from_raw(result) }
+            }
+        }
+    };
+}
+
+signatures!();
+signatures!(A);
+signatures!(A, B);
+signatures!(A, B, C);
+signatures!(A, B, C, D);
+signatures!(A, B, C, D, E);
+signatures!(A, B, C, D, E, F);
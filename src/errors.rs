@@ -1,17 +1,32 @@
 use std::{error::Error, fs};
+use std::cell::{Cell, RefCell};
 use std::fmt;
-use std::io::BufRead;
-use std::path::{Path, PathBuf, Display};
-use itertools::Itertools;
-use std::iter::once;
-use owo_colors::OwoColorize as _;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use owo_colors::{AnsiColors, OwoColorize as _, Style};
 
-use crate::frontend::tokenizer::{slice_into_snippets, Location};
+use crate::color;
+use crate::lint::{self, Level, Lint};
+use crate::frontend::tokenizer::{Location, Span};
+use crate::messages::{message, Message};
+use crate::diagnostics::render;
+use crate::sources;
 
 #[derive(Debug)]
-pub struct LocalizedError(Box<dyn Error>, Location);
+pub struct LocalizedError(Box<dyn Error>, Span, Box<Notes>);
 #[derive(Debug)]
-pub struct LocalizedSourcedError(Box<dyn Error>, Location, PathBuf);
+pub struct LocalizedSourcedError(Box<dyn Error>, Span, PathBuf, Box<Notes>);
+/// What is shown with a diagnostic besides its error and its span, boxed for errors to stay small
+#[derive(Debug, Default)]
+struct Notes {
+    /// The code of the error, e.g. `E0102`, if it has one, see `Coded`
+    code: Option<&'static str>,
+    labels: Vec<Label>,
+    suggestions: Vec<Suggestion>,
+}
+/// Several errors reported at once, e.g. by the compilation of different functions
+#[derive(Debug)]
+pub struct Diagnostics(pub Vec<LocalizedSourcedError>);
 
 
 impl Error for LocalizedError {
@@ -26,100 +41,481 @@ impl Error for LocalizedSourcedError {
     }
 }
 
+impl Error for Diagnostics {}
+
+/// Another span a diagnostic is about, with what it is there for, e.g. where a name was first defined
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub span: Span,
+    /// Shown under the code of the span
+    pub message: String,
+}
+
+/// What the compiler proposes to fix an error with: writing `replacement` in place of the `code` at `span`,
+/// e.g. the name in scope closest to a misspelled one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Suggestion {
+    /// Shown under the snippet, e.g. "did you mean `count`?"
+    pub message: String,
+    pub span: Span,
+    /// The code at `span`, checked before applying the suggestion, empty for an insertion
+    pub code: String,
+    pub replacement: String,
+    pub applicability: Applicability,
+}
+
+/// Whether a suggestion can be applied without a person looking at it, see `moo fix`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Applicability {
+    /// The suggestion is what the code means, e.g. the replacement of a deprecated construct
+    MachineApplicable,
+    /// The suggestion is a guess, e.g. of the name meant by a misspelled one
+    MaybeIncorrect,
+}
+
+impl Suggestion {
+    /// Suggests writing `replacement` instead of the misspelled `code` at `span`
+    pub fn did_you_mean(span: Span, code: &str, replacement: &str) -> Self {
+        let message = message!("did-you-mean", replacement = replacement);
+        Self::new(message, span, code, replacement, Applicability::MaybeIncorrect)
+    }
+    /// Like `did_you_mean`, naming the misspelled `code`, for when it isn't where the error is
+    pub fn instead_of(span: Span, code: &str, replacement: &str) -> Self {
+        let message = message!("instead-of", replacement = replacement, code = code);
+        Self::new(message, span, code, replacement, Applicability::MaybeIncorrect)
+    }
+    /// Suggests changing the `code` at `span` to `replacement`, e.g. `=` to `==`
+    pub fn replace(span: Span, code: &str, replacement: &str, applicability: Applicability) -> Self {
+        let message = message!("change", code = code, replacement = replacement);
+        Self::new(message, span, code, replacement, applicability)
+    }
+    /// Suggests inserting `code` at `location`, at the end of its line, e.g. a missing `;`
+    pub fn insert(location: Location, code: &str, applicability: Applicability) -> Self {
+        let message = message!("insert", code = code, line = location.line);
+        Self::new(message, Span::from(location), "", code, applicability)
+    }
+    /// Suggests removing the `code` at `span`
+    pub fn remove(span: Span, code: &str, applicability: Applicability) -> Self {
+        let message = message!("remove", code = code);
+        Self::new(message, span, code, "", applicability)
+    }
+    fn new(message: Message, span: Span, code: &str, replacement: &str, applicability: Applicability) -> Self {
+        Self { message: message.into(), span, code: code.to_string(), replacement: replacement.to_string(), applicability }
+    }
+}
+
+/// How serious a diagnostic is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// Reported without stopping the compilation
+    Warning,
+    /// Failing the compilation
+    Error,
+}
+
+/// A problem which doesn't stop the compilation, e.g. the use of a deprecated construct,
+/// unless its lint is denied, see `lint`
+#[derive(Debug)]
+pub struct Warning {
+    pub lint: Lint,
+    pub message: Message,
+    pub severity: Severity,
+}
+
+impl Warning {
+    pub fn new(lint: Lint, message: Message) -> Self {
+        Self { lint, message, severity: Severity::Warning }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.severity {
+            Severity::Warning => write!(f, "Warning: {}", self.message),
+            Severity::Error => write!(f, "LintError: {}", self.message),
+        }
+    }
+}
+
+impl Error for Warning {}
+
+impl Coded for Warning {
+    fn code(&self) -> Option<&'static str> {
+        self.message.code()
+    }
+}
+
+thread_local! {
+    /// The warnings reported so far, printed by the driver
+    static WARNINGS: RefCell<Vec<LocalizedSourcedError>> = const { RefCell::new(Vec::new()) };
+    /// The number of warnings reported as errors since the last call to `take_denied`
+    static DENIED: Cell<usize> = const { Cell::new(0) };
+    /// The machine-applicable suggestions of what is reported while collecting them, see `collect_fixes`
+    static FIXES: RefCell<Option<Vec<(PathBuf, Suggestion)>>> = const { RefCell::new(None) };
+    /// The JSON objects of what is reported while collecting them, see `collect_diagnostics`
+    #[cfg(feature = "lsp")]
+    static DIAGNOSTICS: RefCell<Option<Vec<serde_json::Value>>> = const { RefCell::new(None) };
+}
+
+/// Reports a warning, see `take_warnings`, unless its lint is allowed, or as an error if it is denied
+pub fn warn(mut warning: LocalizedSourcedError) {
+    if let Some(inner) = warning.0.downcast_mut::<Warning>() {
+        match lint::level(inner.lint) {
+            Level::Allow => return,
+            Level::Warn => (),
+            Level::Deny => {
+                inner.severity = Severity::Error;
+                DENIED.with(|denied| denied.set(denied.get() + 1));
+            }
+        }
+    }
+    WARNINGS.with(|warnings| warnings.borrow_mut().push(warning));
+}
+
+/// Returns the number of warnings reported as errors since the last call, which fail the compilation
+pub fn take_denied() -> usize {
+    DENIED.with(|denied| denied.replace(0))
+}
+
+/// The severity of an error, that of a warning as its lint is set, see `warn`
+pub fn severity(error: &(dyn Error + 'static)) -> Severity {
+    let error = match (error.downcast_ref::<LocalizedSourcedError>(), error.downcast_ref::<LocalizedError>()) {
+        (Some(error), _) => &*error.0,
+        (_, Some(error)) => &*error.0,
+        _ => error,
+    };
+    error.downcast_ref::<Warning>().map_or(Severity::Error, |warning| warning.severity)
+}
+
+/// Returns the warnings reported since the last call, in the order they were reported
+pub fn take_warnings() -> Vec<LocalizedSourcedError> {
+    WARNINGS.with(|warnings| warnings.take())
+}
+
+/// How the errors and warnings are reported on stderr
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// With a snippet of the code they are in, for people to read
+    #[default]
+    Human,
+    /// As JSON objects, one per line, for editors and other tools to consume, see `to_json`
+    Json,
+}
+
+static ERROR_FORMAT: AtomicU8 = AtomicU8::new(ErrorFormat::Human as u8);
+
+/// Sets how the errors and warnings are reported from now on
+pub fn set_format(format: ErrorFormat) {
+    ERROR_FORMAT.store(format as u8, Ordering::Relaxed);
+}
+
+/// How the errors and warnings are reported, for people to read unless set otherwise
+pub fn format() -> ErrorFormat {
+    match ERROR_FORMAT.load(Ordering::Relaxed) {
+        0 => ErrorFormat::Human,
+        _ => ErrorFormat::Json,
+    }
+}
+
+/// The errors and warnings reported so far, for the `summary`
+static REPORTED: [AtomicUsize; 2] = [AtomicUsize::new(0), AtomicUsize::new(0)];
+
+/// Prints an error, or the errors of `Diagnostics`, to stderr in the format set, see `set_format`,
+/// unless they or their fixes are being collected
+pub fn report(error: &(dyn Error + 'static)) {
+    if FIXES.with(|fixes| fixes.borrow().is_some()) {
+        return collect(error);
+    }
+    #[cfg(feature = "lsp")]
+    if DIAGNOSTICS.with(|diagnostics| diagnostics.borrow().is_some()) {
+        let objects = to_json(error);
+        return DIAGNOSTICS.with(|diagnostics| diagnostics.borrow_mut().as_mut().map_or((), |diagnostics| diagnostics.extend(objects)));
+    }
+    count(error);
+    match format() {
+        ErrorFormat::Human => {
+            // interrupted phases may have no errors to report
+            let errors = error.to_string();
+            if !errors.is_empty() {
+                anstream::eprintln!("{}", errors);
+            }
+        }
+        ErrorFormat::Json => to_json(error).iter().for_each(|object| anstream::eprintln!("{}", object)),
+    }
+}
+
+/// Runs `f`, collecting the machine-applicable suggestions of the errors and warnings it reports,
+/// along with the files they are for, instead of printing them
+pub fn collect_fixes<T>(f: impl FnOnce() -> T) -> (T, Vec<(PathBuf, Suggestion)>) {
+    FIXES.with(|fixes| fixes.replace(Some(Vec::new())));
+    let result = f();
+    (result, FIXES.with(|fixes| fixes.take()).unwrap_or_default())
+}
+
+/// Runs `f`, collecting the JSON objects of the errors and warnings it reports, see `to_json`, instead of printing them
+#[cfg(feature = "lsp")]
+pub fn collect_diagnostics<T>(f: impl FnOnce() -> T) -> (T, Vec<serde_json::Value>) {
+    DIAGNOSTICS.with(|diagnostics| diagnostics.replace(Some(Vec::new())));
+    let result = f();
+    (result, DIAGNOSTICS.with(|diagnostics| diagnostics.take()).unwrap_or_default())
+}
+
+fn collect(error: &(dyn Error + 'static)) {
+    if let Some(diagnostics) = error.downcast_ref::<Diagnostics>() {
+        return diagnostics.0.iter().for_each(|error| collect(error));
+    }
+    let Some(error) = error.downcast_ref::<LocalizedSourcedError>() else { return };
+    let fixes = error.suggestions().iter()
+        .filter(|suggestion| suggestion.applicability == Applicability::MachineApplicable)
+        .map(|suggestion| (error.source_path().to_path_buf(), suggestion.clone()));
+    FIXES.with(|collected| collected.borrow_mut().as_mut().map(|collected| collected.extend(fixes)));
+}
+
+/// Counts the errors and warnings reported, `Diagnostics` counting for each of theirs
+fn count(error: &(dyn Error + 'static)) {
+    if let Some(diagnostics) = error.downcast_ref::<Diagnostics>() {
+        return diagnostics.0.iter().for_each(|error| count(error));
+    }
+    let warning = severity(error) == Severity::Warning;
+    REPORTED[warning as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Sums up the errors and warnings reported so far, e.g. "3 errors, 1 warning", if there were errors
+pub fn summary() -> Option<String> {
+    let [errors, warnings] = REPORTED.each_ref().map(|count| count.load(Ordering::Relaxed));
+    if errors == 0 {
+        return None;
+    }
+    let errors = match errors {
+        1 => message!("one-error").into(),
+        count => message!("errors", count = count).into(),
+    };
+    Some(match warnings {
+        0 => errors,
+        1 => format!("{}, {}", errors, message!("one-warning")),
+        count => format!("{}, {}", errors, message!("warnings", count = count)),
+    })
+}
+
+/// The first line of a diagnostic: the error, with its `code` after its kind if it has one,
+/// e.g. "ResolveError[E0102]: Cannot find `x` in this scope"
+fn headline(error: &dyn Error, code: Option<&str>) -> String {
+    let text = error.to_string();
+    let coded = text.split_once(": ")
+        .filter(|(kind, _)| !kind.contains(char::is_whitespace))
+        .zip(code)
+        .map(|((kind, message), code)| format!("{}[{}]: {}", kind, code, message));
+    coded.unwrap_or(text)
+}
+
+/// Describes an error, or each of the errors of `Diagnostics`, as a JSON object:
+/// its `severity`, `error` or `warning`, the `kind` of error, e.g. `ParseError`, its `code`, e.g. `E0001`,
+/// its `message`, the `file` and the `span` it is at, if known, its `labels`, the other spans it is about
+/// in the same file, each with its `message`, and its `suggestions`, each with
+/// its `message`, the `replacement` of the code at its `span`, and its `applicability`, `machine-applicable`
+/// for those `moo fix` applies, or `maybe-incorrect`.
+/// Lines count from 1, columns are byte offsets in their line, offsets are byte offsets in the whole file
+pub fn to_json(error: &(dyn Error + 'static)) -> Vec<serde_json::Value> {
+    if let Some(diagnostics) = error.downcast_ref::<Diagnostics>() {
+        return diagnostics.0.iter().flat_map(|error| to_json(error)).collect();
+    }
+    let (error, location, notes) = match (error.downcast_ref::<LocalizedSourcedError>(), error.downcast_ref::<LocalizedError>()) {
+        (Some(error), _) => (&*error.0, Some((error.1, Some(error.source_path()))), Some(&error.3)),
+        (_, Some(error)) => (&*error.0, Some((error.1, None)), Some(&error.2)),
+        _ => (error, None, None),
+    };
+    let (labels, suggestions) = notes.map_or((&[][..], &[][..]), |notes| (&notes.labels[..], &notes.suggestions[..]));
+    let text = error.to_string();
+    let (kind, message) = text.split_once(": ").unwrap_or(("Error", &text));
+    let position = |location: &Location| serde_json::json!({
+        "line": location.line,
+        "column": location.column,
+        "offset": location.offset,
+    });
+    vec![serde_json::json!({
+        "severity": match severity(error) {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        },
+        "kind": kind,
+        "code": notes.and_then(|notes| notes.code),
+        "message": message,
+        "file": location.and_then(|(_, path)| path).map(|path| path.display().to_string()),
+        "span": location.map(|(span, _)| serde_json::json!({ "start": position(&span.start), "end": position(&span.end) })),
+        "labels": labels.iter().map(|label| serde_json::json!({
+            "message": label.message,
+            "span": { "start": position(&label.span.start), "end": position(&label.span.end) },
+        })).collect::<Vec<_>>(),
+        "suggestions": suggestions.iter().map(|suggestion| serde_json::json!({
+            "message": suggestion.message,
+            "replacement": suggestion.replacement,
+            "applicability": match suggestion.applicability {
+                Applicability::MachineApplicable => "machine-applicable",
+                Applicability::MaybeIncorrect => "maybe-incorrect",
+            },
+            "span": { "start": position(&suggestion.span.start), "end": position(&suggestion.span.end) },
+        })).collect::<Vec<_>>(),
+    })]
+}
+
+impl From<LocalizedSourcedError> for Diagnostics {
+    fn from(error: LocalizedSourcedError) -> Self {
+        Self(vec![error])
+    }
+}
+
+impl FromIterator<LocalizedSourcedError> for Diagnostics {
+    fn from_iter<T: IntoIterator<Item = LocalizedSourcedError>>(iter: T) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+/// An error which may have a code, e.g. `E0102`, from the key of its message in the catalog, see `messages::Message`
+pub trait Coded {
+    fn code(&self) -> Option<&'static str>;
+}
+
 pub trait LocalizableError {
     fn with_location(self, location: Location) -> LocalizedError;
+    /// Localizes the error to all the tokens of `span`, e.g. of an expression
+    fn with_span(self, span: Span) -> LocalizedError;
+}
+
+/// The errors reading sources and writing outputs have no code
+impl Coded for std::io::Error {
+    fn code(&self) -> Option<&'static str> {
+        None
+    }
 }
 
 impl<E> LocalizableError for E 
-where E: Error + 'static {
+where E: Error + Coded + 'static {
     fn with_location(self, location: Location) -> LocalizedError {
-        LocalizedError::new(Box::new(self), location)
+        LocalizedError::new(self, location)
+    }
+    fn with_span(self, span: Span) -> LocalizedError {
+        let notes = Notes { code: self.code(), ..Notes::default() };
+        LocalizedError(Box::new(self), span, Box::new(notes))
     }
 }
 
 impl LocalizedError {
-    pub fn new(error: Box<dyn Error + 'static>, location: Location) -> Self 
-    {
-        Self(error, location)
+    pub fn new<E>(error: E, location: Location) -> Self 
+    where E: Error + Coded + 'static {
+        error.with_span(Span::from(location))
+    }
+    /// Proposes `suggestion` to fix the error
+    pub fn with_suggestion(mut self, suggestion: Suggestion) -> Self {
+        self.2.suggestions.push(suggestion);
+        self
+    }
+    /// Points at `span` too, showing `message` under its code
+    pub fn with_label(mut self, span: Span, message: Message) -> Self {
+        self.2.labels.push(Label { span, message: message.into() });
+        self
+    }
+    pub fn suggestions(&self) -> &[Suggestion] {
+        &self.2.suggestions
+    }
+    pub fn labels(&self) -> &[Label] {
+        &self.2.labels
     }
     pub fn location(&self) -> &Location {
-        &self.1
+        &self.1.start
     }
+    pub fn span(&self) -> Span {
+        self.1
+    }
+    /// Reports the error in the file at `source_path`, or in the source its span is from if known,
+    /// e.g. another file of a program merged from several, see `sources`
     pub fn with_source<P>(self, source_path: P) -> LocalizedSourcedError 
     where P: AsRef<Path> {
-        LocalizedSourcedError(self.0, self.1, source_path.as_ref().to_path_buf())
+        let path = sources::file(self.1.start.file).map_or_else(|| source_path.as_ref().to_path_buf(), |source| source.name.clone());
+        let mut notes = self.2;
+        // the labels in other sources can't be shown in its snippet
+        notes.labels.retain(|label| label.span.start.file == self.1.start.file);
+        LocalizedSourcedError(self.0, self.1, path, notes)
     }
 }
 
 impl LocalizedSourcedError {
     pub fn new<E>(error: Box<E>, location: Location, source_path: PathBuf) -> Self 
-    where E: Error + 'static {
-        Self(error, location, source_path)
+    where E: Error + Coded + 'static {
+        let notes = Notes { code: error.code(), ..Notes::default() };
+        Self(error, Span::from(location), source_path, Box::new(notes))
     }
     pub fn source_path(&self) -> &Path {
         &self.2
     }
     pub fn location(&self) -> &Location {
-        &self.1
+        &self.1.start
+    }
+    pub fn span(&self) -> Span {
+        self.1
+    }
+    pub fn suggestions(&self) -> &[Suggestion] {
+        &self.3.suggestions
+    }
+    pub fn labels(&self) -> &[Label] {
+        &self.3.labels
     }
 }
 
 impl fmt::Display for LocalizedError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "Error at [line:{},column:{}]:", self.1.line, self.1.column)?;
-        write!(f, "{}", self.0)
+        writeln!(f, "Error at {}:", self.1.start)?;
+        write!(f, "{}", headline(&*self.0, self.2.code))?;
+        for label in self.labels() {
+            write!(f, "\n{}", message!("label-at", message = label.message, location = label.span.start))?;
+        }
+        for suggestion in self.suggestions() {
+            write!(f, "\n{} {}", message!("help"), suggestion.message)?;
+        }
+        Ok(())
     } 
 }
 
-impl fmt::Display for LocalizedSourcedError {
-
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result 
-    {
-        let file = std::fs::File::open(self.source_path());
-        if let Err(err) = file {
-            write!(f, "{}", self)?;
-            return write!(f, "Couldn't show snippet, error opening file: {}", err);
-        }
-        let file = file.unwrap();
-        let reader = std::io::BufReader::new(file);
-        let (prev, line, next) = once("".to_string())
-            .chain(reader.lines().map(Result::unwrap))
-            .chain(once("".to_string()))
-            .skip(self.location().line.saturating_sub(1))
-            .take(3)
-            .collect_tuple()
-            .unwrap();
-        
-        writeln!(f, "{}", self.red())?;
-        writeln!(f, "Inside file '{}':", fs::canonicalize(self.source_path()).unwrap().display())?;
-
-        let pad = self.location().line.to_string().len() + 1;
-        assert!(pad <= line.len()); // avoid uncontrolled padding
-
-        writeln!(f, "{}─┬{}", "─".repeat(pad), "─".repeat(f.width().unwrap_or(30)))?;
-        writeln!(f, "{:pad$} │ {}", self.location().line-1, prev, pad=pad)?;
-        writeln!(f, "{:pad$} │", "", pad=pad)?; 
-
-        write!(f, "{:pad$} │ ", self.location().line.red(), pad=pad)?;
-        let mut last = line.as_ptr() as usize;
-        for (i, tok) in slice_into_snippets(line.as_str()).enumerate() {
-            let pad = tok.as_ptr() as usize - last;
-            assert!(pad <= line.len()); // avoid uncontrolled padding
-            write!(f, "{:pad$}", "", pad=pad)?;
-            if i == self.location().column {
-                write!(f, "{}", tok.red().bold())?;
-            } else {
-                write!(f, "{}", tok)?;
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, error) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+                writeln!(f)?;
             }
-            last = tok.as_ptr() as usize + tok.len();
+            write!(f, "{}", error)?;
         }
+        Ok(())
+    }
+}
 
-        let snippet = slice_into_snippets(line.as_str()).nth(self.location().column).unwrap();
-        let padd = snippet.as_ptr() as usize - line.as_ptr() as usize;
-        writeln!(f, "\n{0:pad$} │ {0:padd$}{1}", "", "^".repeat(snippet.len()).red(), pad=pad, padd=padd)?;
-
-        writeln!(f, "{:pad$} │ {}", self.location().line+1, next, pad=pad)?;
-        write!(f, "{}─┴{}", "─".repeat(pad), "─".repeat(f.width().unwrap_or(30)))
+impl fmt::Display for LocalizedSourcedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let color = match severity(&*self.0) {
+            Severity::Warning => AnsiColors::Yellow,
+            Severity::Error => AnsiColors::Red,
+        };
+        match sources::load(self.source_path()) {
+            Ok(source) => {
+                writeln!(f, "{}", headline(&*self.0, self.3.code).style(color::style(Style::new().color(color))))?;
+                match source.registered {
+                    true => writeln!(f, "{}", message!("inside-source", name = self.source_path().display()))?,
+                    false => {
+                        let path = fs::canonicalize(self.source_path()).unwrap_or_else(|_| self.source_path().to_path_buf());
+                        writeln!(f, "{}", message!("inside-file", path = path.display()))?
+                    }
+                }
+                render::snippet(f, &source, self.span(), self.labels(), color, f.width().unwrap_or(30))?;
+            }
+            Err(err) => {
+                writeln!(f, "{}", headline(&*self.0, self.3.code))?;
+                write!(f, "{}", message!("no-snippet", error = err))?;
+            }
+        }
+        let help = color::style(Style::new().cyan().bold());
+        for suggestion in self.suggestions() {
+            write!(f, "\n{} {}", message!("help").style(help), suggestion.message)?;
+        }
+        Ok(())
     }
 }
 
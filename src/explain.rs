@@ -0,0 +1,75 @@
+// The error index `explain` prints: a longer explanation of each code of the diagnostics, with an example
+// reproducing it, one markdown file per code in `explanations/`.
+// The first ```moo block of an explanation is its example, which the tests compile, checking it still
+// reports the code, and optionally starts with a `// compile-flags:` line, like the UI tests.
+// Examples which aren't a single program, like those of the manifest or of the C API, are ```text blocks.
+
+use core::fmt;
+use std::error::Error;
+
+use crate::errors::Coded;
+use crate::messages::{message, ENGLISH, Message};
+
+#[derive(Debug)]
+pub struct ExplainError {
+    message: Message,
+}
+
+impl fmt::Display for ExplainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ExplainError: {}", self.message)
+    }
+}
+
+impl Error for ExplainError {}
+
+impl Coded for ExplainError {
+    fn code(&self) -> Option<&'static str> {
+        self.message.code()
+    }
+}
+
+macro_rules! explanations {
+    ($($code:literal),* $(,)?) => {
+        &[$(($code, include_str!(concat!("explanations/", $code, ".md")))),*]
+    };
+}
+
+/// The explanations by code, in the order of the catalog
+const EXPLANATIONS: &[(&str, &str)] = explanations![
+    "E0001", "E0002", "E0003", "E0004", "E0005", "E0006", "E0007", "E0008", "E0009",
+    "E0101", "E0102", "E0103", "E0104", "E0105",
+    "E0201", "E0202", "E0203", "E0204", "E0205",
+    "E0301", "E0302", "E0303", "E0304", "E0305", "E0306", "E0307", "E0308", "E0309", "E0310",
+    "E0311", "E0312", "E0313", "E0314", "E0315", "E0316", "E0317", "E0318", "E0319", "E0320",
+    "E0321", "E0322", "E0323",
+    "E0401", "E0402", "E0403", "E0404", "E0405", "E0406", "E0407", "E0408", "E0409", "E0410",
+    "E0411", "E0412", "E0413", "E0414", "E0415", "E0416", "E0417", "E0418", "E0419", "E0420",
+    "E0421", "E0422", "E0423", "E0424", "E0425", "E0426", "E0427", "E0428", "E0429", "E0430",
+    "E0431", "E0432", "E0433", "E0434", "E0435",
+    "E0501", "E0502", "E0503", "E0504", "E0505", "E0506", "E0507", "E0508", "E0509", "E0510",
+    "E0511",
+    "W0001", "W0002", "W0003", "W0004",
+];
+
+/// Returns the explanation of the error or warning `code`, e.g. `E0102`, in markdown. The code is case insensitive
+pub fn explain(code: &str) -> Result<&'static str, ExplainError> {
+    EXPLANATIONS.iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(code))
+        .map(|(_, explanation)| *explanation)
+        .ok_or_else(|| ExplainError {
+            message: message!("E0432", code = code),
+        })
+}
+
+/// The codes of the errors and warnings of the catalog, which all have an explanation
+pub fn codes() -> impl Iterator<Item = &'static str> {
+    ENGLISH.iter()
+        .map(|(key, _)| *key)
+        .filter(|key| key.starts_with(['E', 'W']) && key[1..].bytes().all(|byte| byte.is_ascii_digit()))
+}
+
+/// The codes which have an explanation
+pub fn explained() -> impl Iterator<Item = &'static str> {
+    EXPLANATIONS.iter().map(|(code, _)| *code)
+}
@@ -0,0 +1,129 @@
+// The memory the syntax trees are allocated in.
+// Nodes are bump allocated next to each other in an arena, instead of each in its own heap allocation,
+// and every parse allocates the nodes of its module in an arena of its own, see `ParseContext`.
+// Each node keeps a reference to its arena, which is freed along with the last of them,
+// once the module, and whatever was cloned from it, is dropped.
+
+use core::fmt;
+use std::cell::RefCell;
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+use std::rc::{Rc, Weak};
+
+use bumpalo::Bump;
+use serde::{Serialize, Serializer};
+
+use crate::frontend::ast::AST;
+
+/// The memory nodes are allocated in, freed once none of them is left
+struct Arena(Bump);
+
+thread_local! {
+    /// The arena the nodes allocated on the thread go into, while it has any of them, or a parse is running
+    static CURRENT: RefCell<Weak<Arena>> = const { RefCell::new(Weak::new()) };
+}
+
+/// Allocates the nodes of a parse in an arena of their own, from its creation to its drop,
+/// the nodes allocated before and after going in the arena they did
+pub struct ParseContext {
+    arena: Rc<Arena>,
+    /// The arena of the nodes allocated before, to go back to
+    previous: Weak<Arena>,
+}
+
+impl ParseContext {
+    pub fn new() -> Self {
+        let arena = Rc::new(Arena(Bump::new()));
+        let previous = CURRENT.with(|current| current.replace(Rc::downgrade(&arena)));
+        Self { arena, previous }
+    }
+
+    /// Moves `ast` into the arena
+    pub fn alloc(&self, ast: AST) -> Node {
+        Node::in_arena(ast, self.arena.clone())
+    }
+}
+
+impl Default for ParseContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for ParseContext {
+    fn drop(&mut self) {
+        CURRENT.with(|current| *current.borrow_mut() = std::mem::take(&mut self.previous));
+    }
+}
+
+/// A child of a node of the syntax tree, stored in an arena, which it keeps alive.
+/// Derefs to the child, so it is used like the `Box` it replaces.
+pub struct Node {
+    ast: NonNull<AST>,
+    /// Only held, for the arena to outlive the child
+    #[allow(dead_code)]
+    arena: Rc<Arena>,
+}
+
+impl Node {
+    /// Moves `ast` into the arena of the running parse, or that of the nodes allocated last on the thread,
+    /// or a new one if none of them is left
+    pub fn new(ast: AST) -> Self {
+        let arena = CURRENT.with(|current| {
+            let mut current = current.borrow_mut();
+            current.upgrade().unwrap_or_else(|| {
+                let arena = Rc::new(Arena(Bump::new()));
+                *current = Rc::downgrade(&arena);
+                arena
+            })
+        });
+        Self::in_arena(ast, arena)
+    }
+
+    fn in_arena(ast: AST, arena: Rc<Arena>) -> Self {
+        let ast = NonNull::from(arena.0.alloc(ast));
+        Self { ast, arena }
+    }
+}
+
+impl Deref for Node {
+    type Target = AST;
+    fn deref(&self) -> &Self::Target {
+        // Safety: the node is the only reference to its child, which lives as long as the arena it keeps
+        unsafe { self.ast.as_ref() }
+    }
+}
+
+impl DerefMut for Node {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safety: see `deref`
+        unsafe { self.ast.as_mut() }
+    }
+}
+
+/// The arena frees the memory of the child, but doesn't run its destructor, e.g. freeing its names
+impl Drop for Node {
+    fn drop(&mut self) {
+        // Safety: the child is dropped once, before its arena may be
+        unsafe { std::ptr::drop_in_place(self.ast.as_ptr()) }
+    }
+}
+
+/// Copies the child into a new node, in the arena nodes are allocated in, see `Node::new`
+impl Clone for Node {
+    fn clone(&self) -> Self {
+        Node::new((**self).clone())
+    }
+}
+
+impl fmt::Debug for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl Serialize for Node {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (**self).serialize(serializer)
+    }
+}
@@ -1,50 +1,138 @@
 use core::fmt;
-use std::ops::{Deref, DerefMut};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, VecDeque};
+use std::iter::once;
+use std::ops::{Deref, DerefMut, Range};
 use std::{error::Error, iter::Peekable, fmt::Debug};
 
-use owo_colors::OwoColorize;
+use owo_colors::{OwoColorize, Style};
+use serde::Serialize;
 
-use crate::frontend::tokenizer::{Operator, Token, Location, Type as TokenT, Tokenizer};
-use crate::errors::{LocalizableError, LocalizedError};
+use crate::frontend::tokenizer::{split_numeric, Comment, Operator, Part, Token, Location, Span, Type as TokenT, Tokenizer, TokenizerExt};
+use crate::frontend::arena::{Node, ParseContext};
+use crate::frontend::edition::Deprecation;
+use crate::frontend::suggest;
+use crate::frontend::symbol::Symbol;
+use crate::frontend::types::{IntTy, Ty};
+use crate::color;
+use crate::errors::{Applicability, Coded, LocalizableError, LocalizedError, Suggestion, Warning};
+use crate::lint::Lint;
+use crate::messages::{message, Message};
+use crate::sources::FileId;
 
+/// A node of the syntax tree, along with the source it was parsed from.
+/// As JSON, an object with the `type` of the node, e.g. `{"Identifier": "x"}`, and its `span`
+#[derive(Clone, Serialize)]
 pub struct  AST {
+    #[serde(rename = "type")]
     type_: Type,
-    location: Location,
+    span: Span,
 }
 
 impl Debug for AST {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "[{}:{}]", self.location.line.blue(), self.location.column.blue())?;
+        let blue = color::style(Style::new().blue());
+        write!(f, "[{}:{}]", self.span.start.line.style(blue), self.span.start.column.style(blue))?;
         write!(f, "{:#?}", self.type_)
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 pub enum Type {
-    Literal(String),
-    Identifier(String),
+    Literal(Symbol),
+    Identifier(Symbol),
     // name, type
-    TypedLiteral(String, String),
+    TypedLiteral(Symbol, TypeName),
     // operator, lhs, rhs - arithmetic expression
-    Expression(Operator, Box<AST>, Box<AST>),
+    Expression(Operator, Node, Node),
     // return type, arguments, body
-    Lambda(String, Vec<AST>, Box<AST>),
+    Lambda(TypeName, Vec<AST>, Node),
+    // callee, arguments
+    Call(Node, Vec<AST>),
+    // type parameters, lambda
+    Generic(Vec<Symbol>, Node),
+    // symbol, arguments, return type - a function of another library, e.g. of C,
+    // bound at module level to the name of its symbol, see `parse_extern`
+    Extern(Symbol, Vec<AST>, TypeName),
+    // module name - replaced by the module's bindings when loading
+    Import(Symbol),
+    // expression statement - evaluated for its effects, its value is `unit`
+    Discard(Node),
+    // value, target type, source type - the source type is filled in by the checker
+    Cast(Node, TypeName, Option<Ty>),
+    // integer type, value - wraps the value around to the width of the type,
+    // inserted by the checker where values of sized integer types are computed
+    Truncate(IntTy, Node),
+    // value - arithmetic on floats, inserted by the checker around the only one it allows, `**`
+    Float(Node),
+    // parts - string literals and the expressions between them, concatenated into a string.
+    // The checker turns the expressions into strings with casts
+    Interpolation(Vec<AST>),
     Block(Vec<AST>),
     Module(Vec<AST>),
 }
 
+/// A type annotation, e.g. `int`, `[string]` or `fn(int, int): int`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum TypeName {
+    Named(String),
+    // element type
+    Array(Box<TypeName>),
+    // argument types, return type
+    Function(Vec<TypeName>, Box<TypeName>),
+}
+
+impl fmt::Display for TypeName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeName::Named(name) => write!(f, "{}", name),
+            TypeName::Array(element) => write!(f, "[{}]", element),
+            TypeName::Function(args, ret) => {
+                write!(f, "fn(")?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ") -> {}", ret)
+            }
+        }
+    }
+}
+
 impl Type {
-    fn wrap(self, location: Location) -> AST {
+    /// Wraps the node into an AST at the given location
+    pub fn wrap(self, location: Location) -> AST {
+        self.wrap_span(Span::from(location))
+    }
+    /// Wraps the node into an AST covering the given span
+    pub fn wrap_span(self, span: Span) -> AST {
         AST {
             type_: self,
-            location,
+            span,
         }
     }
+    /// Wraps a node just parsed into an AST, spanning from `start`
+    /// to the end of the last token taken from `tokens`
+    fn wrap_parsed(self, start: Location, tokens: &mut Peekable<impl Iterator<Item = Token>>) -> AST {
+        // a token is pulled from the tokenizer when peeked: once one is peeked,
+        // the last token taken is the one pulled before it
+        let end = match tokens.peek() {
+            Some(_) => TAKEN.with(|taken| taken.get().0),
+            None => TAKEN.with(|taken| taken.get().1),
+        };
+        self.wrap_span(Span::new(start, end))
+    }
 }
 
 impl AST {
     pub fn location(&self) -> &Location {
-        &self.location
+        &self.span.start
+    }
+    /// Returns the tokens the AST was parsed from
+    pub fn span(&self) -> Span {
+        self.span
     }
     /// Returns the type of the AST
     pub fn type_(self) -> Type {
@@ -52,114 +140,332 @@ impl AST {
     }
 }
 
-#[derive(Debug)]
-pub struct ParseError {
-    message: String,
+/// Something the parser would have accepted where it failed
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expected {
+    /// A specific operator or keyword
+    Operator(Operator),
+    /// A kind of token or syntax, e.g. `identifier` or `expression`
+    Kind(&'static str),
+}
+
+impl From<Operator> for Expected {
+    fn from(operator: Operator) -> Self {
+        Expected::Operator(operator)
+    }
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expected::Operator(operator) => write!(f, "`{}`", operator.as_str()),
+            Expected::Kind(kind) => write!(f, "{}", kind),
+        }
+    }
+}
+
+/// What went wrong while parsing, so callers can match on the kind of error
+/// instead of its message
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// A token other than the `expected` ones was found
+    UnexpectedToken { expected: Vec<Expected>, found: Token },
+    /// The input ended while one of the `expected` tokens was still missing
+    UnexpectedEof { expected: Vec<Expected> },
+    /// A construct removed in the current edition was `found`
+    Removed { deprecation: Deprecation, found: Token },
+}
+
+impl ParseError {
+    /// Returns the span of the offending token, if there is one
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            ParseError::UnexpectedToken { found, .. } | ParseError::Removed { found, .. } => Some(found.span()),
+            ParseError::UnexpectedEof { .. } => None,
+        }
+    }
+    /// Returns everything the parser would have accepted instead
+    pub fn expected(&self) -> &[Expected] {
+        match self {
+            ParseError::UnexpectedToken { expected, .. } | ParseError::UnexpectedEof { expected } => expected,
+            ParseError::Removed { .. } => &[],
+        }
+    }
+    /// The message of the error, from the catalog
+    fn message(&self) -> Message {
+        let expected = match self.expected() {
+            [init @ .., last] if !init.is_empty() => message!("one-of",
+                alternatives = init.iter().map(Expected::to_string).collect::<Vec<_>>().join(", "),
+                last = last).into(),
+            expected => expected.iter().map(Expected::to_string).collect::<String>(),
+        };
+        match self {
+            ParseError::UnexpectedToken { found, .. } => message!("E0001",
+                expected = expected,
+                found = format!("{} `{}`", found.type_.kind(), found.type_.text())),
+            ParseError::UnexpectedEof { .. } => message!("E0002", expected = expected),
+            ParseError::Removed { deprecation, .. } => deprecation.error(),
+        }
+    }
 }
 
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "ParseError: {}", self.message)
+        write!(f, "ParseError: {}", self.message())
     }
 }
 
 impl Error for ParseError {}
 
+impl Coded for ParseError {
+    fn code(&self) -> Option<&'static str> {
+        self.message().code()
+    }
+}
+
 
-pub fn parse<I, S>(tokenizer: &mut Tokenizer<I>) -> Result<AST, LocalizedError>
+/// Parses a module, reporting every syntax error in it.
+/// After an error, the parser skips to the end of the statement and carries on
+/// with the next one, see `synchronize`.
+/// An invalid token is the only error reported: the parser stops there, and its errors
+/// about the truncated input would be misleading.
+pub fn parse<I, S>(tokenizer: &mut Tokenizer<I>) -> Result<AST, Vec<LocalizedError>>
 where I: Iterator<Item = S>, S: AsRef<str>
 {
-    let mut tokens = tokenizer.peekable();
-    parse_module(&mut tokens)
-        .map_err(|err| err.with_location(Location::default())) // FIXME: this is a hack
-        // .map_err(|err| err.with_location(Location { 
-        //     line: tokenizer.location().line, 
-        //     column: tokenizer.location().column-1 
-        // }))
+    // the nodes of the module go in an arena of their own, freed along with it
+    let _context = ParseContext::new();
+    // remember where the last token seen ends, errors at the end of input point there
+    let last = Cell::new(Location::default());
+    // the number of braces left open by the tokens seen
+    let depth = Cell::new(0usize);
+    TAKEN.with(|taken| taken.set(Default::default()));
+    WARNINGS.with(|warnings| warnings.borrow_mut().clear());
+    let invalid = Cell::new(None);
+    // the last tokens seen, for the keyword a misspelled one could be, see `misspelled_keyword`
+    let recent = RefCell::new(VecDeque::with_capacity(RECENT));
+    let mut tokens = tokenizer
+        .map_while(|token| token.map_err(|error| invalid.set(Some(error))).ok())
+        .inspect(|token| {
+            let mut recent = recent.borrow_mut();
+            if recent.len() == RECENT {
+                recent.pop_front();
+            }
+            recent.push_back(token.clone());
+            last.set(token.span().end);
+            TAKEN.with(|taken| taken.set((taken.get().1, token.span().end)));
+            match token.type_ {
+                TokenT::Operator(Operator::LCurl) => depth.set(depth.get() + 1),
+                TokenT::Operator(Operator::RCurl) => depth.set(depth.get().saturating_sub(1)),
+                _ => (),
+            }
+        })
+        .peekable();
+
+    let _production = Production::enter("module", None, &mut tokens);
+    let location = locate(&mut tokens);
+    let mut asts = Vec::new();
+    let mut errors = Vec::new();
+    while tokens.peek().is_some() {
+        match parse_statement(&mut tokens) {
+            Ok(ast) => asts.push(ast),
+            Err(err) => {
+                let span = err.span().unwrap_or(Span::from(last.get()));
+                let suggestions = suggestions(&err, &recent.borrow(), span);
+                errors.push(suggestions.into_iter().fold(err.with_span(span), LocalizedError::with_suggestion));
+                synchronize(&mut tokens, &depth);
+            }
+        }
+    }
+    if let Some(error) = invalid.take() {
+        return Err(vec![error]);
+    }
+    match errors.is_empty() {
+        true => Ok(Type::Module(asts).wrap_parsed(location, &mut tokens)),
+        false => Err(errors),
+    }
+}
+
+/// The number of tokens looked back at for a misspelled keyword, when parsing fails
+const RECENT: usize = 8;
+
+/// The keywords a misspelled identifier may have been meant as
+const KEYWORDS: &[Operator] = &[Operator::Let, Operator::Fn, Operator::Import, Operator::Extern, Operator::As];
+
+/// What could fix the syntax error `error` at `span`
+/// * `recent` - the last tokens seen, in order, see `RECENT`
+fn suggestions(error: &ParseError, recent: &VecDeque<Token>, span: Span) -> Vec<Suggestion> {
+    let mut suggestions = Vec::new();
+    let semicolon = error.expected().contains(&Operator::Semicolon.into());
+    match error {
+        // a `;` is missing at the end of the line the statement ends on
+        ParseError::UnexpectedToken { found, .. } if semicolon => {
+            let before = recent.iter().take_while(|token| token.location.offset < found.location.offset).last();
+            if let Some(before) = before.filter(|before| before.span().end.line < found.location.line) {
+                suggestions.push(Suggestion::insert(before.span().end, ";", Applicability::MachineApplicable));
+            }
+        }
+        // what is missing at the end of the input is only a guess, which may not be all that is
+        ParseError::UnexpectedEof { expected } => {
+            let closing = [Operator::RCurl, Operator::Semicolon].into_iter().find(|operator| expected.contains(&(*operator).into()));
+            if let Some(closing) = closing {
+                suggestions.push(Suggestion::insert(span.start, closing.as_str(), Applicability::MaybeIncorrect));
+            }
+        }
+        ParseError::Removed { deprecation, found } => suggestions.push(migration(*deprecation, found)),
+        _ => (),
+    }
+    suggestions.extend(misspelled_keyword(recent, span));
+    suggestions
+}
+
+/// The keyword the identifier before the error at `span` was likely meant to be, in the statement it is in,
+/// e.g. `let` for `lte` in `lte x = 1;`, where parsing fails at `x`.
+/// The identifier must be close to the keyword and followed by what would follow it, e.g. a name after `let`
+/// or `(` after `fn`, not to read every name close to a keyword as a misspelling
+/// * `recent` - the last tokens seen, in order, see `RECENT`
+fn misspelled_keyword(recent: &VecDeque<Token>, span: Span) -> Option<Suggestion> {
+    let is_identifier = |token: &Token| matches!(&token.type_, TokenT::Literal(name) if !is_numeric(name) && !is_string(name));
+    let seen = recent.iter().take_while(|token| token.location.offset <= span.start.offset).collect::<Vec<_>>();
+    seen.windows(2).rev()
+        .take_while(|pair| !matches!(pair[1].type_, TokenT::Operator(Operator::Semicolon | Operator::LCurl | Operator::RCurl)))
+        .find_map(|pair| {
+            let (token, next) = (pair[0], pair[1]);
+            let TokenT::Literal(name) = &token.type_ else { return None };
+            let keywords = KEYWORDS.iter().filter(|keyword| match keyword {
+                Operator::Fn => matches!(next.type_, TokenT::Operator(Operator::LParen)),
+                Operator::Extern => matches!(next.type_, TokenT::Operator(Operator::Fn)),
+                _ => is_identifier(next),
+            });
+            let keyword = suggest::similar(name, keywords.map(Operator::as_str))?;
+            is_identifier(token).then(|| Suggestion::instead_of(token.span(), name, keyword))
+        })
 }
 
-/// Parses a module
+/// Skips the rest of a module level statement which failed to parse:
+/// up to the `;` outside of any braces, or up to the next `let` or `import` outside of any braces,
+/// which must start a new statement.
+/// * `depth` - the number of braces left open by the tokens taken from `tokens` so far
+fn synchronize(tokens: &mut Peekable<impl Iterator<Item = Token>>, depth: &Cell<usize>) {
+    let _production = Production::enter("synchronize", None, tokens);
+    while tokens.peek().is_some() {
+        match tokens.peek_operator() {
+            Some(Operator::Let | Operator::Import) if depth.get() == 0 => return,
+            Some(Operator::Semicolon) if depth.get() == 0 => {
+                tokens.next();
+                return;
+            }
+            _ => {
+                tokens.next();
+            }
+        }
+    }
+}
+
+/// Parses a module, stopping at the first syntax error, see `parse` to report them all
 /// * `tokens` - the tokens to parse
 pub fn parse_module(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Result<AST, ParseError> {
+    let _production = Production::enter("module", None, tokens);
     let location = locate(tokens);
     let mut asts = Vec::new();
-    while let Some(token) = tokens.peek() {
-        match token.type_ {
-            _ => asts.push(parse_statement(tokens)?),
-        }
+    while tokens.peek().is_some() {
+        asts.push(parse_statement(tokens)?);
     }
-    Ok(Type::Module(asts).wrap(location))
+    Ok(Type::Module(asts).wrap_parsed(location, tokens))
 }
 
 /// parse an _arithmetic_ expression, e.g. `1 + 2 * 3`
 /// * `tokens` - the tokens to parse
 pub fn parse_expression(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Result<AST, ParseError> {
-    match tokens.peek().map(|x| x.type_.clone()) {
-        Some(TokenT::Operator(Operator::LCurl)) => parse_block(tokens),
-        Some(TokenT::Operator(Operator::Fn)) => parse_function(tokens),
-        Some(_) => parse_airthmetic_expression(tokens),
-        None => Err(ParseError {
-            message: format!("Expected expression, found end of input"),
-        }),
+    let _production = Production::enter("expression", None, tokens);
+    match tokens.peek_operator() {
+        Some(Operator::LCurl) => parse_block(tokens),
+        Some(Operator::Fn) => parse_function(tokens),
+        _ if tokens.peek().is_some() => parse_binary(tokens),
+        _ => Err(tokens.unexpected(vec![Expected::Kind("expression")])),
     }
 }
 
 
-/// Parses an arithmetic expression, e.g. `1 + 2 * 3`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Associativity {
+    Left,
+    Right,
+}
 
-pub fn parse_airthmetic_expression(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Result<AST, ParseError> {
-    let location = locate(tokens);
-    let mut ast = parse_term(tokens)?;
-    while let Some(token) = tokens.peek() {
-        match token.type_ {
-            TokenT::Operator(Operator::Add) => {
-                tokens.next();
-                ast = Type::Expression(Operator::Add, Box::new(ast), Box::new(parse_term(tokens)?)).wrap(location);
-            }
-            TokenT::Operator(Operator::Sub) => {
-                tokens.next();
-                ast = Type::Expression(Operator::Sub, Box::new(ast), Box::new(parse_term(tokens)?)).wrap(location);
-            }
-            _ => break,
-        }
-    }
-    Ok(ast)
+/// The binary operators sharing one level of precedence
+pub struct PrecedenceLevel {
+    pub operators: &'static [Operator],
+    pub associativity: Associativity,
 }
 
-pub fn parse_term(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Result<AST, ParseError> {
+/// The binary operators, from the loosest to the tightest binding level.
+/// This is the one place to add an operator to, the parser is driven by it.
+pub const PRECEDENCE: &[PrecedenceLevel] = &[
+    PrecedenceLevel { operators: &[Operator::Add, Operator::Sub], associativity: Associativity::Left },
+    PrecedenceLevel { operators: &[Operator::Mul, Operator::Div, Operator::Mod], associativity: Associativity::Left },
+    PrecedenceLevel { operators: &[Operator::Pow], associativity: Associativity::Right },
+];
+
+/// The productions of the grammar, in EBNF, except for the binary operators
+/// which are described by `PRECEDENCE`. Keep in sync with the parse functions,
+/// the tests check that an example of the grammar enters each of its productions.
+pub const GRAMMAR: &[(&str, &str)] = &[
+    ("module", "{ statement }"),
+    ("statement", "( import | extern | let | expression ) \";\""),
+    ("import", "\"import\" identifier"),
+    ("extern", "\"extern\" \"fn\" identifier signature"),
+    ("let", "\"let\" identifier [ \":\" type ] \"=\" expression"),
+    ("expression", "block | function | binary"),
+    ("block", "\"{\" [ ( let | expression ) { \";\" ( let | expression ) } [ \";\" ] ] \"}\""),
+    ("function", "\"fn\" [ \"<\" identifier { \",\" identifier } \">\" ] signature block"),
+    ("signature", "\"(\" [ argument { \",\" argument } ] \")\" ( \"->\" | \":\" ) type"),
+    ("argument", "identifier \":\" type"),
+    ("type", "identifier | \"[\" type \"]\" | \"fn\" [ \"(\" [ type { \",\" type } ] \")\" ( \"->\" | \":\" ) type ]"),
+    ("cast", "atom { \"as\" type }"),
+    ("atom", "number | string | ( \"-\" | \"+\" ) atom | ( name | \"(\" expression \")\" ) { call }"),
+    ("string", "'\"' { character | \"{\" expression \"}\" } '\"'"),
+    ("name", "identifier { \".\" identifier }"),
+    ("call", "\"(\" [ expression { \",\" expression } ] \")\""),
+];
+
+/// Parses a binary expression, e.g. `1 + 2 * 3`, by precedence climbing over `PRECEDENCE`
+/// * `tokens` - the tokens to parse
+pub fn parse_binary(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Result<AST, ParseError> {
+    parse_binary_from(tokens, 0)
+}
+
+/// Parses operands joined by the operators of `level` and of the tighter levels.
+/// The operand on the right of an operator is parsed from the next tighter level,
+/// or from the same level for right associative operators, e.g. `2 ** 3 ** 2` is `2 ** (3 ** 2)`.
+/// * `tokens` - the tokens to parse
+/// * `level` - the index in `PRECEDENCE` of the loosest level to parse
+fn parse_binary_from(tokens: &mut Peekable<impl Iterator<Item = Token>>, level: usize) -> Result<AST, ParseError> {
+    let _production = Production::enter("binary", Some(level + 1), tokens);
     let location = locate(tokens);
-    let mut ast = parse_factor(tokens)?;
-    while let Some(token) = tokens.peek() {
-        match token.type_ {
-            TokenT::Operator(Operator::Mul) => {
-                tokens.next();
-                ast = Type::Expression(Operator::Mul, Box::new(ast), Box::new(parse_factor(tokens)?)).wrap(location);
-            }
-            TokenT::Operator(Operator::Div) => {
-                tokens.next();
-                ast = Type::Expression(Operator::Div, Box::new(ast), Box::new(parse_factor(tokens)?)).wrap(location);
-            }
-            TokenT::Operator(Operator::Mod) => {
-                tokens.next();
-                ast = Type::Expression(Operator::Mod, Box::new(ast), Box::new(parse_factor(tokens)?)).wrap(location);
-            }
-            _ => break,
+    let mut ast = parse_cast(tokens)?;
+    while let Some((operator, operator_level)) = tokens.peek().and_then(binary_operator) {
+        if operator_level < level {
+            break;
         }
+        tokens.next();
+        let rhs_level = match PRECEDENCE[operator_level].associativity {
+            Associativity::Left => operator_level + 1,
+            Associativity::Right => operator_level,
+        };
+        let rhs = parse_binary_from(tokens, rhs_level)?;
+        ast = Type::Expression(operator, Node::new(ast), Node::new(rhs)).wrap_parsed(location, tokens);
     }
     Ok(ast)
 }
 
-pub fn parse_factor(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Result<AST, ParseError> {
+/// Parses an atom followed by casts, e.g. `x as i8`
+/// * `tokens` - the tokens to parse
+pub fn parse_cast(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Result<AST, ParseError> {
+    let _production = Production::enter("cast", None, tokens);
     let location = locate(tokens);
     let mut ast = parse_atom(tokens)?;
-    while let Some(token) = tokens.peek() {
-        match token.type_ {
-            TokenT::Operator(Operator::Pow) => {
-                tokens.next();
-                ast = Type::Expression(Operator::Pow, Box::new(ast), Box::new(parse_atom(tokens)?)).wrap(location);
-            }
-            _ => break,
-        }
+    while tokens.next_if_operator(Operator::As) {
+        ast = Type::Cast(Node::new(ast), parse_type_name(tokens)?, None).wrap_parsed(location, tokens);
     }
     Ok(ast)
 }
@@ -167,22 +473,99 @@ pub fn parse_factor(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Resul
 /// Parses an atom of an arithmetic expression, e.g. `1`, `2`, `3`, `1 + 2`, `(1 + 2) * 3`, etc.
 /// * `tokens` - the tokens to parse
 pub fn parse_atom(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Result<AST, ParseError> {
+    let _production = Production::enter("atom", None, tokens);
     let location = locate(tokens);
-    match tokens.next().map(|x| x.type_) {
-        Some(TokenT::Literal(s)) => Ok(Type::Identifier(s).wrap(location)),
-        Some(TokenT::Operator(Operator::Sub)) => Ok(Type::Expression(Operator::Sub, 
-            Box::new(Type::Literal("0".to_owned()).wrap(location)), 
-            Box::new(parse_atom(tokens)?)).wrap(location)),
-        Some(TokenT::Operator(Operator::Add)) => Ok(parse_atom(tokens)?),
-        Some(TokenT::Operator(Operator::LParen)) => {
-            let ast = parse_airthmetic_expression(tokens)?;
-            match tokens.next().map(|x| x.type_) {
-                Some(TokenT::Operator(Operator::RParen)) => Ok(ast),
-                x => Err(expected_found("closing parenthesis", x)),
+    let callee = match tokens.next() {
+        Some(Token { type_: TokenT::Literal(s), .. }) if is_numeric(&s) || is_string(&s) => return Ok(Type::Literal(s).wrap_parsed(location, tokens)),
+        Some(Token { type_: TokenT::Literal(s), .. }) => Type::Identifier(parse_qualified_name(tokens, s)?).wrap_parsed(location, tokens),
+        Some(Token { type_: TokenT::Interpolated(_, parts), .. }) => return Ok(Type::Interpolation(parse_interpolation(parts, location)?).wrap_parsed(location, tokens)),
+        Some(Token { type_: TokenT::Operator(Operator::Sub), .. }) => return Ok(Type::Expression(Operator::Sub, 
+            Node::new(Type::Literal(Symbol::intern("0")).wrap(location)), 
+            Node::new(parse_atom(tokens)?)).wrap_parsed(location, tokens)),
+        Some(token @ Token { type_: TokenT::Operator(Operator::Add), .. }) => {
+            deprecated(Deprecation::UnaryPlus, token)?;
+            return parse_atom(tokens);
+        }
+        Some(Token { type_: TokenT::Operator(Operator::LParen), .. }) => {
+            let ast = parse_expression(tokens)?;
+            if !tokens.next_if_operator(Operator::RParen) {
+                return Err(tokens.unexpected(continued(vec![Operator::RParen.into()], &ast)));
             }
+            ast
+        }
+        x => return Err(expected_found(vec![Expected::Kind("literal"), Expected::Kind("identifier"), Operator::Sub.into(), Operator::Add.into(), Operator::LParen.into()], x)),
+    };
+    parse_call(tokens, callee)
+}
+
+/// Parses the parts of an interpolated string literal, e.g. `"x = {x + 1}"`,
+/// its text into string literals
+/// * `parts` - the parts of the literal, each expression with tokens of its own
+/// * `location` - the location of the literal
+pub fn parse_interpolation(parts: Vec<Part>, location: Location) -> Result<Vec<AST>, ParseError> {
+    // the tokens of the expressions don't come from the tokenizer,
+    // the last token taken from it is restored once they're parsed
+    let taken = TAKEN.with(Cell::get);
+    let parts = parts.into_iter()
+        .map(|part| match part {
+            Part::Text(text) => Ok(Type::Literal(Symbol::intern(&format!("\"{}\"", text))).wrap(location)),
+            Part::Code(code) => {
+                let mut code = code.into_iter()
+                    .inspect(|token| TAKEN.with(|taken| taken.set((taken.get().1, token.span().end))))
+                    .peekable();
+                let _production = Production::enter("string", None, &mut code);
+                let ast = parse_expression(&mut code)?;
+                match code.peek() {
+                    Some(_) => Err(code.unexpected(continued(vec![Operator::RCurl.into()], &ast))),
+                    None => Ok(ast),
+                }
+            }
+        })
+        .collect();
+    TAKEN.with(|cell| cell.set(taken));
+    parts
+}
+
+/// Parses the rest of a name qualified by the modules it is imported from, e.g. `math.abs`
+/// * `tokens` - the tokens to parse
+/// * `name` - the already parsed first segment of the name
+pub fn parse_qualified_name(tokens: &mut Peekable<impl Iterator<Item = Token>>, name: Symbol) -> Result<Symbol, ParseError> {
+    let _production = Production::enter("name", None, tokens);
+    if tokens.peek_operator() != Some(Operator::Dot) {
+        return Ok(name);
+    }
+    let mut name = name.to_string();
+    while tokens.next_if_operator(Operator::Dot) {
+        let segment = tokens.expect_literal("identifier", |segment| !is_numeric(segment) && !is_string(segment))?;
+        name.push_str(Operator::Dot.as_str());
+        name.push_str(&segment);
+    }
+    Ok(Symbol::intern(&name))
+}
+
+/// Parses the argument lists following a callee, e.g. the `(1, 2)(3)` in `f(1, 2)(3)`
+/// * `tokens` - the tokens to parse
+/// * `callee` - the already parsed expression being called
+pub fn parse_call(tokens: &mut Peekable<impl Iterator<Item = Token>>, callee: AST) -> Result<AST, ParseError> {
+    let _production = Production::enter("call", None, tokens);
+    let mut ast = callee;
+    while tokens.peek_operator() == Some(Operator::LParen) {
+        let location = *ast.location();
+        tokens.next();
+        let mut args = Vec::new();
+        while tokens.peek().is_some() && tokens.peek_operator() != Some(Operator::RParen) {
+            let arg = parse_expression(tokens)?;
+            if !tokens.next_if_operator(Operator::Comma) && tokens.peek_operator() != Some(Operator::RParen) {
+                return Err(tokens.unexpected(continued(vec![Operator::Comma.into(), Operator::RParen.into()], &arg)));
+            }
+            args.push(arg);
+        }
+        if !tokens.next_if_operator(Operator::RParen) {
+            return Err(tokens.unexpected(vec![Expected::Kind("expression"), Operator::RParen.into()]));
         }
-        x => Err(expected_found("literal, unary operator or opening parenthesis", x)),
+        ast = Type::Call(Node::new(ast), args).wrap_parsed(location, tokens);
     }
+    Ok(ast)
 }
 
 ///////////////////////////////
@@ -191,46 +574,30 @@ pub fn parse_atom(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Result<
 /// * `tokens` - the tokens to parse
 /// * `strict` - whether to require a type annotation (type information can still be provided by the user)
 pub fn parse_typed_literal(tokens: &mut Peekable<impl Iterator<Item = Token>>, strict: bool) -> Result<AST, ParseError> {
+    let _production = Production::enter("typed_literal", None, tokens);
     let location = locate(tokens);
-    match tokens.next().map(|x| x.type_) {
-        Some(TokenT::Literal(s)) => {
-            match tokens.peek().map(|x| x.type_.clone()) {
-                Some(TokenT::Operator(Operator::Colon)) => {
-                    tokens.next();
-                    match tokens.next().map(|x| x.type_) {
-                        Some(TokenT::Literal(t)) => Ok(Type::TypedLiteral(s, t).wrap(location)),
-                        x => Err(expected_found("literal [type information]", x)),
-                    }
-                }
-                x => if strict { 
-                    Err(expected_found("colon [type information]", x))
-                } else {
-                    Ok(Type::Literal(s).wrap(location))
-                },
-            }
-        }
-        x => Err(expected_found("literal [name information]", x)),
+    let name = tokens.expect_literal("name", |_| true)?;
+    if tokens.next_if_operator(Operator::Colon) {
+        Ok(Type::TypedLiteral(name, parse_type_name(tokens)?).wrap_parsed(location, tokens))
+    } else if strict {
+        Err(tokens.unexpected(vec![Operator::Colon.into()]))
+    } else {
+        Ok(Type::Literal(name).wrap_parsed(location, tokens))
     }
 }
 
 /// parse a literal, e.g. `1`
 pub fn parse_literal(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Result<AST, ParseError> {
+    let _production = Production::enter("literal", None, tokens);
     // TODO FIXME add checks for literal type
     // eg "" for string, pure numbers for int, float, etc.
-    let check_numeric = |x: &str| -> bool {
-        x.chars().all(|x| x.is_numeric() || x == '_')
-    };
-    let check_string = |x: &str| -> bool {
-        x.chars().next() == Some('"') && x.chars().last() == Some('"')
-    };
     let location = locate(tokens);
-    match tokens.next().map(|x| x.type_) {
-        Some(TokenT::Literal(s)) if check_numeric(&s) || check_string(&s) => Ok(Type::Literal(s).wrap(location)),
-        x => Err(expected_found("literal", x)),
-    }
+    let literal = tokens.expect_literal("literal", |literal| is_numeric(literal) || is_string(literal))?;
+    Ok(Type::Literal(literal).wrap_parsed(location, tokens))
 }
 
 pub fn parse_identifier(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Result<AST, ParseError> {
+    let _production = Production::enter("identifier", None, tokens);
     // TODO FIXME add checks for reserved keywords
     // eg only letters for identifiers
     let check_literal = |x: &str| -> bool {
@@ -238,10 +605,8 @@ pub fn parse_identifier(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> R
         && x.chars().next().map(|x| x.is_alphabetic()).unwrap_or(false)
     };
     let location = locate(tokens);
-    match tokens.next().map(|x| x.type_) {
-        Some(TokenT::Literal(s)) if check_literal(&s) => Ok(Type::Identifier(s).wrap(location)),
-        x => Err(expected_found("identifier", x)),
-    }
+    let identifier = tokens.expect_literal("identifier", check_literal)?;
+    Ok(Type::Identifier(identifier).wrap_parsed(location, tokens))
 }
 
 
@@ -249,112 +614,219 @@ pub fn parse_identifier(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> R
 /// parse an assignment expression, e.g. `let x = 1`
 /// * `tokens` - the tokens to parse
 pub fn parse_let(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Result<AST, ParseError> {
+    let _production = Production::enter("let", None, tokens);
     let location = locate(tokens);
-    match tokens.next().map(|x| x.type_) {
-        Some(TokenT::Operator(Operator::Let)) => (),
-        x => return Err(expected_found("let keyword", x)),
-    }
+    tokens.expect_operator(Operator::Let)?;
     let name = parse_typed_literal(tokens, false)?;
-    match tokens.next().map(|x| x.type_) {
-        Some(TokenT::Operator(Operator::Assign)) => {
-            let ast = parse_expression(tokens)?;
-            Ok(Type::Expression(Operator::Let, Box::new(name), Box::new(ast)).wrap(location))
-        }
-        x => Err(expected_found("assignment operator", x)),
+    if !tokens.next_if_operator(Operator::Assign) {
+        return Err(tokens.unexpected(match *name {
+            Type::Literal(_) => vec![Operator::Colon.into(), Operator::Assign.into()],
+            _ => vec![Operator::Assign.into()],
+        }));
     }
+    let ast = parse_expression(tokens)?;
+    Ok(Type::Expression(Operator::Let, Node::new(name), Node::new(ast)).wrap_parsed(location, tokens))
 }
 
 /// parse an assignment expression, e.g. `x = 1`
 pub fn parse_assignement(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Result<AST, ParseError> {
+    let _production = Production::enter("assignment", None, tokens);
     let location = locate(tokens);
     let name = parse_literal(tokens)?;
-    match tokens.next().map(|x| x.type_) {
-        Some(TokenT::Operator(Operator::Assign)) => {
-            let ast = parse_expression(tokens)?;
-            Ok(Type::Expression(Operator::Assign, Box::new(name), Box::new(ast)).wrap(location))
-        }
-        x => Err(expected_found("assignment operator", x)),
-    }
+    tokens.expect_operator(Operator::Assign)?;
+    let ast = parse_expression(tokens)?;
+    Ok(Type::Expression(Operator::Assign, Node::new(name), Node::new(ast)).wrap_parsed(location, tokens))
 }
 
 /// parse a top level module statement
 /// * `tokens` - the tokens to parse
 pub fn parse_statement(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Result<AST, ParseError> {
-    let ast = match tokens.peek().map(|x| x.type_.clone()) {
-        Some(TokenT::Operator(Operator::Let)) => parse_let(tokens)?,
-        _ => parse_expression(tokens)?,
+    let _production = Production::enter("statement", None, tokens);
+    let ast = match tokens.peek_operator() {
+        Some(Operator::Import) => parse_import(tokens)?,
+        Some(Operator::Extern) => parse_extern(tokens)?,
+        _ => parse_unterminated_statement(tokens)?,
     };
-    match tokens.next().map(|x| x.type_) {
-        Some(TokenT::Operator(Operator::Semicolon)) => Ok(ast),
-        x => Err(expected_found("semicolon", x)),
+    // the token found instead of `;` is left for the next statement, it may well start it
+    match tokens.next_if_operator(Operator::Semicolon) {
+        true => Ok(ast),
+        false => Err(tokens.unexpected(continued(vec![Operator::Semicolon.into()], &ast))),
     }
 }
 
-/// parse a curly brace delimited block
+/// parse an import of another module, e.g. `import math`
 /// * `tokens` - the tokens to parse
-pub fn parse_block(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Result<AST, ParseError> {
+pub fn parse_import(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Result<AST, ParseError> {
+    let _production = Production::enter("import", None, tokens);
     let location = locate(tokens);
-    match tokens.next().map(|x| x.type_) {
-        Some(TokenT::Operator(Operator::LCurl)) => (),
-        x => return Err(expected_found("opening curly brace", x)),
+    tokens.expect_operator(Operator::Import)?;
+    let name = tokens.expect_literal("module name", |name| !is_numeric(name) && !is_string(name))?;
+    Ok(Type::Import(name).wrap_parsed(location, tokens))
+}
+
+/// parse the declaration of a function of another library, e.g. `extern fn puts(s: string) -> i32`,
+/// into a `let` binding it to the name of its symbol, like that of a module level function
+pub fn parse_extern(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Result<AST, ParseError> {
+    let _production = Production::enter("extern", None, tokens);
+    let location = locate(tokens);
+    tokens.expect_operator(Operator::Extern)?;
+    tokens.expect_operator(Operator::Fn)?;
+    let name_location = locate(tokens);
+    let name = tokens.expect_literal("function name", |name| !is_numeric(name) && !is_string(name))?;
+    let binding = Type::Literal(name).wrap_parsed(name_location, tokens);
+    let (args, return_type) = parse_signature(tokens)?;
+    let function = Type::Extern(name, args, return_type).wrap_parsed(location, tokens);
+    Ok(Type::Expression(Operator::Let, Node::new(binding), Node::new(function)).wrap_parsed(location, tokens))
+}
+
+/// parse a statement without its terminating semicolon
+/// * `tokens` - the tokens to parse
+pub fn parse_unterminated_statement(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Result<AST, ParseError> {
+    let _production = Production::enter("unterminated_statement", None, tokens);
+    match tokens.peek_operator() {
+        Some(Operator::Let) => parse_let(tokens),
+        _ => parse_expression(tokens),
     }
+}
+
+/// parse a curly brace delimited block,
+/// the semicolon after its last statement is optional.
+/// The value of the block is the value of its last statement,
+/// an expression followed by a semicolon discards its value.
+/// * `tokens` - the tokens to parse
+pub fn parse_block(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Result<AST, ParseError> {
+    let _production = Production::enter("block", None, tokens);
+    let location = locate(tokens);
+    tokens.expect_operator(Operator::LCurl)?;
     let mut asts = Vec::new();
-    while let Some(token) = tokens.peek() {
-        match token.type_ {
-            TokenT::Operator(Operator::RCurl) => {
-                tokens.next();
-                break;
+    while !tokens.next_if_operator(Operator::RCurl) {
+        if tokens.peek().is_none() {
+            return Err(tokens.unexpected(vec![Operator::RCurl.into()]));
+        }
+        let mut statement = parse_unterminated_statement(tokens)?;
+        if tokens.next_if_operator(Operator::Semicolon) {
+            if !matches!(*statement, Type::Expression(Operator::Let, ..)) {
+                let span = statement.span;
+                statement = Type::Discard(Node::new(statement)).wrap_span(span);
             }
-            _ => asts.push(parse_statement(tokens)?),
+        } else if tokens.peek_operator() != Some(Operator::RCurl) {
+            // at the end of the input, only a `}` can close the block, not a `;`
+            let expected = match tokens.peek() {
+                Some(_) => vec![Operator::Semicolon.into(), Operator::RCurl.into()],
+                None => vec![Operator::RCurl.into()],
+            };
+            return Err(tokens.unexpected(continued(expected, &statement)));
         }
+        asts.push(statement);
     }
-    Ok(Type::Block(asts).wrap(location))
+    Ok(Type::Block(asts).wrap_parsed(location, tokens))
 }
 
 
 /////////////////////////////
 
-/// parse a lambda expression
+/// parse a lambda expression, optionally generic, e.g. `fn<T>(x: T) -> T { x }`,
+/// or with the deprecated `:` before its return type, e.g. `fn(x: int): int { x }`
 pub fn parse_function(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Result<AST, ParseError> {
+    let _production = Production::enter("function", None, tokens);
     let location = locate(tokens);
-    match tokens.next().map(|x| x.type_) {
-        Some(TokenT::Operator(Operator::Fn)) => (),
-        x => return Err(expected_found("fn keyword", x)),
+    tokens.expect_operator(Operator::Fn)?;
+    let type_params = match tokens.peek_operator() {
+        Some(Operator::Lt) => Some(parse_type_parameters(tokens)?),
+        _ => None,
+    };
+    if tokens.peek_operator() != Some(Operator::LParen) {
+        return Err(tokens.unexpected(match type_params {
+            Some(_) => vec![Operator::LParen.into()],
+            None => vec![Operator::Lt.into(), Operator::LParen.into()],
+        }));
     }
-    match tokens.next().map(|x| x.type_) {
-        Some(TokenT::Operator(Operator::LParen)) => (),
-        x => return Err(expected_found("opening parenthesis", x)),
+    let (args, typ) = parse_signature(tokens)?;
+    let block = parse_block(tokens)?;
+    let lambda = Type::Lambda(typ, args, Node::new(block)).wrap_parsed(location, tokens);
+    match type_params {
+        Some(type_params) => Ok(Type::Generic(type_params, Node::new(lambda)).wrap_parsed(location, tokens)),
+        None => Ok(lambda),
     }
+}
+
+/// parse the arguments and the return type of a function, e.g. `(x: int, y: int) -> int`
+pub fn parse_signature(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Result<(Vec<AST>, TypeName), ParseError> {
+    let _production = Production::enter("signature", None, tokens);
+    tokens.expect_operator(Operator::LParen)?;
     let mut args = Vec::new();
-    while let Some(token) = tokens.peek() {
-        match token.type_.clone() {
-            TokenT::Operator(Operator::RParen) => {
-                tokens.next();
-                break;
-            }
-            TokenT::Literal(_) => {
-                args.push(parse_typed_literal(tokens, true)?);
-                match tokens.peek().map(|x| x.type_.clone()) {
-                    Some(TokenT::Operator(Operator::Comma)) => {
-                        tokens.next();
-                    }
-                    Some(TokenT::Operator(Operator::RParen)) => (),
-                    x => return Err(expected_found("comma or closing parenthesis", x)),
-                }
-            }
-            x => return Err(expected_found("literal or closing parenthesis", Some(x))),
+    while tokens.peek().is_some() && !tokens.next_if_operator(Operator::RParen) {
+        let _production = Production::enter("argument", None, tokens);
+        if tokens.peek_literal().is_none() {
+            return Err(tokens.unexpected(vec![Expected::Kind("argument"), Operator::RParen.into()]));
+        }
+        args.push(parse_typed_literal(tokens, true)?);
+        if !tokens.next_if_operator(Operator::Comma) && tokens.peek_operator() != Some(Operator::RParen) {
+            return Err(tokens.unexpected(vec![Operator::Comma.into(), Operator::RParen.into()]));
         }
     }
-    match tokens.next().map(|x| x.type_) {
-        Some(TokenT::Operator(Operator::Colon)) => (),
-        x => return Err(expected_found("colon [type information] ", x)),
+    // `:` is the form the arrow replaces
+    match tokens.peek_operator() {
+        Some(Operator::Colon) => deprecated(Deprecation::ColonReturnType, tokens.next().unwrap())?,
+        _ => {
+            tokens.expect_operator(Operator::Arrow)?;
+        }
+    }
+    Ok((args, parse_type_name(tokens)?))
+}
+
+/// parse the type parameters of a generic function, e.g. `<T, U>`
+pub fn parse_type_parameters(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Result<Vec<Symbol>, ParseError> {
+    let _production = Production::enter("type_parameters", None, tokens);
+    tokens.expect_operator(Operator::Lt)?;
+    let mut params = Vec::new();
+    loop {
+        params.push(tokens.expect_literal("type parameter", |_| true)?);
+        if tokens.next_if_operator(Operator::Gt) {
+            return Ok(params);
+        }
+        if !tokens.next_if_operator(Operator::Comma) {
+            return Err(tokens.unexpected(vec![Operator::Comma.into(), Operator::Gt.into()]));
+        }
     }
-    let typ = match tokens.next().map(|x| x.type_) {
-        Some(TokenT::Literal(t)) => Ok(t),
-        x => return Err(expected_found("literal [type information]", x)),
-    }?;
-    let block = parse_block(tokens)?;
-    Ok(Type::Lambda(typ, args, Box::new(block)).wrap(location))
+}
+
+
+/// parse a type annotation, e.g. `int`, `[string]`, `fn(int, int) -> int`
+/// or a bare `fn` for a function of any signature
+pub fn parse_type_name(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Result<TypeName, ParseError> {
+    let _production = Production::enter("type", None, tokens);
+    if let Some(name) = tokens.peek_literal() {
+        tokens.next();
+        return Ok(TypeName::Named(name.to_string()));
+    }
+    if tokens.next_if_operator(Operator::LBracket) {
+        let element = parse_type_name(tokens)?;
+        tokens.expect_operator(Operator::RBracket)?;
+        return Ok(TypeName::Array(Box::new(element)));
+    }
+    if !tokens.next_if_operator(Operator::Fn) {
+        return Err(tokens.unexpected(vec![Expected::Kind("type")]));
+    }
+    if !tokens.next_if_operator(Operator::LParen) {
+        return Ok(TypeName::Named(Operator::Fn.as_str().to_owned()));
+    }
+    let mut args = Vec::new();
+    while tokens.peek().is_some() && tokens.peek_operator() != Some(Operator::RParen) {
+        args.push(parse_type_name(tokens)?);
+        if !tokens.next_if_operator(Operator::Comma) && tokens.peek_operator() != Some(Operator::RParen) {
+            return Err(tokens.unexpected(vec![Operator::Comma.into(), Operator::RParen.into()]));
+        }
+    }
+    tokens.expect_operator(Operator::RParen)?;
+    // `:` is the form the arrow replaces
+    match tokens.peek_operator() {
+        Some(Operator::Colon) => deprecated(Deprecation::ColonReturnType, tokens.next().unwrap())?,
+        _ => {
+            tokens.expect_operator(Operator::Arrow)?;
+        }
+    }
+    Ok(TypeName::Function(args, Box::new(parse_type_name(tokens)?)))
 }
 
 
@@ -374,21 +846,461 @@ impl DerefMut for AST {
 }
 
 
-// PRIVATE HELPER FUNCTIONS
+/// Prints the AST back as source text, in a canonical layout: a statement per line,
+/// blocks indented by four spaces unless they hold a single line, and only the parentheses
+/// the precedence of the operators requires. Parsing the text gives back the same tree, spans aside.
+pub fn to_source(ast: &AST) -> String {
+    match &**ast {
+        Type::Module(statements) => module_source(statements),
+        _ => source(ast, 0),
+    }
+}
 
-fn expected_found<T>(expected: &str, found: Option<T>) -> ParseError
-where T: fmt::Debug,
-{
-    match found {
-        Some(found) => ParseError {
-            message: format!("Expected {}, found {:?}", expected, found),
+/// Prints a module back as source text in the layout of `to_source`, along with its comments: those on lines
+/// of their own before the statement they precede, at its indentation, and those following a statement on the line
+/// it ends on after it. Comments inside a statement, but not inside one of its blocks, go before it.
+/// Blank lines between statements are kept, several in a row as one.
+/// * `comments` - the comments of the module, see `Tokenizer::keep_comments`
+#[cfg(feature = "fmt")]
+pub fn to_formatted_source(module: &AST, comments: Vec<Comment>) -> String {
+    let comments = comments.into_iter().map(|comment| (comment.span.start.offset, comment)).collect();
+    COMMENTS.with(|kept| kept.replace(Some(comments)));
+    let source = to_source(module);
+    COMMENTS.with(|kept| kept.take());
+    source
+}
+
+/// Takes the comments of the module being formatted which start in `range`, and on `line` if given, in order
+fn take_comments(range: Range<usize>, line: Option<usize>) -> Vec<Comment> {
+    COMMENTS.with(|kept| match &mut *kept.borrow_mut() {
+        Some(comments) if range.start <= range.end => {
+            let taken = comments.range(range)
+                .filter(|(_, comment)| line.is_none_or(|line| comment.span.start.line == line))
+                .map(|(offset, _)| *offset)
+                .collect::<Vec<_>>();
+            taken.iter().filter_map(|offset| comments.remove(offset)).collect()
+        }
+        _ => Vec::new(),
+    })
+}
+
+/// A line of a block or a module: a statement, or a comment on a line of its own
+struct Line {
+    text: String,
+    /// Whether a blank line separates it from the line before
+    blank: bool,
+    /// Whether it ends with a comment, which nothing can follow on the same line
+    comment: bool,
+}
+
+/// Prints the statements of a block or a module, and the comments between them when formatting, see `to_formatted_source`
+/// * `range` - the offsets of the source inside the block or the module
+/// * `terminated` - whether the statement at an index is followed by `;`
+fn lines(statements: &[AST], range: Range<usize>, indent: usize, terminated: impl Fn(usize) -> bool) -> Vec<Line> {
+    let mut lines = Vec::new();
+    // the line of the source the last line printed ends on, for the blank lines after it
+    let mut last = None;
+    let mut push = |text: String, comment: bool, start: usize, end: usize| {
+        let blank = last.is_some_and(|last| start > last + 1);
+        lines.push(Line { text, blank, comment });
+        last = Some(end);
+    };
+    let mut cursor = range.start;
+    for (i, statement) in statements.iter().enumerate() {
+        let span = statement.span();
+        for comment in take_comments(cursor..span.start.offset, None) {
+            push(comment.text, true, comment.span.start.line, comment.span.end.line);
+        }
+        let mut text = source(statement, indent);
+        if terminated(i) {
+            text.push(';');
+        }
+        // those its blocks didn't take, e.g. between the arguments of a call
+        for comment in take_comments(span.start.offset..span.end.offset, None) {
+            push(comment.text, true, span.start.line, span.start.line);
+        }
+        let next = statements.get(i + 1).map_or(range.end, |next| next.span().start.offset);
+        let trailing = take_comments(span.end.offset..next, Some(span.end.line));
+        let end = trailing.last().map_or(span.end.line, |comment| comment.span.end.line);
+        for comment in &trailing {
+            text.push(' ');
+            text.push_str(&comment.text);
+        }
+        push(text, !trailing.is_empty(), span.start.line, end);
+        cursor = span.end.offset;
+    }
+    for comment in take_comments(cursor..range.end, None) {
+        push(comment.text, true, comment.span.start.line, comment.span.end.line);
+    }
+    // the layout of the source is only kept when formatting
+    if COMMENTS.with(|kept| kept.borrow().is_none()) {
+        lines.iter_mut().for_each(|line| line.blank = false);
+    }
+    lines
+}
+
+/// Prints the statements of a module, each terminated, on lines of their own
+fn module_source(statements: &[AST]) -> String {
+    lines(statements, 0..usize::MAX, 0, |_| true).into_iter()
+        .map(|line| format!("{}{}\n", if line.blank { "\n" } else { "" }, line.text))
+        .collect()
+}
+
+/// Whether the left operand of a subtraction is the zero the parser puts before a negated atom, e.g. `-x`,
+/// which spans no source, unlike a zero written out
+fn is_negation(zero: &AST) -> bool {
+    matches!(**zero, Type::Literal(literal) if literal.as_str() == "0") && zero.span.start == zero.span.end
+}
+
+/// How tightly a node binds, to know when it needs parentheses as an operand:
+/// 0 for the nodes only an `expression` may be, e.g. blocks, then one more per level of `PRECEDENCE`,
+/// then casts, then atoms
+fn tightness(ast: &AST) -> usize {
+    match &**ast {
+        Type::Expression(Operator::Sub, zero, _) if is_negation(zero) => PRECEDENCE.len() + 2,
+        Type::Expression(operator, ..) => match binary_operator_level(*operator) {
+            Some(level) => level + 1,
+            None => 0,
         },
-        None => ParseError {
-            message: format!("Expected {}, found end of input", expected),
+        Type::Cast(..) => PRECEDENCE.len() + 1,
+        Type::Literal(_) | Type::Identifier(_) | Type::Call(..) | Type::Interpolation(_) => PRECEDENCE.len() + 2,
+        Type::Truncate(_, value) | Type::Float(value) => tightness(value),
+        _ => 0,
+    }
+}
+
+/// Prints `ast` as an operand, in parentheses if it binds looser than `tightness`
+fn operand(ast: &AST, tightness_needed: usize, indent: usize) -> String {
+    match tightness(ast) < tightness_needed {
+        true => format!("({})", source(ast, indent)),
+        false => source(ast, indent),
+    }
+}
+
+/// Prints `ast` as source text
+/// * `indent` - the nesting depth of the blocks around it, for the lines it spans
+fn source(ast: &AST, indent: usize) -> String {
+    match &**ast {
+        Type::Literal(literal) | Type::Identifier(literal) => literal.to_string(),
+        Type::TypedLiteral(name, type_name) => format!("{}: {}", name, type_name),
+        Type::Import(name) => format!("{} {}", Operator::Import.as_str(), name),
+        // the name is that of the symbol
+        Type::Expression(Operator::Let, _, value) if matches!(***value, Type::Extern(..)) => source(value, indent),
+        Type::Extern(name, args, return_type) => {
+            let args = args.iter().map(|arg| source(arg, indent)).collect::<Vec<_>>().join(", ");
+            format!("{} {} {}({}) {} {}", Operator::Extern.as_str(), Operator::Fn.as_str(), name, args, Operator::Arrow.as_str(), return_type)
+        }
+        Type::Expression(Operator::Let, name, value) => format!("{} {} = {}", Operator::Let.as_str(), source(name, indent), source(value, indent)),
+        Type::Expression(Operator::Sub, zero, value) if is_negation(zero) => format!("{}{}", Operator::Sub.as_str(), operand(value, PRECEDENCE.len() + 2, indent)),
+        Type::Expression(operator, lhs, rhs) => match binary_operator_level(*operator) {
+            Some(level) => {
+                let (lhs_tightness, rhs_tightness) = match PRECEDENCE[level].associativity {
+                    Associativity::Left => (level + 1, level + 2),
+                    Associativity::Right => (level + 2, level + 1),
+                };
+                format!("{} {} {}", operand(lhs, lhs_tightness, indent), operator.as_str(), operand(rhs, rhs_tightness, indent))
+            }
+            None => format!("{} {} {}", source(lhs, indent), operator.as_str(), source(rhs, indent)),
         },
+        Type::Lambda(..) => lambda_source(ast, &[], indent),
+        Type::Generic(params, lambda) => lambda_source(lambda, params, indent),
+        Type::Interpolation(parts) => {
+            let parts = parts.iter()
+                .map(|part| match &**part {
+                    Type::Literal(text) if is_string(text) => text[1..text.len() - 1].to_owned(),
+                    _ => format!("{{{}}}", source(part, indent)),
+                })
+                .collect::<String>();
+            format!("\"{}\"", parts)
+        }
+        Type::Call(callee, args) => {
+            let callee = match &***callee {
+                Type::Identifier(_) | Type::Call(..) => source(callee, indent),
+                _ => format!("({})", source(callee, indent)),
+            };
+            let args = args.iter().map(|arg| source(arg, indent)).collect::<Vec<_>>().join(", ");
+            format!("{}({})", callee, args)
+        }
+        Type::Discard(value) | Type::Truncate(_, value) | Type::Float(value) => source(value, indent),
+        Type::Cast(value, type_name, _) => format!("{} {} {}", operand(value, PRECEDENCE.len() + 1, indent), Operator::As.as_str(), type_name),
+        Type::Block(statements) => block_source(statements, ast.span(), indent),
+        Type::Module(statements) => module_source(statements),
+    }
+}
+
+/// Prints a lambda, along with its type parameters if it's generic
+fn lambda_source(lambda: &AST, params: &[Symbol], indent: usize) -> String {
+    let (return_type, args, body) = match &**lambda {
+        Type::Lambda(return_type, args, body) => (return_type, args, body),
+        _ => return source(lambda, indent),
+    };
+    let params = match params.is_empty() {
+        true => String::new(),
+        false => format!("<{}>", params.iter().map(|param| param.as_str()).collect::<Vec<_>>().join(", ")),
+    };
+    let args = args.iter().map(|arg| source(arg, indent)).collect::<Vec<_>>().join(", ");
+    // the body of a lambda is always a block
+    let body = match &***body {
+        Type::Block(statements) => block_source(statements, body.span(), indent),
+        _ => block_source(std::slice::from_ref(body), body.span(), indent),
+    };
+    format!("{}{}({}) {} {} {}", Operator::Fn.as_str(), params, args, Operator::Arrow.as_str(), return_type, body)
+}
+
+/// Prints the statements of a block, terminating those whose value is discarded
+/// * `span` - the span of the block, braces included
+fn block_source(statements: &[AST], span: Span, indent: usize) -> String {
+    let terminated = |i: usize| i + 1 < statements.len() || matches!(*statements[i], Type::Discard(_) | Type::Expression(Operator::Let, ..));
+    let lines = lines(statements, span.start.offset + 1..span.end.offset.saturating_sub(1), indent + 1, terminated);
+    match lines.as_slice() {
+        [] => "{}".to_owned(),
+        [line] if !line.comment && !line.text.contains('\n') => format!("{{ {} }}", line.text),
+        _ => {
+            let mut block = String::from("{\n");
+            for line in lines {
+                if line.blank {
+                    block.push('\n');
+                }
+                block.push_str(&"    ".repeat(indent + 1));
+                block.push_str(&line.text);
+                block.push('\n');
+            }
+            block.push_str(&"    ".repeat(indent));
+            block.push('}');
+            block
+        }
     }
 }
 
+/// A pass reading the AST. The default methods walk every node and type annotation in source order,
+/// override `visit` to act on nodes and call `walk` from it to carry on into their children.
+pub trait Visitor {
+    fn visit(&mut self, ast: &AST) {
+        walk(self, ast)
+    }
+    /// Visits a type annotation, of a typed literal, of the return type of a lambda or of a cast
+    fn visit_type_name(&mut self, _type_name: &TypeName) {}
+}
+
+/// Visits the children of `ast` and its type annotations, in source order
+pub fn walk<V: Visitor + ?Sized>(visitor: &mut V, ast: &AST) {
+    match &**ast {
+        Type::Literal(_) | Type::Identifier(_) | Type::Import(_) => (),
+        Type::TypedLiteral(_, type_name) => visitor.visit_type_name(type_name),
+        Type::Expression(_, lhs, rhs) => {
+            visitor.visit(lhs);
+            visitor.visit(rhs);
+        }
+        Type::Lambda(return_type, args, body) => {
+            args.iter().for_each(|arg| visitor.visit(arg));
+            visitor.visit_type_name(return_type);
+            visitor.visit(body);
+        }
+        Type::Extern(_, args, return_type) => {
+            args.iter().for_each(|arg| visitor.visit(arg));
+            visitor.visit_type_name(return_type);
+        }
+        Type::Call(callee, args) => {
+            visitor.visit(callee);
+            args.iter().for_each(|arg| visitor.visit(arg));
+        }
+        Type::Interpolation(parts) => parts.iter().for_each(|part| visitor.visit(part)),
+        Type::Generic(_, inner) | Type::Discard(inner) | Type::Truncate(_, inner) | Type::Float(inner) => visitor.visit(inner),
+        Type::Cast(value, type_name, _) => {
+            visitor.visit(value);
+            visitor.visit_type_name(type_name);
+        }
+        Type::Block(statements) | Type::Module(statements) => statements.iter().for_each(|statement| visitor.visit(statement)),
+    }
+}
+
+/// A pass rewriting the AST in place, see `Visitor`
+pub trait VisitorMut {
+    fn visit_mut(&mut self, ast: &mut AST) {
+        walk_mut(self, ast)
+    }
+    fn visit_type_name_mut(&mut self, _type_name: &mut TypeName) {}
+}
+
+/// Like `walk`, for `VisitorMut`
+pub fn walk_mut<V: VisitorMut + ?Sized>(visitor: &mut V, ast: &mut AST) {
+    match &mut **ast {
+        Type::Literal(_) | Type::Identifier(_) | Type::Import(_) => (),
+        Type::TypedLiteral(_, type_name) => visitor.visit_type_name_mut(type_name),
+        Type::Expression(_, lhs, rhs) => {
+            visitor.visit_mut(lhs);
+            visitor.visit_mut(rhs);
+        }
+        Type::Lambda(return_type, args, body) => {
+            args.iter_mut().for_each(|arg| visitor.visit_mut(arg));
+            visitor.visit_type_name_mut(return_type);
+            visitor.visit_mut(body);
+        }
+        Type::Extern(_, args, return_type) => {
+            args.iter_mut().for_each(|arg| visitor.visit_mut(arg));
+            visitor.visit_type_name_mut(return_type);
+        }
+        Type::Call(callee, args) => {
+            visitor.visit_mut(callee);
+            args.iter_mut().for_each(|arg| visitor.visit_mut(arg));
+        }
+        Type::Interpolation(parts) => parts.iter_mut().for_each(|part| visitor.visit_mut(part)),
+        Type::Generic(_, inner) | Type::Discard(inner) | Type::Truncate(_, inner) | Type::Float(inner) => visitor.visit_mut(inner),
+        Type::Cast(value, type_name, _) => {
+            visitor.visit_mut(value);
+            visitor.visit_type_name_mut(type_name);
+        }
+        Type::Block(statements) | Type::Module(statements) => statements.iter_mut().for_each(|statement| visitor.visit_mut(statement)),
+    }
+}
+
+// PRIVATE HELPER FUNCTIONS
+
+/// The target of the parser trace, see `--explain-parse`
+pub const TRACE_TARGET: &str = "moolang::parse";
+
+const START: Location = Location { line: 0, column: 0, offset: 0, file: FileId::NONE };
+
+thread_local! {
+    /// The number of productions being parsed, to indent the trace
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+    /// Where the last two tokens pulled from the tokenizer end, the last one last
+    static TAKEN: Cell<(Location, Location)> = const { Cell::new((START, START)) };
+    /// The warnings of the module being parsed, see `take_warnings`
+    static WARNINGS: RefCell<Vec<LocalizedError>> = const { RefCell::new(Vec::new()) };
+    /// The comments of the module being formatted left to print, by offset, see `to_formatted_source`
+    static COMMENTS: RefCell<Option<BTreeMap<usize, Comment>>> = const { RefCell::new(None) };
+}
+
+/// Returns the warnings of the last module parsed, e.g. about deprecated constructs
+pub fn take_warnings() -> Vec<LocalizedError> {
+    WARNINGS.with(|warnings| warnings.take())
+}
+
+/// The rewrite of a deprecated construct into what replaces it, which means the same
+/// * `found` - the token starting the construct
+fn migration(deprecation: Deprecation, found: &Token) -> Suggestion {
+    match deprecation {
+        Deprecation::UnaryPlus => Suggestion::remove(found.span(), "+", Applicability::MachineApplicable),
+        Deprecation::ColonReturnType => {
+            let mut migration = Suggestion::replace(found.span(), ":", "->", Applicability::MachineApplicable);
+            // the arrow is spaced from the `)` before it, which the colon often isn't, e.g. `fn(): int`
+            let (before, taken) = TAKEN.with(Cell::get);
+            if taken == found.span().end && before.offset == found.location.offset {
+                migration.replacement.insert(0, ' ');
+            }
+            migration
+        }
+    }
+}
+
+/// Accepts the use of a deprecated construct with a warning,
+/// or rejects it if it was removed in the current edition
+/// * `found` - the token starting the construct
+fn deprecated(deprecation: Deprecation, found: Token) -> Result<(), ParseError> {
+    if deprecation.is_removed() {
+        return Err(ParseError::Removed { deprecation, found });
+    }
+    if !deprecation.is_deprecated() {
+        return Ok(());
+    }
+    let warning = Warning::new(Lint::Deprecated, deprecation.warning()).with_span(found.span())
+        .with_suggestion(migration(deprecation, &found));
+    WARNINGS.with(|warnings| warnings.borrow_mut().push(warning));
+    Ok(())
+}
+
+/// Traces a production of the grammar: entering it is logged along with the current token,
+/// leaving it once the guard is dropped
+struct Production {
+    name: &'static str,
+    level: Option<usize>,
+}
+
+impl Production {
+    /// * `name` - the name of the production, as in `GRAMMAR`
+    /// * `level` - the precedence level, for binary operator productions
+    fn enter(name: &'static str, level: Option<usize>, tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Self {
+        let production = Production { name, level };
+        let depth = DEPTH.with(|depth| depth.replace(depth.get() + 1));
+        tracing::trace!(target: TRACE_TARGET, "{:indent$}> {} at {}", "", production, match tokens.peek() {
+            Some(token) => format!("{} `{}` {}", token.type_.kind(), token.type_.text(), token.location),
+            None => "end of input".to_owned(),
+        }, indent = depth * 2);
+        production
+    }
+}
+
+impl Drop for Production {
+    fn drop(&mut self) {
+        let depth = DEPTH.with(|depth| {
+            depth.set(depth.get() - 1);
+            depth.get()
+        });
+        tracing::trace!(target: TRACE_TARGET, "{:indent$}< {}", "", self, indent = depth * 2);
+    }
+}
+
+impl fmt::Display for Production {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.level {
+            Some(level) => write!(f, "{}_{}", self.name, level),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// The binary operator of the token, with the index of its level in `PRECEDENCE`
+fn binary_operator(token: &Token) -> Option<(Operator, usize)> {
+    match token.type_ {
+        TokenT::Operator(operator) => binary_operator_level(operator).map(|level| (operator, level)),
+        _ => None,
+    }
+}
+
+/// The index in `PRECEDENCE` of the level of `operator`, if it's a binary operator
+fn binary_operator_level(operator: Operator) -> Option<usize> {
+    PRECEDENCE.iter().position(|level| level.operators.contains(&operator))
+}
+
+/// The error for finding `found` instead of one of `expected`, `None` at the end of input
+pub fn expected_found(expected: Vec<Expected>, found: Option<Token>) -> ParseError {
+    match found {
+        Some(found) => ParseError::UnexpectedToken { expected, found },
+        None => ParseError::UnexpectedEof { expected },
+    }
+}
+
+/// Adds the tokens which could have continued the expression `ast` to `expected`,
+/// e.g. a binary operator or the `(` of a call after `f`
+fn continued(mut expected: Vec<Expected>, ast: &AST) -> Vec<Expected> {
+    let binary = PRECEDENCE.iter()
+        .flat_map(|level| level.operators)
+        .map(|operator| Expected::from(*operator));
+    let continuations = match &**ast {
+        Type::Expression(Operator::Let, _, value) => return continued(expected, value),
+        Type::Expression(_, _, rhs) => return continued(expected, rhs),
+        Type::Identifier(_) => [Operator::Dot.into(), Operator::LParen.into(), Operator::As.into()].into_iter().chain(binary).collect(),
+        Type::Call(..) => [Operator::LParen.into(), Operator::As.into()].into_iter().chain(binary).collect(),
+        Type::Literal(_) | Type::Interpolation(_) | Type::Cast(..) => once(Operator::As.into()).chain(binary).collect(),
+        _ => vec![],
+    };
+    for continuation in continuations {
+        if !expected.contains(&continuation) {
+            expected.push(continuation);
+        }
+    }
+    expected
+}
+
+fn is_numeric(x: &str) -> bool {
+    x.chars().all(|x| x.is_ascii_digit() || x == '_') || split_numeric(x).is_some()
+}
+
+fn is_string(x: &str) -> bool {
+    x.starts_with('"') && x.ends_with('"')
+}
+
 fn locate(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Location {
     tokens.peek().map(|x| x.location).unwrap_or_default()
 }
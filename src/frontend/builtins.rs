@@ -0,0 +1,181 @@
+// The functions built into the language, which every program can call without defining or importing them,
+// e.g. `println`. Any binding of the same name shadows a builtin, like a binding of an enclosing scope.
+// Calls to builtins are lowered to `ir::Inst::Builtin`, which every backend implements on its own.
+// The intrinsics, named with two leading underscores, are the builtins the standard library is written with,
+// e.g. `__sqrt` for `math.sqrt`, and aren't meant to be called by programs directly.
+
+use std::io::{BufRead, Write};
+
+use crate::frontend::symbol::Symbol;
+use crate::messages::{message, Message};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Builtin {
+    /// Writes its argument to the standard output
+    Print,
+    /// Writes its argument to the standard output, followed by a line break
+    Println,
+    /// Reads the next integer of the standard input, see `read_int`
+    ReadInt,
+    /// Reads the next line of the standard input, see `read_line`
+    ReadLine,
+    /// The square root of a float
+    Sqrt,
+    /// The sine of a float, in radians
+    Sin,
+    /// The cosine of a float, in radians
+    Cos,
+    /// The largest integer float at most a float
+    Floor,
+    /// The absolute value of a float
+    Abs,
+    /// The smaller of two floats, the other one if one is NaN
+    Min,
+    /// The larger of two floats, the other one if one is NaN
+    Max,
+    /// The number of elements of an array
+    Len,
+    /// The element of an array at an index, the first being at 0, see `index`
+    At,
+}
+
+impl Builtin {
+    pub const ALL: &'static [Builtin] = &[
+        Builtin::Print,
+        Builtin::Println,
+        Builtin::ReadInt,
+        Builtin::ReadLine,
+        Builtin::Sqrt,
+        Builtin::Sin,
+        Builtin::Cos,
+        Builtin::Floor,
+        Builtin::Abs,
+        Builtin::Min,
+        Builtin::Max,
+        Builtin::Len,
+        Builtin::At,
+    ];
+
+    /// The builtin called `name`, if any
+    pub fn from_name(name: Symbol) -> Option<Builtin> {
+        Builtin::ALL.iter().copied().find(|builtin| name == builtin.name())
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::Print => "print",
+            Builtin::Println => "println",
+            Builtin::ReadInt => "read_int",
+            Builtin::ReadLine => "read_line",
+            Builtin::Sqrt => "__sqrt",
+            Builtin::Sin => "__sin",
+            Builtin::Cos => "__cos",
+            Builtin::Floor => "__floor",
+            Builtin::Abs => "__abs",
+            Builtin::Min => "__min",
+            Builtin::Max => "__max",
+            Builtin::Len => "len",
+            Builtin::At => "at",
+        }
+    }
+
+    /// The number of arguments the builtin takes
+    pub fn arity(self) -> usize {
+        match self {
+            Builtin::ReadInt | Builtin::ReadLine => 0,
+            Builtin::Min | Builtin::Max | Builtin::At => 2,
+            _ => 1,
+        }
+    }
+
+    /// Whether the builtin is a function of floats, kept as their bits, to a float, see `ir::math`
+    pub fn is_math(self) -> bool {
+        !matches!(self, Builtin::Print | Builtin::Println | Builtin::ReadInt | Builtin::ReadLine | Builtin::Len | Builtin::At)
+    }
+
+    /// The function compiled code calls for the builtin: of the runtime,
+    /// or of the C library for the math ones the JIT doesn't have an instruction for
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Builtin::Print => "__moo_print",
+            Builtin::Println => "__moo_println",
+            Builtin::ReadInt => "__moo_read_int",
+            Builtin::ReadLine => "__moo_read_line",
+            Builtin::Sqrt => "sqrt",
+            Builtin::Sin => "sin",
+            Builtin::Cos => "cos",
+            Builtin::Floor => "floor",
+            Builtin::Abs => "fabs",
+            Builtin::Min => "fmin",
+            Builtin::Max => "fmax",
+            Builtin::Len => "__moo_len",
+            Builtin::At => "__moo_at",
+        }
+    }
+}
+
+/// Writes the output of `print` or `println` for the backends running programs in the process of the compiler.
+/// The output is flushed, so that it comes before the errors reported if the program then traps
+pub fn print(text: &str, newline: bool) {
+    let mut stdout = std::io::stdout().lock();
+    let written = match newline {
+        true => writeln!(stdout, "{}", text),
+        false => write!(stdout, "{}", text),
+    };
+    // like `print!`, the program doesn't go on without its output
+    written.and_then(|_| stdout.flush()).expect("failed writing to stdout");
+}
+
+/// Reads the next integer of the standard input for `read_int`, skipping the whitespace before it,
+/// or returns the message of the error reported if the input ends first, or has something else next.
+/// The rest of the line is left for the next read
+pub fn read_int() -> Result<i64, Message> {
+    let mut stdin = std::io::stdin().lock();
+    let mut token = Vec::new();
+    loop {
+        let buffer = stdin.fill_buf().expect("failed reading from stdin");
+        if buffer.is_empty() {
+            break;
+        }
+        let skipped = match token.is_empty() {
+            true => buffer.iter().take_while(|byte| byte.is_ascii_whitespace()).count(),
+            false => 0,
+        };
+        let length = buffer[skipped..].iter().take_while(|byte| !byte.is_ascii_whitespace()).count();
+        token.extend_from_slice(&buffer[skipped..skipped + length]);
+        // the token ends before the buffer does
+        let ended = skipped + length < buffer.len();
+        stdin.consume(skipped + length);
+        if ended {
+            break;
+        }
+    }
+    if token.is_empty() {
+        return Err(message!("E0425"));
+    }
+    let token = String::from_utf8_lossy(&token);
+    token.parse().map_err(|_| message!("E0424", found = token))
+}
+
+/// Reads the next line of the standard input for `read_line`, without its line break,
+/// or an empty string once the input has ended
+pub fn read_line() -> String {
+    let mut line = String::new();
+    std::io::stdin().lock().read_line(&mut line).expect("failed reading from stdin");
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+    line
+}
+
+/// Checks an index of `at` against the length of the array, returning it as an offset into the elements
+/// or the message of the error reported if it is out of bounds
+pub fn index(index: i64, len: usize) -> Result<usize, Message> {
+    match usize::try_from(index) {
+        Ok(offset) if offset < len => Ok(offset),
+        _ => Err(message!("E0426", index = index, len = len)),
+    }
+}
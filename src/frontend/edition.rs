@@ -0,0 +1,98 @@
+use core::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::messages::{message, Message};
+
+/// A version of the language. Editions let the language drop constructs without breaking
+/// the programs written for an older edition, which keep compiling with warnings.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Edition {
+    /// The language as first released
+    #[default]
+    #[value(name = "2023")]
+    E2023,
+    #[value(name = "2024")]
+    E2024,
+}
+
+impl Edition {
+    pub const LATEST: Edition = Edition::E2024;
+}
+
+impl fmt::Display for Edition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Edition::E2023 => write!(f, "2023"),
+            Edition::E2024 => write!(f, "2024"),
+        }
+    }
+}
+
+static EDITION: AtomicU8 = AtomicU8::new(Edition::E2023 as u8);
+
+/// Sets the edition the programs are compiled in
+pub fn set(edition: Edition) {
+    EDITION.store(edition as u8, Ordering::Relaxed);
+}
+
+/// The edition the programs are compiled in, the first one unless set otherwise
+pub fn current() -> Edition {
+    match EDITION.load(Ordering::Relaxed) {
+        0 => Edition::E2023,
+        _ => Edition::E2024,
+    }
+}
+
+/// A construct on its way out of the language: accepted with a warning
+/// from the edition it's deprecated in, an error from the one it's removed in, if any
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Deprecation {
+    /// `+x`, which is just `x`
+    UnaryPlus,
+    /// `fn(x: int): int` or `fn(int): int`, with a `:` before the return type instead of `->`
+    ColonReturnType,
+}
+
+impl Deprecation {
+    /// The first edition warning about the construct
+    pub fn deprecated_in(self) -> Edition {
+        match self {
+            Deprecation::UnaryPlus => Edition::E2023,
+            Deprecation::ColonReturnType => Edition::E2024,
+        }
+    }
+
+    /// The first edition rejecting the construct, `None` while it's only deprecated
+    pub fn removed_in(self) -> Option<Edition> {
+        match self {
+            Deprecation::UnaryPlus => Some(Edition::E2024),
+            Deprecation::ColonReturnType => None,
+        }
+    }
+
+    /// Whether the construct is warned about in the current edition
+    pub fn is_deprecated(self) -> bool {
+        current() >= self.deprecated_in()
+    }
+
+    /// Whether the construct is rejected in the current edition
+    pub fn is_removed(self) -> bool {
+        self.removed_in().is_some_and(|edition| current() >= edition)
+    }
+
+    /// The warning for a use of the construct, while it's deprecated
+    pub fn warning(self) -> Message {
+        match self {
+            Deprecation::UnaryPlus => message!("W0001", edition = Edition::E2024),
+            Deprecation::ColonReturnType => message!("W0002"),
+        }
+    }
+
+    /// The error for a use of the construct, once it's removed
+    pub fn error(self) -> Message {
+        match self {
+            Deprecation::UnaryPlus => message!("E0006", edition = Edition::E2024),
+            Deprecation::ColonReturnType => unreachable!("`:` before a return type isn't removed in any edition"),
+        }
+    }
+}
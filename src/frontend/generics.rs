@@ -0,0 +1,660 @@
+use core::fmt;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+use crate::frontend::arena::Node;
+use crate::frontend::ast::{AST, Type, TypeName, VisitorMut};
+use crate::frontend::builtins::Builtin;
+use crate::frontend::types::{lambda_type, render_diff, Aliases, IntTy, Ty};
+use crate::frontend::resolve::binding_name;
+use crate::frontend::symbol::Symbol;
+use crate::frontend::tokenizer::{is_float, split_numeric, Operator, Span};
+use crate::errors::{Coded, LocalizableError, LocalizedError};
+use crate::messages::{message, Message};
+
+/// Upper bound on the number of instances, which guards against
+/// generic functions instantiating themselves with ever growing types
+const MAX_INSTANCES: usize = 1024;
+
+#[derive(Debug)]
+pub struct GenericsError {
+    message: Message,
+}
+
+impl fmt::Display for GenericsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "GenericsError: {}", self.message)
+    }
+}
+
+impl Error for GenericsError {}
+
+impl Coded for GenericsError {
+    fn code(&self) -> Option<&'static str> {
+        self.message.code()
+    }
+}
+
+/// A module level generic function, instantiated on demand
+struct Template {
+    params: Vec<String>,
+    lambda: AST,
+}
+
+/// A requested instance of a template
+struct Instance {
+    template: Symbol,
+    name: Symbol,
+    bindings: HashMap<String, Ty>,
+}
+
+/// Monomorphization: replaces every module level generic function with one
+/// instance per combination of types it is called with, e.g. `id<int>`,
+/// and points every call to the matching instance.
+/// Generic functions which are never called are dropped.
+///
+/// Type parameters are constraint-free: the body of a generic function
+/// can pass values of generic type around, but not compute with them.
+/// * `module` - the resolved module
+pub fn monomorphize(module: AST) -> Result<AST, LocalizedError> {
+    monomorphize_with_types(module).map(|(module, _)| module)
+}
+
+/// Like `monomorphize`, also returning the types of the module level bindings
+/// which could be inferred
+pub fn monomorphize_with_types(module: AST) -> Result<(AST, HashMap<Symbol, Ty>), LocalizedError> {
+    let location = *module.location();
+    let statements = match module.type_() {
+        Type::Module(statements) => statements,
+        _ => unreachable!("monomorphize expects a module"),
+    };
+
+    let aliases = Aliases::default();
+    let mut templates = HashMap::new();
+    let mut globals = HashMap::new();
+    let mut externs = HashSet::new();
+    let mut concrete = Vec::new();
+    for statement in statements {
+        if let Type::Expression(Operator::Let, name, value) = &*statement {
+            let name = binding_name(name).expect("resolved binding");
+            match &***value {
+                Type::Generic(params, lambda) => {
+                    templates.insert(name, Template { params: params.iter().map(Symbol::to_string).collect(), lambda: (**lambda).clone() });
+                    continue;
+                }
+                Type::Lambda(..) => {
+                    globals.insert(name, lambda_type(value, &aliases));
+                }
+                Type::Extern(..) => {
+                    globals.insert(name, lambda_type(value, &aliases));
+                    externs.insert(name);
+                }
+                _ => (),
+            }
+        }
+        concrete.push(statement);
+    }
+
+    let mut checker = Checker {
+        aliases,
+        templates,
+        globals,
+        externs,
+        type_params: HashSet::new(),
+        scopes: Vec::new(),
+        queue: Vec::new(),
+        instantiated: HashSet::new(),
+    };
+
+    // check the templates themselves, with their type parameters left abstract
+    for template in checker.templates.values() {
+        let mut checker = Checker {
+            aliases: checker.aliases.clone(),
+            templates: HashMap::new(),
+            globals: checker.globals.clone(),
+            externs: checker.externs.clone(),
+            type_params: template.params.iter().cloned().collect(),
+            scopes: Vec::new(),
+            queue: Vec::new(),
+            instantiated: HashSet::new(),
+        };
+        checker.check(&mut template.lambda.clone())?;
+    }
+
+    for statement in concrete.iter_mut() {
+        checker.check(statement)?;
+    }
+
+    // instances can call generic functions in turn
+    while let Some(instance) = checker.queue.pop() {
+        if checker.instantiated.len() > MAX_INSTANCES {
+            return Err(GenericsError {
+                message: message!("E0301", instance = instance.name),
+            }.with_span(checker.templates[&instance.template].lambda.span()));
+        }
+        let mut lambda = checker.templates[&instance.template].lambda.clone();
+        substitute(&mut lambda, &instance.bindings, &checker.aliases);
+        checker.check(&mut lambda)?;
+        let lambda_span = lambda.span();
+        concrete.push(Type::Expression(
+            Operator::Let,
+            Node::new(Type::Literal(instance.name).wrap(*lambda.location())),
+            Node::new(lambda),
+        ).wrap_span(lambda_span));
+    }
+
+    Ok((Type::Module(concrete).wrap(location), checker.globals))
+}
+
+/// Infers the types of expressions as far as needed for generic functions:
+/// values whose type can't be inferred are `None`.
+struct Checker {
+    aliases: Aliases,
+    templates: HashMap<Symbol, Template>,
+    /// Types of the module level functions
+    globals: HashMap<Symbol, Ty>,
+    /// The module level functions which are extern, see `Type::Extern`
+    externs: HashSet<Symbol>,
+    /// Type parameters of the template being checked, if any
+    type_params: HashSet<String>,
+    scopes: Vec<HashMap<Symbol, Option<Ty>>>,
+    queue: Vec<Instance>,
+    instantiated: HashSet<Symbol>,
+}
+
+impl Checker {
+    fn check(&mut self, ast: &mut AST) -> Result<Option<Ty>, LocalizedError> {
+        let span = ast.span();
+        match &mut **ast {
+            Type::Literal(literal) if literal.starts_with('"') => Ok(Some(Ty::String)),
+            Type::Literal(literal) if is_float(literal) => Ok(Some(Ty::Float)),
+            Type::Literal(literal) => match split_numeric(literal) {
+                Some((digits, Some(suffix))) => match IntTy::from_name(suffix) {
+                    Some(int) => {
+                        if digits.replace('_', "").parse().is_ok_and(|value| !int.contains(value)) {
+                            return Err(GenericsError {
+                                message: message!("E0303", literal = literal, ty = int.name()),
+                            }.with_span(span));
+                        }
+                        Ok(Some(Ty::Sized(int)))
+                    }
+                    // `i64`, the width of `int`
+                    None => Ok(Some(Ty::Int)),
+                },
+                _ => Ok(Some(Ty::Int)),
+            },
+            Type::TypedLiteral(_, type_name) => Ok(Some(self.aliases.normalize(type_name))),
+            Type::Identifier(name) => {
+                if let Some(type_name) = self.lookup(*name) {
+                    return Ok(type_name);
+                }
+                if self.templates.contains_key(name) {
+                    return Err(GenericsError {
+                        message: message!("E0302", name = name),
+                    }.with_span(span));
+                }
+                if self.builtin(*name).is_some() {
+                    return Err(GenericsError {
+                        message: message!("E0320", name = name),
+                    }.with_span(span));
+                }
+                if self.externs.contains(name) {
+                    return Err(GenericsError {
+                        message: message!("E0322", name = name),
+                    }.with_span(span));
+                }
+                Ok(self.globals.get(name).cloned())
+            }
+            Type::Expression(Operator::Let, name, value) => {
+                let inferred = self.check(value)?;
+                let type_name = match &***name {
+                    Type::TypedLiteral(_, type_name) => Some(self.aliases.normalize(type_name)),
+                    _ => inferred.clone(),
+                };
+                if let Some(Ty::Sized(int)) = type_name {
+                    if let Type::Literal(literal) = &***value {
+                        // a suffixed literal has the type of its suffix, checked on its own
+                        if let Some((digits, None)) = split_numeric(literal) {
+                            if digits.replace('_', "").parse().is_ok_and(|value| !int.contains(value)) {
+                                return Err(GenericsError {
+                                    message: message!("E0303", literal = literal, ty = int.name()),
+                                }.with_span(span));
+                            }
+                        }
+                    }
+                    if inferred != type_name {
+                        truncate(value, int);
+                    }
+                }
+                match (binding_name(name), self.scopes.last_mut(), &type_name) {
+                    (Some(name), Some(scope), _) => {
+                        scope.insert(name, type_name.clone());
+                    }
+                    // a module level value, for the statements after it
+                    (Some(name), None, Some(ty)) => {
+                        self.globals.insert(name, ty.clone());
+                    }
+                    _ => (),
+                }
+                Ok(type_name)
+            }
+            Type::Expression(operator, lhs, rhs) => {
+                let lhs = self.check(lhs)?;
+                let rhs = self.check(rhs)?;
+                for ty in [&lhs, &rhs].into_iter().flatten() {
+                    if self.is_type_param(ty) {
+                        return Err(GenericsError {
+                            message: message!("E0304", operator = operator.as_str(), ty = ty),
+                        }.with_span(span));
+                    }
+                    if *ty == Ty::Unit {
+                        return Err(GenericsError {
+                            message: message!("E0305", operator = operator.as_str()),
+                        }.with_span(span));
+                    }
+                    if *ty == Ty::Float && *operator != Operator::Pow {
+                        return Err(GenericsError {
+                            message: message!("E0306", operator = operator.as_str()),
+                        }.with_span(span));
+                    }
+                    if *ty == Ty::String {
+                        return Err(GenericsError {
+                            message: message!("E0318", operator = operator.as_str()),
+                        }.with_span(span));
+                    }
+                }
+                if *operator == Operator::Pow {
+                    return power(ast, lhs, rhs);
+                }
+                let ty = match (lhs, rhs) {
+                    (Some(Ty::Sized(lhs)), Some(Ty::Sized(rhs))) if lhs != rhs => return Err(GenericsError {
+                        message: message!("E0307", operator = operator.as_str(), lhs = lhs.name(), rhs = rhs.name()),
+                    }.with_span(span)),
+                    (Some(Ty::Sized(int)), _) | (_, Some(Ty::Sized(int))) => {
+                        truncate(ast, int);
+                        Some(Ty::Sized(int))
+                    }
+                    (lhs, rhs) => lhs.or(rhs),
+                };
+                Ok(ty)
+            }
+            Type::Call(callee, args) => {
+                let arg_types = args.iter_mut().map(|arg| self.check(arg)).collect::<Result<Vec<_>, _>>()?;
+                let builtin = match &***callee {
+                    Type::Identifier(name) => self.builtin(*name),
+                    _ => None,
+                };
+                if let Some(builtin) = builtin {
+                    return self.check_builtin(builtin, args, arg_types, span);
+                }
+                if let Type::Identifier(name) = &mut ***callee {
+                    if self.lookup(*name).is_none() && self.templates.contains_key(name) {
+                        if !self.type_params.is_empty() {
+                            // checked once the template is instantiated
+                            return Ok(None);
+                        }
+                        let (instance, return_type) = self.instantiate(*name, &arg_types, span)?;
+                        *name = instance;
+                        return Ok(return_type);
+                    }
+                }
+                let callee_type = match &***callee {
+                    // extern functions can't be values, only called
+                    Type::Identifier(name) if self.lookup(*name).is_none() && self.externs.contains(name) => self.globals.get(name).cloned(),
+                    _ => self.check(callee)?,
+                };
+                match callee_type {
+                    Some(ty) if self.is_type_param(&ty) => Err(GenericsError {
+                        message: message!("E0308", ty = ty),
+                    }.with_span(span)),
+                    Some(Ty::Function(params, return_type)) => {
+                        // a wrong number of arguments is reported by codegen
+                        if params.len() == arg_types.len() {
+                            for (i, (param, arg)) in params.iter().zip(arg_types.iter()).enumerate() {
+                                if let Some(arg) = arg {
+                                    unify(param, arg, &[], &mut HashMap::new())
+                                        .map_err(|mismatch| mismatch.error(i, param, arg, &HashMap::new()).with_span(span))?;
+                                }
+                                if let (Ty::Sized(int), false) = (param, arg.as_ref() == Some(param)) {
+                                    truncate(&mut args[i], *int);
+                                }
+                            }
+                        }
+                        Ok(Some(*return_type))
+                    }
+                    _ => Ok(None),
+                }
+            }
+            Type::Lambda(return_type, args, body) => {
+                let return_type = self.aliases.normalize(return_type);
+                let mut scope = HashMap::new();
+                for arg in args.iter() {
+                    if let Type::TypedLiteral(name, type_name) = &**arg {
+                        scope.insert(*name, Some(self.aliases.normalize(type_name)));
+                    }
+                }
+                self.scopes.push(scope);
+                let result = self.check(body);
+                self.scopes.pop();
+                if let Some(body_type) = result? {
+                    unify(&return_type, &body_type, &[], &mut HashMap::new()).map_err(|_| GenericsError {
+                        message: message!("E0314", diff = diff(&return_type, &body_type)),
+                    }.with_span(span))?;
+                }
+                Ok(Some(lambda_type(ast, &self.aliases)))
+            }
+            Type::Generic(..) => Err(GenericsError {
+                message: message!("E0309"),
+            }.with_span(span)),
+            // C knows nothing of closures and the layout of strings, only numbers and the bytes of strings
+            Type::Extern(_, args, return_type) => {
+                for arg in args.iter() {
+                    if let Type::TypedLiteral(_, type_name) = &**arg {
+                        let ty = self.aliases.normalize(type_name);
+                        if !ty.is_numeric() && ty != Ty::String {
+                            return Err(GenericsError {
+                                message: message!("E0323", ty = ty),
+                            }.with_span(arg.span()));
+                        }
+                    }
+                }
+                let ty = self.aliases.normalize(return_type);
+                if !ty.is_numeric() && ty != Ty::Unit {
+                    return Err(GenericsError {
+                        message: message!("E0323", ty = ty),
+                    }.with_span(span));
+                }
+                Ok(Some(lambda_type(ast, &self.aliases)))
+            }
+            Type::Block(statements) => {
+                self.scopes.push(HashMap::new());
+                let mut ty = Some(Ty::Unit);
+                for statement in statements.iter_mut() {
+                    match self.check(statement) {
+                        Ok(statement_type) => ty = statement_type,
+                        Err(error) => {
+                            self.scopes.pop();
+                            return Err(error);
+                        }
+                    }
+                }
+                self.scopes.pop();
+                Ok(ty)
+            }
+            Type::Import(_) => Ok(None),
+            Type::Cast(value, type_name, from) => {
+                let to = self.aliases.normalize(type_name);
+                *from = self.check(value)?;
+                match from {
+                    Some(from) if self.is_type_param(from) => Err(GenericsError {
+                        message: message!("E0310", ty = from),
+                    }.with_span(span)),
+                    Some(from) if *from != to && !(from.is_numeric() && to.is_numeric()) => Err(GenericsError {
+                        message: message!("E0311", from = from, to = to),
+                    }.with_span(span)),
+                    _ => Ok(Some(to)),
+                }
+            }
+            Type::Interpolation(parts) => {
+                for part in parts.iter_mut() {
+                    match self.check(part)? {
+                        Some(Ty::String) => (),
+                        // numbers are formatted by casting them to strings, which only the checker does
+                        Some(ty) if ty.is_numeric() => format(part, Some(ty)),
+                        Some(ty) => return Err(GenericsError {
+                            message: message!("E0317", ty = ty),
+                        }.with_span(part.span())),
+                        // left to the code generator to report
+                        None => format(part, None),
+                    }
+                }
+                Ok(Some(Ty::String))
+            }
+            Type::Truncate(int, value) => {
+                let int = *int;
+                self.check(value)?;
+                Ok(Some(Ty::Sized(int)))
+            }
+            // checked already, the exponent cast to `float` if it had to be
+            Type::Float(_) => Ok(Some(Ty::Float)),
+            Type::Discard(value) => {
+                self.check(value)?;
+                Ok(Some(Ty::Unit))
+            }
+            Type::Module(_) => unreachable!("modules don't nest"),
+        }
+    }
+
+    /// Infers the type parameters of the template `name` from the types of the
+    /// arguments it is called with, and queues the instance if it is new.
+    /// Returns the name of the instance and its return type.
+    fn instantiate(&mut self, name: Symbol, args: &[Option<Ty>], span: Span) -> Result<(Symbol, Option<Ty>), LocalizedError> {
+        let template = &self.templates[&name];
+        let (params, return_type) = match lambda_type(&template.lambda, &self.aliases) {
+            Ty::Function(params, return_type) => (params, return_type),
+            _ => unreachable!("templates are lambdas"),
+        };
+        if params.len() != args.len() {
+            return Err(GenericsError {
+                message: message!("E0312", name = name, expected = params.len(), given = args.len()),
+            }.with_span(span));
+        }
+
+        let mut bindings = HashMap::new();
+        for (i, (param, arg)) in params.iter().zip(args).enumerate() {
+            if let Some(arg) = arg {
+                unify(param, arg, &template.params, &mut bindings)
+                    .map_err(|mismatch| mismatch.error(i, param, arg, &bindings).with_span(span))?;
+            }
+        }
+        let types = template.params
+            .iter()
+            .map(|param| bindings.get(param).map(|t| t.to_string()).ok_or_else(|| GenericsError {
+                message: message!("E0313", param = param, name = name),
+            }.with_span(span)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let instance = Symbol::intern(&format!("{}<{}>", name, types.join(", ")));
+        let return_type = return_type.substitute(&bindings);
+        if self.instantiated.insert(instance) {
+            self.queue.push(Instance { template: name, name: instance, bindings });
+        }
+        Ok((instance, Some(return_type)))
+    }
+
+    /// Types a call to a builtin: the math ones take floats, those reading the input nothing,
+    /// and those printing format their arguments of type `arg_types` into strings
+    fn check_builtin(&mut self, builtin: Builtin, args: &mut [AST], arg_types: Vec<Option<Ty>>, span: Span) -> Result<Option<Ty>, LocalizedError> {
+        if args.len() != builtin.arity() {
+            return Err(GenericsError {
+                message: message!("E0312", name = builtin.name(), expected = builtin.arity(), given = args.len()),
+            }.with_span(span));
+        }
+        if builtin.is_math() {
+            for (i, ty) in arg_types.iter().enumerate() {
+                if let Some(ty) = ty.as_ref().filter(|ty| **ty != Ty::Float) {
+                    return Err(Mismatch::Types.error(i, &Ty::Float, ty, &HashMap::new()).with_span(span));
+                }
+            }
+            return Ok(Some(Ty::Float));
+        }
+        match builtin {
+            Builtin::ReadInt => return Ok(Some(Ty::Int)),
+            Builtin::ReadLine => return Ok(Some(Ty::String)),
+            Builtin::Len | Builtin::At => {
+                let element = match &arg_types[0] {
+                    Some(Ty::Array(element)) => Some((**element).clone()),
+                    Some(ty) => return Err(GenericsError {
+                        message: message!("E0321", name = builtin.name(), ty = ty),
+                    }.with_span(args[0].span())),
+                    None => None,
+                };
+                if builtin == Builtin::Len {
+                    return Ok(Some(Ty::Int));
+                }
+                if let Some(ty) = arg_types[1].as_ref().filter(|ty| **ty != Ty::Int) {
+                    return Err(Mismatch::Types.error(1, &Ty::Int, ty, &HashMap::new()).with_span(span));
+                }
+                return Ok(element);
+            }
+            _ => (),
+        }
+        for (arg, ty) in args.iter_mut().zip(arg_types) {
+            match ty {
+                Some(Ty::String) => (),
+                Some(ty) if ty.is_numeric() => format(arg, Some(ty)),
+                Some(ty) => return Err(GenericsError {
+                    message: message!("E0317", ty = ty),
+                }.with_span(arg.span())),
+                // left to the code generator to report
+                None => format(arg, None),
+            }
+        }
+        Ok(Some(Ty::Unit))
+    }
+
+    /// The builtin `name` refers to, unless a binding shadows it
+    fn builtin(&self, name: Symbol) -> Option<Builtin> {
+        match self.lookup(name).is_none() && !self.globals.contains_key(&name) && !self.templates.contains_key(&name) {
+            true => Builtin::from_name(name),
+            false => None,
+        }
+    }
+
+    fn lookup(&self, name: Symbol) -> Option<Option<Ty>> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(&name)).cloned()
+    }
+
+    fn is_type_param(&self, ty: &Ty) -> bool {
+        matches!(ty, Ty::Named(name) if self.type_params.contains(name))
+    }
+}
+
+/// Why an argument doesn't match its parameter
+enum Mismatch {
+    /// A type parameter was bound to two different types
+    Conflict { param: String, bound: Ty, arg: Ty },
+    /// The concrete parts of the types differ
+    Types,
+}
+
+impl Mismatch {
+    /// The error for argument `index`, of type `arg`, passed for `param`
+    /// * `bindings` - the type parameters bound so far
+    fn error(self, index: usize, param: &Ty, arg: &Ty, bindings: &HashMap<String, Ty>) -> GenericsError {
+        let message = match self {
+            Mismatch::Conflict { param, bound, arg } => message!("E0315", param = param, diff = diff(&bound, &arg)),
+            Mismatch::Types => message!("E0316", index = index + 1, diff = diff(&param.substitute(bindings), arg)),
+        };
+        GenericsError { message }
+    }
+}
+
+/// The difference between two types, for the messages ending with it:
+/// on the same line for simple types, on the next lines for the marked up composite types
+fn diff(expected: &Ty, found: &Ty) -> String {
+    match expected.is_composite() || found.is_composite() {
+        true => format!("\n{}", render_diff(expected, found)),
+        false => format!(" {}", render_diff(expected, found)),
+    }
+}
+
+/// Binds the type parameters occurring in `param` so that it matches `arg`.
+/// Types the checker knows nothing about match anything.
+fn unify(param: &Ty, arg: &Ty, type_params: &[String], bindings: &mut HashMap<String, Ty>) -> Result<(), Mismatch> {
+    match (param, arg) {
+        (Ty::Named(name), _) if type_params.contains(name) => match bindings.get(name) {
+            Some(bound) if bound != arg => Err(Mismatch::Conflict { param: name.clone(), bound: bound.clone(), arg: arg.clone() }),
+            Some(_) => Ok(()),
+            None => {
+                bindings.insert(name.clone(), arg.clone());
+                Ok(())
+            }
+        },
+        (Ty::Function(params, ret), Ty::Function(args, arg_ret)) if params.len() == args.len() => {
+            for (param, arg) in params.iter().zip(args) {
+                unify(param, arg, type_params, bindings)?;
+            }
+            unify(ret, arg_ret, type_params, bindings)
+        }
+        (Ty::Array(param), Ty::Array(arg)) => unify(param, arg, type_params, bindings),
+        (Ty::Map(key, value), Ty::Map(arg_key, arg_value)) => {
+            unify(key, arg_key, type_params, bindings)?;
+            unify(value, arg_value, type_params, bindings)
+        }
+        (Ty::AnyFunction, Ty::Function(..)) | (Ty::Function(..), Ty::AnyFunction) => Ok(()),
+        // the default integer type mixes with integers of any width
+        (Ty::Int, Ty::Sized(_)) | (Ty::Sized(_), Ty::Int) => Ok(()),
+        (Ty::Named(_), _) | (_, Ty::Named(_)) => Ok(()),
+        _ if param == arg => Ok(()),
+        _ => Err(Mismatch::Types),
+    }
+}
+
+/// Types the power `ast`, of a base of type `lhs` to an exponent of type `rhs`:
+/// a float to the power of a float or of an integer, which is cast to a float,
+/// or an integer to the power of an integer of any width, the power having the type of the base
+fn power(ast: &mut AST, lhs: Option<Ty>, rhs: Option<Ty>) -> Result<Option<Ty>, LocalizedError> {
+    let span = ast.span();
+    match (lhs, rhs) {
+        (Some(Ty::Float), rhs) => {
+            let Type::Expression(_, _, exponent) = &mut **ast else {
+                unreachable!("a power is an expression");
+            };
+            if rhs != Some(Ty::Float) {
+                cast(exponent, rhs, "float");
+            }
+            let value = std::mem::replace(ast, Type::Block(Vec::new()).wrap_span(span));
+            *ast = Type::Float(Node::new(value)).wrap_span(span);
+            Ok(Some(Ty::Float))
+        }
+        (Some(base), Some(Ty::Float)) => Err(GenericsError {
+            message: message!("E0319", ty = base),
+        }.with_span(span)),
+        (Some(Ty::Sized(int)), _) => {
+            truncate(ast, int);
+            Ok(Some(Ty::Sized(int)))
+        }
+        (lhs, _) => Ok(lhs),
+    }
+}
+
+/// Wraps `ast` so that its value is wrapped around to the width of `int`
+fn truncate(ast: &mut AST, int: IntTy) {
+    let span = ast.span();
+    let value = std::mem::replace(ast, Type::Block(Vec::new()).wrap_span(span));
+    *ast = Type::Truncate(int, Node::new(value)).wrap_span(span);
+}
+
+/// Wraps `ast` into a cast of its value of type `ty` to a string
+fn format(ast: &mut AST, ty: Option<Ty>) {
+    cast(ast, ty, "string")
+}
+
+/// Wraps `ast` into a cast of its value of type `ty` to the type `to`
+fn cast(ast: &mut AST, ty: Option<Ty>, to: &str) {
+    let span = ast.span();
+    let value = std::mem::replace(ast, Type::Block(Vec::new()).wrap_span(span));
+    *ast = Type::Cast(Node::new(value), TypeName::Named(to.to_owned()), ty).wrap_span(span);
+}
+
+/// Replaces the type parameters in an annotation, which is normalized on the way
+fn substitute_type(type_name: &TypeName, bindings: &HashMap<String, Ty>, aliases: &Aliases) -> TypeName {
+    TypeName::from(&aliases.normalize(type_name).substitute(bindings))
+}
+
+/// Replaces the type parameters in every type annotation of `ast`
+fn substitute(ast: &mut AST, bindings: &HashMap<String, Ty>, aliases: &Aliases) {
+    Substitute { bindings, aliases }.visit_mut(ast)
+}
+
+struct Substitute<'a> {
+    bindings: &'a HashMap<String, Ty>,
+    aliases: &'a Aliases,
+}
+
+impl VisitorMut for Substitute<'_> {
+    fn visit_type_name_mut(&mut self, type_name: &mut TypeName) {
+        *type_name = substitute_type(type_name, self.bindings, self.aliases);
+    }
+}
@@ -1,2 +1,17 @@
+pub mod arena;
 pub mod ast;
-pub mod tokenizer;
\ No newline at end of file
+pub mod tokenizer;
+pub mod resolve;
+pub mod generics;
+pub mod modules;
+pub mod types;
+pub mod edition;
+pub mod optimize;
+pub mod overflow;
+pub mod unused;
+pub mod symbol;
+pub mod plugin;
+pub mod purity;
+pub mod stdlib;
+pub mod builtins;
+pub mod suggest;
\ No newline at end of file
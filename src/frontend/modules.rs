@@ -0,0 +1,384 @@
+use core::fmt;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::frontend::ast::{self, AST, Type};
+use crate::frontend::resolve::{binding_name, resolve, resolve_with_globals};
+use crate::frontend::stdlib;
+use crate::frontend::symbol::Symbol;
+use crate::frontend::tokenizer::{tokenize, Location, Operator, Span};
+use crate::compile::{self, Phase};
+use crate::errors::{self, Coded, Diagnostics, LocalizableError, LocalizedSourcedError};
+use crate::manifest::{self, FILE_NAME};
+use crate::messages::{message, Message};
+use crate::sources::{self, SourceFile};
+
+/// The extension of source files, `import math` loads `math.moo`
+pub const EXTENSION: &str = "moo";
+
+#[derive(Debug)]
+pub struct ModuleError {
+    message: Message,
+}
+
+impl fmt::Display for ModuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ModuleError: {}", self.message)
+    }
+}
+
+impl Error for ModuleError {}
+
+impl Coded for ModuleError {
+    fn code(&self) -> Option<&'static str> {
+        self.message.code()
+    }
+}
+
+/// The source file at `path` from the source map, read the first time.
+/// Sources registered in `sources`, e.g. REPL inputs, are read from memory instead.
+/// * `path` - the path of the source file
+pub fn read_source(path: &Path) -> Result<Arc<SourceFile>, LocalizedSourcedError> {
+    sources::load(path).map_err(|err| err
+        .with_location(Location::default())
+        .with_source(path))
+}
+
+/// Tokenizes and parses a source file, without loading its imports
+/// * `path` - the path of the source file
+pub fn parse_file(path: &Path) -> Result<AST, Diagnostics> {
+    tracing::debug!(path = %path.display(), "parsing");
+    let source = read_source(path)?;
+    let ast = compile::time(Phase::Parse, || ast::parse(&mut tokenize(source.lines()).in_file(&source)));
+    ast::take_warnings().into_iter().for_each(|warning| errors::warn(warning.with_source(path)));
+    ast.map_err(|errors| errors.into_iter().map(|error| error.with_source(path)).collect())
+}
+
+/// Replaces every `import` of a module by the bindings of the imported module,
+/// loading imports of imported modules in turn.
+///
+/// Modules are looked up next to the entry file, i.e. `import math` loads `math.moo`
+/// from the directory of `path`, then among the dependencies in the manifest of the project
+/// the entry file is in, if any, then in the standard library. The bindings of an imported module are namespaced
+/// by the module name, so `abs` of `math` is referred to as `math.abs`.
+/// * `module` - the parsed entry module
+/// * `path` - the path of the entry file
+pub fn load_imports(module: AST, path: &Path) -> Result<AST, Diagnostics> {
+    let mut files = load_all_imports(vec![(path.to_path_buf(), module)])?;
+    Ok(files.pop().expect("one file in, one file out").1)
+}
+
+/// Like `load_imports`, for the entry files of a program compiled together.
+/// A module imported by several entry files is only loaded by the first one,
+/// so its bindings aren't defined twice.
+/// * `files` - the entry files with their parsed modules
+pub fn load_all_imports(files: Vec<(PathBuf, AST)>) -> Result<Vec<(PathBuf, AST)>, Diagnostics> {
+    load_all_imports_with_files(files).map(|(files, _)| files)
+}
+
+/// Source files along with their parsed modules
+pub type Files = Vec<(PathBuf, AST)>;
+
+/// Like `load_all_imports`, also returning the paths of the module files read, in the order they were read
+pub fn load_all_imports_with_files(files: Files) -> Result<(Files, Vec<PathBuf>), Diagnostics> {
+    let mut loader = Loader {
+        root: PathBuf::new(),
+        loading: Vec::new(),
+        loaded: HashSet::new(),
+        read: Vec::new(),
+        deps: Vec::new(),
+        packages: Vec::new(),
+    };
+    let files = files.into_iter()
+        .map(|(path, module)| {
+            loader.root = path.parent().map(Path::to_path_buf).unwrap_or_default();
+            let package = manifest::find(&loader.root).map(|file| manifest::load(&file)).transpose()?;
+            loader.packages = package.iter().map(|package| (package.name.clone(), canonical(&package.root))).collect();
+            loader.deps = package.map(|package| package.deps).unwrap_or_default();
+            loader.loading = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).into_iter().collect();
+            let module = loader.link(module, &path)?;
+            Ok((path, module))
+        })
+        .collect::<Result<_, Diagnostics>>()?;
+    Ok((files, loader.read))
+}
+
+/// Merges the modules of the entry files of a program into one module,
+/// checking that every name is defined in only one file and
+/// that every file refers only to names defined in one of the files
+/// * `files` - the entry files with their modules, imports loaded
+pub fn merge(files: Vec<(PathBuf, AST)>) -> Result<AST, Diagnostics> {
+    let mut definitions: HashMap<Symbol, (&Path, Span)> = HashMap::new();
+    for (path, module) in files.iter() {
+        for (name, span) in module_bindings(module) {
+            if let Some((first, first_span)) = definitions.get(&name) {
+                let mut error = ModuleError {
+                    message: message!("E0201", name = name, file = first.display()),
+                }.with_span(span);
+                // labels are in the file of the error
+                if first == path {
+                    error = error.with_label(*first_span, message!("first-defined"));
+                }
+                return Err(error.with_source(path).into());
+            }
+            definitions.insert(name, (path, span));
+        }
+    }
+
+    for (path, module) in files.iter() {
+        let others = definitions.iter()
+            .filter(|(_, (defined_in, _))| *defined_in != path.as_path())
+            .map(|(name, _)| *name);
+        resolve_with_globals(module, others).map_err(|errors| errors.into_iter().map(|error| error.with_source(path)).collect::<Diagnostics>())?;
+    }
+
+    let location = files.first().map(|(_, module)| *module.location()).unwrap_or_default();
+    let statements = files.into_iter()
+        .flat_map(|(_, module)| match module.type_() {
+            Type::Module(statements) => statements,
+            _ => unreachable!("merge expects modules"),
+        })
+        .collect();
+    Ok(Type::Module(statements).wrap(location))
+}
+
+/// Expands the directories among `paths` into the source files they contain, in name order
+/// * `paths` - source files and directories of source files
+pub fn source_files(paths: &[PathBuf]) -> Result<Vec<PathBuf>, LocalizedSourcedError> {
+    let mut files = Vec::new();
+    for path in paths {
+        if !path.is_dir() {
+            files.push(path.clone());
+            continue;
+        }
+        let entries = fs::read_dir(path)
+            .and_then(|entries| entries.map(|entry| entry.map(|entry| entry.path())).collect::<Result<Vec<_>, _>>())
+            .map_err(|err| err.with_location(Location::default()).with_source(path))?;
+        let mut sources = entries.into_iter()
+            .filter(|entry| entry.is_file() && entry.extension().is_some_and(|extension| extension == EXTENSION))
+            .collect::<Vec<_>>();
+        if sources.is_empty() {
+            return Err(ModuleError {
+                message: message!("E0202", extension = EXTENSION, directory = path.display()),
+            }.with_location(Location::default()).with_source(path));
+        }
+        sources.sort();
+        files.extend(sources);
+    }
+    Ok(files)
+}
+
+/// The names bound at module level, with the locations of their bindings
+fn module_bindings(module: &AST) -> Vec<(Symbol, Span)> {
+    match &**module {
+        Type::Module(statements) => statements.iter()
+            .filter_map(|statement| match &**statement {
+                Type::Expression(Operator::Let, name, _) => binding_name(name).map(|binding| (binding, name.span())),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+struct Loader {
+    /// The directory modules are looked up in
+    root: PathBuf,
+    /// The modules being loaded, outermost first, to detect cyclic imports
+    loading: Vec<String>,
+    /// The modules already imported by an entry file
+    loaded: HashSet<Symbol>,
+    /// The paths of the module files read
+    read: Vec<PathBuf>,
+    /// The dependencies of the package being loaded, by name, with their roots
+    deps: Vec<(String, PathBuf)>,
+    /// The names and roots of the packages being loaded, outermost first, to detect cyclic dependencies
+    packages: Vec<(String, PathBuf)>,
+}
+
+impl Loader {
+    /// Replaces the imports of `module`, parsed from `path`, by the imported bindings
+    fn link(&mut self, module: AST, path: &Path) -> Result<AST, Diagnostics> {
+        let location = *module.location();
+        let statements = match module.type_() {
+            Type::Module(statements) => statements,
+            _ => unreachable!("link expects a module"),
+        };
+
+        let mut imported = HashSet::new();
+        let mut linked = Vec::new();
+        for statement in statements {
+            let span = statement.span();
+            match statement.type_() {
+                Type::Import(name) => {
+                    if !imported.insert(name) {
+                        return Err(ModuleError {
+                            message: message!("E0203", module = name),
+                        }.with_span(span).with_source(path).into());
+                    }
+                    // entry files share the modules they import, unlike the entry modules of dependencies
+                    let entry = self.loading.len() == 1 && self.packages.len() <= 1;
+                    if entry && !self.loaded.insert(name) {
+                        continue;
+                    }
+                    linked.extend(self.load(&name, span, path)?);
+                }
+                type_ => linked.push(type_.wrap_span(span)),
+            }
+        }
+        Ok(Type::Module(linked).wrap(location))
+    }
+
+    /// Loads the module `name` and returns its namespaced bindings
+    /// * `span` - the span of the import statement
+    /// * `importer` - the path of the importing file
+    fn load(&mut self, name: &str, span: Span, importer: &Path) -> Result<Vec<AST>, Diagnostics> {
+        if let Some(start) = self.loading.iter().position(|loading| loading == name) {
+            return Err(ModuleError {
+                message: message!("E0204", cycle = format!("{} -> {}", self.loading[start..].join(" -> "), name)),
+            }.with_span(span).with_source(importer).into());
+        }
+
+        // a module next to the importer takes precedence over a dependency, which takes precedence over the standard library
+        let local = self.root.join(name).with_extension(EXTENSION);
+        let dependency = self.deps.iter().find(|(dependency, _)| dependency == name).map(|(_, root)| root.clone());
+        let module = match dependency {
+            Some(root) if !local.is_file() => self.load_package(&root, span, importer)?,
+            _ => {
+                let path = match local.is_file() {
+                    true => local,
+                    false => stdlib::find(name).ok_or_else(|| ModuleError {
+                        message: message!("E0205", module = name, path = local.display()),
+                    }.with_span(span).with_source(importer))?,
+                };
+                self.load_file(name, &path)?
+            }
+        };
+
+        let statements = match module.type_() {
+            Type::Module(statements) => statements,
+            _ => unreachable!("parse returns a module"),
+        };
+        Ok(namespace(statements, name))
+    }
+
+    /// Parses the module `name` from `path` and replaces its imports
+    fn load_file(&mut self, name: &str, path: &Path) -> Result<AST, Diagnostics> {
+        self.loading.push(name.to_owned());
+        self.read.push(path.to_path_buf());
+        let module = parse_file(path)?;
+        let module = self.link(module, path)?;
+        // resolve the module on its own, so errors point into the right file
+        resolve(&module).map_err(|errors| errors.into_iter().map(|error| error.with_source(path)).collect::<Diagnostics>())?;
+        self.loading.pop();
+        Ok(module)
+    }
+
+    /// Loads the entry module of the package at `root`, whose modules are looked up next to its entry,
+    /// and whose dependencies are those of its manifest
+    fn load_package(&mut self, root: &Path, span: Span, importer: &Path) -> Result<AST, Diagnostics> {
+        let file = root.join(FILE_NAME);
+        if !file.is_file() {
+            let package = root.file_name().unwrap_or(root.as_os_str()).to_string_lossy();
+            return Err(ModuleError {
+                message: message!("E0510", package = package, file = FILE_NAME, path = file.display()),
+            }.with_span(span).with_source(importer).into());
+        }
+        let package = manifest::load(&file)?;
+        let id = canonical(&package.root);
+        if let Some(start) = self.packages.iter().position(|(_, root)| *root == id) {
+            let cycle = self.packages[start..].iter().map(|(name, _)| name.as_str())
+                .chain([package.name.as_str()])
+                .collect::<Vec<_>>();
+            return Err(ModuleError {
+                message: message!("E0511", cycle = cycle.join(" -> ")),
+            }.with_span(span).with_source(importer).into());
+        }
+
+        let entry = package.entry.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+        let root = package.entry.parent().map(Path::to_path_buf).unwrap_or_default();
+        let root = mem::replace(&mut self.root, root);
+        let loading = mem::take(&mut self.loading);
+        let deps = mem::replace(&mut self.deps, package.deps);
+        self.packages.push((package.name, id));
+        let module = self.load_file(&entry, &package.entry)?;
+        self.packages.pop();
+        self.deps = deps;
+        self.loading = loading;
+        self.root = root;
+        Ok(module)
+    }
+}
+
+/// The path of `root` made absolute, to tell whether two paths lead to the same package
+fn canonical(root: &Path) -> PathBuf {
+    fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf())
+}
+
+/// Prefixes the module level bindings with `prefix.`, along with every reference to them
+/// * `statements` - the statements of a resolved module
+/// * `prefix` - the name of the module
+fn namespace(mut statements: Vec<AST>, prefix: &str) -> Vec<AST> {
+    let globals = statements.iter()
+        .filter_map(|statement| match &**statement {
+            Type::Expression(Operator::Let, name, _) => binding_name(name),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+    for statement in statements.iter_mut() {
+        qualify(statement, prefix, &globals, &mut Vec::new());
+    }
+    statements
+}
+
+/// Qualifies the module level names bound or referred to in `ast`
+/// * `shadowed` - the names bound by the enclosing local scopes, innermost last
+fn qualify(ast: &mut AST, prefix: &str, globals: &HashSet<Symbol>, shadowed: &mut Vec<HashSet<Symbol>>) {
+    match &mut **ast {
+        // an extern function keeps the name of its symbol, only its binding is qualified
+        Type::Literal(_) | Type::TypedLiteral(_, _) | Type::Import(_) | Type::Extern(..) | Type::Module(_) => (),
+        Type::Identifier(name) => {
+            if globals.contains(name) && !shadowed.iter().any(|scope| scope.contains(name)) {
+                *name = qualified(prefix, name);
+            }
+        }
+        Type::Expression(Operator::Let, name, value) => {
+            qualify(value, prefix, globals, shadowed);
+            match shadowed.last_mut() {
+                Some(scope) => scope.extend(binding_name(name)),
+                None => match &mut ***name {
+                    Type::Literal(name) | Type::TypedLiteral(name, _) => *name = qualified(prefix, name),
+                    _ => (),
+                },
+            }
+        }
+        Type::Expression(_, lhs, rhs) => {
+            qualify(lhs, prefix, globals, shadowed);
+            qualify(rhs, prefix, globals, shadowed);
+        }
+        Type::Call(callee, args) => {
+            qualify(callee, prefix, globals, shadowed);
+            args.iter_mut().for_each(|arg| qualify(arg, prefix, globals, shadowed));
+        }
+        Type::Interpolation(parts) => parts.iter_mut().for_each(|part| qualify(part, prefix, globals, shadowed)),
+        Type::Generic(_, lambda) | Type::Discard(lambda) | Type::Truncate(_, lambda) | Type::Float(lambda) | Type::Cast(lambda, _, _) => qualify(lambda, prefix, globals, shadowed),
+        Type::Lambda(_, args, body) => {
+            shadowed.push(args.iter().filter_map(binding_name).collect());
+            qualify(body, prefix, globals, shadowed);
+            shadowed.pop();
+        }
+        Type::Block(statements) => {
+            shadowed.push(HashSet::new());
+            statements.iter_mut().for_each(|statement| qualify(statement, prefix, globals, shadowed));
+            shadowed.pop();
+        }
+    }
+}
+
+fn qualified(prefix: &str, name: &str) -> Symbol {
+    Symbol::intern(&format!("{}{}{}", prefix, Operator::Dot.as_str(), name))
+}
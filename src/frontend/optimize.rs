@@ -0,0 +1,43 @@
+use core::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// How much the compiler optimizes the programs, trading compile time for speed
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+    /// No optimizations, and the generated code is verified, to debug the compiler
+    #[default]
+    #[value(name = "0")]
+    O0,
+    /// Optimizations for speed, the generated code still being verified
+    #[value(name = "1")]
+    O1,
+    /// Optimizations for speed and size, without verifying the generated code
+    #[value(name = "2")]
+    O2,
+}
+
+impl fmt::Display for OptLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OptLevel::O0 => write!(f, "0"),
+            OptLevel::O1 => write!(f, "1"),
+            OptLevel::O2 => write!(f, "2"),
+        }
+    }
+}
+
+static OPT_LEVEL: AtomicU8 = AtomicU8::new(OptLevel::O0 as u8);
+
+/// Sets how much the programs are optimized
+pub fn set(level: OptLevel) {
+    OPT_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// How much the programs are optimized, not at all unless set otherwise
+pub fn current() -> OptLevel {
+    match OPT_LEVEL.load(Ordering::Relaxed) {
+        0 => OptLevel::O0,
+        1 => OptLevel::O1,
+        _ => OptLevel::O2,
+    }
+}
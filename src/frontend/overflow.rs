@@ -0,0 +1,41 @@
+use core::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// What integer arithmetic does when its result doesn't fit the type of its operands,
+/// on every backend and when folding constants
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Overflow {
+    /// Wrap around, the fastest. Dividing the smallest value of a signed type by -1 is still an error
+    #[default]
+    Wrap,
+    /// Report an error where the arithmetic overflows, and stop the program
+    Trap,
+    /// Clamp the result to the smallest or largest value of the type
+    Saturate,
+}
+
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Overflow::Wrap => write!(f, "wrap"),
+            Overflow::Trap => write!(f, "trap"),
+            Overflow::Saturate => write!(f, "saturate"),
+        }
+    }
+}
+
+static OVERFLOW: AtomicU8 = AtomicU8::new(Overflow::Wrap as u8);
+
+/// Sets what the arithmetic of the programs compiled from now on does when it overflows
+pub fn set(overflow: Overflow) {
+    OVERFLOW.store(overflow as u8, Ordering::Relaxed);
+}
+
+/// What arithmetic does when it overflows, wrapping around unless set otherwise
+pub fn current() -> Overflow {
+    match OVERFLOW.load(Ordering::Relaxed) {
+        0 => Overflow::Wrap,
+        1 => Overflow::Trap,
+        _ => Overflow::Saturate,
+    }
+}
@@ -0,0 +1,88 @@
+// Extension point for lints and code generators living outside of the compiler.
+// A plugin is registered once, then run on every program compiled, after type checking:
+// it sees the module as the backends do, and reports what it finds to a `Sink`.
+
+use core::fmt;
+use std::error::Error;
+use std::sync::RwLock;
+
+use crate::errors::{Coded, LocalizableError, LocalizedError, Warning};
+use crate::lint::Lint;
+use crate::frontend::ast::AST;
+use crate::frontend::tokenizer::Span;
+use crate::messages::{message, Message};
+
+/// A lint or code generator run on every program compiled
+pub trait Plugin: Send + Sync {
+    /// The name of the plugin, shown along with what it reports
+    fn name(&self) -> &str;
+    /// Inspects the module once type checked: generic functions are instantiated,
+    /// casts know the type they convert from, the wrapping of sized integers is explicit
+    /// in `Truncate` nodes, and float arithmetic is in `Float` nodes
+    fn check(&self, module: &AST, sink: &mut Sink);
+}
+
+/// An error reported by a plugin, which fails the compilation
+#[derive(Debug)]
+pub struct PluginError {
+    pub message: Message,
+}
+
+impl fmt::Display for PluginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PluginError: {}", self.message)
+    }
+}
+
+impl Error for PluginError {}
+
+impl Coded for PluginError {
+    fn code(&self) -> Option<&'static str> {
+        self.message.code()
+    }
+}
+
+/// Collects what the plugins report
+#[derive(Debug, Default)]
+pub struct Sink {
+    /// The name of the plugin being run
+    plugin: String,
+    pub warnings: Vec<LocalizedError>,
+    pub errors: Vec<LocalizedError>,
+}
+
+impl Sink {
+    /// Reports a warning about the source in `span`
+    pub fn warn(&mut self, message: impl fmt::Display, span: Span) {
+        let message = message!("plugin-message", message = message, plugin = self.plugin);
+        self.warnings.push(Warning::new(Lint::Plugins, message).with_span(span));
+    }
+
+    /// Reports an error about the source in `span`, failing the compilation once all the plugins ran
+    pub fn error(&mut self, message: impl fmt::Display, span: Span) {
+        let message = message!("plugin-message", message = message, plugin = self.plugin);
+        self.errors.push(PluginError { message }.with_span(span));
+    }
+}
+
+static PLUGINS: RwLock<Vec<Box<dyn Plugin>>> = RwLock::new(Vec::new());
+
+/// Registers a plugin, run on the programs compiled from then on, after those registered before it
+pub fn register(plugin: Box<dyn Plugin>) {
+    PLUGINS.write().unwrap().push(plugin);
+}
+
+/// Whether a plugin is registered
+pub fn registered() -> bool {
+    !PLUGINS.read().unwrap().is_empty()
+}
+
+/// Runs the registered plugins on the type checked `module`, returning what they reported
+pub fn run_plugins(module: &AST) -> Sink {
+    let mut sink = Sink::default();
+    for plugin in PLUGINS.read().unwrap().iter() {
+        sink.plugin = plugin.name().to_owned();
+        plugin.check(module, &mut sink);
+    }
+    sink
+}
@@ -0,0 +1,120 @@
+// Purity analysis: finds the module level functions whose calls have no effect
+// besides computing their value, so a call can be replaced by the value of an earlier call
+// with the same arguments, which the interpreter memoizes, see `interp`.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::frontend::ast::{walk, Visitor, AST, Type};
+use crate::frontend::builtins::Builtin;
+use crate::frontend::resolve::binding_name;
+use crate::frontend::symbol::Symbol;
+use crate::frontend::tokenizer::Operator;
+
+/// Returns the module level functions proven free of side effects.
+///
+/// A function is pure if it doesn't assign to a variable it doesn't bind itself,
+/// and only calls pure module level functions and math builtins by name. Calls through function values,
+/// e.g. of a closure passed as an argument, aren't analysed and make the caller impure.
+/// * `module` - a resolved module, after monomorphization for generic functions to be analysed
+pub fn pure_functions(module: &AST) -> HashSet<Symbol> {
+    let statements = match &**module {
+        Type::Module(statements) => statements,
+        _ => return HashSet::new(),
+    };
+    let functions = statements.iter()
+        .filter_map(|statement| match &**statement {
+            Type::Expression(Operator::Let, name, value) if matches!(***value, Type::Lambda(..)) => {
+                binding_name(name).map(|name| (name, &**value))
+            }
+            _ => None,
+        })
+        .collect::<HashMap<_, _>>();
+
+    // the functions without effects of their own, along with the functions they call
+    let mut calls = HashMap::new();
+    for (name, lambda) in functions.iter() {
+        let mut effects = Effects { globals: &functions, scopes: Vec::new(), calls: HashSet::new(), impure: false };
+        effects.visit(lambda);
+        if !effects.impure {
+            calls.insert(*name, effects.calls);
+        }
+    }
+
+    // a function calling an impure one is impure in turn
+    let mut pure = calls.keys().copied().collect::<HashSet<_>>();
+    loop {
+        let impure = pure.iter()
+            .filter(|name| !calls[*name].iter().all(|callee| pure.contains(callee)))
+            .copied()
+            .collect::<Vec<_>>();
+        if impure.is_empty() {
+            return pure;
+        }
+        impure.iter().for_each(|name| { pure.remove(name); });
+    }
+}
+
+/// Collects the effects of a function: the module level functions it calls, and whether it does
+/// anything else making it impure
+struct Effects<'a> {
+    globals: &'a HashMap<Symbol, &'a AST>,
+    /// The names bound inside the function, innermost scope last
+    scopes: Vec<HashSet<Symbol>>,
+    calls: HashSet<Symbol>,
+    impure: bool,
+}
+
+impl Effects<'_> {
+    fn is_local(&self, name: Symbol) -> bool {
+        self.scopes.iter().any(|scope| scope.contains(&name))
+    }
+
+    fn bind(&mut self, binding: &AST) {
+        if let (Some(name), Some(scope)) = (binding_name(binding), self.scopes.last_mut()) {
+            scope.insert(name);
+        }
+    }
+}
+
+impl Visitor for Effects<'_> {
+    fn visit(&mut self, ast: &AST) {
+        match &**ast {
+            Type::Lambda(_, args, body) => {
+                self.scopes.push(HashSet::new());
+                args.iter().for_each(|arg| self.bind(arg));
+                self.visit(body);
+                self.scopes.pop();
+            }
+            Type::Block(statements) => {
+                self.scopes.push(HashSet::new());
+                statements.iter().for_each(|statement| self.visit(statement));
+                self.scopes.pop();
+            }
+            Type::Expression(Operator::Let, name, value) => {
+                self.visit(value);
+                self.bind(name);
+            }
+            Type::Expression(Operator::Assign, name, value) => {
+                self.visit(value);
+                match &***name {
+                    Type::Identifier(name) | Type::Literal(name) if self.is_local(*name) => (),
+                    _ => self.impure = true,
+                }
+            }
+            Type::Call(callee, args) => {
+                match &***callee {
+                    Type::Identifier(name) if !self.is_local(*name) && self.globals.contains_key(name) => {
+                        self.calls.insert(*name);
+                    }
+                    Type::Identifier(name) if !self.is_local(*name) && Builtin::from_name(*name).is_some_and(Builtin::is_math) => (),
+                    _ => {
+                        self.impure = true;
+                        self.visit(callee);
+                    }
+                }
+                args.iter().for_each(|arg| self.visit(arg));
+            }
+            _ => walk(self, ast),
+        }
+    }
+}
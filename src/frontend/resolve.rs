@@ -0,0 +1,267 @@
+use core::fmt;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+use crate::frontend::ast::{AST, Type};
+use crate::frontend::builtins::Builtin;
+use crate::frontend::suggest;
+use crate::frontend::symbol::Symbol;
+use crate::frontend::tokenizer::{Operator, Span};
+use crate::errors::{Coded, LocalizableError, LocalizedError, Suggestion};
+use crate::messages::{message, Message};
+
+#[derive(Debug)]
+pub struct ResolveError {
+    message: Message,
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ResolveError: {}", self.message)
+    }
+}
+
+impl Error for ResolveError {}
+
+impl Coded for ResolveError {
+    fn code(&self) -> Option<&'static str> {
+        self.message.code()
+    }
+}
+
+/// A stack of lexical scopes, innermost last.
+/// Every scope maps a bound name to the location of its binding.
+///
+/// Shadowing semantics:
+/// * a name may be re-bound in a nested scope, hiding the outer binding
+/// * binding the same name twice in one scope is an error
+struct Scopes {
+    scopes: Vec<HashMap<Symbol, Span>>,
+    /// The errors found so far, resolving going on past them to find the others
+    errors: Vec<LocalizedError>,
+}
+
+impl Scopes {
+    fn new() -> Self {
+        Self { scopes: Vec::new(), errors: Vec::new() }
+    }
+
+    fn push(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn pop(&mut self) {
+        self.scopes.pop();
+    }
+
+    /// Binds `name` in the innermost scope, unless it's bound there already
+    fn define(&mut self, name: Symbol, span: Span) {
+        let scope = self.scopes.last_mut().expect("no scope to define into");
+        match scope.get(&name) {
+            Some(first) => {
+                let mut error = ResolveError {
+                    message: message!("E0101", name = name),
+                }.with_span(span);
+                // the names defined by the other files of a program have no span in this one
+                if *first != Span::default() {
+                    error = error.with_label(*first, message!("first-defined"));
+                }
+                self.errors.push(error);
+            }
+            None => {
+                scope.insert(name, span);
+            }
+        }
+    }
+
+    /// Finds the location of the innermost binding of `name`
+    fn lookup(&self, name: Symbol) -> Option<&Span> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(&name))
+    }
+
+    /// The names in scope, the builtins included, but for those only the standard library calls
+    fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        let builtins = Builtin::ALL.iter().map(|builtin| builtin.name()).filter(|name| !name.starts_with("__"));
+        self.scopes.iter().flat_map(|scope| scope.keys().map(|name| name.as_str())).chain(builtins)
+    }
+
+    /// The errors found, in source order
+    fn finish(mut self) -> Result<(), Vec<LocalizedError>> {
+        if self.errors.is_empty() {
+            return Ok(());
+        }
+        self.errors.sort_by_key(|error| error.location().offset);
+        Err(self.errors)
+    }
+}
+
+/// Checks that every identifier refers to a binding in scope
+/// and that no name is bound twice within the same scope,
+/// returning every error found rather than the first
+/// * `ast` - the module to resolve
+pub fn resolve(ast: &AST) -> Result<(), Vec<LocalizedError>> {
+    let mut scopes = Scopes::new();
+    resolve_ast(ast, &mut scopes);
+    scopes.finish()
+}
+
+/// Like `resolve`, with `globals` defined outside of the module,
+/// e.g. by the other files of a program
+/// * `ast` - the module to resolve
+/// * `globals` - the names defined elsewhere
+pub fn resolve_with_globals(ast: &AST, globals: impl IntoIterator<Item = Symbol>) -> Result<(), Vec<LocalizedError>> {
+    let mut scopes = Scopes::new();
+    scopes.push();
+    for global in globals {
+        scopes.define(global, Span::default());
+    }
+    resolve_ast(ast, &mut scopes);
+    scopes.finish()
+}
+
+fn resolve_ast(ast: &AST, scopes: &mut Scopes) {
+    match &**ast {
+        // the arguments of an extern function only name them
+        Type::Literal(_) | Type::TypedLiteral(_, _) | Type::Extern(..) => (),
+        Type::Identifier(name) => {
+            // the builtins are in scope everywhere, outside of the module
+            if scopes.lookup(*name).is_none() && Builtin::from_name(*name).is_none() {
+                let mut error = ResolveError {
+                    message: message!("E0102", name = name),
+                }.with_span(ast.span());
+                if let Some(similar) = suggest::similar(name, scopes.names()) {
+                    error = error.with_suggestion(Suggestion::did_you_mean(ast.span(), name, similar));
+                }
+                scopes.errors.push(error);
+            }
+        }
+        Type::Expression(Operator::Let, name, value) => {
+            resolve_ast(value, scopes);
+            define_binding(name, scopes);
+        }
+        Type::Expression(_, lhs, rhs) => {
+            resolve_ast(lhs, scopes);
+            resolve_ast(rhs, scopes);
+        }
+        Type::Call(callee, args) => {
+            resolve_ast(callee, scopes);
+            args.iter().for_each(|arg| resolve_ast(arg, scopes));
+        }
+        Type::Interpolation(parts) => parts.iter().for_each(|part| resolve_ast(part, scopes)),
+        Type::Discard(value) | Type::Truncate(_, value) | Type::Float(value) | Type::Cast(value, _, _) => resolve_ast(value, scopes),
+        Type::Generic(params, lambda) => {
+            let mut seen = HashSet::new();
+            for param in params {
+                if !seen.insert(param) {
+                    scopes.errors.push(ResolveError {
+                        message: message!("E0103", param = param),
+                    }.with_span(ast.span()));
+                }
+            }
+            resolve_ast(lambda, scopes);
+        }
+        Type::Import(name) => scopes.errors.push(ResolveError {
+            message: message!("E0104", module = name),
+        }.with_span(ast.span())),
+        Type::Lambda(_, args, body) => {
+            scopes.push();
+            for arg in args {
+                define_binding(arg, scopes);
+            }
+            resolve_ast(body, scopes);
+            scopes.pop();
+        }
+        Type::Block(statements) => {
+            scopes.push();
+            statements.iter().for_each(|statement| resolve_ast(statement, scopes));
+            scopes.pop();
+        }
+        Type::Module(statements) => {
+            // module level bindings are visible to every statement of the module,
+            // regardless of the order in which they are defined
+            scopes.push();
+            for statement in statements {
+                if let Type::Expression(Operator::Let, name, _) = &**statement {
+                    define_binding(name, scopes);
+                }
+            }
+            for statement in statements {
+                match &**statement {
+                    Type::Expression(Operator::Let, _, value) => resolve_ast(value, scopes),
+                    _ => resolve_ast(statement, scopes),
+                }
+            }
+            scopes.pop();
+        }
+    }
+}
+
+fn define_binding(name: &AST, scopes: &mut Scopes) {
+    match binding_name(name) {
+        Some(identifier) => scopes.define(identifier, name.span()),
+        None => scopes.errors.push(ResolveError {
+            message: message!("E0105", found = format!("{:?}", name)),
+        }.with_span(name.span())),
+    }
+}
+
+/// Returns the name introduced by a binding, e.g. `x` for `let x: int = ...`
+pub fn binding_name(ast: &AST) -> Option<Symbol> {
+    match &**ast {
+        Type::Literal(name) | Type::TypedLiteral(name, _) => Some(*name),
+        _ => None,
+    }
+}
+
+/// Capture analysis: returns the names a lambda refers to without binding them itself,
+/// in order of first use. These have to be captured from the enclosing scopes.
+/// * `lambda` - the lambda to analyse
+pub fn free_variables(lambda: &AST) -> Vec<Symbol> {
+    let mut scopes = Scopes::new();
+    let mut free = Vec::new();
+    collect_free_variables(lambda, &mut scopes, &mut free);
+    free
+}
+
+fn collect_free_variables(ast: &AST, scopes: &mut Scopes, free: &mut Vec<Symbol>) {
+    match &**ast {
+        Type::Literal(_) | Type::TypedLiteral(_, _) | Type::Import(_) | Type::Extern(..) => (),
+        Type::Identifier(name) => {
+            if scopes.lookup(*name).is_none() && !free.contains(name) {
+                free.push(*name);
+            }
+        }
+        Type::Expression(Operator::Let, name, value) => {
+            collect_free_variables(value, scopes, free);
+            bind_unchecked(name, scopes);
+        }
+        Type::Expression(_, lhs, rhs) => {
+            collect_free_variables(lhs, scopes, free);
+            collect_free_variables(rhs, scopes, free);
+        }
+        Type::Call(callee, args) => {
+            collect_free_variables(callee, scopes, free);
+            args.iter().for_each(|arg| collect_free_variables(arg, scopes, free));
+        }
+        Type::Interpolation(parts) => parts.iter().for_each(|part| collect_free_variables(part, scopes, free)),
+        Type::Generic(_, lambda) | Type::Discard(lambda) | Type::Truncate(_, lambda) | Type::Float(lambda) | Type::Cast(lambda, _, _) => collect_free_variables(lambda, scopes, free),
+        Type::Lambda(_, args, body) => {
+            scopes.push();
+            args.iter().for_each(|arg| bind_unchecked(arg, scopes));
+            collect_free_variables(body, scopes, free);
+            scopes.pop();
+        }
+        Type::Block(statements) | Type::Module(statements) => {
+            scopes.push();
+            statements.iter().for_each(|statement| collect_free_variables(statement, scopes, free));
+            scopes.pop();
+        }
+    }
+}
+
+/// Binds a name without checking for duplicates, which `resolve` already reported
+fn bind_unchecked(name: &AST, scopes: &mut Scopes) {
+    if let (Some(identifier), Some(scope)) = (binding_name(name), scopes.scopes.last_mut()) {
+        scope.insert(identifier, name.span());
+    }
+}
@@ -0,0 +1,36 @@
+// The standard library: modules written in moolang, imported like any other module.
+// They are installed as the `.moo` files of `$MOO_HOME/std`, where they can be read and
+// overridden, and embedded in the compiler as a fallback for when `MOO_HOME` isn't set.
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::frontend::modules::EXTENSION;
+use crate::sources;
+
+/// The modules of the standard library, as shipped in the `std` directory of the repository
+const EMBEDDED: &[(&str, &str)] = &[
+    ("func", include_str!("../../std/func.moo")),
+    ("math", include_str!("../../std/math.moo")),
+    ("ops", include_str!("../../std/ops.moo")),
+];
+
+/// The directory the standard library is installed in, `$MOO_HOME/std`,
+/// if `MOO_HOME` is set. Read once, at the first lookup.
+pub fn home() -> Option<&'static Path> {
+    static HOME: OnceLock<Option<PathBuf>> = OnceLock::new();
+    HOME.get_or_init(|| std::env::var_os("MOO_HOME").map(|home| PathBuf::from(home).join("std")))
+        .as_deref()
+}
+
+/// Finds the standard library module `name`, in `MOO_HOME` first.
+/// An embedded module is registered in `sources` under `<std>/name.moo`, which stands for its path.
+pub fn find(name: &str) -> Option<PathBuf> {
+    if let Some(path) = home().map(|home| home.join(name).with_extension(EXTENSION)).filter(|path| path.is_file()) {
+        return Some(path);
+    }
+    let (_, source) = EMBEDDED.iter().find(|(module, _)| *module == name)?;
+    let path = Path::new("<std>").join(name).with_extension(EXTENSION);
+    sources::register(&path, *source);
+    Some(path)
+}
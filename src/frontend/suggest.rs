@@ -0,0 +1,39 @@
+// "Did you mean" suggestions: the known name closest to a misspelled one, by edit distance.
+
+/// The candidate closest to `name`, if one is close enough to be what was meant:
+/// at most a third of the length of `name` away, and not as far as writing it anew, so single letters aren't suggested
+/// for each other. Ties go to the first candidate in alphabetical order, for the suggestion not to vary between runs
+pub fn similar<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let length = name.chars().count();
+    candidates.into_iter()
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= length.max(3) / 3 && *distance < length)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// The number of characters to insert, remove, replace or swap with the next one to turn `a` into `b`,
+/// the optimal string alignment distance
+fn distance(a: &str, b: &str) -> usize {
+    let (a, b) = (a.chars().collect::<Vec<_>>(), b.chars().collect::<Vec<_>>());
+    // `table[i][j]` is the distance between the first `i` characters of `a` and the first `j` of `b`
+    let mut table = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for i in 0..=a.len() {
+        for j in 0..=b.len() {
+            table[i][j] = match (i, j) {
+                (0, j) => j,
+                (i, 0) => i,
+                (i, j) => {
+                    let replaced = table[i - 1][j - 1] + (a[i - 1] != b[j - 1]) as usize;
+                    let best = replaced.min(table[i - 1][j] + 1).min(table[i][j - 1] + 1);
+                    match i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                        true => best.min(table[i - 2][j - 2] + 1),
+                        false => best,
+                    }
+                }
+            };
+        }
+    }
+    table[a.len()][b.len()]
+}
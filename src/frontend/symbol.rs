@@ -0,0 +1,100 @@
+// Interned strings, for the names and literals of the source.
+// Every distinct string is stored once, for the whole process, and referred to by its index:
+// symbols are copied instead of cloned, and compared as integers.
+
+use core::fmt;
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::{OnceLock, RwLock};
+
+use serde::{Serialize, Serializer};
+
+/// A string of the interner, e.g. the name of an identifier
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+/// The strings interned so far, each stored once. They are never freed, which lets symbols
+/// give them out as `&'static str`
+#[derive(Default)]
+struct Interner {
+    strings: Vec<&'static str>,
+    symbols: HashMap<&'static str, Symbol>,
+}
+
+fn interner() -> &'static RwLock<Interner> {
+    static INTERNER: OnceLock<RwLock<Interner>> = OnceLock::new();
+    INTERNER.get_or_init(Default::default)
+}
+
+impl Symbol {
+    /// Returns the symbol of `string`, interning it the first time
+    pub fn intern(string: &str) -> Symbol {
+        if let Some(symbol) = interner().read().unwrap().symbols.get(string) {
+            return *symbol;
+        }
+        let mut interner = interner().write().unwrap();
+        // another thread may have interned it in between
+        if let Some(symbol) = interner.symbols.get(string) {
+            return *symbol;
+        }
+        let symbol = Symbol(interner.strings.len() as u32);
+        let string: &'static str = Box::leak(string.to_owned().into_boxed_str());
+        interner.strings.push(string);
+        interner.symbols.insert(string, symbol);
+        symbol
+    }
+
+    /// Returns the interned string
+    pub fn as_str(self) -> &'static str {
+        interner().read().unwrap().strings[self.0 as usize]
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+    fn deref(&self) -> &Self::Target {
+        self.as_str()
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(string: &str) -> Self {
+        Symbol::intern(string)
+    }
+}
+
+impl From<String> for Symbol {
+    fn from(string: String) -> Self {
+        Symbol::intern(&string)
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl Serialize for Symbol {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
@@ -1,13 +1,25 @@
 
+use std::collections::VecDeque;
 use std::error::Error;
+use std::iter::Peekable;
+use std::ops::Range;
 use std::str::FromStr;
+use std::sync::Arc;
 use std::fmt;
-use itertools::Itertools;
 
-use crate::errors::{LocalizableError, LocalizedError};
+use serde::Serialize;
+use unicode_ident::{is_xid_continue, is_xid_start};
+use unicode_normalization::UnicodeNormalization;
 
+use crate::compile::{self, Phase};
+use crate::errors::{Coded, LocalizableError, LocalizedError};
+use crate::frontend::ast::{expected_found, Expected, ParseError};
+use crate::frontend::symbol::Symbol;
+use crate::messages::{message, Message};
+use crate::sources::{FileId, SourceFile};
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
 pub enum Operator{
     Add,
     Sub,
@@ -25,28 +37,66 @@ pub enum Operator{
     RParen,
     LCurl,
     RCurl,
+    LBracket,
+    RBracket,
+    Lt,
+    Gt,
+    Import,
+    Extern,
+    Dot,
+    As,
+    Eq,
+    Ne,
+    Le,
+    Ge,
+    And,
+    Or,
+    Arrow,
+    FatArrow,
 }
 
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub enum Type{
     Operator(Operator),
-    Literal(String),
+    Literal(Symbol),
+    // source text, parts - a string literal with expressions between braces, e.g. `"x = {x + 1}"`
+    Interpolated(Symbol, Vec<Part>),
 }
 
-#[derive(Debug)]
+/// A part of an interpolated string literal
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum Part {
+    /// Text between the expressions, as written in the source
+    Text(Symbol),
+    /// The tokens of an expression between braces
+    Code(Vec<Token>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Token{
+    #[serde(rename = "type")]
     pub type_: Type,
     pub location: Location,
+    /// The length of the source text of the token, in bytes. It may differ from the length
+    /// of its text, which is normalized
+    #[serde(skip)]
+    pub len: usize,
 }
 
 #[derive(Debug)]
 pub struct TokenError {
-    pub message: String,
+    pub message: Message,
 }
 
 impl Error for TokenError {}
 
+impl Coded for TokenError {
+    fn code(&self) -> Option<&'static str> {
+        self.message.code()
+    }
+}
+
 impl fmt::Display for TokenError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "TokenizerError: {}", self.message)
@@ -55,6 +105,80 @@ impl fmt::Display for TokenError {
 
 
 
+impl Operator {
+    /// Returns the source text of the operator
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Operator::Add => "+",
+            Operator::Sub => "-",
+            Operator::Mul => "*",
+            Operator::Div => "/",
+            Operator::Mod => "%",
+            Operator::Pow => "**",
+            Operator::Let => "let",
+            Operator::Fn => "fn",
+            Operator::Comma => ",",
+            Operator::Colon => ":",
+            Operator::Semicolon => ";",
+            Operator::Assign => "=",
+            Operator::LParen => "(",
+            Operator::RParen => ")",
+            Operator::LCurl => "{",
+            Operator::RCurl => "}",
+            Operator::LBracket => "[",
+            Operator::RBracket => "]",
+            Operator::Lt => "<",
+            Operator::Gt => ">",
+            Operator::Import => "import",
+            Operator::Extern => "extern",
+            Operator::Dot => ".",
+            Operator::As => "as",
+            Operator::Eq => "==",
+            Operator::Ne => "!=",
+            Operator::Le => "<=",
+            Operator::Ge => ">=",
+            Operator::And => "&&",
+            Operator::Or => "||",
+            Operator::Arrow => "->",
+            Operator::FatArrow => "=>",
+        }
+    }
+}
+
+impl Type {
+    /// Returns the kind of the token, e.g. `operator` or `literal`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Type::Operator(_) => "operator",
+            Type::Literal(_) | Type::Interpolated(..) => "literal",
+        }
+    }
+    /// Returns the source text of the token
+    pub fn text(&self) -> &str {
+        match self {
+            Type::Operator(op) => op.as_str(),
+            Type::Literal(s) | Type::Interpolated(s, _) => s.as_str(),
+        }
+    }
+}
+
+impl Token {
+    /// Returns the span of the token, which covers only itself
+    pub fn span(&self) -> Span {
+        Span::new(self.location, Location {
+            column: self.location.column + self.len,
+            offset: self.location.offset + self.len,
+            ..self.location
+        })
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>4}:{:<4} {:<8} {}", self.location.line, self.location.column, self.type_.kind(), self.type_.text())
+    }
+}
+
 impl FromStr for Type {
     type Err = TokenError;
 
@@ -75,37 +199,193 @@ impl FromStr for Type {
             ")" => Ok(Op(Operator::RParen)),
             "{" => Ok(Op(Operator::LCurl)),
             "}" => Ok(Op(Operator::RCurl)),
+            "[" => Ok(Op(Operator::LBracket)),
+            "]" => Ok(Op(Operator::RBracket)),
+            "<" => Ok(Op(Operator::Lt)),
+            ">" => Ok(Op(Operator::Gt)),
+            "." => Ok(Op(Operator::Dot)),
+            "==" => Ok(Op(Operator::Eq)),
+            "!=" => Ok(Op(Operator::Ne)),
+            "<=" => Ok(Op(Operator::Le)),
+            ">=" => Ok(Op(Operator::Ge)),
+            "&&" => Ok(Op(Operator::And)),
+            "||" => Ok(Op(Operator::Or)),
+            "->" => Ok(Op(Operator::Arrow)),
+            "=>" => Ok(Op(Operator::FatArrow)),
             "let" => Ok(Op(Operator::Let)),
             "fn" => Ok(Op(Operator::Fn)), 
-            _ if s.chars().all(|x| x.is_alphanumeric() || x == '_') => Ok(Type::Literal(s.to_owned())),
+            "import" => Ok(Op(Operator::Import)),
+            "extern" => Ok(Op(Operator::Extern)),
+            "as" => Ok(Op(Operator::As)),
+            _ if split_numeric(s).is_some_and(|(digits, suffix)| suffix.is_some_and(|suffix| !suffixes(digits).contains(&suffix))) => Err(TokenError {
+                message: message!("E0007", literal = s, suffixes = suffixes(s).join(", ")),
+            }),
+            _ if split_numeric(s).is_some() => Ok(Type::Literal(Symbol::intern(s))),
+            // identifiers are compared in NFC, whichever form they are written in
+            _ if s.chars().all(is_word) => Ok(Type::Literal(match s.is_ascii() {
+                true => Symbol::intern(s),
+                false => Symbol::from(s.nfc().collect::<String>()),
+            })),
+            _ if s.starts_with('"') => match s.len() >= 2 && s.ends_with('"') {
+                true => Ok(Type::Literal(Symbol::intern(s))),
+                false => Err(TokenError {
+                    message: message!("E0004", literal = s),
+                }),
+            },
+            // outside of comments and strings, a non-ASCII character which can't be part of an identifier
+            // is a token of its own
+            _ if !s.is_ascii() => Err(TokenError {
+                message: message!("E0008", character = s, codepoint = format!("U+{:04X}", s.chars().next().map_or(0, u32::from))),
+            }),
             _ => Err(TokenError {
-                message: format!("Invalid token: {}", s),
+                message: message!("E0003", token = s),
             }),
         }
     }
 }
 
+/// The suffixes giving a numeric literal its type, e.g. the `u8` of `255u8`
+pub const SUFFIXES: [&str; 10] = ["i8", "i16", "i32", "i64", "u8", "u16", "u32", "u64", "f32", "f64"];
+
+/// The suffixes making a numeric literal a float, e.g. the `f64` of `1f64`.
+/// Floats are 64 bit, `f32` rounds the literal to single precision.
+pub const FLOAT_SUFFIXES: [&str; 2] = ["f32", "f64"];
+
+/// The suffixes valid on a numeric literal, only the float ones if it has a fractional part
+fn suffixes(literal: &str) -> &'static [&'static str] {
+    match literal.contains('.') {
+        true => &FLOAT_SUFFIXES,
+        false => &SUFFIXES,
+    }
+}
+
+/// Splits a numeric literal into its digits and its type suffix, if any,
+/// e.g. `255u8` into `255` and `u8`, or `1.5f32` into `1.5` and `f32`.
+/// The digits may be separated by `_`, e.g. `1_000_000`. Returns `None` for other literals.
+/// The suffix may be any word, see `SUFFIXES` for the valid ones.
+pub fn split_numeric(literal: &str) -> Option<(&str, Option<&str>)> {
+    if !literal.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let end = literal.find(|c: char| !c.is_ascii_digit() && c != '_' && c != '.').unwrap_or(literal.len());
+    match &literal[end..] {
+        "" => Some((literal, None)),
+        suffix => Some((&literal[..end], Some(suffix))),
+    }
+}
+
+/// Replaces the escape sequences of the text of a string literal by the characters they stand for,
+/// e.g. `\\n` by a line break. An unknown escape sequence stands for the escaped character.
+pub fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => unescaped.push('\n'),
+                Some('t') => unescaped.push('\t'),
+                Some('r') => unescaped.push('\r'),
+                Some('0') => unescaped.push('\0'),
+                Some(escaped) => unescaped.push(escaped),
+                None => unescaped.push('\\'),
+            },
+            c => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+/// Whether a numeric literal is a float, by its fractional part or its suffix
+pub fn is_float(literal: &str) -> bool {
+    split_numeric(literal).is_some_and(|(digits, suffix)| {
+        digits.contains('.') || suffix.is_some_and(|suffix| FLOAT_SUFFIXES.contains(&suffix))
+    })
+}
 
-#[derive(Debug, Clone, Copy)]
+/// A position in the source
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
 pub struct Location {
+    /// The line, counting from 1
     pub line: usize,
+    /// The byte offset in the line
     pub column: usize,
+    /// The byte offset in the whole source
+    pub offset: usize,
+    /// The source, see `sources`
+    #[serde(skip)]
+    pub file: FileId,
 }
 
-impl Default for Location {
-    fn default() -> Self {
-        Self {
-            line: 0,
-            column: 0,
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[line:{},column:{}]", self.line, self.column)
+    }
+}
+
+/// The source from `start` up to `end` excluded, e.g. all the tokens of an expression
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Span {
+    pub start: Location,
+    pub end: Location,
+}
+
+impl Span {
+    pub fn new(start: Location, end: Location) -> Self {
+        Self { start, end }
+    }
+    /// Returns the smallest span covering both spans
+    pub fn to(self, other: Span) -> Span {
+        let key = |location: &Location| location.offset;
+        Span {
+            start: std::cmp::min_by_key(self.start, other.start, key),
+            end: std::cmp::max_by_key(self.end, other.end, key),
         }
     }
 }
 
+impl From<Location> for Span {
+    /// The empty span at `location`
+    fn from(location: Location) -> Self {
+        Self { start: location, end: location }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.start == self.end {
+            true => write!(f, "{}", self.start),
+            false => write!(f, "{}-{}", self.start, self.end),
+        }
+    }
+}
+
+/// A comment of the source, which the tokenizer keeps aside from the tokens when asked to, see `Tokenizer::keep_comments`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    /// The source text of the comment, with its `//` or `/* */`, and the lines of a block comment spanning several
+    pub text: String,
+    pub span: Span,
+}
+
+/// A stream of the tokens of a whole input, read line by line.
+/// Tokens carry their own location, so a statement may span any number of lines.
+/// An invalid token is yielded as an error, which ends the stream.
 pub struct Tokenizer<I> {
     lines: I,
-    tokens: Vec<Token>,
-    location: Location,
-    error: Option<LocalizedError>,
+    /// The tokens of the last line read which haven't been yielded yet, up to the first invalid one
+    tokens: VecDeque<Result<Token, LocalizedError>>,
+    /// The number of the last line read
+    line: usize,
+    /// The offset the next line starts at, for lines of no source, which count line breaks as one byte
+    offset: usize,
+    /// Whether an error was yielded, after which there are no more tokens
+    failed: bool,
+    /// Where the block comment the next line starts in was opened, if any
+    block_comment: Option<Location>,
+    /// The source the lines are from, if any, see `in_file`
+    source: Option<Arc<SourceFile>>,
+    /// The comments read so far, if they are kept
+    comments: Option<Vec<Comment>>,
 }
 
 impl <I, S> Tokenizer<I> 
@@ -114,111 +394,339 @@ where I: Iterator<Item = S>, S: AsRef<str>
     fn new(lines: I) -> Self {
         Self {
             lines,
-            location: Location { line: 0, column: 0 },
-            tokens: Vec::new(),
-            error: None,
-        }
-    }
-
-    pub fn error(self) -> Option<LocalizedError> {
-        self.error
-    }
-}
-
-// TODO
-// pub trait TokenizerExt {
-//     fn peek(&mut self) -> Option<&Token>;
-//     fn peek_type(&mut self) -> Option<&Type>;
-//     fn peek_operator(&mut self) -> Option<&Operator>;
-//     fn peek_literal(&mut self) -> Option<&String>;
-//     fn next_if(&mut self, type_: Type) -> bool;
-//     fn next_if_operator(&mut self, operator: Operator) -> bool;
-//     fn next_if_literal(&mut self, literal: &str) -> bool;
-//     fn next(&mut self) -> Option<Token>;
-//     fn expect(&mut self, type_: Type) -> Result<Token, CompileError>;
-//     fn expect_operator(&mut self, operator: Operator) -> Result<Token, CompileError>;
-//     fn expect_literal(&mut self, literal: &str) -> Result<Token, CompileError>;
-// }
-
-
-
-pub fn slice_into_snippets<'a>(line: &'a str) -> impl Iterator<Item = &'a str> {
-    let category = |c: char| -> u8 {
-        if c.is_whitespace() { 0 }
-        else if c.is_alphanumeric() { 1 }
-        else if c.is_ascii_punctuation() { 
-            match c {
-                '_' => 1, // same as alphanumeric
-                '(' => 2,
-                ')' => 3,
-                '{' => 4,
-                '}' => 5,
-                ';' => 6,
-                ':' => 7,
-                '=' => 8,
-                '+' => 9,
-                '-' => 10,
-                '*' => 11,
-                '/' => 12,
-                '%' => 13,
-                ',' => 14,
-                _ => 99,
+            tokens: VecDeque::new(),
+            line: 0,
+            offset: 0,
+            failed: false,
+            block_comment: None,
+            source: None,
+            comments: None,
+        }
+    }
+
+    /// Keeps the comments of the input as trivia, for `take_comments`, e.g. for the formatter to put them back
+    pub fn keep_comments(mut self) -> Self {
+        self.comments = Some(Vec::new());
+        self
+    }
+
+    /// Returns the comments of the lines read so far, in order, if they are kept
+    pub fn take_comments(&mut self) -> Vec<Comment> {
+        self.comments.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Locates the tokens in `source`, the lines being those of its text, for their diagnostics to be reported in it,
+    /// and their offsets to be those of the bytes of its text, whatever its line breaks
+    pub fn in_file(mut self, source: &Arc<SourceFile>) -> Self {
+        self.source = Some(source.clone());
+        self
+    }
+
+    /// Queues the tokens of the next line of the input
+    fn tokenize_line(&mut self, line: &str) {
+        let start = self.source.as_ref().map_or(self.offset, |source| source.offset(self.line, 0));
+        self.offset = start + line.len() + 1;
+        let file = self.source.as_ref().map_or(FileId::NONE, |source| source.id);
+        let locate = |column: usize| Location { line: self.line, column, offset: start + column, file };
+
+        let mut block_comment = self.block_comment.map(|location| location.column);
+        let (snippets, comments) = slice_into_snippets_and_comments(line, &mut block_comment);
+        if let Some(kept) = &mut self.comments {
+            for (i, range) in comments.into_iter().enumerate() {
+                let text = line[range.clone()].trim_end();
+                let end = locate(range.start + text.len());
+                match kept.last_mut() {
+                    // the rest of a block comment opened on an earlier line
+                    Some(comment) if i == 0 && self.block_comment.is_some() => {
+                        comment.text.push('\n');
+                        comment.text.push_str(text);
+                        comment.span.end = end;
+                    }
+                    _ => kept.push(Comment { text: text.to_owned(), span: Span::new(locate(range.start), end) }),
+                }
+            }
+        }
+        self.block_comment = match block_comment {
+            Some(column) => self.block_comment.or(Some(locate(column))),
+            None => None,
+        };
+
+        for (column, snippet) in snippets {
+            let token = token(snippet, column, &locate);
+            let failed = token.is_err();
+            self.tokens.push_back(token);
+            if failed {
+                break;
             }
         }
-        else { 99 }
+    }
+}
+
+/// Makes the token of a snippet of source text
+/// * `column` - the column the snippet starts at
+/// * `locate` - the location of a column of the line
+fn token(snippet: &str, column: usize, locate: &impl Fn(usize) -> Location) -> Result<Token, LocalizedError> {
+    let type_ = match interpolate(snippet, column, locate)? {
+        Some(parts) => Type::Interpolated(Symbol::intern(snippet), parts),
+        None => Type::from_str(snippet)
+            .map_err(|error| error.with_span(Span::new(locate(column), locate(column + snippet.len()))))?,
     };
+    Ok(Token { type_, location: locate(column), len: snippet.len() })
+}
 
-    line
-        .char_indices()
-        .inspect(|(_, c)| assert!(c.is_ascii()))
-        .group_by(move |(_, c)| category(*c))
-        .into_iter()
-        .filter(|(category, _)| *category != 0)
-        .map(|(_, mut group)| -> &str {
-            match (group.next(), group.last()) {
-                (Some((i, _)), Some((j, _))) => &line[i..j+1],
-                (Some((i, _)), None) => &line[i..i+1],
-                _ => panic!("Empty group"),
+/// Splits a string literal into its text and the expressions between braces, tokenized.
+/// Returns `None` for a snippet which isn't a string literal, or holds no expression.
+/// Braces are written `\{` and `\}` in the text. An expression can't hold a string literal,
+/// its quote would end the one around it.
+/// * `column` - the column the snippet starts at
+/// * `locate` - the location of a column of the line
+fn interpolate(snippet: &str, column: usize, locate: &impl Fn(usize) -> Location) -> Result<Option<Vec<Part>>, LocalizedError> {
+    let body = match snippet.len() >= 2 && snippet.starts_with('"') && snippet.ends_with('"') {
+        true => &snippet[1..snippet.len() - 1],
+        false => return Ok(None),
+    };
+    // the column of a byte of the body
+    let at = |i: usize| column + 1 + i;
+    let mut parts = Vec::new();
+    let mut text = 0;
+    let mut escaped = false;
+    let mut chars = body.char_indices();
+    while let Some((open, c)) = chars.next() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '{' => {
+                let mut depth = 1;
+                let close = chars.by_ref()
+                    .find(|(_, c)| {
+                        match c {
+                            '{' => depth += 1,
+                            '}' => depth -= 1,
+                            _ => (),
+                        }
+                        depth == 0
+                    })
+                    .map(|(close, _)| close)
+                    .ok_or_else(|| TokenError {
+                        message: message!("E0009"),
+                    }.with_span(Span::new(locate(at(open)), locate(at(open) + 1))))?;
+                if text < open {
+                    parts.push(Part::Text(Symbol::intern(&body[text..open])));
+                }
+                let code = slice_into_snippets(&body[open + 1..close])
+                    .map(|(i, snippet)| token(snippet, at(open + 1 + i), locate))
+                    .collect::<Result<Vec<_>, _>>()?;
+                parts.push(Part::Code(code));
+                text = close + 1;
             }
-        })
-        .take_while(|s| !s.contains("//"))
-        .collect::<Vec<_>>()
-        .into_iter()
+            _ => (),
+        }
+    }
+    if parts.is_empty() {
+        return Ok(None);
+    }
+    if text < body.len() {
+        parts.push(Part::Text(Symbol::intern(&body[text..])));
+    }
+    Ok(Some(parts))
+}
+
+/// Conveniences for the parser over a stream of tokens: looking at the next token,
+/// and taking it only if it is the one expected.
+/// A token which isn't the one expected is left in the stream, for the error to point at.
+pub trait TokenizerExt {
+    /// Returns the next token if it's an operator, without taking it
+    fn peek_operator(&mut self) -> Option<Operator>;
+    /// Returns the text of the next token if it's a literal, without taking it
+    fn peek_literal(&mut self) -> Option<Symbol>;
+    /// Takes the next token if it is `operator`, returning whether it did
+    fn next_if_operator(&mut self, operator: Operator) -> bool;
+    /// Takes the next token, which has to be `operator`
+    fn expect_operator(&mut self, operator: Operator) -> Result<Token, ParseError>;
+    /// Takes the next token, which has to be a literal `accept` holds for
+    /// * `expected` - what the literal stands for in the error, e.g. `identifier`
+    fn expect_literal(&mut self, expected: &'static str, accept: impl FnOnce(&str) -> bool) -> Result<Symbol, ParseError>;
+    /// The error for finding the next token instead of one of `expected`
+    fn unexpected(&mut self, expected: Vec<Expected>) -> ParseError;
+}
+
+impl<I: Iterator<Item = Token>> TokenizerExt for Peekable<I> {
+    fn peek_operator(&mut self) -> Option<Operator> {
+        match self.peek() {
+            Some(Token { type_: Type::Operator(operator), .. }) => Some(*operator),
+            _ => None,
+        }
+    }
+
+    fn peek_literal(&mut self) -> Option<Symbol> {
+        match self.peek() {
+            Some(Token { type_: Type::Literal(literal), .. }) => Some(*literal),
+            _ => None,
+        }
+    }
+
+    fn next_if_operator(&mut self, operator: Operator) -> bool {
+        self.next_if(|token| token.type_ == Type::Operator(operator)).is_some()
+    }
+
+    fn expect_operator(&mut self, operator: Operator) -> Result<Token, ParseError> {
+        self.next_if(|token| token.type_ == Type::Operator(operator))
+            .ok_or_else(|| self.unexpected(vec![operator.into()]))
+    }
+
+    fn expect_literal(&mut self, expected: &'static str, accept: impl FnOnce(&str) -> bool) -> Result<Symbol, ParseError> {
+        match self.peek_literal() {
+            Some(literal) if accept(&literal) => {
+                self.next();
+                Ok(literal)
+            }
+            _ => Err(self.unexpected(vec![Expected::Kind(expected)])),
+        }
+    }
+
+    fn unexpected(&mut self, expected: Vec<Expected>) -> ParseError {
+        expected_found(expected, self.peek().cloned())
+    }
+}
+
+
+/// The operators spelled with punctuation, longest first: the scanner takes the longest one
+/// a line continues with, so `**` is one operator and `* *` two
+const PUNCTUATION: &[Operator] = &[
+    Operator::Pow, Operator::Eq, Operator::Ne, Operator::Le, Operator::Ge,
+    Operator::And, Operator::Or, Operator::Arrow, Operator::FatArrow,
+    Operator::Add, Operator::Sub, Operator::Mul, Operator::Div, Operator::Mod,
+    Operator::Comma, Operator::Colon, Operator::Semicolon, Operator::Assign,
+    Operator::LParen, Operator::RParen, Operator::LCurl, Operator::RCurl,
+    Operator::LBracket, Operator::RBracket, Operator::Lt, Operator::Gt, Operator::Dot,
+];
+
+/// Whether `c` can start an identifier, a keyword or a number.
+/// Identifiers follow UAX #31, e.g. `π` or `変数`, numbers are made of ASCII digits.
+fn is_word_start(c: char) -> bool {
+    is_xid_start(c) || c == '_' || c.is_ascii_digit()
+}
+
+/// Whether `c` can continue an identifier, a keyword or a number
+fn is_word(c: char) -> bool {
+    is_xid_continue(c)
+}
+
+/// Slices the line into the source text of its tokens, along with the byte offsets they start at
+pub fn slice_into_snippets(line: &str) -> impl Iterator<Item = (usize, &str)> {
+    slice_into_snippets_after(line, &mut None)
+}
+
+/// Like `slice_into_snippets`, for a line which may start inside a block comment.
+/// * `block_comment` - the column of the `/*` opening the block comment the line starts in, if any,
+///   updated for the next line. For a comment opened on an earlier line, the column is on that line.
+pub fn slice_into_snippets_after<'a>(line: &'a str, block_comment: &mut Option<usize>) -> impl Iterator<Item = (usize, &'a str)> {
+    slice_into_snippets_and_comments(line, block_comment).0.into_iter()
+}
+
+/// Like `slice_into_snippets_after`, also returning the byte ranges of the comments of the line,
+/// the first one starting at 0 if the line starts inside a block comment
+pub fn slice_into_snippets_and_comments<'a>(line: &'a str, block_comment: &mut Option<usize>) -> (Vec<(usize, &'a str)>, Vec<Range<usize>>) {
+    let mut snippets = Vec::new();
+    let mut comments = Vec::new();
+    // where the block comment the scanner is in starts on this line
+    let mut comment = block_comment.map(|_| 0);
+    let mut chars = line.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        match c {
+            // a block comment runs until the closing `*/`, across lines
+            _ if block_comment.is_some() => {
+                if c == '*' && chars.next_if(|(_, c)| *c == '/').is_some() {
+                    *block_comment = None;
+                    comments.extend(comment.take().map(|comment| comment..start + 2));
+                }
+            }
+            _ if c.is_whitespace() => (),
+            // a comment runs until the end of the line
+            '/' if chars.peek().map(|(_, c)| *c) == Some('/') => {
+                comments.push(start..line.len());
+                break;
+            }
+            '/' if chars.next_if(|(_, c)| *c == '*').is_some() => {
+                *block_comment = Some(start);
+                comment = Some(start);
+            }
+            // a string runs until the closing quote, whatever it contains
+            '"' => {
+                let mut end = line.len();
+                let mut escaped = false;
+                for (i, c) in chars.by_ref() {
+                    match c {
+                        _ if escaped => escaped = false,
+                        '\\' => escaped = true,
+                        '"' => {
+                            end = i + 1;
+                            break;
+                        }
+                        _ => (),
+                    }
+                }
+                snippets.push((start, &line[start..end]));
+            }
+            // a word runs until the next character which can't be part of an identifier or a number
+            _ if is_word_start(c) => {
+                let mut end = start + c.len_utf8();
+                while let Some((i, next)) = chars.next_if(|(_, next)| is_word(*next)) {
+                    end = i + next.len_utf8();
+                }
+                // the fractional part of a number, e.g. the `.5f32` of `1.5f32`
+                let fraction = line[end..].strip_prefix('.').is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_digit()));
+                if c.is_ascii_digit() && fraction {
+                    chars.next();
+                    end += 1;
+                    while let Some((i, next)) = chars.next_if(|(_, next)| is_word(*next)) {
+                        end = i + next.len_utf8();
+                    }
+                }
+                snippets.push((start, &line[start..end]));
+            }
+            // the longest operator the line continues with, or the single character for the tokenizer
+            // to report as invalid
+            _ => {
+                let len = PUNCTUATION.iter()
+                    .map(Operator::as_str)
+                    .find(|operator| line[start..].starts_with(operator))
+                    .map_or(c.len_utf8(), str::len);
+                while chars.next_if(|(i, _)| *i < start + len).is_some() {}
+                snippets.push((start, &line[start..start + len]));
+            }
+        }
+    }
+    comments.extend(comment.map(|comment| comment..line.len()));
+    (snippets, comments)
 }
 
 impl<I, S> Iterator for Tokenizer<I>
 where I: Iterator<Item = S>, S: AsRef<str>
 {
-    type Item = Token;
+    type Item = Result<Token, LocalizedError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.error.is_some() { return None; }
-        else if let Some(val) = self.tokens.pop() { 
-            self.location.column += 1;
-            return Some(val); 
-        } else {
-            let line = self.lines.next()?;
-            self.location.line += 1;
-            let snippets = slice_into_snippets(line.as_ref());
-
-            self.tokens = vec![];
-
-            for (i, snippet) in snippets.enumerate() {
-                let type_ = Type::from_str(snippet);
-                self.location.column = i;
-                match type_ {
-                    Ok(type_) => self.tokens.push(Token { type_, location: self.location }),
-                    Err(error) => {
-                        self.error = Some(error.with_location(self.location));
-                        break;
-                    }
-                }
+        // lines without tokens, e.g. blank lines, are skipped until a line has some
+        loop {
+            if self.failed {
+                return None;
             }
-    
-            self.tokens.reverse();
-
-            self.location.column = 0;
-            self.next()
+            if let Some(token) = self.tokens.pop_front() {
+                self.failed = token.is_err();
+                return Some(token);
+            }
+            let line = match self.lines.next() {
+                Some(line) => line,
+                None => {
+                    self.failed = true;
+                    return self.block_comment.take().map(|location| Err(TokenError {
+                        message: message!("E0005"),
+                    }.with_location(location)));
+                }
+            };
+            self.line += 1;
+            compile::time(Phase::Tokenize, || self.tokenize_line(line.as_ref()));
         }
     }
 }
@@ -0,0 +1,350 @@
+use core::fmt;
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::frontend::ast::{AST, Type, TypeName};
+use crate::frontend::resolve::binding_name;
+use crate::frontend::symbol::Symbol;
+use crate::frontend::tokenizer::Operator;
+
+/// The canonical representation of a type, as opposed to `TypeName`,
+/// which is a type as written in the source.
+/// Two types are the same type iff their `Ty`s are equal.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub enum Ty {
+    /// The type of expressions without a value, e.g. of an expression statement
+    Unit,
+    /// The default integer type, 64 bits wide, which mixes with integers of any width
+    Int,
+    /// An integer type of explicit width
+    Sized(IntTy),
+    /// A 64 bits floating point number
+    Float,
+    String,
+    // argument types, return type
+    Function(Vec<Ty>, Box<Ty>),
+    /// A function of any signature, written as a bare `fn`
+    AnyFunction,
+    // element type
+    Array(Box<Ty>),
+    // key type, value type
+    Map(Box<Ty>, Box<Ty>),
+    /// A type parameter, or a type the compiler knows nothing about
+    Named(String),
+}
+
+impl fmt::Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ty::Unit => write!(f, "unit"),
+            Ty::Int => write!(f, "int"),
+            Ty::Sized(int) => write!(f, "{}", int.name()),
+            Ty::Float => write!(f, "float"),
+            Ty::String => write!(f, "string"),
+            Ty::Function(args, ret) => {
+                write!(f, "fn(")?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ") -> {}", ret)
+            }
+            Ty::AnyFunction => write!(f, "fn"),
+            Ty::Array(element) => write!(f, "[{}]", element),
+            Ty::Map(key, value) => write!(f, "map<{}, {}>", key, value),
+            Ty::Named(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl Ty {
+    /// Replaces the type parameters occurring in the type by their `bindings`
+    pub fn substitute(&self, bindings: &HashMap<String, Ty>) -> Ty {
+        match self {
+            Ty::Named(name) => bindings.get(name).cloned().unwrap_or_else(|| self.clone()),
+            Ty::Function(args, ret) => Ty::Function(
+                args.iter().map(|arg| arg.substitute(bindings)).collect(),
+                Box::new(ret.substitute(bindings)),
+            ),
+            Ty::Array(element) => Ty::Array(Box::new(element.substitute(bindings))),
+            Ty::Map(key, value) => Ty::Map(Box::new(key.substitute(bindings)), Box::new(value.substitute(bindings))),
+            Ty::Unit | Ty::Int | Ty::Sized(_) | Ty::Float | Ty::String | Ty::AnyFunction => self.clone(),
+        }
+    }
+}
+
+/// The integer types of explicit width, `i64` being the default `int`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum IntTy {
+    I8,
+    I16,
+    I32,
+    U8,
+    U16,
+    U32,
+    U64,
+}
+
+impl IntTy {
+    pub const ALL: [IntTy; 7] = [IntTy::I8, IntTy::I16, IntTy::I32, IntTy::U8, IntTy::U16, IntTy::U32, IntTy::U64];
+
+    /// Returns the name of the type in annotations
+    pub fn name(self) -> &'static str {
+        match self {
+            IntTy::I8 => "i8",
+            IntTy::I16 => "i16",
+            IntTy::I32 => "i32",
+            IntTy::U8 => "u8",
+            IntTy::U16 => "u16",
+            IntTy::U32 => "u32",
+            IntTy::U64 => "u64",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<IntTy> {
+        IntTy::ALL.into_iter().find(|int| int.name() == name)
+    }
+
+    pub fn bits(self) -> u32 {
+        match self {
+            IntTy::I8 | IntTy::U8 => 8,
+            IntTy::I16 | IntTy::U16 => 16,
+            IntTy::I32 | IntTy::U32 => 32,
+            IntTy::U64 => 64,
+        }
+    }
+
+    pub fn is_signed(self) -> bool {
+        matches!(self, IntTy::I8 | IntTy::I16 | IntTy::I32)
+    }
+
+    /// Wraps `value` around to the width of the type, extended back to 64 bits,
+    /// like the sized integer arithmetic of every backend
+    pub fn wrap(self, value: i64) -> i64 {
+        let shift = 64 - self.bits();
+        match self.is_signed() {
+            true => (value << shift) >> shift,
+            false => ((value as u64) << shift >> shift) as i64,
+        }
+    }
+
+    /// Whether `value` is representable in the type
+    pub fn contains(self, value: i128) -> bool {
+        match self.is_signed() {
+            true => (-(1 << (self.bits() - 1))..(1 << (self.bits() - 1))).contains(&value),
+            false => (0..(1 << self.bits())).contains(&value),
+        }
+    }
+}
+
+impl Ty {
+    /// Whether values of the type are numbers, which can be cast to one another
+    pub fn is_numeric(&self) -> bool {
+        matches!(self, Ty::Int | Ty::Sized(_) | Ty::Float)
+    }
+
+    /// Whether the type is built from other types, e.g. a function type
+    pub fn is_composite(&self) -> bool {
+        matches!(self, Ty::Function(..) | Ty::Array(_) | Ty::Map(..))
+    }
+}
+
+/// Describes a mismatch between an `expected` and a `found` type.
+/// Composite types are rendered one above the other, with `^` under the
+/// parts that differ, so users don't have to compare long types by eye:
+/// ```text
+/// expected: fn(int, [string]) -> int
+///                    ^^^^^^
+///    found: fn(int, [int]) -> int
+///                    ^^^
+/// ```
+pub fn render_diff(expected: &Ty, found: &Ty) -> String {
+    if !expected.is_composite() && !found.is_composite() {
+        return format!("expected `{}`, found `{}`", expected, found);
+    }
+    let mut lhs = Marked::default();
+    let mut rhs = Marked::default();
+    diff(expected, found, &mut lhs, &mut rhs);
+    format!("expected: {}\n          {}\n   found: {}\n          {}", lhs.text, lhs.marker(), rhs.text, rhs.marker())
+}
+
+/// A rendered type along with the byte ranges of its differing parts
+#[derive(Default)]
+struct Marked {
+    text: String,
+    marks: Vec<(usize, usize)>,
+}
+
+impl Marked {
+    fn push(&mut self, text: &str) {
+        self.text.push_str(text);
+    }
+    fn push_marked(&mut self, ty: &Ty) {
+        let start = self.text.len();
+        self.text.push_str(&ty.to_string());
+        self.marks.push((start, self.text.len()));
+    }
+    /// The line of `^` under the marked parts
+    fn marker(&self) -> String {
+        let mut marker = String::new();
+        for (start, end) in self.marks.iter() {
+            marker.push_str(&" ".repeat(start - marker.len()));
+            marker.push_str(&"^".repeat(end - start));
+        }
+        marker
+    }
+}
+
+/// Renders `lhs` and `rhs` side by side, structurally, marking the subtypes that differ
+fn diff(lhs: &Ty, rhs: &Ty, lhs_out: &mut Marked, rhs_out: &mut Marked) {
+    match (lhs, rhs) {
+        _ if lhs == rhs => {
+            lhs_out.push(&lhs.to_string());
+            rhs_out.push(&rhs.to_string());
+        }
+        (Ty::Function(lhs_args, lhs_ret), Ty::Function(rhs_args, rhs_ret)) if lhs_args.len() == rhs_args.len() => {
+            lhs_out.push("fn(");
+            rhs_out.push("fn(");
+            for (i, (lhs_arg, rhs_arg)) in lhs_args.iter().zip(rhs_args).enumerate() {
+                if i > 0 {
+                    lhs_out.push(", ");
+                    rhs_out.push(", ");
+                }
+                diff(lhs_arg, rhs_arg, lhs_out, rhs_out);
+            }
+            lhs_out.push("): ");
+            rhs_out.push("): ");
+            diff(lhs_ret, rhs_ret, lhs_out, rhs_out);
+        }
+        (Ty::Array(lhs_element), Ty::Array(rhs_element)) => {
+            lhs_out.push("[");
+            rhs_out.push("[");
+            diff(lhs_element, rhs_element, lhs_out, rhs_out);
+            lhs_out.push("]");
+            rhs_out.push("]");
+        }
+        (Ty::Map(lhs_key, lhs_value), Ty::Map(rhs_key, rhs_value)) => {
+            lhs_out.push("map<");
+            rhs_out.push("map<");
+            diff(lhs_key, rhs_key, lhs_out, rhs_out);
+            lhs_out.push(", ");
+            rhs_out.push(", ");
+            diff(lhs_value, rhs_value, lhs_out, rhs_out);
+            lhs_out.push(">");
+            rhs_out.push(">");
+        }
+        _ => {
+            lhs_out.push_marked(lhs);
+            rhs_out.push_marked(rhs);
+        }
+    }
+}
+
+impl From<&Ty> for TypeName {
+    /// Writes the type as an annotation, in its canonical spelling
+    fn from(ty: &Ty) -> Self {
+        match ty {
+            Ty::Array(element) => TypeName::Array(Box::new(TypeName::from(&**element))),
+            Ty::Function(args, ret) => TypeName::Function(
+                args.iter().map(TypeName::from).collect(),
+                Box::new(TypeName::from(&**ret)),
+            ),
+            _ => TypeName::Named(ty.to_string()),
+        }
+    }
+}
+
+/// The type aliases in scope, by name.
+/// Aliases map to normalized types, so they never need to be resolved twice.
+#[derive(Debug, Clone)]
+pub struct Aliases {
+    aliases: HashMap<String, Ty>,
+}
+
+impl Default for Aliases {
+    /// The builtin aliases
+    fn default() -> Self {
+        Self {
+            aliases: HashMap::from([
+                ("str".to_owned(), Ty::String),
+                ("void".to_owned(), Ty::Unit),
+                ("i64".to_owned(), Ty::Int),
+                ("f64".to_owned(), Ty::Float),
+            ]),
+        }
+    }
+}
+
+impl Aliases {
+    /// Defines `name` as an alias of `type_name`
+    pub fn define(&mut self, name: &str, type_name: &TypeName) {
+        let ty = self.normalize(type_name);
+        self.aliases.insert(name.to_owned(), ty);
+    }
+
+    /// Returns the canonical type of an annotation, with every alias resolved
+    pub fn normalize(&self, type_name: &TypeName) -> Ty {
+        match type_name {
+            TypeName::Named(name) => match name.as_str() {
+                _ if self.aliases.contains_key(name) => self.aliases[name].clone(),
+                "unit" => Ty::Unit,
+                "int" => Ty::Int,
+                "float" => Ty::Float,
+                _ if IntTy::from_name(name).is_some() => Ty::Sized(IntTy::from_name(name).unwrap()),
+                "string" => Ty::String,
+                "fn" => Ty::AnyFunction,
+                _ => Ty::Named(name.clone()),
+            },
+            TypeName::Array(element) => Ty::Array(Box::new(self.normalize(element))),
+            TypeName::Function(args, ret) => Ty::Function(
+                args.iter().map(|arg| self.normalize(arg)).collect(),
+                Box::new(self.normalize(ret)),
+            ),
+        }
+    }
+}
+
+/// The function type of a lambda, or of an extern function, from its annotations
+pub fn lambda_type(lambda: &AST, aliases: &Aliases) -> Ty {
+    match &**lambda {
+        Type::Lambda(return_type, args, _) | Type::Extern(_, args, return_type) => Ty::Function(
+            args.iter()
+                .map(|arg| match &**arg {
+                    Type::TypedLiteral(_, type_name) => aliases.normalize(type_name),
+                    _ => unreachable!("arguments are annotated"),
+                })
+                .collect(),
+            Box::new(aliases.normalize(return_type)),
+        ),
+        _ => unreachable!("expected a lambda"),
+    }
+}
+
+/// The declared types of the module level bindings, in order of definition,
+/// with the type parameters of generic functions.
+/// The type of a binding is `None` if it is neither annotated nor a function.
+/// * `module` - the resolved module
+pub fn binding_types(module: &AST, aliases: &Aliases) -> Vec<(Symbol, Vec<Symbol>, Option<Ty>)> {
+    let statements = match &**module {
+        Type::Module(statements) => statements,
+        _ => return Vec::new(),
+    };
+    statements.iter()
+        .filter_map(|statement| match &**statement {
+            Type::Expression(Operator::Let, name, value) => Some((name, value)),
+            _ => None,
+        })
+        .map(|(name, value)| {
+            let binding = binding_name(name).expect("resolved binding");
+            match (&***name, &***value) {
+                (Type::TypedLiteral(_, type_name), _) => (binding, Vec::new(), Some(aliases.normalize(type_name))),
+                (_, Type::Lambda(..) | Type::Extern(..)) => (binding, Vec::new(), Some(lambda_type(value, aliases))),
+                (_, Type::Generic(params, lambda)) => (binding, params.clone(), Some(lambda_type(lambda, aliases))),
+                _ => (binding, Vec::new(), None),
+            }
+        })
+        .collect()
+}
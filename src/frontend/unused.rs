@@ -0,0 +1,134 @@
+// Finds the variables bound by `let` inside functions which are never used, to warn about them once resolved.
+// The code computing them is removed by the optimizations of the IR, see `ir::optimize`.
+// Module level bindings are the functions of the program, which are exported, and only unused
+// in a program with a `main` never calling them.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::errors::{Applicability, LocalizableError, LocalizedError, Suggestion, Warning};
+use crate::frontend::ast::{walk, Visitor, AST, Type};
+use crate::frontend::resolve::{binding_name, free_variables};
+use crate::frontend::symbol::Symbol;
+use crate::frontend::tokenizer::{Location, Operator, Span};
+use crate::lint::Lint;
+use crate::messages::message;
+
+/// Warns about the variables never used, except those named with a leading `_`
+/// * `module` - a resolved module, before monomorphization for generic functions to be warned about once
+pub fn unused_variables(module: &AST) -> Vec<LocalizedError> {
+    let mut uses = Uses::default();
+    uses.visit(module);
+    uses.bindings
+        .into_iter()
+        .filter(|binding| !binding.used && !binding.name.starts_with('_'))
+        .map(|binding| {
+            // the span of a binding covers its type too, e.g. `x: int`
+            let start = binding.span.start;
+            let end = Location { column: start.column + binding.name.len(), offset: start.offset + binding.name.len(), ..start };
+            let rename = Suggestion::replace(Span { start, end }, &binding.name, &format!("_{}", binding.name), Applicability::MachineApplicable);
+            Warning::new(Lint::UnusedVariables, message!("W0003", name = binding.name)).with_span(binding.span).with_suggestion(rename)
+        })
+        .collect()
+}
+
+/// Warns about the module level functions `main` never calls, directly or not, in a program with a `main`,
+/// except those named with a leading `_`, and those of the modules imported, which are libraries
+/// * `module` - a resolved module
+pub fn unused_functions(module: &AST) -> Vec<LocalizedError> {
+    let Type::Module(statements) = &**module else {
+        return Vec::new();
+    };
+    // the functions, with the span of their name and the names they refer to
+    let functions = statements.iter()
+        .filter_map(|statement| match &**statement {
+            Type::Expression(Operator::Let, name, value) if matches!(&***value, Type::Lambda(..) | Type::Generic(..)) =>
+                binding_name(name).map(|function| (function, (name.span(), free_variables(value)))),
+            _ => None,
+        })
+        .collect::<HashMap<_, _>>();
+    let main = Symbol::intern("main");
+    if !functions.contains_key(&main) {
+        return Vec::new();
+    }
+
+    let mut called = HashSet::from([main]);
+    let mut calling = vec![main];
+    while let Some(function) = calling.pop() {
+        for name in functions[&function].1.iter() {
+            if functions.contains_key(name) && called.insert(*name) {
+                calling.push(*name);
+            }
+        }
+    }
+    let mut unused = functions.into_iter()
+        .filter(|(name, _)| !called.contains(name) && !name.starts_with('_') && !name.contains('.'))
+        .map(|(name, (span, _))| (name, span))
+        .collect::<Vec<_>>();
+    unused.sort_by_key(|(_, span)| span.start.offset);
+    unused.into_iter()
+        .map(|(name, span)| Warning::new(Lint::UnusedFunctions, message!("W0004", name = name)).with_span(span))
+        .collect()
+}
+
+/// A variable bound by `let`
+struct Binding {
+    name: Symbol,
+    /// The span of the name in the binding
+    span: Span,
+    used: bool,
+}
+
+/// Finds which variables are used, following their scopes
+#[derive(Default)]
+struct Uses {
+    bindings: Vec<Binding>,
+    /// The variables in scope, by the index of their binding, innermost scope last.
+    /// Arguments are in scope too, without a binding
+    scopes: Vec<HashMap<Symbol, Option<usize>>>,
+}
+
+impl Uses {
+    fn bind(&mut self, name: &AST, binding: bool) {
+        let Some(symbol) = binding_name(name) else {
+            return;
+        };
+        let index = binding.then(|| {
+            self.bindings.push(Binding { name: symbol, span: name.span(), used: false });
+            self.bindings.len() - 1
+        });
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(symbol, index);
+        }
+    }
+}
+
+impl Visitor for Uses {
+    fn visit(&mut self, ast: &AST) {
+        match &**ast {
+            Type::Identifier(name) => {
+                // module level functions aren't in any scope
+                let binding = self.scopes.iter().rev().find_map(|scope| scope.get(name));
+                if let Some(Some(index)) = binding {
+                    self.bindings[*index].used = true;
+                }
+            }
+            // the value can't see the variable it's bound to
+            Type::Expression(Operator::Let, name, value) => {
+                self.visit(value);
+                self.bind(name, !self.scopes.is_empty());
+            }
+            Type::Lambda(_, args, body) => {
+                self.scopes.push(HashMap::new());
+                args.iter().for_each(|arg| self.bind(arg, false));
+                self.visit(body);
+                self.scopes.pop();
+            }
+            Type::Block(_) => {
+                self.scopes.push(HashMap::new());
+                walk(self, ast);
+                self.scopes.pop();
+            }
+            _ => walk(self, ast),
+        }
+    }
+}
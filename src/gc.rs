@@ -0,0 +1,42 @@
+// How the memory of the strings, arrays and closures of compiled programs is managed, see `runtime::HEADER`.
+// By default, codegen inserts the retains and releases of the objects, see `ir::rc`, which frees them
+// as soon as they are no longer used, but never frees objects referencing each other in a cycle.
+// The tracing collector frees those too: every function records the objects its values are in a frame
+// of a shadow stack, the roots, and the runtime marks the objects reachable from the frames of the functions running
+// to free the others, every so many allocations, see `runtime::collect`.
+
+use core::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Gc {
+    /// Count the references to every object, freeing it once the last one is dropped
+    #[default]
+    Rc,
+    /// Mark the objects reachable from the functions running, and free the others once in a while
+    Tracing,
+}
+
+impl fmt::Display for Gc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Gc::Rc => write!(f, "rc"),
+            Gc::Tracing => write!(f, "tracing"),
+        }
+    }
+}
+
+static GC: AtomicU8 = AtomicU8::new(Gc::Rc as u8);
+
+/// Sets how the programs compiled from now on manage their memory, and the runtime of the compiled code running
+pub fn set(gc: Gc) {
+    GC.store(gc as u8, Ordering::Relaxed);
+}
+
+/// How the programs manage their memory, by counting references unless set otherwise
+pub fn current() -> Gc {
+    match GC.load(Ordering::Relaxed) {
+        0 => Gc::Rc,
+        _ => Gc::Tracing,
+    }
+}
@@ -0,0 +1,502 @@
+// A tree-walking interpreter, the `interp` backend: evaluates the checked syntax tree directly,
+// without generating code. It runs where Cranelift can't, and is a reference for the semantics
+// of the code the JIT generates, which it follows: integers are 64 bits wide and wrap around unless set
+// otherwise, see `frontend::overflow`,
+// floats are kept as their bits, and closures capture the values of the variables they use when created.
+// Where compiled code traps, e.g. on a division by zero, the interpreter reports an error.
+// The calls of the functions without side effects, see `purity`, are memoized by their arguments when those are numbers.
+
+use core::fmt;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::rc::Rc;
+
+use crate::errors::{Coded, LocalizableError, LocalizedError};
+use crate::frontend::ast::{AST, Type};
+use crate::frontend::builtins::{self, Builtin};
+use crate::frontend::overflow::{self, Overflow};
+use crate::frontend::purity::pure_functions;
+use crate::frontend::resolve::{binding_name, free_variables};
+use crate::frontend::symbol::Symbol;
+use crate::frontend::tokenizer::{is_float, split_numeric, unescape, Operator};
+use crate::frontend::types::{Aliases, IntTy, Ty};
+use crate::interrupt;
+use crate::ir::{self, BinaryOp};
+use crate::messages::{message, Message};
+
+#[derive(Debug)]
+pub struct InterpError {
+    message: Message,
+}
+
+impl fmt::Display for InterpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "InterpError: {}", self.message)
+    }
+}
+
+impl Error for InterpError {}
+
+impl Coded for InterpError {
+    fn code(&self) -> Option<&'static str> {
+        self.message.code()
+    }
+}
+
+/// A value of the program being run
+#[derive(Clone)]
+pub enum Value<'a> {
+    /// An integer, or the bits of a float, like in compiled code
+    Int(i64),
+    String(Rc<str>),
+    Array(Rc<[Value<'a>]>),
+    Closure(Rc<Closure<'a>>),
+}
+
+impl fmt::Display for Value<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(value) => write!(f, "{}", value),
+            Value::String(string) => write!(f, "{}", string),
+            Value::Array(elements) => write!(f, "[{}]", elements.iter().map(Value::to_string).collect::<Vec<_>>().join(", ")),
+            Value::Closure(closure) => write!(f, "<fn {}>", closure.name),
+        }
+    }
+}
+
+/// A function along with the values it captured, see `free_variables`
+pub struct Closure<'a> {
+    /// The name of the function, `<enclosing function>::lambda` for lambdas
+    name: String,
+    lambda: &'a AST,
+    captures: Vec<(Symbol, Value<'a>)>,
+}
+
+/// Runs the module level functions of a checked module
+pub struct Interpreter<'a> {
+    globals: HashMap<Symbol, &'a AST>,
+    /// What the arithmetic does when it overflows
+    overflow: Overflow,
+    /// The lambdas of the module level functions without side effects
+    pure: HashSet<*const AST>,
+    /// The values the calls of those functions returned, by lambda and arguments
+    memo: RefCell<HashMap<(*const AST, Vec<i64>), Value<'a>>>,
+}
+
+impl<'a> Interpreter<'a> {
+    /// Loads the module level functions of a checked module
+    pub fn new(module: &'a AST) -> Result<Self, LocalizedError> {
+        let statements = match &**module {
+            Type::Module(statements) => statements,
+            _ => return Err(InterpError {
+                message: message!("E0401"),
+            }.with_location(*module.location())),
+        };
+        let mut globals = HashMap::new();
+        for statement in statements {
+            match &**statement {
+                Type::Expression(Operator::Let, name, lambda) if matches!(***lambda, Type::Lambda(..)) => {
+                    globals.insert(binding_name(name).expect("resolved binding"), &**lambda);
+                }
+                Type::Expression(Operator::Let, _, function) if matches!(***function, Type::Extern(..)) => {
+                    let Type::Extern(symbol, ..) = &***function else {
+                        unreachable!();
+                    };
+                    return Err(InterpError {
+                        message: message!("E0427", name = symbol),
+                    }.with_span(function.span()));
+                }
+                _ => return Err(InterpError {
+                    message: message!("E0402"),
+                }.with_span(statement.span())),
+            }
+        }
+        let pure = pure_functions(module).iter().map(|name| globals[name] as *const AST).collect();
+        Ok(Self { globals, overflow: overflow::current(), pure, memo: RefCell::default() })
+    }
+
+    /// The number of functions of the module, lambdas included
+    pub fn functions(&self) -> usize {
+        self.globals.values().map(|lambda| count_lambdas(lambda)).sum()
+    }
+
+    /// Calls the module level function `name` with `args`
+    pub fn call(&self, name: &str, args: Vec<Value<'a>>) -> Result<Value<'a>, LocalizedError> {
+        let closure = self.global(Symbol::from(name));
+        self.apply(&closure, args)
+    }
+
+    fn global(&self, name: Symbol) -> Rc<Closure<'a>> {
+        Rc::new(Closure {
+            name: name.to_string(),
+            lambda: self.globals[&name],
+            captures: Vec::new(),
+        })
+    }
+
+    /// Calls a closure with `args`, checked to be as many as it takes
+    fn apply(&self, closure: &Closure<'a>, args: Vec<Value<'a>>) -> Result<Value<'a>, LocalizedError> {
+        // like compiled code, on entry to every function
+        if interrupt::interrupted() {
+            interrupt::exit();
+        }
+        let Type::Lambda(_, params, body) = &**closure.lambda else {
+            unreachable!("only lambdas are called");
+        };
+        // a pure function returns the same value when called with the same arguments
+        let key = self.pure.contains(&(closure.lambda as *const AST))
+            .then(|| args.iter().map(|arg| match arg {
+                Value::Int(value) => Some(*value),
+                _ => None,
+            }).collect::<Option<Vec<_>>>())
+            .flatten()
+            .map(|args| (closure.lambda as *const AST, args));
+        if let Some(value) = key.as_ref().and_then(|key| self.memo.borrow().get(key).cloned()) {
+            return Ok(value);
+        }
+        let mut frame = Frame {
+            interpreter: self,
+            name: &closure.name,
+            scopes: vec![closure.captures.iter().cloned().collect()],
+        };
+        for (param, arg) in params.iter().zip(args) {
+            frame.define(binding_name(param).expect("resolved binding"), arg);
+        }
+        let value = frame.eval(body)?;
+        if let Some(key) = key {
+            self.memo.borrow_mut().insert(key, value.clone());
+        }
+        Ok(value)
+    }
+}
+
+/// The variables of a function being run
+struct Frame<'a, 'b> {
+    interpreter: &'b Interpreter<'a>,
+    /// The name of the function, to name its lambdas
+    name: &'b str,
+    /// Lexical scopes of the function, innermost last
+    scopes: Vec<HashMap<Symbol, Value<'a>>>,
+}
+
+impl<'a> Frame<'a, '_> {
+    fn eval(&mut self, expr: &'a AST) -> Result<Value<'a>, LocalizedError> {
+        use Operator::*;
+        use Type::Expression as Expr;
+        Ok(match &**expr {
+            Type::Literal(literal) if literal.starts_with('"') => Value::String(unescape(&literal[1..literal.len() - 1]).into()),
+            Type::Literal(literal) => {
+                let (digits, suffix) = split_numeric(literal).unwrap_or((literal, None));
+                let digits = digits.replace('_', "");
+                let value = match suffix {
+                    // floats are kept as their bits
+                    Some("f32") => digits.parse::<f32>().map(|value| f64::from(value).to_bits() as i64).map_err(drop),
+                    _ if is_float(literal) => digits.parse::<f64>().map(|value| value.to_bits() as i64).map_err(drop),
+                    // the values above `i64::MAX` keep their bits
+                    Some("u64") => digits.parse::<u64>().map(|value| value as i64).map_err(drop),
+                    _ => digits.parse::<i64>().map_err(drop),
+                }.map_err(|_| InterpError {
+                    message: message!("E0403", literal = literal),
+                }.with_span(expr.span()))?;
+                Value::Int(value)
+            }
+
+            Expr(operator @ (Add | Sub | Mul | Div | Mod | Pow), lhs, rhs) if self.interpreter.overflow != Overflow::Wrap => {
+                Value::Int(self.eval_checked(*operator, None, lhs, rhs, expr)?)
+            }
+            Expr(Pow, lhs, rhs) => Value::Int(self.eval_power(BinaryOp::Pow, lhs, rhs, expr)?),
+            Expr(operator @ (Add | Sub | Mul | Div | Mod), lhs, rhs) => {
+                let (lhs, rhs) = (self.eval_int(lhs)?, self.eval_int(rhs)?);
+                Value::Int(match operator {
+                    Add => lhs.wrapping_add(rhs),
+                    Sub => lhs.wrapping_sub(rhs),
+                    Mul => lhs.wrapping_mul(rhs),
+                    Div => checked(lhs.checked_div(rhs), lhs, rhs, expr)?,
+                    // the remainder of the smallest `int` by -1 doesn't overflow in compiled code
+                    _ => checked((rhs != 0).then(|| lhs.wrapping_rem(rhs)), lhs, rhs, expr)?,
+                })
+            }
+
+            Expr(Let, name, value) => {
+                let value = self.eval(value)?;
+                self.define(binding_name(name).expect("resolved binding"), value.clone());
+                value
+            }
+
+            Expr(Assign, name, value) => {
+                let value = self.eval(value)?;
+                let variable = binding_name(name)
+                    .and_then(|name| self.scopes.iter_mut().rev().find_map(|scope| scope.get_mut(&name)))
+                    .ok_or_else(|| unsupported(name))?;
+                *variable = value.clone();
+                value
+            }
+
+            Type::Identifier(name) => match self.local(*name) {
+                Some(value) => value,
+                None if self.interpreter.globals.contains_key(name) => Value::Closure(self.interpreter.global(*name)),
+                None => return Err(InterpError {
+                    message: message!("E0102", name = name),
+                }.with_span(expr.span())),
+            },
+
+            Type::Block(statements) => {
+                self.scopes.push(HashMap::new());
+                let mut value = Value::Int(0);
+                for statement in statements {
+                    value = self.eval(statement)?;
+                }
+                self.scopes.pop();
+                value
+            }
+
+            // values of sized integer types are kept extended to 64 bits,
+            // arithmetic is done on 64 bits and wrapped around afterwards, unless it doesn't wrap
+            Type::Truncate(int, truncated) => {
+                let value = match (int, &***truncated) {
+                    (_, Expr(operator @ (Add | Sub | Mul | Div | Mod | Pow), lhs, rhs)) if self.interpreter.overflow != Overflow::Wrap => {
+                        return Ok(Value::Int(self.eval_checked(*operator, Some(*int), lhs, rhs, truncated)?));
+                    }
+                    (_, Expr(Pow, lhs, rhs)) => self.eval_power(BinaryOp::of(Pow, Some(*int)), lhs, rhs, truncated)?,
+                    // the only type whose values don't all fit into a signed 64 bits integer
+                    (IntTy::U64, Expr(operator @ (Div | Mod), lhs, rhs)) => {
+                        let (lhs, rhs) = (self.eval_int(lhs)? as u64, self.eval_int(rhs)? as u64);
+                        let value = match operator {
+                            Div => lhs.checked_div(rhs),
+                            _ => lhs.checked_rem(rhs),
+                        };
+                        checked(value, lhs as i64, rhs as i64, truncated)? as i64
+                    }
+                    _ => self.eval_int(truncated)?,
+                };
+                Value::Int(int.wrap(value))
+            }
+
+            // floats are kept as their bits, the exponent is cast to a float by the checker
+            Type::Float(value) => match &***value {
+                Expr(Pow, lhs, rhs) => Value::Int(ir::float_power(self.eval_int(lhs)?, self.eval_int(rhs)?)),
+                _ => return Err(unsupported(value)),
+            },
+
+            Type::Cast(value, type_name, from) => {
+                let value = self.eval(value)?;
+                let to = Aliases::default().normalize(type_name);
+                cast(value, from.as_ref(), &to)
+                    .ok_or_else(|| InterpError {
+                        message: match from {
+                            Some(from) => message!("E0433", from = from, to = to),
+                            None => message!("E0434", to = to),
+                        },
+                    }.with_span(expr.span()))?
+            }
+
+            // `unit` is represented by 0
+            Type::Discard(value) => {
+                self.eval(value)?;
+                Value::Int(0)
+            }
+
+            // the parts are strings, the checker casts the expressions to strings
+            Type::Interpolation(parts) => {
+                let mut string = String::new();
+                for part in parts {
+                    string.push_str(&self.eval(part)?.to_string());
+                }
+                Value::String(string.into())
+            }
+
+            Type::Lambda(..) => {
+                // Module level functions are looked up by name, only locals need capturing.
+                let captures = free_variables(expr)
+                    .into_iter()
+                    .filter_map(|name| self.local(name).map(|value| (name, value)))
+                    .collect();
+                Value::Closure(Rc::new(Closure {
+                    name: format!("{}::lambda", self.name),
+                    lambda: expr,
+                    captures,
+                }))
+            }
+
+            Type::Call(callee, args) => {
+                if let Some(builtin) = self.builtin(callee) {
+                    return self.call_builtin(builtin, callee, args, expr);
+                }
+                let callee_value = self.eval(callee)?;
+                let args = args.iter().map(|arg| self.eval(arg)).collect::<Result<Vec<_>, _>>()?;
+                let Value::Closure(closure) = callee_value else {
+                    return Err(unsupported(expr));
+                };
+                let Type::Lambda(_, params, _) = &**closure.lambda else {
+                    unreachable!("only lambdas are called");
+                };
+                if params.len() != args.len() {
+                    let name = match &***callee {
+                        Type::Identifier(name) => name.to_string(),
+                        _ => closure.name.clone(),
+                    };
+                    return Err(InterpError {
+                        message: message!("E0312", name = name, expected = params.len(), given = args.len()),
+                    }.with_span(callee.span()));
+                }
+                self.interpreter.apply(&closure, args)?
+            }
+
+            _ => return Err(unsupported(expr)),
+        })
+    }
+
+    /// Evaluates an expression the checker typed as a number
+    fn eval_int(&mut self, expr: &'a AST) -> Result<i64, LocalizedError> {
+        match self.eval(expr)? {
+            Value::Int(value) => Ok(value),
+            _ => Err(unsupported(expr)),
+        }
+    }
+
+    /// Evaluates arithmetic on values of an integer type, `int` for `None`, which doesn't wrap around, see `ir::checked`
+    fn eval_checked(&mut self, operator: Operator, int: Option<IntTy>, lhs: &'a AST, rhs: &'a AST, expr: &AST) -> Result<i64, LocalizedError> {
+        let (lhs, rhs) = (self.eval_int(lhs)?, self.eval_int(rhs)?);
+        ir::checked(self.interpreter.overflow, BinaryOp::of(operator, int), int, lhs, rhs)
+            .map_err(|message| InterpError { message }.with_span(expr.span()))
+    }
+
+    /// Evaluates an integer power which wraps around, see `ir::power`
+    fn eval_power(&mut self, op: BinaryOp, lhs: &'a AST, rhs: &'a AST, expr: &AST) -> Result<i64, LocalizedError> {
+        let (lhs, rhs) = (self.eval_int(lhs)?, self.eval_int(rhs)?);
+        ir::power(op, lhs, rhs).ok_or_else(|| InterpError {
+            message: message!("E0413"),
+        }.with_span(expr.span()))
+    }
+
+    /// Binds `name` to a value in the innermost scope, shadowing any previous variable.
+    fn define(&mut self, name: Symbol, value: Value<'a>) {
+        self.scopes
+            .last_mut()
+            .expect("function scope")
+            .insert(name, value);
+    }
+
+    /// Calls `builtin` with `args`, evaluated first, checking their number as compiled code would.
+    /// `unit` is represented by 0, and floats by their bits
+    fn call_builtin(&mut self, builtin: Builtin, callee: &AST, args: &'a [AST], expr: &AST) -> Result<Value<'a>, LocalizedError> {
+        let args = args.iter().map(|arg| self.eval(arg)).collect::<Result<Vec<_>, _>>()?;
+        if args.len() != builtin.arity() {
+            return Err(InterpError {
+                message: message!("E0312", name = builtin.name(), expected = builtin.arity(), given = args.len()),
+            }.with_span(callee.span()));
+        }
+        if builtin.is_math() {
+            let args = args.iter()
+                .map(|arg| match arg {
+                    Value::Int(value) => Ok(*value),
+                    _ => Err(unsupported(expr)),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            return Ok(Value::Int(ir::math(builtin, &args)));
+        }
+        Ok(match (builtin, args.first()) {
+            (Builtin::ReadInt, _) => Value::Int(builtins::read_int().map_err(|message| InterpError { message }.with_span(expr.span()))?),
+            (Builtin::ReadLine, _) => Value::String(builtins::read_line().into()),
+            (Builtin::Len, Some(Value::Array(elements))) => Value::Int(elements.len() as i64),
+            (Builtin::At, Some(Value::Array(elements))) => match args[1] {
+                Value::Int(index) => {
+                    let offset = builtins::index(index, elements.len()).map_err(|message| InterpError { message }.with_span(expr.span()))?;
+                    elements[offset].clone()
+                }
+                _ => return Err(unsupported(expr)),
+            },
+            // the checker casts the argument to a string
+            (_, Some(Value::String(string))) => {
+                builtins::print(string, builtin == Builtin::Println);
+                Value::Int(0)
+            }
+            _ => return Err(unsupported(expr)),
+        })
+    }
+
+    /// The builtin `callee` refers to, unless a binding shadows it
+    fn builtin(&self, callee: &AST) -> Option<Builtin> {
+        match &**callee {
+            Type::Identifier(name) if self.local(*name).is_none() && !self.interpreter.globals.contains_key(name) => Builtin::from_name(*name),
+            _ => None,
+        }
+    }
+
+    /// Finds the innermost variable bound to `name` in this function.
+    fn local(&self, name: Symbol) -> Option<Value<'a>> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(&name)).cloned()
+    }
+}
+
+/// The result of a division, or the error compiled code would trap with
+fn checked<T>(value: Option<T>, lhs: i64, rhs: i64, expr: &AST) -> Result<T, LocalizedError> {
+    value.ok_or_else(|| InterpError {
+        message: match rhs {
+            0 => message!("E0413"),
+            _ => message!("E0414", lhs = lhs, rhs = rhs),
+        },
+    }.with_span(expr.span()))
+}
+
+/// Converts a number between types, like compiled code: floats are kept as their bits in an integer,
+/// converting them to integers saturates to 64 bits and then wraps around.
+/// Returns `None` for unsupported casts.
+fn cast<'a>(value: Value<'a>, from: Option<&Ty>, to: &Ty) -> Option<Value<'a>> {
+    let unsigned = |ty: &Ty| matches!(ty, Ty::Sized(int) if !int.is_signed());
+    let from = from?;
+    if from == to {
+        return Some(value);
+    }
+    let Value::Int(value) = value else {
+        return None;
+    };
+    let float = f64::from_bits(value as u64);
+    Some(match (from, to) {
+        (Ty::Int | Ty::Sized(_), Ty::Int) => Value::Int(value),
+        (Ty::Int | Ty::Sized(_), Ty::Sized(int)) => Value::Int(int.wrap(value)),
+        (from @ (Ty::Int | Ty::Sized(_)), Ty::Float) => match unsigned(from) {
+            true => Value::Int((value as u64 as f64).to_bits() as i64),
+            false => Value::Int((value as f64).to_bits() as i64),
+        },
+        // the values of the narrower integer types are extended to 64 bits by their signedness
+        (Ty::Sized(IntTy::U64), Ty::String) => Value::String((value as u64).to_string().into()),
+        (Ty::Int | Ty::Sized(_), Ty::String) => Value::String(value.to_string().into()),
+        (Ty::Float, Ty::String) => Value::String(float.to_string().into()),
+        (Ty::Float, to @ (Ty::Int | Ty::Sized(_))) => {
+            let value = match unsigned(to) {
+                true => float as u64 as i64,
+                false => float as i64,
+            };
+            match to {
+                Ty::Sized(int) => Value::Int(int.wrap(value)),
+                _ => Value::Int(value),
+            }
+        }
+        _ => return None,
+    })
+}
+
+/// The number of lambdas in a tree
+fn count_lambdas(ast: &AST) -> usize {
+    use crate::frontend::ast::{walk, Visitor};
+    struct Lambdas(usize);
+    impl Visitor for Lambdas {
+        fn visit(&mut self, ast: &AST) {
+            if let Type::Lambda(..) = &**ast {
+                self.0 += 1;
+            }
+            walk(self, ast)
+        }
+    }
+    let mut lambdas = Lambdas(0);
+    lambdas.visit(ast);
+    lambdas.0
+}
+
+fn unsupported(ast: &AST) -> LocalizedError {
+    InterpError {
+        message: message!("E0404", expression = format!("{:?}", ast)),
+    }.with_span(ast.span())
+}
@@ -0,0 +1,51 @@
+// Ctrl-C handling.
+// SIGINT only sets a flag, which the compiler checks between phases and functions
+// and compiled programs check on every function call, so both stop at a point
+// where the diagnostics so far can be printed and the output flushed.
+// A second Ctrl-C exits right away, in case the flag is never checked.
+
+use std::io::{IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// The exit code of an interrupted process, as if it had been killed by SIGINT
+pub const EXIT_CODE: i32 = 130;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Installs the SIGINT handler
+pub fn install() {
+    #[cfg(unix)]
+    unsafe {
+        libc::signal(libc::SIGINT, on_sigint as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn on_sigint(_: libc::c_int) {
+    if INTERRUPTED.swap(true, Ordering::SeqCst) {
+        // only async-signal-safe functions may be called here
+        unsafe { libc::_exit(EXIT_CODE) };
+    }
+}
+
+/// Whether Ctrl-C was pressed
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// The address of the flag set by Ctrl-C, a byte which compiled code polls
+#[cfg(feature = "jit")]
+pub fn flag() -> *const u8 {
+    INTERRUPTED.as_ptr() as *const u8
+}
+
+/// Exits after an interruption, flushing the output and resetting the colors of the terminal
+pub fn exit() -> ! {
+    let _ = std::io::stdout().flush();
+    let mut stderr = std::io::stderr();
+    if stderr.is_terminal() {
+        let _ = write!(stderr, "\x1b[0m");
+    }
+    let _ = writeln!(stderr, "Interrupted");
+    std::process::exit(EXIT_CODE)
+}
@@ -0,0 +1,536 @@
+// Lowering of a checked module into the IR, see `ir`.
+// Variables become the values assigned to them: assigning to a variable binds it to a new value,
+// which the code after the assignment uses.
+// The type of every value is worked out from the annotations of the functions along the way,
+// to tell the values which are objects apart, see `rc`. The objects a lambda captures come first in its environment.
+
+use std::collections::HashMap;
+
+use crate::errors::{LocalizableError, LocalizedError};
+use crate::frontend::ast::{AST, Type as AstType, TypeName};
+use crate::frontend::builtins::Builtin;
+use crate::frontend::overflow::{self, Overflow};
+use crate::frontend::resolve::{binding_name, free_variables};
+use crate::frontend::symbol::Symbol;
+use crate::frontend::tokenizer::{is_float, split_numeric, unescape, Operator, Span};
+use crate::frontend::types::{lambda_type, Aliases, IntTy, Ty};
+use crate::ir::{rc, BinaryOp, CType, Conversion, Extern, Format, Function, Inst, IrError, Program, Value};
+use crate::messages::message;
+
+/// Lowers every function of a checked module.
+///
+/// A function which fails to lower doesn't stop the others from being lowered,
+/// so the errors of every function are reported at once.
+/// * `globals` - the module level functions of the modules compiled before, with their types
+/// * `values` - the module level values defined outside of the module, e.g. by the REPL, with their types
+pub fn lower(module: &AST, globals: &HashMap<Symbol, Ty>, values: &HashMap<Symbol, Ty>) -> Result<Program, Vec<LocalizedError>> {
+    let statements = match &**module {
+        AstType::Module(statements) => statements,
+        _ => return Err(vec![IrError {
+            message: message!("E0401"),
+        }.with_location(*module.location())]),
+    };
+    let mut errors = Vec::new();
+
+    // Every module level function is known first, so that they can
+    // call each other regardless of the order they are defined in.
+    let mut globals = globals.clone();
+    let mut externs = HashMap::new();
+    let mut program = Program::default();
+    let mut pending = Vec::new();
+    for statement in statements {
+        match &**statement {
+            AstType::Expression(Operator::Let, name, function) if matches!(***function, AstType::Extern(..)) => {
+                let AstType::Extern(symbol, args, return_type) = &***function else {
+                    unreachable!();
+                };
+                let aliases = Aliases::default();
+                let params = args.iter()
+                    .map(|arg| match &**arg {
+                        AstType::TypedLiteral(_, type_name) => CType::of(&aliases.normalize(type_name)),
+                        _ => unreachable!("arguments are annotated"),
+                    })
+                    .collect::<Vec<_>>();
+                let name = binding_name(name).expect("resolved binding");
+                externs.insert(name, (program.externs.len(), lambda_type(function, &aliases)));
+                program.externs.push(Extern {
+                    symbol: *symbol,
+                    params,
+                    result: CType::of(&aliases.normalize(return_type)),
+                    span: function.span(),
+                });
+            }
+            AstType::Expression(Operator::Let, name, lambda) if matches!(***lambda, AstType::Lambda(..)) => {
+                let name = binding_name(name).expect("resolved binding");
+                globals.insert(name, lambda_type(lambda, &Aliases::default()));
+                pending.push(Pending { index: program.functions.len(), lambda, captures: Vec::new() });
+                program.functions.push(Function::declare(name.to_string(), Some(name), lambda, 0));
+            }
+            _ => errors.push(IrError {
+                message: message!("E0402"),
+            }.with_span(statement.span())),
+        }
+    }
+
+    // Lowering a function can lift more functions out of it (its lambdas),
+    // so keep going until there is nothing left to lower.
+    let mut lambda_count = 0;
+    let overflow = overflow::current();
+    while let Some(function) = pending.pop() {
+        let mut lowering = Lowering {
+            insts: Vec::new(),
+            spans: Vec::new(),
+            tys: Vec::new(),
+            span: function.lambda.span(),
+            variables: Vec::new(),
+            scopes: vec![HashMap::new()],
+            name: program.functions[function.index].name.clone(),
+            globals: &globals,
+            externs: &externs,
+            values,
+            functions: &mut program.functions,
+            lifted: Vec::new(),
+            lambda_count: &mut lambda_count,
+            overflow,
+        };
+        let lowered = lowering.lower_function(&function);
+        let lifted = std::mem::take(&mut lowering.lifted);
+        let insts = std::mem::take(&mut lowering.insts);
+        let spans = std::mem::take(&mut lowering.spans);
+        let managed = lowering.tys.iter().map(rc::is_object).collect();
+        let variables = std::mem::take(&mut lowering.variables);
+        match lowered {
+            Ok(result) => {
+                let lowered = &mut program.functions[function.index];
+                lowered.insts = insts;
+                lowered.spans = spans;
+                lowered.managed = managed;
+                lowered.variables = variables;
+                lowered.result = result;
+            }
+            Err(error) => errors.push(error),
+        }
+        pending.extend(lifted);
+    }
+
+    if !errors.is_empty() {
+        // report in source order rather than in the order of the worklist
+        errors.sort_by_key(|error| error.location().offset);
+        return Err(errors);
+    }
+    Ok(program)
+}
+
+impl Function {
+    /// A function still to be lowered
+    fn declare(name: String, global: Option<Symbol>, lambda: &AST, captures: usize) -> Function {
+        let AstType::Lambda(_, args, _) = &**lambda else {
+            unreachable!("only lambdas are lowered into functions");
+        };
+        Function {
+            name,
+            global,
+            params: args.len(),
+            captures,
+            ty: lambda_type(lambda, &Aliases::default()),
+            insts: Vec::new(),
+            managed: Vec::new(),
+            result: Value(0),
+            span: lambda.span(),
+            spans: Vec::new(),
+            variables: Vec::new(),
+        }
+    }
+}
+
+/// A function which was declared but still has to be lowered
+struct Pending<'a> {
+    /// The index of the function in the program
+    index: usize,
+    lambda: &'a AST,
+    /// The names the function captures from its environment, in environment order, with their types
+    captures: Vec<(Symbol, Ty)>,
+}
+
+/// A variable of the function being lowered
+#[derive(Clone, Copy)]
+struct Local {
+    value: Value,
+    /// The number of arguments, when the variable is known to hold a function
+    arity: Option<usize>,
+}
+
+/// The state of the lowering of a function
+struct Lowering<'a, 'b> {
+    insts: Vec<Inst>,
+    /// The span of every instruction
+    spans: Vec<Span>,
+    /// The type of every value
+    tys: Vec<Ty>,
+    /// The span of the expression being lowered, which its instructions come from
+    span: Span,
+    /// The variables bound so far, with the values bound to them
+    variables: Vec<(Symbol, Value)>,
+    /// Lexical scopes of the function, innermost last
+    scopes: Vec<HashMap<Symbol, Local>>,
+    /// The name of the function, used to name its lambdas
+    name: String,
+    /// The module level functions, with their types
+    globals: &'a HashMap<Symbol, Ty>,
+    /// The extern functions of the program, by name: their index in it and their type
+    externs: &'a HashMap<Symbol, (usize, Ty)>,
+    values: &'a HashMap<Symbol, Ty>,
+    functions: &'a mut Vec<Function>,
+    /// The lambdas lifted out of the function, to lower next
+    lifted: Vec<Pending<'b>>,
+    lambda_count: &'a mut usize,
+    /// What the arithmetic does when it overflows
+    overflow: Overflow,
+}
+
+impl<'a, 'b> Lowering<'a, 'b> {
+    /// Lowers the body of a function, returning its value
+    fn lower_function(&mut self, function: &Pending<'b>) -> Result<Value, LocalizedError> {
+        let AstType::Lambda(_, args, body) = &**function.lambda else {
+            unreachable!("only lambdas are lowered into functions");
+        };
+        // the captured variables come out of the closure environment
+        for (i, (capture, ty)) in function.captures.iter().enumerate() {
+            let value = self.push(Inst::Capture(i), ty.clone());
+            self.define(*capture, value, None);
+        }
+        let Ty::Function(params, _) = lambda_type(function.lambda, &Aliases::default()) else {
+            unreachable!("lambdas are functions");
+        };
+        for (i, (arg, ty)) in args.iter().zip(params).enumerate() {
+            let value = self.push(Inst::Param(i), ty);
+            self.define(binding_name(arg).expect("resolved binding"), value, arity(arg, None));
+        }
+        self.lower_expr(body)
+    }
+
+    /// Lowers an expression, its instructions coming from its span, except those of its subexpressions
+    fn lower_expr(&mut self, expr: &'b AST) -> Result<Value, LocalizedError> {
+        let outer = std::mem::replace(&mut self.span, expr.span());
+        let value = self.lower_node(expr);
+        self.span = outer;
+        value
+    }
+
+    fn lower_node(&mut self, expr: &'b AST) -> Result<Value, LocalizedError> {
+        use Operator::*;
+        use AstType::Expression as Expr;
+        Ok(match &**expr {
+            AstType::Literal(literal) if literal.starts_with('"') => self.push(Inst::String(unescape(&literal[1..literal.len() - 1])), Ty::String),
+            AstType::Literal(literal) => {
+                let (digits, suffix) = split_numeric(literal).unwrap_or((literal, None));
+                let digits = digits.replace('_', "");
+                let value = match suffix {
+                    // floats are kept as their bits
+                    Some("f32") => digits.parse::<f32>().map(|value| f64::from(value).to_bits() as i64).map_err(drop),
+                    _ if is_float(literal) => digits.parse::<f64>().map(|value| value.to_bits() as i64).map_err(drop),
+                    // the values above `i64::MAX` keep their bits
+                    Some("u64") => digits.parse::<u64>().map(|value| value as i64).map_err(drop),
+                    _ => digits.parse::<i64>().map_err(drop),
+                }.map_err(|_| IrError {
+                    message: message!("E0403", literal = literal),
+                }.with_span(expr.span()))?;
+                let ty = match suffix.and_then(IntTy::from_name) {
+                    Some(int) => Ty::Sized(int),
+                    None if is_float(literal) => Ty::Float,
+                    None => Ty::Int,
+                };
+                self.push(Inst::Const(value), ty)
+            }
+
+            Expr(operator @ (Add | Sub | Mul | Div | Mod | Pow), lhs, rhs) => self.lower_arithmetic(*operator, None, lhs, rhs)?,
+
+            Expr(Let, name, value) => {
+                let arity = arity(name, Some(&**value));
+                let value = self.lower_expr(value)?;
+                self.define(binding_name(name).expect("resolved binding"), value, arity);
+                value
+            }
+
+            Expr(Assign, name, value) => {
+                let value = self.lower_expr(value)?;
+                let (symbol, local) = binding_name(name)
+                    .and_then(|name| self.scopes.iter_mut().rev().find_map(|scope| scope.get_mut(&name)).map(|local| (name, local)))
+                    .ok_or_else(|| unsupported(name))?;
+                local.value = value;
+                self.variables.push((symbol, value));
+                value
+            }
+
+            AstType::Identifier(name) => match self.local(*name) {
+                Some(local) => local.value,
+                None if self.globals.contains_key(name) => self.push(Inst::Global(*name), self.globals[name].clone()),
+                None if self.values.contains_key(name) => self.push(Inst::Load(*name), self.values[name].clone()),
+                None => return Err(IrError {
+                    message: message!("E0102", name = name),
+                }.with_span(expr.span())),
+            },
+
+            AstType::Block(statements) => {
+                self.scopes.push(HashMap::new());
+                let mut value = None;
+                for statement in statements {
+                    value = Some(self.lower_expr(statement)?);
+                }
+                self.scopes.pop();
+                match value {
+                    Some(value) => value,
+                    None => self.push(Inst::Const(0), Ty::Unit),
+                }
+            }
+
+            // values of sized integer types are kept extended to 64 bits,
+            // arithmetic is done on 64 bits and wrapped around afterwards, unless it doesn't wrap
+            AstType::Truncate(int, value) => match &***value {
+                Expr(operator @ (Add | Sub | Mul | Div | Mod | Pow), lhs, rhs) => {
+                    let value = self.lower_arithmetic(*operator, Some(*int), lhs, rhs)?;
+                    // checked arithmetic stays in the range of the type
+                    match self.overflow {
+                        Overflow::Wrap => self.wrap(value, *int),
+                        Overflow::Trap | Overflow::Saturate => value,
+                    }
+                }
+                _ => {
+                    let value = self.lower_expr(value)?;
+                    self.wrap(value, *int)
+                }
+            },
+
+            // floats are kept as their bits, the exponent is cast to a float by the checker
+            AstType::Float(value) => match &***value {
+                Expr(Pow, lhs, rhs) => self.lower_binary(BinaryOp::FPow, lhs, rhs, Ty::Float)?,
+                _ => return Err(unsupported(value)),
+            },
+
+            AstType::Cast(value, type_name, from) => {
+                let value = self.lower_expr(value)?;
+                let to = Aliases::default().normalize(type_name);
+                self.lower_cast(value, from.as_ref(), &to)
+                    .ok_or_else(|| IrError {
+                        message: match from {
+                            Some(from) => message!("E0433", from = from, to = to),
+                            None => message!("E0434", to = to),
+                        },
+                    }.with_span(expr.span()))?
+            }
+
+            // `unit` is represented by 0
+            AstType::Discard(value) => {
+                self.lower_expr(value)?;
+                self.push(Inst::Const(0), Ty::Unit)
+            }
+
+            // the parts are strings, the checker casts the expressions to strings
+            AstType::Interpolation(parts) => {
+                let mut string = None;
+                for part in parts {
+                    let part = self.lower_expr(part)?;
+                    string = Some(match string {
+                        Some(string) => self.push(Inst::Concat(string, part), Ty::String),
+                        None => part,
+                    });
+                }
+                match string {
+                    Some(string) => string,
+                    None => self.push(Inst::String(String::new()), Ty::String),
+                }
+            }
+
+            AstType::Lambda(..) => self.lower_lambda(expr),
+
+            AstType::Call(callee, args) => self.lower_call(callee, args)?,
+
+            _ => return Err(unsupported(expr)),
+        })
+    }
+
+    /// Lowers arithmetic on values of an integer type, `int` for `None`, which wraps around
+    /// unless the arithmetic is set not to, see `Inst::Checked`
+    fn lower_arithmetic(&mut self, operator: Operator, int: Option<IntTy>, lhs: &'b AST, rhs: &'b AST) -> Result<Value, LocalizedError> {
+        let op = BinaryOp::of(operator, int);
+        let ty = int.map_or(Ty::Int, Ty::Sized);
+        if self.overflow == Overflow::Wrap {
+            return self.lower_binary(op, lhs, rhs, ty);
+        }
+        let lhs = self.lower_expr(lhs)?;
+        let rhs = self.lower_expr(rhs)?;
+        Ok(self.push(Inst::Checked(self.overflow, op, int, lhs, rhs), ty))
+    }
+
+    fn lower_binary(&mut self, op: BinaryOp, lhs: &'b AST, rhs: &'b AST, ty: Ty) -> Result<Value, LocalizedError> {
+        let lhs = self.lower_expr(lhs)?;
+        let rhs = self.lower_expr(rhs)?;
+        Ok(self.push(Inst::Binary(op, lhs, rhs), ty))
+    }
+
+    /// Wraps a value, extended to 64 bits, around to the width of `int`
+    fn wrap(&mut self, value: Value, int: IntTy) -> Value {
+        match int.bits() {
+            64 => value,
+            _ => self.push(Inst::Wrap(int, value), Ty::Sized(int)),
+        }
+    }
+
+    /// Converts a number between types. Floats are kept as their bits in an integer,
+    /// converting them to integers saturates to 64 bits and then wraps around.
+    /// Returns `None` for unsupported casts.
+    fn lower_cast(&mut self, value: Value, from: Option<&Ty>, to: &Ty) -> Option<Value> {
+        let unsigned = |ty: &Ty| matches!(ty, Ty::Sized(int) if !int.is_signed());
+        Some(match (from?, to) {
+            (from, to) if from == to => value,
+            (Ty::Int | Ty::Sized(_), Ty::Int) => value,
+            (Ty::Int | Ty::Sized(_), Ty::Sized(int)) => self.wrap(value, *int),
+            (from @ (Ty::Int | Ty::Sized(_)), Ty::Float) => match unsigned(from) {
+                true => self.push(Inst::Convert(Conversion::UnsignedToFloat, value), Ty::Float),
+                false => self.push(Inst::Convert(Conversion::SignedToFloat, value), Ty::Float),
+            },
+            // the values of the narrower integer types are extended to 64 bits by their signedness
+            (Ty::Sized(IntTy::U64), Ty::String) => self.push(Inst::Format(Format::Uint, value), Ty::String),
+            (Ty::Int | Ty::Sized(_), Ty::String) => self.push(Inst::Format(Format::Int, value), Ty::String),
+            (Ty::Float, Ty::String) => self.push(Inst::Format(Format::Float, value), Ty::String),
+            (Ty::Float, to @ (Ty::Int | Ty::Sized(_))) => {
+                let value = match unsigned(to) {
+                    true => self.push(Inst::Convert(Conversion::FloatToUnsigned, value), Ty::Int),
+                    false => self.push(Inst::Convert(Conversion::FloatToSigned, value), Ty::Int),
+                };
+                match to {
+                    Ty::Sized(int) => self.wrap(value, *int),
+                    _ => value,
+                }
+            }
+            _ => return None,
+        })
+    }
+
+    /// Closure conversion: the lambda is lifted into a function of its own,
+    /// and its value is a closure of that function capturing the variables it uses,
+    /// the objects first, see `jit::FunctionTranslator::translate_closure`.
+    fn lower_lambda(&mut self, lambda: &'b AST) -> Value {
+        // Module level functions are called directly, only locals need capturing.
+        let mut captures: Vec<_> = free_variables(lambda)
+            .into_iter()
+            .filter_map(|name| self.local(name).map(|local| (name, local.value)))
+            .collect();
+        captures.sort_by_key(|(_, value)| !rc::is_object(&self.tys[value.index()]));
+        let values = captures.iter().map(|(_, value)| *value).collect();
+        let captures = captures.into_iter().map(|(name, value)| (name, self.tys[value.index()].clone())).collect::<Vec<_>>();
+
+        let name = format!("{}::lambda{}", self.name, self.lambda_count);
+        *self.lambda_count += 1;
+        let index = self.functions.len();
+        self.functions.push(Function::declare(name, None, lambda, captures.len()));
+        self.lifted.push(Pending { index, lambda, captures });
+        self.push(Inst::Closure(index, values), lambda_type(lambda, &Aliases::default()))
+    }
+
+    fn lower_call(&mut self, callee: &'b AST, args: &'b [AST]) -> Result<Value, LocalizedError> {
+        // Calls to module level functions, extern functions and builtins by name are direct.
+        if let AstType::Identifier(name) = &**callee {
+            if let (None, Some((index, Ty::Function(params, result)))) = (self.local(*name), self.externs.get(name)) {
+                if params.len() != args.len() {
+                    return Err(IrError {
+                        message: message!("E0312", name = name, expected = params.len(), given = args.len()),
+                    }.with_span(callee.span()));
+                }
+                let args = self.lower_args(args)?;
+                return Ok(self.push(Inst::CallExtern(*index, args), (**result).clone()));
+            }
+            if let (None, Some(Ty::Function(params, result))) = (self.local(*name), self.globals.get(name)) {
+                if params.len() != args.len() {
+                    return Err(IrError {
+                        message: message!("E0312", name = name, expected = params.len(), given = args.len()),
+                    }.with_span(callee.span()));
+                }
+                let args = self.lower_args(args)?;
+                return Ok(self.push(Inst::Call(*name, args), (**result).clone()));
+            }
+            let builtin = Builtin::from_name(*name)
+                .filter(|_| self.local(*name).is_none() && !self.globals.contains_key(name) && !self.values.contains_key(name));
+            if let Some(builtin) = builtin {
+                if builtin.arity() != args.len() {
+                    return Err(IrError {
+                        message: message!("E0312", name = name, expected = builtin.arity(), given = args.len()),
+                    }.with_span(callee.span()));
+                }
+                let args = self.lower_args(args)?;
+                let ty = match builtin {
+                    Builtin::Print | Builtin::Println => Ty::Unit,
+                    Builtin::ReadInt | Builtin::Len => Ty::Int,
+                    Builtin::ReadLine => Ty::String,
+                    Builtin::At => match &self.tys[args[0].index()] {
+                        Ty::Array(element) => (**element).clone(),
+                        ty => unreachable!("`at` of a value of type `{}`", ty),
+                    },
+                    _ => Ty::Float,
+                };
+                return Ok(self.push(Inst::Builtin(builtin, args), ty));
+            }
+        }
+
+        // Everything else is a closure, called indirectly.
+        if let AstType::Identifier(name) = &**callee {
+            if let Some(Local { arity: Some(arity), .. }) = self.local(*name) {
+                if arity != args.len() {
+                    return Err(IrError {
+                        message: message!("E0312", name = name, expected = arity, given = args.len()),
+                    }.with_span(callee.span()));
+                }
+            }
+        }
+        let closure = self.lower_expr(callee)?;
+        let args = self.lower_args(args)?;
+        // the result of a bare `fn` is of a type nobody knows, which is taken not to be an object
+        let ty = match &self.tys[closure.index()] {
+            Ty::Function(_, result) => (**result).clone(),
+            _ => Ty::Int,
+        };
+        Ok(self.push(Inst::CallIndirect(closure, args), ty))
+    }
+
+    fn lower_args(&mut self, args: &'b [AST]) -> Result<Vec<Value>, LocalizedError> {
+        args.iter().map(|arg| self.lower_expr(arg)).collect()
+    }
+
+    /// Appends an instruction to the function, computing a value of type `ty`, returning the value
+    fn push(&mut self, inst: Inst, ty: Ty) -> Value {
+        self.insts.push(inst);
+        self.spans.push(self.span);
+        self.tys.push(ty);
+        Value(self.insts.len() as u32 - 1)
+    }
+
+    /// Binds `name` to a value in the innermost scope, shadowing any previous variable.
+    fn define(&mut self, name: Symbol, value: Value, arity: Option<usize>) {
+        self.variables.push((name, value));
+        self.scopes
+            .last_mut()
+            .expect("function scope")
+            .insert(name, Local { value, arity });
+    }
+
+    /// Finds the innermost variable bound to `name` in this function.
+    fn local(&self, name: Symbol) -> Option<Local> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(&name)).copied()
+    }
+}
+
+/// The number of arguments of the function bound by `binding`, if known
+/// from its type annotation, or else from the lambda assigned to it.
+fn arity(binding: &AST, value: Option<&AST>) -> Option<usize> {
+    match (&**binding, value.map(|value| &**value)) {
+        (AstType::TypedLiteral(_, TypeName::Function(args, _)), _) => Some(args.len()),
+        (_, Some(AstType::Lambda(_, args, _))) => Some(args.len()),
+        _ => None,
+    }
+}
+
+fn unsupported(ast: &AST) -> LocalizedError {
+    IrError {
+        message: message!("E0404", expression = format!("{:?}", ast)),
+    }.with_span(ast.span())
+}
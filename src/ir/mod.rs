@@ -0,0 +1,646 @@
+// The intermediate representation between the checked syntax tree and the backends.
+// A program is a list of functions, each a straight line of instructions in SSA form:
+// every instruction computes one value, named after its position in the function,
+// from the values computed before it. The language has no control flow yet, so there are no blocks.
+// The syntax tree is lowered into it by `lower`, optimized by `optimize`, then translated by the backends.
+// Closures are explicit: a lambda is lifted into a function of its own, taking the values it captures
+// from its environment, and creating a closure allocates that environment.
+// The backends generating code insert the retains and releases of the objects the values are, see `rc`.
+
+pub mod lower;
+pub mod optimize;
+pub mod rc;
+
+use core::fmt;
+use std::collections::HashMap;
+use std::error::Error;
+
+use crate::errors::{Coded, LocalizableError, LocalizedError};
+use crate::frontend::ast::AST;
+use crate::frontend::builtins::Builtin;
+use crate::frontend::optimize::{self as opt_level, OptLevel};
+use crate::frontend::overflow::Overflow;
+use crate::frontend::symbol::Symbol;
+use crate::frontend::tokenizer::{Operator, Span};
+use crate::frontend::types::{IntTy, Ty};
+use crate::messages::{message, Message};
+
+#[derive(Debug)]
+pub struct IrError {
+    message: Message,
+}
+
+impl fmt::Display for IrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "IrError: {}", self.message)
+    }
+}
+
+impl Error for IrError {}
+
+impl Coded for IrError {
+    fn code(&self) -> Option<&'static str> {
+        self.message.code()
+    }
+}
+
+/// Lowers a checked module into the IR, verified unless at `-O2`, and optimized from `-O1`.
+/// * `globals` - the module level functions of the modules compiled before, with their types
+/// * `values` - the module level values defined outside of the module, e.g. by the REPL, with their types
+pub fn compile(module: &AST, globals: &HashMap<Symbol, Ty>, values: &HashMap<Symbol, Ty>) -> Result<Program, Vec<LocalizedError>> {
+    let mut program = lower::lower(module, globals, values)?;
+    let level = opt_level::current();
+    if level < OptLevel::O2 {
+        verify(&program).map_err(|error| vec![IrError {
+            message: message!("E0412", error = error),
+        }.with_location(*module.location())])?;
+    }
+    if level >= OptLevel::O1 {
+        optimize::optimize(&mut program);
+    }
+    Ok(program)
+}
+
+/// A value computed by an instruction, by the index of the instruction in its function
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Value(pub u32);
+
+impl Value {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v{}", self.0)
+    }
+}
+
+/// The functions of a module, the module level ones and the lambdas lifted out of them,
+/// along with the extern functions it declares
+#[derive(Debug, Clone, Default)]
+pub struct Program {
+    pub functions: Vec<Function>,
+    pub externs: Vec<Extern>,
+}
+
+/// A function of another library, e.g. of C, declared with `extern fn`, which the program calls by its symbol
+#[derive(Debug, Clone)]
+pub struct Extern {
+    /// The name of its symbol, which the binding of the function is, unless qualified by the module declaring it
+    pub symbol: Symbol,
+    pub params: Vec<CType>,
+    pub result: CType,
+    /// Where the function is declared in the source
+    pub span: Span,
+}
+
+/// How a value is passed to an extern function, or returned by it, following the C calling convention
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CType {
+    /// An `int64_t`
+    Int,
+    /// An integer of explicit width, e.g. an `int32_t` for `i32`
+    Sized(IntTy),
+    /// A `double`
+    Float,
+    /// A pointer to the bytes of a string, followed by a null byte, see `runtime::encode`
+    String,
+    /// `void`, returning `unit`
+    Unit,
+}
+
+impl CType {
+    /// The C type of a type the checker allows for the arguments and the result of extern functions
+    pub fn of(ty: &Ty) -> CType {
+        match ty {
+            Ty::Int => CType::Int,
+            Ty::Sized(int) => CType::Sized(*int),
+            Ty::Float => CType::Float,
+            Ty::String => CType::String,
+            Ty::Unit => CType::Unit,
+            _ => unreachable!("extern functions don't take nor return `{}`", ty),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CType::Int => "int",
+            CType::Sized(int) => int.name(),
+            CType::Float => "float",
+            CType::String => "string",
+            CType::Unit => "unit",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    /// The name of the function, `<enclosing function>::lambda<n>` for lambdas
+    pub name: String,
+    /// The name of a module level function, which other modules can call it by
+    pub global: Option<Symbol>,
+    /// The number of arguments, besides the closure environment every function takes first
+    pub params: usize,
+    /// The number of values the function captures, stored in its environment
+    pub captures: usize,
+    /// The type of the function, from its annotations
+    pub ty: Ty,
+    pub insts: Vec<Inst>,
+    /// Whether every value is an object whose memory is reference counted, a string, an array or a closure,
+    /// by instruction, see `rc`
+    pub managed: Vec<bool>,
+    /// The value the function returns
+    pub result: Value,
+    /// Where the function is defined in the source
+    #[cfg_attr(not(feature = "jit"), allow(dead_code))]
+    pub span: Span,
+    /// Where the code every instruction was lowered from is in the source, by instruction
+    pub spans: Vec<Span>,
+    /// The variables of the function, its arguments and captures included, with every value bound to them
+    /// in the order they are bound, for debuggers. A variable assigned to is bound to several values
+    #[cfg_attr(not(feature = "jit"), allow(dead_code))]
+    pub variables: Vec<(Symbol, Value)>,
+}
+
+/// An instruction, computing one value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inst {
+    /// An integer, or the bits of a float
+    Const(i64),
+    /// A string, allocated with the program
+    String(String),
+    /// An argument of the function, counting from 0
+    Param(usize),
+    /// A value captured by the function, counting from 0
+    Capture(usize),
+    Binary(BinaryOp, Value, Value),
+    /// Arithmetic on values of an integer type, `int` for `None`, whose results out of the range of the type
+    /// are reported as errors, or saturated to its bounds, rather than wrapped around, see `checked`.
+    /// Never with `Overflow::Wrap`, which is `Binary`
+    Checked(Overflow, BinaryOp, Option<IntTy>, Value, Value),
+    /// Wraps a value, extended to 64 bits, around to the width of a sized integer type
+    Wrap(IntTy, Value),
+    /// Converts a number between integers and floats, see `Conversion`
+    Convert(Conversion, Value),
+    /// Formats a number into a string through the runtime
+    Format(Format, Value),
+    /// Concatenates two strings through the runtime
+    Concat(Value, Value),
+    /// Creates a closure of a function of the program, by its index, capturing the values
+    Closure(usize, Vec<Value>),
+    /// Creates a closure of a module level function, of the program or compiled before it
+    Global(Symbol),
+    /// Reads a module level value defined outside of the program, e.g. by the REPL
+    Load(Symbol),
+    /// Calls a module level function by name
+    Call(Symbol, Vec<Value>),
+    /// Calls a closure with the arguments
+    CallIndirect(Value, Vec<Value>),
+    /// Calls a builtin with the arguments, see `frontend::builtins`
+    Builtin(Builtin, Vec<Value>),
+    /// Calls an extern function of the program, by its index, with the arguments
+    CallExtern(usize, Vec<Value>),
+    /// Takes a reference to an object, see `rc`
+    Retain(Value),
+    /// Drops a reference to an object, freeing it if it was the last one
+    Release(Value),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    /// Signed division, trapping on a division by zero and on an overflow
+    Div,
+    Rem,
+    /// Unsigned division, for `u64` values, trapping on a division by zero
+    UDiv,
+    URem,
+    /// Integer power, see `power`
+    Pow,
+    /// Integer power of a `u64` base
+    UPow,
+    /// Float power, through the `pow` function of the C library, see `float_power`
+    FPow,
+}
+
+impl BinaryOp {
+    /// The operation of an arithmetic operator on values of an integer type, `int` for `None`
+    pub fn of(operator: Operator, int: Option<IntTy>) -> BinaryOp {
+        match (operator, int) {
+            (Operator::Add, _) => BinaryOp::Add,
+            (Operator::Sub, _) => BinaryOp::Sub,
+            (Operator::Mul, _) => BinaryOp::Mul,
+            // the only type whose values don't all fit into a signed 64 bits integer
+            (Operator::Div, Some(IntTy::U64)) => BinaryOp::UDiv,
+            (Operator::Div, _) => BinaryOp::Div,
+            (Operator::Pow, Some(IntTy::U64)) => BinaryOp::UPow,
+            (Operator::Pow, _) => BinaryOp::Pow,
+            (_, Some(IntTy::U64)) => BinaryOp::URem,
+            _ => BinaryOp::Rem,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "add",
+            BinaryOp::Sub => "sub",
+            BinaryOp::Mul => "mul",
+            BinaryOp::Div => "div",
+            BinaryOp::Rem => "rem",
+            BinaryOp::UDiv => "udiv",
+            BinaryOp::URem => "urem",
+            BinaryOp::Pow => "pow",
+            BinaryOp::UPow => "upow",
+            BinaryOp::FPow => "fpow",
+        }
+    }
+
+    /// The operator of the source the operation comes from
+    pub fn operator(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div | BinaryOp::UDiv => "/",
+            BinaryOp::Rem | BinaryOp::URem => "%",
+            BinaryOp::Pow | BinaryOp::UPow | BinaryOp::FPow => "**",
+        }
+    }
+
+    pub fn is_division(self) -> bool {
+        matches!(self, BinaryOp::Div | BinaryOp::Rem | BinaryOp::UDiv | BinaryOp::URem)
+    }
+
+    pub fn is_power(self) -> bool {
+        matches!(self, BinaryOp::Pow | BinaryOp::UPow)
+    }
+}
+
+/// An integer power on 64 bits, wrapping around, by squaring, or `None` for zero to a negative power,
+/// which like the division it stands for is a division by zero.
+/// The exponent is an `int`, whatever its type: negative powers round toward zero like divisions,
+/// to 0 unless the base is 1, or -1 if it's signed
+pub fn power(op: BinaryOp, base: i64, exponent: i64) -> Option<i64> {
+    if exponent < 0 {
+        return match base {
+            0 => None,
+            1 => Some(1),
+            -1 if op == BinaryOp::Pow => Some(if exponent % 2 == 0 { 1 } else { -1 }),
+            _ => Some(0),
+        };
+    }
+    let (mut base, mut exponent, mut result) = (base, exponent, 1i64);
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = result.wrapping_mul(base);
+        }
+        base = base.wrapping_mul(base);
+        exponent >>= 1;
+    }
+    Some(result)
+}
+
+/// A float power, of floats kept as their bits
+pub fn float_power(base: i64, exponent: i64) -> i64 {
+    f64::from_bits(base as u64).powf(f64::from_bits(exponent as u64)).to_bits() as i64
+}
+
+/// A math builtin, of floats kept as their bits, computed like the C library does
+pub fn math(builtin: Builtin, args: &[i64]) -> i64 {
+    let float = |index: usize| f64::from_bits(args[index] as u64);
+    let result = match builtin {
+        Builtin::Sqrt => float(0).sqrt(),
+        Builtin::Sin => float(0).sin(),
+        Builtin::Cos => float(0).cos(),
+        Builtin::Floor => float(0).floor(),
+        Builtin::Abs => float(0).abs(),
+        Builtin::Min => float(0).min(float(1)),
+        Builtin::Max => float(0).max(float(1)),
+        Builtin::Print | Builtin::Println | Builtin::ReadInt | Builtin::ReadLine | Builtin::Len | Builtin::At => unreachable!("`{}` isn't a math builtin", builtin.name()),
+    };
+    result.to_bits() as i64
+}
+
+/// The smallest and largest values of an integer type, `int` for `None`
+pub fn bounds(int: Option<IntTy>) -> (i128, i128) {
+    match int {
+        None => (i64::MIN.into(), i64::MAX.into()),
+        Some(int) if int.is_signed() => (-(1 << (int.bits() - 1)), (1 << (int.bits() - 1)) - 1),
+        Some(int) => (0, (1 << int.bits()) - 1),
+    }
+}
+
+/// The result of the arithmetic of `Inst::Checked` on values of an integer type, `int` for `None`,
+/// extended to 64 bits, or the message of the error reported instead
+pub fn checked(overflow: Overflow, op: BinaryOp, int: Option<IntTy>, lhs: i64, rhs: i64) -> Result<i64, Message> {
+    // the exponent of a power is an `int`
+    let (base, exponent) = (lhs, rhs);
+    let (lhs, rhs) = match int {
+        Some(IntTy::U64) => (i128::from(lhs as u64), i128::from(rhs as u64)),
+        _ => (i128::from(lhs), i128::from(rhs)),
+    };
+    if (op.is_division() && rhs == 0) || (op.is_power() && base == 0 && exponent < 0) {
+        return Err(message!("E0413"));
+    }
+    let exact = match op {
+        BinaryOp::Add => lhs + rhs,
+        BinaryOp::Sub => lhs - rhs,
+        // only the product of two `u64` values can be out of the range of `i128`, above it
+        BinaryOp::Mul => lhs.checked_mul(rhs).unwrap_or(i128::MAX),
+        BinaryOp::Div | BinaryOp::UDiv => lhs / rhs,
+        BinaryOp::Rem | BinaryOp::URem => lhs % rhs,
+        // the powers of -1, 0 and 1, and negative powers, don't overflow;
+        // the others are beyond the range of `i128` from an exponent of 128, on the side of their sign
+        BinaryOp::Pow | BinaryOp::UPow if exponent < 0 || (-1..=1).contains(&lhs) => {
+            i128::from(power(op, base, exponent).expect("zero to a negative power is checked"))
+        }
+        BinaryOp::Pow | BinaryOp::UPow => u32::try_from(exponent)
+            .ok()
+            .and_then(|exponent| lhs.checked_pow(exponent))
+            .unwrap_or(if lhs < 0 && exponent % 2 != 0 { i128::MIN } else { i128::MAX }),
+        BinaryOp::FPow => unreachable!("only integer arithmetic is checked"),
+    };
+    let (min, max) = bounds(int);
+    match (min..=max).contains(&exact) {
+        // `u64` values above `i64::MAX` keep their bits
+        true => Ok(exact as i64),
+        false => match overflow {
+            Overflow::Saturate => Ok(exact.clamp(min, max) as i64),
+            Overflow::Wrap | Overflow::Trap => Err(overflow_message(op, int)),
+        },
+    }
+}
+
+/// The message of the error arithmetic on values of an integer type, `int` for `None`, reports when it overflows
+pub fn overflow_message(op: BinaryOp, int: Option<IntTy>) -> Message {
+    match op {
+        // only the smallest value divided by -1 overflows
+        BinaryOp::Div | BinaryOp::UDiv => message!("E0414", lhs = bounds(int).0, rhs = -1),
+        _ => message!("E0423", operator = op.operator(), ty = int.map_or("int", IntTy::name)),
+    }
+}
+
+/// A conversion between integers, as their 64 bits, and floats, as their bits.
+/// Converting floats to integers saturates to 64 bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Conversion {
+    SignedToFloat,
+    UnsignedToFloat,
+    FloatToSigned,
+    FloatToUnsigned,
+}
+
+impl Conversion {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Conversion::SignedToFloat => "sint_to_float",
+            Conversion::UnsignedToFloat => "uint_to_float",
+            Conversion::FloatToSigned => "float_to_sint",
+            Conversion::FloatToUnsigned => "float_to_uint",
+        }
+    }
+}
+
+/// How a number is formatted into a string
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Int,
+    /// A `u64` value
+    Uint,
+    Float,
+}
+
+impl Format {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Format::Int => "format_int",
+            Format::Uint => "format_uint",
+            Format::Float => "format_float",
+        }
+    }
+
+    /// Formats a number, floats being passed as their bits
+    pub fn format(self, value: i64) -> String {
+        match self {
+            Format::Int => value.to_string(),
+            Format::Uint => (value as u64).to_string(),
+            Format::Float => f64::from_bits(value as u64).to_string(),
+        }
+    }
+}
+
+/// How the value returned by a `main` without arguments is printed, by its type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Printed {
+    /// A number, or a value of another type, e.g. `unit`, printed as an `int`
+    Number(Format),
+    /// A string, printed as its text
+    String,
+}
+
+impl Printed {
+    pub const ALL: [Printed; 4] = [Printed::Number(Format::Int), Printed::Number(Format::Uint), Printed::Number(Format::Float), Printed::String];
+
+    /// How a value of type `ty` is printed
+    pub fn of(ty: &Ty) -> Self {
+        match ty {
+            Ty::String => Printed::String,
+            Ty::Float => Printed::Number(Format::Float),
+            Ty::Sized(IntTy::U64) => Printed::Number(Format::Uint),
+            _ => Printed::Number(Format::Int),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Printed::Number(Format::Int) => "int",
+            Printed::Number(Format::Uint) => "uint",
+            Printed::Number(Format::Float) => "float",
+            Printed::String => "string",
+        }
+    }
+}
+
+impl Inst {
+    /// The values the instruction computes its value from
+    pub fn operands(&self) -> Vec<Value> {
+        match self {
+            Inst::Const(_) | Inst::String(_) | Inst::Param(_) | Inst::Capture(_) | Inst::Global(_) | Inst::Load(_) => Vec::new(),
+            Inst::Binary(_, lhs, rhs) | Inst::Checked(_, _, _, lhs, rhs) | Inst::Concat(lhs, rhs) => vec![*lhs, *rhs],
+            Inst::Wrap(_, value) | Inst::Convert(_, value) | Inst::Format(_, value) | Inst::Retain(value) | Inst::Release(value) => vec![*value],
+            Inst::Closure(_, values) | Inst::Call(_, values) | Inst::Builtin(_, values) | Inst::CallExtern(_, values) => values.clone(),
+            Inst::CallIndirect(callee, args) => std::iter::once(*callee).chain(args.iter().copied()).collect(),
+        }
+    }
+
+    /// Replaces the values the instruction computes its value from
+    pub fn map_operands(&mut self, mut f: impl FnMut(Value) -> Value) {
+        match self {
+            Inst::Const(_) | Inst::String(_) | Inst::Param(_) | Inst::Capture(_) | Inst::Global(_) | Inst::Load(_) => (),
+            Inst::Binary(_, lhs, rhs) | Inst::Checked(_, _, _, lhs, rhs) | Inst::Concat(lhs, rhs) => {
+                *lhs = f(*lhs);
+                *rhs = f(*rhs);
+            }
+            Inst::Wrap(_, value) | Inst::Convert(_, value) | Inst::Format(_, value) | Inst::Retain(value) | Inst::Release(value) => *value = f(*value),
+            Inst::Closure(_, values) | Inst::Call(_, values) | Inst::Builtin(_, values) | Inst::CallExtern(_, values) => values.iter_mut().for_each(|value| *value = f(*value)),
+            Inst::CallIndirect(callee, args) => {
+                *callee = f(*callee);
+                args.iter_mut().for_each(|value| *value = f(*value));
+            }
+        }
+    }
+
+    /// Whether computing the value may do anything else: call a function, which may do anything,
+    /// or trap. Instructions without effects whose value isn't used can be removed
+    pub fn has_effects(&self) -> bool {
+        match self {
+            Inst::Call(..) | Inst::CallIndirect(..) | Inst::CallExtern(..) | Inst::Retain(_) | Inst::Release(_) => true,
+            // printing and reading the input are effects, math isn't
+            Inst::Builtin(builtin, _) => !builtin.is_math(),
+            // zero to a negative power is a division by zero
+            Inst::Binary(op, ..) => op.is_division() || op.is_power(),
+            Inst::Checked(overflow, op, ..) => *overflow == Overflow::Trap || op.is_division() || op.is_power(),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Inst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list = |values: &[Value]| values.iter().map(Value::to_string).collect::<Vec<_>>().join(", ");
+        match self {
+            Inst::Const(value) => write!(f, "const {}", value),
+            Inst::String(string) => write!(f, "string {:?}", string),
+            Inst::Param(index) => write!(f, "param {}", index),
+            Inst::Capture(index) => write!(f, "capture {}", index),
+            Inst::Binary(op, lhs, rhs) => write!(f, "{} {}, {}", op.as_str(), lhs, rhs),
+            Inst::Checked(overflow, op, int, lhs, rhs) => {
+                let prefix = match overflow {
+                    Overflow::Saturate => "saturating",
+                    Overflow::Wrap | Overflow::Trap => "checked",
+                };
+                let suffix = int.map(|int| format!(".{}", int.name())).unwrap_or_default();
+                write!(f, "{}_{}{} {}, {}", prefix, op.as_str(), suffix, lhs, rhs)
+            }
+            Inst::Wrap(int, value) => write!(f, "wrap.{} {}", int.name(), value),
+            Inst::Convert(conversion, value) => write!(f, "{} {}", conversion.as_str(), value),
+            Inst::Format(format, value) => write!(f, "{} {}", format.as_str(), value),
+            Inst::Concat(lhs, rhs) => write!(f, "concat {}, {}", lhs, rhs),
+            Inst::Closure(function, captures) => write!(f, "closure f{}({})", function, list(captures)),
+            Inst::Global(name) => write!(f, "global {}", name),
+            Inst::Load(name) => write!(f, "load {}", name),
+            Inst::Call(name, args) => write!(f, "call {}({})", name, list(args)),
+            Inst::CallIndirect(callee, args) => write!(f, "call_indirect {}({})", callee, list(args)),
+            Inst::Builtin(builtin, args) => write!(f, "builtin {}({})", builtin.name(), list(args)),
+            Inst::CallExtern(index, args) => write!(f, "call_extern e{}({})", index, list(args)),
+            Inst::Retain(value) => write!(f, "retain {}", value),
+            Inst::Release(value) => write!(f, "release {}", value),
+        }
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "fn {}(params: {}, captures: {}) {{", self.name, self.params, self.captures)?;
+        for (i, inst) in self.insts.iter().enumerate() {
+            writeln!(f, "    v{} = {}", i, inst)?;
+        }
+        writeln!(f, "    return {}", self.result)?;
+        writeln!(f, "}}")
+    }
+}
+
+impl fmt::Display for Extern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let params = self.params.iter().map(|param| param.name()).collect::<Vec<_>>().join(", ");
+        writeln!(f, "extern {}({}) -> {}", self.symbol, params, self.result.name())
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, function) in self.externs.iter().enumerate() {
+            write!(f, "e{}: {}", i, function)?;
+        }
+        if !self.externs.is_empty() && !self.functions.is_empty() {
+            writeln!(f)?;
+        }
+        for (i, function) in self.functions.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "f{}: {}", i, function)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks that a program is well formed, returning what is wrong with it otherwise:
+/// every value is computed before it's used, arguments and captures exist,
+/// and closures and calls of the functions of the program get as many values as they take.
+/// A program which isn't well formed is a bug of the compiler.
+pub fn verify(program: &Program) -> Result<(), String> {
+    for function in program.functions.iter() {
+        verify_function(program, function).map_err(|error| format!("in `{}`: {}", function.name, error))?;
+    }
+    Ok(())
+}
+
+fn verify_function(program: &Program, function: &Function) -> Result<(), String> {
+    let defined = |value: Value, at: usize| match value.index() < at {
+        true => Ok(()),
+        false => Err(format!("v{} uses {} before it's computed", at, value)),
+    };
+    for (at, inst) in function.insts.iter().enumerate() {
+        inst.operands().into_iter().try_for_each(|value| defined(value, at))?;
+        match inst {
+            Inst::Param(index) if *index >= function.params => {
+                return Err(format!("v{} reads argument {} of {}", at, index, function.params));
+            }
+            Inst::Capture(index) if *index >= function.captures => {
+                return Err(format!("v{} reads capture {} of {}", at, index, function.captures));
+            }
+            Inst::Closure(index, captures) => match program.functions.get(*index) {
+                None => return Err(format!("v{} creates a closure of the missing function f{}", at, index)),
+                Some(callee) if callee.captures != captures.len() => {
+                    return Err(format!("v{} captures {} values for `{}`, which takes {}", at, captures.len(), callee.name, callee.captures));
+                }
+                Some(_) => (),
+            },
+            Inst::Call(name, args) => {
+                let callee = program.functions.iter().find(|callee| callee.global == Some(*name));
+                if let Some(callee) = callee.filter(|callee| callee.params != args.len()) {
+                    return Err(format!("v{} calls `{}` with {} arguments, which takes {}", at, name, args.len(), callee.params));
+                }
+            }
+            Inst::Builtin(builtin, args) if builtin.arity() != args.len() => {
+                return Err(format!("v{} calls `{}` with {} arguments, which takes {}", at, builtin.name(), args.len(), builtin.arity()));
+            }
+            Inst::CallExtern(index, args) => match program.externs.get(*index) {
+                None => return Err(format!("v{} calls the missing extern function e{}", at, index)),
+                Some(callee) if callee.params.len() != args.len() => {
+                    return Err(format!("v{} calls `{}` with {} arguments, which takes {}", at, callee.symbol, args.len(), callee.params.len()));
+                }
+                Some(_) => (),
+            },
+            _ => (),
+        }
+    }
+    if function.spans.len() != function.insts.len() {
+        return Err(format!("it has {} instructions but {} spans", function.insts.len(), function.spans.len()));
+    }
+    if function.managed.len() != function.insts.len() {
+        return Err(format!("it has {} instructions but {} are known to be objects or not", function.insts.len(), function.managed.len()));
+    }
+    if let Some((name, value)) = function.variables.iter().find(|(_, value)| value.index() >= function.insts.len()) {
+        return Err(format!("`{}` is bound to {}, which isn't computed", name, value));
+    }
+    match function.result.index() < function.insts.len() {
+        true => Ok(()),
+        false => Err(format!("it returns {}, which isn't computed", function.result)),
+    }
+}
@@ -0,0 +1,152 @@
+// The optimizations of the IR, from `-O1`:
+// constant folding replaces the arithmetic and the math builtins on constants by their result, e.g. `2 * 3 + 1` by `7`,
+// then dead code elimination removes the instructions whose value isn't used and which have no effects,
+// and the lambdas no closure is created of anymore.
+// The results are those of the generated code: arithmetic wraps around on 64 bits, then to the width
+// of sized integer types, unless it's checked or saturating, see `ir::checked`.
+// Divisions by zero, zero to a negative power, the smallest `int` divided by -1 and checked arithmetic
+// which overflows are left for the code to report when it runs.
+
+use crate::ir::{self, BinaryOp, Function, Inst, Program, Value};
+
+/// Optimizes every function of a verified program
+pub fn optimize(program: &mut Program) {
+    for function in program.functions.iter_mut() {
+        fold(function);
+        eliminate(function);
+    }
+    remove_unused_functions(program);
+}
+
+/// Folds the constant arithmetic of a function.
+/// An instruction computing the same value as another one is left unused, its uses using the other one.
+fn fold(function: &mut Function) {
+    // the value every value is replaced by
+    let mut replacements: Vec<Value> = Vec::with_capacity(function.insts.len());
+    for at in 0..function.insts.len() {
+        function.insts[at].map_operands(|value| replacements[value.index()]);
+        let constant = |value: Value| match function.insts[value.index()] {
+            Inst::Const(value) => Some(value),
+            _ => None,
+        };
+        let mut replacement = Value(at as u32);
+        let folded = match &function.insts[at] {
+            // `-x` is lowered to `0 - x`, so `- -x` is `x`
+            Inst::Binary(BinaryOp::Sub, zero, negated) if constant(*zero) == Some(0) => match function.insts[negated.index()] {
+                Inst::Binary(BinaryOp::Sub, inner_zero, value) if constant(inner_zero) == Some(0) => {
+                    replacement = value;
+                    None
+                }
+                _ => constant(*negated).map(i64::wrapping_neg),
+            },
+            Inst::Binary(op, lhs, rhs) => constant(*lhs)
+                .zip(constant(*rhs))
+                .and_then(|(lhs, rhs)| arithmetic(*op, lhs, rhs)),
+            Inst::Checked(overflow, op, int, lhs, rhs) => constant(*lhs)
+                .zip(constant(*rhs))
+                .and_then(|(lhs, rhs)| ir::checked(*overflow, *op, *int, lhs, rhs).ok()),
+            Inst::Wrap(int, value) => constant(*value).map(|value| int.wrap(value)),
+            Inst::Builtin(builtin, args) if builtin.is_math() => args.iter()
+                .map(|arg| constant(*arg))
+                .collect::<Option<Vec<_>>>()
+                .map(|args| ir::math(*builtin, &args)),
+            _ => None,
+        };
+        if let Some(folded) = folded {
+            function.insts[at] = Inst::Const(folded);
+        }
+        replacements.push(replacement);
+    }
+    function.result = replacements[function.result.index()];
+    for (_, value) in function.variables.iter_mut() {
+        *value = replacements[value.index()];
+    }
+}
+
+/// The result of arithmetic on 64 bits, if it doesn't trap
+fn arithmetic(op: BinaryOp, lhs: i64, rhs: i64) -> Option<i64> {
+    match op {
+        BinaryOp::Add => Some(lhs.wrapping_add(rhs)),
+        BinaryOp::Sub => Some(lhs.wrapping_sub(rhs)),
+        BinaryOp::Mul => Some(lhs.wrapping_mul(rhs)),
+        BinaryOp::Div => lhs.checked_div(rhs),
+        BinaryOp::Rem => lhs.checked_rem(rhs),
+        BinaryOp::UDiv => (lhs as u64).checked_div(rhs as u64).map(|value| value as i64),
+        BinaryOp::URem => (lhs as u64).checked_rem(rhs as u64).map(|value| value as i64),
+        BinaryOp::Pow | BinaryOp::UPow => ir::power(op, lhs, rhs),
+        BinaryOp::FPow => Some(ir::float_power(lhs, rhs)),
+    }
+}
+
+/// Removes the instructions whose value isn't used, unless they have effects,
+/// renumbering the values left. The variables bound to the values removed are left out
+fn eliminate(function: &mut Function) {
+    // the instructions only use the values computed before them
+    let mut live = vec![false; function.insts.len()];
+    live[function.result.index()] = true;
+    for at in (0..function.insts.len()).rev() {
+        if live[at] || function.insts[at].has_effects() {
+            live[at] = true;
+            function.insts[at].operands().into_iter().for_each(|value| live[value.index()] = true);
+        }
+    }
+
+    let mut renumbered = Vec::with_capacity(function.insts.len());
+    let mut insts = Vec::new();
+    let mut spans = Vec::new();
+    let mut managed = Vec::new();
+    let instructions = std::mem::take(&mut function.insts)
+        .into_iter()
+        .zip(std::mem::take(&mut function.spans))
+        .zip(std::mem::take(&mut function.managed));
+    for (((inst, span), object), live) in instructions.zip(live.iter()) {
+        renumbered.push(Value(insts.len() as u32));
+        if *live {
+            let mut inst = inst;
+            inst.map_operands(|value| renumbered[value.index()]);
+            insts.push(inst);
+            spans.push(span);
+            managed.push(object);
+        }
+    }
+    function.insts = insts;
+    function.spans = spans;
+    function.managed = managed;
+    function.result = renumbered[function.result.index()];
+    function.variables.retain(|(_, value)| live[value.index()]);
+    for (_, value) in function.variables.iter_mut() {
+        *value = renumbered[value.index()];
+    }
+}
+
+/// Removes the lambdas no closure is created of from the functions left,
+/// renumbering the functions left. Module level functions are exported, and always kept
+fn remove_unused_functions(program: &mut Program) {
+    let mut used: Vec<bool> = program.functions.iter().map(|function| function.global.is_some()).collect();
+    let mut worklist: Vec<usize> = (0..used.len()).filter(|index| used[*index]).collect();
+    while let Some(index) = worklist.pop() {
+        for inst in program.functions[index].insts.iter() {
+            if let Inst::Closure(lambda, _) = inst {
+                if !used[*lambda] {
+                    used[*lambda] = true;
+                    worklist.push(*lambda);
+                }
+            }
+        }
+    }
+
+    let mut renumbered = Vec::with_capacity(used.len());
+    let mut functions = Vec::new();
+    for (function, used) in std::mem::take(&mut program.functions).into_iter().zip(used) {
+        renumbered.push(functions.len());
+        if used {
+            functions.push(function);
+        }
+    }
+    for inst in functions.iter_mut().flat_map(|function| function.insts.iter_mut()) {
+        if let Inst::Closure(lambda, _) = inst {
+            *lambda = renumbered[*lambda];
+        }
+    }
+    program.functions = functions;
+}
@@ -0,0 +1,76 @@
+// Reference counting of the objects of a program: the strings, arrays and closures, see `runtime::HEADER`.
+// The backends generating code insert the retains and releases into the IR of the functions before translating it.
+//
+// A function owns a reference to the objects it computes, e.g. by concatenating strings or calling a function,
+// and releases it once its last use is done. It borrows the objects it is given, its arguments and captures,
+// and the module level values, which stay alive for as long as it runs.
+// A closure holds a reference to every object it captures, and a function returns a reference to its result,
+// so the objects stored or returned are retained first, unless they are owned already.
+
+use crate::frontend::types::Ty;
+use crate::ir::{Function, Inst, Program, Value};
+
+/// Whether the values of a type are objects, whose memory is reference counted
+pub fn is_object(ty: &Ty) -> bool {
+    matches!(ty, Ty::String | Ty::Array(_) | Ty::Function(..) | Ty::AnyFunction)
+}
+
+/// Inserts the retains and releases of the objects into every function of the program
+#[cfg_attr(not(feature = "jit"), allow(dead_code))]
+pub fn insert(program: &mut Program) {
+    program.functions.iter_mut().for_each(insert_function);
+}
+
+#[cfg_attr(not(feature = "jit"), allow(dead_code))]
+fn insert_function(function: &mut Function) {
+    let len = function.insts.len();
+    let owned = |at: usize| function.managed[at] && !matches!(function.insts[at], Inst::Param(_) | Inst::Capture(_) | Inst::Load(_));
+
+    // an object owned is released after the instruction using it last, or computing it if it's unused,
+    // but for the result, whose reference the function returns
+    let mut last_uses = (0..len).collect::<Vec<_>>();
+    for (at, inst) in function.insts.iter().enumerate() {
+        inst.operands().into_iter().for_each(|operand| last_uses[operand.index()] = at);
+    }
+    let mut releases = vec![Vec::new(); len];
+    for value in (0..len).filter(|value| owned(*value) && *value != function.result.index()) {
+        releases[last_uses[value]].push(value);
+    }
+
+    let mut insts = Vec::with_capacity(len);
+    let mut spans = Vec::with_capacity(len);
+    let mut managed = Vec::with_capacity(len);
+    // the new value of every value
+    let mut renumbered = Vec::with_capacity(len);
+    let mut push = |inst: Inst, at: usize, object: bool| {
+        insts.push(inst);
+        spans.push(function.spans[at]);
+        managed.push(object);
+        Value((insts.len() - 1) as u32)
+    };
+    for (at, inst) in function.insts.iter().enumerate() {
+        if let Inst::Closure(_, captures) = inst {
+            for capture in captures.iter().filter(|capture| function.managed[capture.index()]) {
+                push(Inst::Retain(renumbered[capture.index()]), at, false);
+            }
+        }
+        let mut inst = inst.clone();
+        inst.map_operands(|operand| renumbered[operand.index()]);
+        renumbered.push(push(inst, at, function.managed[at]));
+        for value in &releases[at] {
+            push(Inst::Release(renumbered[*value]), at, false);
+        }
+    }
+    let result = function.result.index();
+    if function.managed[result] && !owned(result) {
+        push(Inst::Retain(renumbered[result]), result, false);
+    }
+
+    function.insts = insts;
+    function.spans = spans;
+    function.managed = managed;
+    function.result = renumbered[result];
+    for (_, value) in function.variables.iter_mut() {
+        *value = renumbered[value.index()];
+    }
+}
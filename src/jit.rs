@@ -1,183 +1,526 @@
-
 // https://github.com/bytecodealliance/cranelift-jit-demo?tab=readme-ov-file
 
-use crate::frontend::*;
-use crate::frontend::ast::{AST, Type as AstType};
-use crate::frontend::tokenizer::Operator;
+use crate::compile::{self, Phase};
+use crate::errors::{Coded, LocalizableError, LocalizedError};
+use crate::frontend::ast::AST;
+use crate::frontend::builtins::Builtin;
+use crate::frontend::optimize::{self, OptLevel};
+use crate::frontend::overflow::Overflow;
+use crate::frontend::symbol::Symbol;
+use crate::frontend::tokenizer::Span;
+use crate::frontend::types::{IntTy, Ty};
+use crate::gc::{self, Gc};
+use crate::ir::{self, BinaryOp, CType, Conversion, Inst};
+#[cfg(feature = "aot")]
+use crate::debuginfo::FunctionDebugInfo;
+use crate::traps::{self, TrapSite};
+use crate::messages::Message;
+use crate::{interrupt, runtime};
 use cranelift::prelude::*;
+use cranelift::codegen::isa::OwnedTargetIsa;
 use cranelift_jit::{JITBuilder, JITModule};
-use cranelift_module::{DataDescription, Linkage, Module};
+use cranelift_module::{DataDescription, FuncId, Linkage, Module, ModuleError};
+use core::fmt;
 use std::collections::HashMap;
-use std::slice;
+use std::error::Error;
+use std::path::Path;
+#[cfg(feature = "aot")]
+use target_lexicon::Triple;
+
+/// Size in bytes of every value, and thus of every slot of a closure environment
+const SLOT: i32 = 8;
+
+/// The trap after calls to the runtime which don't return
+const UNREACHABLE: TrapCode = TrapCode::unwrap_user(1);
+
+#[derive(Debug)]
+pub struct CodegenError {
+    message: String,
+}
+
+impl fmt::Display for CodegenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CodegenError: {}", self.message)
+    }
+}
 
-/// The basic JIT class.
-pub struct JIT {
+impl Error for CodegenError {}
+
+impl Coded for CodegenError {
+    /// The errors of cranelift have no code
+    fn code(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+/// Generates the code of modules into a cranelift `Module`: the `JIT`,
+/// or an object file, see `object`.
+pub struct Codegen<M: Module> {
     /// The function builder context, which is reused across multiple
     /// FunctionBuilder instances.
     builder_context: FunctionBuilderContext,
 
-    /// The main Cranelift context, which holds the state for codegen. Cranelift
-    /// separates this from `Module` to allow for parallel compilation, with a
-    /// context per thread, though this isn't in the simple demo here.
+    /// The Cranelift context functions are translated into, each then moved
+    /// into a context of its own to be compiled in parallel, see `compile_contexts`
     ctx: codegen::Context,
 
-    /// The data description, which is to data objects what `ctx` is to functions.
-    data_description: DataDescription,
-
     /// The module, with the jit backend, which manages the JIT'd
     /// functions.
-    module: JITModule,
+    module: M,
+
+    /// Whether the generated code checks for Ctrl-C, which only code
+    /// running in the compiler's process can do
+    interruptible: bool,
+
+    /// The number of functions defined so far, lambdas included
+    defined: usize,
+
+    /// The module level functions compiled so far, which the next modules can call
+    globals: HashMap<Symbol, Global>,
+
+    /// The module level values defined outside of the compiled modules, e.g. by the REPL,
+    /// by the address of the 8 bytes holding them, with their types
+    values: HashMap<Symbol, (*const i64, Ty)>,
+
+    /// The code of the functions defined so far, when recording it, see `record_listings`
+    listings: Option<Vec<Listing>>,
+
+    /// The debug information of the functions defined so far, when recording it, see `record_debug_info`
+    #[cfg(feature = "aot")]
+    debug_info: Option<Vec<FunctionDebugInfo>>,
+
+    /// The instructions which can trap of the functions defined so far, when recording them, see `record_traps`
+    traps: Option<Vec<(FuncId, Vec<TrapSite>)>>,
+
+    /// The functions in memory whose traps were registered, to unregister them once freed, see `JIT::free`
+    registered: Vec<*const u8>,
+}
+
+/// The code generated for a function, to inspect it
+#[cfg_attr(not(feature = "aot"), allow(dead_code))]
+pub struct Listing {
+    /// The name of the function, `<enclosing function>::lambda<n>` for lambdas
+    pub name: String,
+    /// Where the function is defined in the source
+    pub offset: usize,
+    /// The Cranelift IR of the function, as translated
+    pub clif: String,
+    /// The Cranelift IR of the function, once optimized and legalized for the target
+    pub optimized_clif: String,
+    /// The machine instructions Cranelift selected for the function, as assembly
+    pub asm: String,
 }
 
+/// Compiles modules to machine code in memory, to run them in the compiler's process
+pub type JIT = Codegen<JITModule>;
+
 impl Default for JIT {
     fn default() -> Self {
-        let mut flag_builder = settings::builder();
-        flag_builder.set("use_colocated_libcalls", "false").unwrap();
-        flag_builder.set("is_pic", "false").unwrap();
-        let isa_builder = cranelift_native::builder().unwrap_or_else(|msg| {
-            panic!("host machine is not supported: {}", msg);
-        });
-        let isa = isa_builder
-            .finish(settings::Flags::new(flag_builder))
-            .unwrap();
-        let builder = JITBuilder::with_isa(isa, cranelift_module::default_libcall_names());
-
-        let module = JITModule::new(builder);
-        Self {
-            builder_context: FunctionBuilderContext::new(),
-            ctx: module.make_context(),
-            data_description: DataDescription::new(),
-            module,
-        }
+        let mut builder = JITBuilder::with_isa(isa(false), cranelift_module::default_libcall_names());
+        builder.symbols(runtime::symbols());
+        let mut jit = Codegen::new(JITModule::new(builder), true);
+        jit.record_traps();
+        jit
     }
 }
 
-impl JIT {
-    /// Compile a string in the toy language into machine code.
-    pub fn compile(&mut self, input: &str) -> Result<*const u8, String> {
-        // First, parse the string, producing AST nodes.
-        let (name, params, the_return, stmts) =
-            parser::function(input).map_err(|e| e.to_string())?;
-
-        // Then, translate the AST nodes into Cranelift IR.
-        self.translate(params, the_return, stmts)?;
-
-        // Next, declare the function to jit. Functions must be declared
-        // before they can be called, or defined.
-        //
-        // TODO: This may be an area where the API should be streamlined; should
-        // we have a version of `declare_function` that automatically declares
-        // the function?
-        let id = self
-            .module
-            .declare_function(&name, Linkage::Export, &self.ctx.func.signature)
-            .map_err(|e| e.to_string())?;
-
-        // Define the function to jit. This finishes compilation, although
-        // there may be outstanding relocations to perform. Currently, jit
-        // cannot finish relocations until all functions to be called are
-        // defined. For this toy demo for now, we'll just finalize the
-        // function below.
-        self.module
-            .define_function(id, &mut self.ctx)
-            .map_err(|e| e.to_string())?;
+/// The instruction set of the host machine
+/// * `pic` - whether to generate position independent code, as executables are linked from
+pub fn isa(pic: bool) -> OwnedTargetIsa {
+    let isa_builder = cranelift_native::builder().unwrap_or_else(|msg| {
+        panic!("host machine is not supported: {}", msg);
+    });
+    isa_builder
+        .finish(flags(pic))
+        .unwrap()
+}
+
+/// The instruction set of the machine described by a target triple, e.g. `aarch64-apple-darwin`,
+/// without the features of the host which `isa` enables
+#[cfg(feature = "aot")]
+pub fn target_isa(triple: &str, pic: bool) -> Result<OwnedTargetIsa, String> {
+    let triple = triple.parse::<Triple>().map_err(|e| e.to_string())?;
+    codegen::isa::lookup(triple)
+        .map_err(|e| e.to_string())?
+        .finish(flags(pic))
+        .map_err(|e| e.to_string())
+}
+
+/// The settings of the code generation for every machine, optimizing as set by `optimize`
+fn flags(pic: bool) -> settings::Flags {
+    let (opt_level, verify) = match optimize::current() {
+        OptLevel::O0 => ("none", true),
+        OptLevel::O1 => ("speed", true),
+        OptLevel::O2 => ("speed_and_size", false),
+    };
+    let mut flag_builder = settings::builder();
+    flag_builder.set("use_colocated_libcalls", "false").unwrap();
+    flag_builder.set("is_pic", if pic { "true" } else { "false" }).unwrap();
+    flag_builder.set("opt_level", opt_level).unwrap();
+    flag_builder.set("enable_verifier", if verify { "true" } else { "false" }).unwrap();
+    settings::Flags::new(flag_builder)
+}
 
-        // Now that compilation is finished, we can clear out the context state.
-        self.module.clear_context(&mut self.ctx);
+/// The symbol of the module level function `name`. Prefixed, so that functions
+/// can't clash with those of the runtime, nor with C functions once linked with them.
+pub fn symbol(name: &str) -> String {
+    format!("moo_{}", name)
+}
+
+/// A module level function, callable directly by name.
+#[derive(Clone)]
+struct Global {
+    id: FuncId,
+    ty: Ty,
+}
+
+impl JIT {
+    /// Compile a parsed module into machine code,
+    /// returning the address of every module level function.
+    /// The module can use the functions of the modules compiled before it.
+    /// * `source` - the source the module was read from, which the traps of its code are reported in, see `traps`
+    pub fn compile(&mut self, module: &AST, source: &Path) -> Result<HashMap<String, *const u8>, Vec<LocalizedError>> {
+        self.define(module, source)?;
 
         // Finalize the functions which we just defined, which resolves any
         // outstanding relocations (patching in addresses, now that they're
         // available).
-        self.module.finalize_definitions().unwrap();
+        compile::time(Phase::Finalize, || self.module.finalize_definitions().map_err(|e| vec![codegen_error(e, module)]))?;
+
+        for (id, sites) in self.traps.as_mut().map(std::mem::take).unwrap_or_default() {
+            let function = self.module.get_finalized_function(id);
+            traps::register(function, &sites, source);
+            self.registered.push(function);
+        }
 
         // We can now retrieve a pointer to the machine code.
-        let code = self.module.get_finalized_function(id);
+        Ok(self.globals
+            .iter()
+            .map(|(name, global)| (name.to_string(), self.module.get_finalized_function(global.id)))
+            .collect())
+    }
 
-        Ok(code)
+    /// Frees the code compiled so far, which mustn't run anymore, e.g. once the `Engine` it was compiled for is dropped
+    pub fn free(self) {
+        traps::unregister(&self.registered);
+        // Safety: the functions compiled aren't called after, nor are the addresses `compile` returned
+        unsafe { self.module.free_memory() };
     }
+}
 
-    /// Create a zero-initialized data section.
-    pub fn create_data(&mut self, name: &str, contents: Vec<u8>) -> Result<&[u8], String> {
-        // The steps here are analogous to `compile`, except that data is much
-        // simpler than functions.
-        self.data_description.define(contents.into_boxed_slice());
-        let id = self
-            .module
-            .declare_data(name, Linkage::Export, true, false)
-            .map_err(|e| e.to_string())?;
+impl<M: Module> Codegen<M> {
+    /// * `interruptible` - whether the generated code checks for Ctrl-C
+    pub fn new(module: M, interruptible: bool) -> Self {
+        Self {
+            builder_context: FunctionBuilderContext::new(),
+            ctx: module.make_context(),
+            module,
+            interruptible,
+            defined: 0,
+            globals: HashMap::new(),
+            values: HashMap::new(),
+            listings: None,
+            #[cfg(feature = "aot")]
+            debug_info: None,
+            traps: None,
+            registered: Vec::new(),
+        }
+    }
+
+    /// Generates the code of every function of a parsed module into the cranelift module,
+    /// lowering it into the IR first, see `ir`.
+    ///
+    /// A function which fails to compile doesn't stop the others from being compiled,
+    /// so the errors of every function are reported at once.
+    /// * `source` - the source the module was read from, which the errors of the code running are reported in
+    pub fn define(&mut self, module: &AST, source: &Path) -> Result<(), Vec<LocalizedError>> {
+        let types = self.globals
+            .iter()
+            .map(|(name, global)| (*name, global.ty.clone()))
+            .collect();
+        let values = self.values
+            .iter()
+            .map(|(name, (_, ty))| (*name, ty.clone()))
+            .collect();
+        let mut program = ir::compile(module, &types, &values)?;
+        if gc::current() == Gc::Rc {
+            ir::rc::insert(&mut program);
+        }
+
+        // Declare every function first, so that they can
+        // call each other regardless of the order they are defined in.
+        let mut globals = self.globals.clone();
+        let mut ids = Vec::new();
+        for function in program.functions.iter() {
+            let declared = match function.global {
+                Some(name) => self.module.declare_function(&symbol(&name), Linkage::Export, &self.signature(function.params)),
+                None => self.module.declare_function(&function.name, Linkage::Local, &self.signature(function.params)),
+            };
+            let id = declared.map_err(|e| vec![codegen_error_at(e, function.span)])?;
+            if let Some(name) = function.global {
+                globals.insert(name, Global { id, ty: function.ty.clone() });
+            }
+            ids.push(id);
+        }
+
+        // translate every function into a context of its own, the module declaring what they refer to
+        let mut errors = Vec::new();
+        let mut translated = Vec::new();
+        for (function, id) in program.functions.iter().zip(ids.iter()) {
+            // report the errors found so far
+            if interrupt::interrupted() {
+                break;
+            }
+            if let Err(error) = self.translate(function, *id, &ids, &globals, &program.externs, source) {
+                errors.push(error);
+                // the builder was abandoned halfway, start over with fresh state
+                self.builder_context = FunctionBuilderContext::new();
+                self.module.clear_context(&mut self.ctx);
+                continue;
+            }
+            let clif = self.listings.is_some().then(|| self.ctx.func.display().to_string());
+            self.ctx.set_disasm(clif.is_some());
+            let ctx = std::mem::replace(&mut self.ctx, self.module.make_context());
+            translated.push((function, *id, ctx, clif));
+        }
+
+        // compiling the functions is most of the work, done by several threads, see `compile::jobs`
+        let compiled = compile_contexts(self.module.isa(), translated.iter_mut().map(|(_, _, ctx, _)| ctx).collect());
+
+        // Define the functions in order, so that the code generated doesn't depend on the threads.
+        // Relocations are left outstanding, and resolved once every function is defined.
+        for ((function, id, ctx, clif), result) in translated.into_iter().zip(compiled) {
+            // the errors found so far are reported, those of the functions left aren't looked for
+            let Some(result) = result else {
+                continue;
+            };
+            let _span = tracing::debug_span!("function", name = %function.name).entered();
+            let defined = result.and_then(|()| {
+                let code = ctx.compiled_code().expect("a compiled function");
+                self.module.define_function_bytes(id, &ctx.func, code.buffer.alignment as u64, code.code_buffer(), code.buffer.relocs())
+                    .map_err(|error| error.to_string())
+            });
+            match defined {
+                Ok(()) => {
+                    self.defined += 1;
+                    let bytes = ctx.compiled_code().map_or(0, |code| code.code_buffer().len());
+                    tracing::debug!(instructions = function.insts.len(), bytes, "compiled");
+                }
+                Err(e) => errors.push(codegen_error_at(e, function.span)),
+            }
+
+            #[cfg(feature = "aot")]
+            if let (Some(debug_info), Some(code)) = (&mut self.debug_info, ctx.compiled_code()) {
+                debug_info.push(FunctionDebugInfo::new(function, id, code, self.module.isa()));
+            }
+            if let (Some(traps), Some(code)) = (&mut self.traps, ctx.compiled_code()) {
+                traps.push((id, trap_sites(function, code)));
+            }
+
+            // compiling optimizes the function in place
+            if let (Some(listings), Some(clif)) = (&mut self.listings, clif) {
+                listings.push(Listing {
+                    name: function.name.clone(),
+                    offset: function.span.start.offset,
+                    clif,
+                    optimized_clif: ctx.func.display().to_string(),
+                    asm: ctx.compiled_code()
+                        .and_then(|code| code.vcode.clone())
+                        .unwrap_or_default(),
+                });
+            }
+        }
+
+        // functions which failed to compile are left undefined, so don't link
+        if !errors.is_empty() || interrupt::interrupted() {
+            // report in source order rather than in the order of the program
+            errors.sort_by_key(|error| error.location().offset);
+            return Err(errors);
+        }
+
+        self.globals = globals;
+        Ok(())
+    }
 
+    /// The cranelift module the code was generated into
+    #[cfg(feature = "aot")]
+    pub fn into_module(self) -> M {
         self.module
-            .define_data(id, &self.data_description)
-            .map_err(|e| e.to_string())?;
-        self.data_description.clear();
-        self.module.finalize_definitions().unwrap();
-        let buffer = self.module.get_finalized_data(id);
-        // TODO: Can we move the unsafe into cranelift?
-        Ok(unsafe { slice::from_raw_parts(buffer.0, buffer.1) })
     }
 
-    // Translate from toy-language AST nodes into Cranelift IR.
+    /// Records the code generated for every function defined from now on, see `take_listings`
+    #[cfg_attr(not(feature = "aot"), allow(dead_code))]
+    pub fn record_listings(&mut self) {
+        self.listings.get_or_insert_with(Vec::new);
+    }
+
+    /// The code of the functions defined since `record_listings`, in source order
+    #[cfg_attr(not(feature = "aot"), allow(dead_code))]
+    pub fn take_listings(&mut self) -> Vec<Listing> {
+        let mut listings = self.listings.as_mut().map(std::mem::take).unwrap_or_default();
+        listings.sort_by_key(|listing| listing.offset);
+        listings
+    }
+
+    /// Records the debug information of every function defined from now on, see `take_debug_info`
+    #[cfg(feature = "aot")]
+    pub fn record_debug_info(&mut self) {
+        self.debug_info.get_or_insert_with(Vec::new);
+    }
+
+    /// The debug information of the functions defined since `record_debug_info`
+    #[cfg(feature = "aot")]
+    pub fn take_debug_info(&mut self) -> Vec<FunctionDebugInfo> {
+        self.debug_info.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Records the instructions which can trap of every function defined from now on,
+    /// for the JIT to register them once placed in memory, see `traps`
+    pub fn record_traps(&mut self) {
+        self.traps.get_or_insert_with(Vec::new);
+    }
+
+    /// The instruction set the code is generated for
+    #[cfg(feature = "aot")]
+    pub fn isa(&self) -> &dyn codegen::isa::TargetIsa {
+        self.module.isa()
+    }
+
+    /// The number of functions compiled, lambdas included
+    pub fn defined(&self) -> usize {
+        self.defined
+    }
+
+    /// Whether `name` is a module level function compiled before, or a value defined before
+    #[cfg(feature = "repl")]
+    pub fn is_defined(&self, name: Symbol) -> bool {
+        self.globals.contains_key(&name) || self.values.contains_key(&name)
+    }
+
+    /// Makes the module level value `name`, of type `ty`, available to the modules compiled from now on,
+    /// which read it from `address` whenever they use it
+    #[cfg(feature = "repl")]
+    pub fn define_value(&mut self, name: Symbol, address: *const i64, ty: Ty) {
+        self.values.insert(name, (address, ty));
+    }
+
+    /// Forgets the module level value `name`, e.g. once the module defining it failed to compile
+    #[cfg(feature = "repl")]
+    pub fn undefine_value(&mut self, name: Symbol) {
+        self.values.remove(&name);
+    }
+
+    /// Declares the module level functions of the other objects of a program, which the modules defined from now on
+    /// call by their symbol, for the linker to resolve, see `object::compile_object`
+    /// * `functions` - the functions, with their types
+    #[cfg(feature = "aot")]
+    pub fn import(&mut self, functions: &[(Symbol, Ty)]) -> Result<(), String> {
+        for (name, ty) in functions {
+            let Ty::Function(args, _) = ty else {
+                unreachable!("module level bindings are functions");
+            };
+            let id = self.module.declare_function(&symbol(name), Linkage::Import, &self.signature(args.len()))
+                .map_err(|error| error.to_string())?;
+            self.globals.insert(*name, Global { id, ty: ty.clone() });
+        }
+        Ok(())
+    }
+
+    /// The signature of every moolang function: the closure environment
+    /// followed by the arguments, returning a single value.
+    fn signature(&self, arity: usize) -> Signature {
+        signature(&self.module, arity)
+    }
+
+    // Translate a function of the IR into Cranelift IR.
     fn translate(
         &mut self,
-        params: Vec<String>,
-        the_return: String,
-        stmts: Vec<AST>,
-    ) -> Result<(), String> {
+        function: &ir::Function,
+        id: FuncId,
+        ids: &[FuncId],
+        globals: &HashMap<Symbol, Global>,
+        externs: &[ir::Extern],
+        source: &Path,
+    ) -> Result<(), LocalizedError> {
         // Our toy language currently only supports I64 values, though Cranelift
         // supports other types.
         let int = self.module.target_config().pointer_type();
-
-        for _p in &params {
-            self.ctx.func.signature.params.push(AbiParam::new(int));
+        self.ctx.func.signature = self.signature(function.params);
+        // named like the references of the functions calling it
+        self.ctx.func.name = codegen::ir::UserFuncName::user(0, id.as_u32());
+        #[cfg(feature = "aot")]
+        let debug_info = self.debug_info.is_some();
+        // without the object backend, there is no debug information to write
+        #[cfg(not(feature = "aot"))]
+        let debug_info = false;
+        if debug_info {
+            self.ctx.func.collect_debug_info();
         }
-
-        // Our toy language currently only supports one return value, though
-        // Cranelift is designed to support more.
-        self.ctx.func.signature.returns.push(AbiParam::new(int));
+        let srclocs = debug_info || self.traps.is_some();
 
         // Create the builder to build a function.
         let mut builder = FunctionBuilder::new(&mut self.ctx.func, &mut self.builder_context);
 
-        // Create the entry block, to start emitting code in.
+        // Create the entry block, to start emitting code in, with block
+        // parameters corresponding to the function's parameters.
         let entry_block = builder.create_block();
-
-        // Since this is the entry block, add block parameters corresponding to
-        // the function's parameters.
-        //
-        // TODO: Streamline the API here.
         builder.append_block_params_for_function_params(entry_block);
-
-        // Tell the builder to emit code in this block.
         builder.switch_to_block(entry_block);
 
-        // And, tell the builder that this block will have no further
-        // predecessors. Since it's the entry block, it won't have any
-        // predecessors.
+        // Since this is the entry block, it won't have any predecessors.
         builder.seal_block(entry_block);
 
-        // The toy language allows variables to be declared implicitly.
-        // Walk the AST and declare all implicitly-declared variables.
-        let variables =
-            declare_variables(int, &mut builder, &params, &the_return, &stmts, entry_block);
-
-        // Now translate the statements of the function body.
+        let params = builder.block_params(entry_block).to_vec();
         let mut trans = FunctionTranslator {
             int,
             builder,
-            variables,
+            params,
+            values: Vec::with_capacity(function.insts.len()),
+            function,
+            span: function.span,
+            source,
+            ids,
+            globals,
+            externs,
+            addresses: &self.values,
             module: &mut self.module,
         };
-        for expr in stmts {
-            trans.translate_expr(expr);
+
+        if self.interruptible {
+            trans.translate_interrupt_check();
+        }
+        let frame = match gc::current() {
+            Gc::Tracing => trans.translate_enter(),
+            Gc::Rc => None,
+        };
+        // the source location of the code of an instruction is its index, see `debuginfo` and `traps`,
+        // and the variables are labelled by their index, from the instruction computing them
+        for (at, inst) in function.insts.iter().enumerate() {
+            if srclocs {
+                trans.builder.set_srcloc(codegen::ir::SourceLoc::new(at as u32));
+            }
+            let value = trans.translate_inst(inst, function.spans[at])?;
+            trans.values.push(value);
+            if let Some(Frame { slot, roots }) = &frame {
+                if let Some(offset) = roots[at] {
+                    trans.builder.ins().stack_store(value, *slot, offset);
+                }
+            }
+            if debug_info {
+                let labels = function.variables.iter().enumerate().filter(|(_, (_, bound))| bound.index() == at);
+                for (label, _) in labels {
+                    trans.builder.set_val_label(value, codegen::ir::ValueLabel::from_u32(label as u32));
+                }
+            }
         }
 
-        // Set up the return variable of the function. Above, we declared a
-        // variable to hold the return value. Here, we just do a use of that
-        // variable.
-        let return_variable = trans.variables.get(&the_return).unwrap();
-        let return_value = trans.builder.use_var(*return_variable);
+        if let Some(frame) = frame {
+            let address = trans.builder.ins().stack_addr(int, frame.slot, 0);
+            trans.translate_runtime_call("__moo_leave", &[address]);
+        }
 
         // Emit the return instruction.
+        let return_value = trans.values[function.result.index()];
         trans.builder.ins().return_(&[return_value]);
 
         // Tell the builder we're done with this function.
@@ -186,294 +529,711 @@ impl JIT {
     }
 }
 
-/// A collection of state used for translating from toy-language AST nodes
+/// Compiles translated functions into machine code, on up to `compile::jobs` threads,
+/// returning the error of each which didn't compile, in the order of the contexts, none for those left on Ctrl-C
+fn compile_contexts(isa: &dyn codegen::isa::TargetIsa, mut contexts: Vec<&mut codegen::Context>) -> Vec<Option<Result<(), String>>> {
+    let compile = |ctx: &mut &mut codegen::Context| (!interrupt::interrupted()).then(|| ctx.compile(isa, &mut Default::default())
+        .map(|_| ())
+        .map_err(|error| ModuleError::Compilation(error.inner).to_string()));
+    let jobs = compile::jobs().min(contexts.len());
+    if jobs <= 1 {
+        return contexts.iter_mut().map(compile).collect();
+    }
+    let chunk = contexts.len().div_ceil(jobs);
+    std::thread::scope(|scope| {
+        let threads = contexts.chunks_mut(chunk)
+            .map(|chunk| scope.spawn(|| chunk.iter_mut().map(compile).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        threads.into_iter()
+            .flat_map(|thread| thread.join().expect("a compiling thread panicked"))
+            .collect()
+    })
+}
+
+/// The frame of a function on the shadow stack of the tracing collector, see `runtime::__moo_enter`
+struct Frame {
+    slot: codegen::ir::StackSlot,
+    /// Where in the frame every value which is an object is stored, by instruction
+    roots: Vec<Option<i32>>,
+}
+
+/// A collection of state used for translating from the instructions of the IR
 /// into Cranelift IR.
 struct FunctionTranslator<'a> {
     int: types::Type,
     builder: FunctionBuilder<'a>,
-    variables: HashMap<String, Variable>,
-    module: &'a mut JITModule,
+    /// The closure environment followed by the arguments
+    params: Vec<Value>,
+    /// The values of the instructions translated so far
+    values: Vec<Value>,
+    function: &'a ir::Function,
+    /// Where the function is defined, which errors are reported at
+    span: Span,
+    /// The source the function was read from, which the errors of its code report
+    source: &'a Path,
+    /// The functions of the program, by their index in it
+    ids: &'a [FuncId],
+    globals: &'a HashMap<Symbol, Global>,
+    /// The extern functions of the program, by their index in it
+    externs: &'a [ir::Extern],
+    addresses: &'a HashMap<Symbol, (*const i64, Ty)>,
+    module: &'a mut dyn Module,
 }
 
 impl<'a> FunctionTranslator<'a> {
     /// When you write out instructions in Cranelift, you get back `Value`s. You
     /// can then use these references in other instructions.
-    fn translate_expr(&mut self, expr: AST) -> Value {
-        use AstType as Ty;
-        use Operator::*;
-        use Ty::Expression as Expr;
-        match *expr {
-            Ty::Literal(literal) => {
-                let imm: i32 = literal.parse().unwrap();
-                self.builder.ins().iconst(self.int, i64::from(imm))
-            }
-
-            Expr(Add, lhs, rhs) => {
-                let lhs = self.translate_expr(*lhs);
-                let rhs = self.translate_expr(*rhs);
-                self.builder.ins().iadd(lhs, rhs)
-            }
-
-            Expr(Sub, lhs, rhs) => {
-                let lhs = self.translate_expr(*lhs);
-                let rhs = self.translate_expr(*rhs);
-                self.builder.ins().isub(lhs, rhs)
-            }
-
-            Expr(Mul,lhs, rhs) => {
-                let lhs = self.translate_expr(*lhs);
-                let rhs = self.translate_expr(*rhs);
-                self.builder.ins().imul(lhs, rhs)
-            }
-
-            Expr(Div, lhs, rhs) => {
-                let lhs = self.translate_expr(*lhs);
-                let rhs = self.translate_expr(*rhs);
-                self.builder.ins().udiv(lhs, rhs)
-            }
-
-            // Ty::Eq(lhs, rhs) => self.translate_icmp(IntCC::Equal, *lhs, *rhs),
-            // Ty::Ne(lhs, rhs) => self.translate_icmp(IntCC::NotEqual, *lhs, *rhs),
-            // Ty::Lt(lhs, rhs) => self.translate_icmp(IntCC::SignedLessThan, *lhs, *rhs),
-            // Ty::Le(lhs, rhs) => self.translate_icmp(IntCC::SignedLessThanOrEqual, *lhs, *rhs),
-            // Ty::Gt(lhs, rhs) => self.translate_icmp(IntCC::SignedGreaterThan, *lhs, *rhs),
-            // Ty::Ge(lhs, rhs) => self.translate_icmp(IntCC::SignedGreaterThanOrEqual, *lhs, *rhs),
-            // Ty::Call(name, args) => self.translate_call(name, args),
-            // Ty::GlobalDataAddr(name) => self.translate_global_data_addr(name),
-            Ty::Identifier(name) => {
-                // `use_var` is used to read the value of a variable.
-                let variable = self.variables.get(&name).expect("variable not defined");
-                self.builder.use_var(*variable)
-            }
-            Expr(Assign, name, expr) => match **name {
-                Ty::Literal(name) => self.translate_assign(name, *expr),
-                _ => panic!("invalid assignment"),
-            }
-            x => panic!("unimplemented/unexpected {:?}", x), // TODO: remove this
-            
-            // Ty::IfElse(condition, then_body, else_body) => {
-            //     self.translate_if_else(*condition, then_body, else_body)
-            // }
-            // Ty::WhileLoop(condition, loop_body) => {
-            //     self.translate_while_loop(*condition, loop_body)
-            // }
-        }
+    /// * `span` - the source the instruction comes from
+    fn translate_inst(&mut self, inst: &Inst, span: Span) -> Result<Value, LocalizedError> {
+        let value = |value: ir::Value| self.values[value.index()];
+        Ok(match inst {
+            Inst::Const(imm) => self.builder.ins().iconst(self.int, *imm),
+            Inst::String(string) => self.translate_string(string)?,
+            Inst::Param(index) => self.params[index + 1],
+
+            // The closure environment is passed as the first parameter.
+            // Slot 0 holds the function pointer, the captured values follow.
+            Inst::Capture(index) => {
+                let environment = self.params[0];
+                self.builder.ins().load(self.int, MemFlags::trusted(), environment, SLOT * (*index as i32 + 1))
+            }
+
+            Inst::Binary(op, lhs, rhs) if op.is_power() => {
+                let checked = !self.is_nonnegative_constant(*rhs);
+                let (lhs, rhs) = (value(*lhs), value(*rhs));
+                self.translate_power(*op, None, lhs, rhs, checked, span)?
+            }
+
+            Inst::Binary(op, lhs, rhs) => {
+                // dividing by a constant is only checked for a zero divisor the IR kept, see `ir::optimize`
+                let checked = op.is_division() && !self.is_nonzero_constant(*rhs);
+                let (lhs, rhs) = (value(*lhs), value(*rhs));
+                if checked {
+                    self.translate_zero_check(rhs, span)?;
+                }
+                match op {
+                    BinaryOp::Add => self.builder.ins().iadd(lhs, rhs),
+                    BinaryOp::Sub => self.builder.ins().isub(lhs, rhs),
+                    BinaryOp::Mul => self.builder.ins().imul(lhs, rhs),
+                    BinaryOp::Div => self.builder.ins().sdiv(lhs, rhs),
+                    BinaryOp::Rem => self.builder.ins().srem(lhs, rhs),
+                    BinaryOp::UDiv => self.builder.ins().udiv(lhs, rhs),
+                    BinaryOp::URem => self.builder.ins().urem(lhs, rhs),
+                    BinaryOp::FPow => self.translate_libm_call("pow", &[lhs, rhs]),
+                    BinaryOp::Pow | BinaryOp::UPow => unreachable!("powers are translated by `translate_power`"),
+                }
+            }
+
+            Inst::Checked(overflow, op, int, lhs, rhs) if op.is_power() => {
+                let checked = !self.is_nonnegative_constant(*rhs);
+                let (lhs, rhs) = (value(*lhs), value(*rhs));
+                self.translate_power(*op, Some((*overflow, *int)), lhs, rhs, checked, span)?
+            }
+
+            Inst::Checked(overflow, op, int, lhs, rhs) => {
+                let checked = op.is_division() && !self.is_nonzero_constant(*rhs);
+                let (lhs, rhs) = (value(*lhs), value(*rhs));
+                if checked {
+                    self.translate_zero_check(rhs, span)?;
+                }
+                self.translate_checked(*overflow, *op, *int, lhs, rhs, span)?
+            }
+
+            Inst::Wrap(int, wrapped) => self.translate_wrap(value(*wrapped), *int),
+
+            // floats are kept as their bits
+            Inst::Convert(conversion, converted) => {
+                let converted = value(*converted);
+                match conversion {
+                    Conversion::SignedToFloat | Conversion::UnsignedToFloat => {
+                        let float = match conversion {
+                            Conversion::UnsignedToFloat => self.builder.ins().fcvt_from_uint(types::F64, converted),
+                            _ => self.builder.ins().fcvt_from_sint(types::F64, converted),
+                        };
+                        self.builder.ins().bitcast(self.int, MemFlags::new(), float)
+                    }
+                    Conversion::FloatToSigned | Conversion::FloatToUnsigned => {
+                        let float = self.builder.ins().bitcast(types::F64, MemFlags::new(), converted);
+                        match conversion {
+                            Conversion::FloatToUnsigned => self.builder.ins().fcvt_to_uint_sat(self.int, float),
+                            _ => self.builder.ins().fcvt_to_sint_sat(self.int, float),
+                        }
+                    }
+                }
+            }
+
+            Inst::Format(format, formatted) => {
+                let formatted = value(*formatted);
+                self.translate_runtime_call(&format!("__moo_{}", format.as_str()), &[formatted])
+            }
+
+            Inst::Concat(lhs, rhs) => {
+                let args = [value(*lhs), value(*rhs)];
+                self.translate_runtime_call("__moo_concat", &args)
+            }
+
+            // the objects captured come first, see `ir::lower`
+            Inst::Closure(index, captures) => {
+                let managed = captures.iter().filter(|capture| self.function.managed[capture.index()]).count();
+                let captures = captures.iter().map(|capture| value(*capture)).collect::<Vec<_>>();
+                self.translate_closure(self.ids[*index], &captures, managed)
+            }
+
+            Inst::Global(name) => {
+                self.translate_closure(self.globals[name].id, &[], 0)
+            }
+
+            Inst::Retain(object) => self.translate_runtime_call("__moo_retain", &[value(*object)]),
+            Inst::Release(object) => self.translate_runtime_call("__moo_release", &[value(*object)]),
+
+            Inst::Load(name) => {
+                let address = self.builder.ins().iconst(self.int, self.addresses[name].0 as i64);
+                self.builder.ins().load(self.int, MemFlags::trusted(), address, 0)
+            }
+
+            // Calls to module level functions by name are direct, with an empty environment.
+            Inst::Call(name, args) => {
+                let args = args.iter().map(|arg| value(*arg)).collect::<Vec<_>>();
+                let local_callee = self.module.declare_func_in_func(self.globals[name].id, self.builder.func);
+                let mut arg_values = vec![self.builder.ins().iconst(self.int, 0)];
+                arg_values.extend(args);
+                let call = self.builder.ins().call(local_callee, &arg_values);
+                self.builder.inst_results(call)[0]
+            }
+
+            // The runtime implements the builtins, returning `unit` as 0,
+            // and the math ones are instructions or functions of the C library, on floats kept as their bits
+            Inst::Builtin(builtin, args) => {
+                let args = args.iter().map(|arg| value(*arg)).collect::<Vec<_>>();
+                match builtin {
+                    Builtin::Sqrt | Builtin::Floor | Builtin::Abs => {
+                        let float = self.builder.ins().bitcast(types::F64, MemFlags::new(), args[0]);
+                        let result = match builtin {
+                            Builtin::Sqrt => self.builder.ins().sqrt(float),
+                            Builtin::Floor => self.builder.ins().floor(float),
+                            _ => self.builder.ins().fabs(float),
+                        };
+                        self.builder.ins().bitcast(self.int, MemFlags::new(), result)
+                    }
+                    Builtin::Sin | Builtin::Cos | Builtin::Min | Builtin::Max => self.translate_libm_call(builtin.symbol(), &args),
+                    // reading an integer and indexing can fail, which the runtime reports at the call
+                    Builtin::ReadInt | Builtin::At => {
+                        let mut args = args;
+                        args.insert(0, self.translate_data(runtime::encode_location(span, self.source))?);
+                        self.translate_runtime_call(builtin.symbol(), &args)
+                    }
+                    Builtin::Print | Builtin::Println | Builtin::ReadLine | Builtin::Len => self.translate_runtime_call(builtin.symbol(), &args),
+                }
+            }
+
+            // Extern functions are imported by their symbol, which the JIT looks up in the process
+            // and the linker in the libraries, and called with the C calling convention
+            Inst::CallExtern(index, args) => {
+                let function = &self.externs[*index];
+                let mut sig = self.module.make_signature();
+                sig.params.extend(function.params.iter().map(|param| self.c_param(*param)));
+                if function.result != CType::Unit {
+                    sig.returns.push(self.c_param(function.result));
+                }
+                let callee = self.module
+                    .declare_function(&function.symbol, Linkage::Import, &sig)
+                    .map_err(|e| codegen_error_at(e, span))?;
+                let local_callee = self.module.declare_func_in_func(callee, self.builder.func);
+                let args = args.iter()
+                    .zip(function.params.iter())
+                    .map(|(arg, param)| {
+                        let arg = value(*arg);
+                        match param {
+                            CType::Int | CType::Unit => arg,
+                            CType::Sized(int) if int_type(*int) == self.int => arg,
+                            CType::Sized(int) => self.builder.ins().ireduce(int_type(*int), arg),
+                            CType::Float => self.builder.ins().bitcast(types::F64, MemFlags::new(), arg),
+                            // past the length
+                            CType::String => self.builder.ins().iadd_imm(arg, SLOT as i64),
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                let call = self.builder.ins().call(local_callee, &args);
+                match function.result {
+                    CType::Unit => self.builder.ins().iconst(self.int, 0),
+                    CType::Float => {
+                        let result = self.builder.inst_results(call)[0];
+                        self.builder.ins().bitcast(self.int, MemFlags::new(), result)
+                    }
+                    CType::Sized(int) if int_type(int) != self.int => {
+                        let result = self.builder.inst_results(call)[0];
+                        match int.is_signed() {
+                            true => self.builder.ins().sextend(self.int, result),
+                            false => self.builder.ins().uextend(self.int, result),
+                        }
+                    }
+                    CType::Int | CType::Sized(_) | CType::String => self.builder.inst_results(call)[0],
+                }
+            }
+
+            // Closures are called indirectly through their environment.
+            Inst::CallIndirect(closure, args) => {
+                let environment = value(*closure);
+                let mut arg_values = vec![environment];
+                arg_values.extend(args.iter().map(|arg| value(*arg)));
+                let function_pointer = self.builder.ins().load(self.int, MemFlags::trusted(), environment, 0);
+                let sig = self.builder.import_signature(signature(self.module, args.len()));
+                let call = self.builder.ins().call_indirect(sig, function_pointer, &arg_values);
+                self.builder.inst_results(call)[0]
+            }
+        })
     }
 
-    fn translate_assign(&mut self, name: String, expr: AST) -> Value {
-        // `def_var` is used to write the value of a variable. Note that
-        // variables can have multiple definitions. Cranelift will
-        // convert them into SSA form for itself automatically.
-        let new_value = self.translate_expr(expr);
-        let variable = self.variables.get(&name).unwrap();
-        self.builder.def_var(*variable, new_value);
-        new_value
+    /// How a value of a C type is passed: integers narrower than a register extended as C expects
+    fn c_param(&self, ty: CType) -> AbiParam {
+        match ty {
+            CType::Sized(int) if int_type(int) != self.int => match int.is_signed() {
+                true => AbiParam::new(int_type(int)).sext(),
+                false => AbiParam::new(int_type(int)).uext(),
+            },
+            CType::Float => AbiParam::new(types::F64),
+            CType::Int | CType::Sized(_) | CType::String | CType::Unit => AbiParam::new(self.int),
+        }
     }
 
-    fn translate_icmp(&mut self, cmp: IntCC, lhs: AST, rhs: AST) -> Value {
-        let lhs = self.translate_expr(lhs);
-        let rhs = self.translate_expr(rhs);
-        self.builder.ins().icmp(cmp, lhs, rhs)
+    /// Wraps a value, extended to 64 bits, around to the width of `int`
+    fn translate_wrap(&mut self, value: Value, int: IntTy) -> Value {
+        let narrow = int_type(int);
+        if narrow == self.int {
+            return value;
+        }
+        let value = self.builder.ins().ireduce(narrow, value);
+        match int.is_signed() {
+            true => self.builder.ins().sextend(self.int, value),
+            false => self.builder.ins().uextend(self.int, value),
+        }
     }
 
-    fn translate_if_else(
-        &mut self,
-        condition: AST,
-        then_body: Vec<AST>,
-        else_body: Vec<AST>,
-    ) -> Value {
-        let condition_value = self.translate_expr(condition);
-
-        let then_block = self.builder.create_block();
-        let else_block = self.builder.create_block();
-        let merge_block = self.builder.create_block();
-
-        // If-else constructs in the toy language have a return value.
-        // In traditional SSA form, this would produce a PHI between
-        // the then and else bodies. Cranelift uses block parameters,
-        // so set up a parameter in the merge block, and we'll pass
-        // the return values to it from the branches.
-        self.builder.append_block_param(merge_block, self.int);
-
-        // Test the if condition and conditionally branch.
-        self.builder
-            .ins()
-            .brif(condition_value, then_block, &[], else_block, &[]);
-
-        self.builder.switch_to_block(then_block);
-        self.builder.seal_block(then_block);
-        let mut then_return = self.builder.ins().iconst(self.int, 0);
-        for expr in then_body {
-            then_return = self.translate_expr(expr);
+    /// Closure conversion: the value of a function is a pointer to an environment holding
+    /// the address of the function followed by the captured values, the `managed` first of which are objects
+    /// the environment holds references to.
+    /// Module level functions used as values get an environment without captures.
+    fn translate_closure(&mut self, id: FuncId, captures: &[Value], managed: usize) -> Value {
+        let environment = self.translate_new(SLOT as i64 * (captures.len() as i64 + 1), managed);
+        let local_function = self.module.declare_func_in_func(id, self.builder.func);
+        let function_pointer = self.builder.ins().func_addr(self.int, local_function);
+        self.builder.ins().store(MemFlags::trusted(), function_pointer, environment, 0);
+        for (i, capture) in captures.iter().enumerate() {
+            self.builder.ins().store(MemFlags::trusted(), *capture, environment, SLOT * (i as i32 + 1));
         }
+        environment
+    }
 
-        // Jump to the merge block, passing it the block return value.
-        self.builder.ins().jump(merge_block, &[then_return]);
+    /// Exits through the runtime if Ctrl-C was pressed. Checked on entry to every function,
+    /// so that programs stuck in deep or endless recursion can be interrupted.
+    fn translate_interrupt_check(&mut self) {
+        let flag = self.builder.ins().iconst(self.int, interrupt::flag() as i64);
+        let interrupted = self.builder.ins().uload8(self.int, MemFlags::trusted(), flag, 0);
+        let exit_block = self.builder.create_block();
+        let body_block = self.builder.create_block();
+        self.builder.ins().brif(interrupted, exit_block, &[], body_block, &[]);
 
-        self.builder.switch_to_block(else_block);
-        self.builder.seal_block(else_block);
-        let mut else_return = self.builder.ins().iconst(self.int, 0);
-        for expr in else_body {
-            else_return = self.translate_expr(expr);
-        }
+        self.builder.switch_to_block(exit_block);
+        self.builder.seal_block(exit_block);
+        let callee = self
+            .module
+            .declare_function("__moo_interrupted", Linkage::Import, &self.module.make_signature())
+            .expect("runtime function declared with a different signature");
+        let local_callee = self.module.declare_func_in_func(callee, self.builder.func);
+        self.builder.ins().call(local_callee, &[]);
+        // the runtime exits the process
+        self.builder.ins().trap(UNREACHABLE);
+
+        self.builder.switch_to_block(body_block);
+        self.builder.seal_block(body_block);
+    }
 
-        // Jump to the merge block, passing it the block return value.
-        self.builder.ins().jump(merge_block, &[else_return]);
+    /// Whether a value of the IR is a constant other than zero, which divisions by don't need checking
+    fn is_nonzero_constant(&self, value: ir::Value) -> bool {
+        matches!(self.function.insts[value.index()], Inst::Const(constant) if constant != 0)
+    }
 
-        // Switch to the merge block for subsequent statements.
-        self.builder.switch_to_block(merge_block);
+    /// Whether a value of the IR is a constant of at least zero, which powers to don't need checking
+    fn is_nonnegative_constant(&self, value: ir::Value) -> bool {
+        matches!(self.function.insts[value.index()], Inst::Const(constant) if constant >= 0)
+    }
 
-        // We've now seen all the predecessors of the merge block.
-        self.builder.seal_block(merge_block);
+    /// Reports the division by zero at `span` through the runtime if `divisor` is zero,
+    /// rather than letting the machine trap, or not, depending on its instructions
+    fn translate_zero_check(&mut self, divisor: Value, span: Span) -> Result<(), LocalizedError> {
+        let zero = self.builder.ins().icmp_imm(IntCC::Equal, divisor, 0);
+        self.translate_failure_check(zero, span, "__moo_division_by_zero", None)
+    }
 
-        // Read the value of the if-else by reading the merge block
-        // parameter.
-        let phi = self.builder.block_params(merge_block)[0];
+    /// Arithmetic on values of an integer type, `int` for `None`, whose results out of the range
+    /// of the type are reported at `span` through the runtime, or saturated, see `ir::checked`.
+    /// The divisor of a division is already checked for zero
+    fn translate_checked(&mut self, overflow: Overflow, op: BinaryOp, int: Option<IntTy>, lhs: Value, rhs: Value, span: Span) -> Result<Value, LocalizedError> {
+        let ins = self.builder.ins();
+        let (result, overflowed) = match (int, op) {
+            (None, BinaryOp::Add) => ins.sadd_overflow(lhs, rhs),
+            (None, BinaryOp::Sub) => ins.ssub_overflow(lhs, rhs),
+            (None, BinaryOp::Mul) => ins.smul_overflow(lhs, rhs),
+            // the smallest `int` divided by -1 is divided by 1 instead, for the machine not to trap
+            (None, BinaryOp::Div) => {
+                let smallest = self.builder.ins().icmp_imm(IntCC::Equal, lhs, i64::MIN);
+                let minus_one = self.builder.ins().icmp_imm(IntCC::Equal, rhs, -1);
+                let overflowed = self.builder.ins().band(smallest, minus_one);
+                let one = self.builder.ins().iconst(self.int, 1);
+                let divisor = self.builder.ins().select(overflowed, one, rhs);
+                (self.builder.ins().sdiv(lhs, divisor), overflowed)
+            }
+            (Some(IntTy::U64), BinaryOp::Add) => ins.uadd_overflow(lhs, rhs),
+            (Some(IntTy::U64), BinaryOp::Sub) => ins.usub_overflow(lhs, rhs),
+            (Some(IntTy::U64), BinaryOp::Mul) => ins.umul_overflow(lhs, rhs),
+            // neither the remainder nor unsigned divisions overflow
+            (None | Some(IntTy::U64), _) => {
+                let result = match op {
+                    BinaryOp::UDiv => ins.udiv(lhs, rhs),
+                    BinaryOp::URem => ins.urem(lhs, rhs),
+                    _ => ins.srem(lhs, rhs),
+                };
+                (result, self.builder.ins().iconst(types::I8, 0))
+            }
+            // the exact result of arithmetic on narrower types fits on 64 bits
+            (Some(narrow), _) => {
+                let result = match op {
+                    BinaryOp::Add => ins.iadd(lhs, rhs),
+                    BinaryOp::Sub => ins.isub(lhs, rhs),
+                    BinaryOp::Mul => ins.imul(lhs, rhs),
+                    BinaryOp::Div => ins.sdiv(lhs, rhs),
+                    BinaryOp::Rem => ins.srem(lhs, rhs),
+                    BinaryOp::UDiv => ins.udiv(lhs, rhs),
+                    BinaryOp::URem => ins.urem(lhs, rhs),
+                    BinaryOp::Pow | BinaryOp::UPow | BinaryOp::FPow => unreachable!("powers are translated by `translate_power`"),
+                };
+                let wrapped = self.translate_wrap(result, narrow);
+                (result, self.builder.ins().icmp(IntCC::NotEqual, wrapped, result))
+            }
+        };
 
-        phi
+        if overflow != Overflow::Saturate {
+            self.translate_failure_check(overflowed, span, "__moo_overflow", Some(&ir::overflow_message(op, int)))?;
+            return Ok(result);
+        }
+        // which bound the exact result is beyond
+        let below = match (int, op) {
+            (Some(int), _) if int.is_signed() => self.builder.ins().icmp_imm(IntCC::SignedLessThan, result, 0),
+            (None, BinaryOp::Add) => self.builder.ins().icmp_imm(IntCC::SignedLessThan, rhs, 0),
+            (None, BinaryOp::Sub) => self.builder.ins().icmp_imm(IntCC::SignedGreaterThan, rhs, 0),
+            (None, BinaryOp::Mul) => {
+                let signs = self.builder.ins().bxor(lhs, rhs);
+                self.builder.ins().icmp_imm(IntCC::SignedLessThan, signs, 0)
+            }
+            // unsigned values only go below zero by subtracting
+            (Some(_), BinaryOp::Sub) => self.builder.ins().iconst(types::I8, 1),
+            _ => self.builder.ins().iconst(types::I8, 0),
+        };
+        let (min, max) = ir::bounds(int);
+        // the largest `u64` keeps its bits
+        let min = self.builder.ins().iconst(self.int, min as i64);
+        let max = self.builder.ins().iconst(self.int, max as i64);
+        let bound = self.builder.ins().select(below, min, max);
+        Ok(self.builder.ins().select(overflowed, bound, result))
     }
 
-    fn translate_while_loop(&mut self, condition: AST, loop_body: Vec<AST>) -> Value {
+    /// An integer power by squaring, see `ir::power`, wrapping around unless `checked` is given with
+    /// what its arithmetic does when it overflows, on values of an integer type, `int` for `None`, see `ir::checked`.
+    /// Zero to a negative power is reported at `span` as a division by zero, unless `zero_check` is false
+    fn translate_power(
+        &mut self,
+        op: BinaryOp,
+        checked: Option<(Overflow, Option<IntTy>)>,
+        base: Value,
+        exponent: Value,
+        zero_check: bool,
+        span: Span,
+    ) -> Result<Value, LocalizedError> {
+        let negative = self.builder.ins().icmp_imm(IntCC::SignedLessThan, exponent, 0);
+        if zero_check {
+            let zero = self.builder.ins().icmp_imm(IntCC::Equal, base, 0);
+            let failed = self.builder.ins().band(zero, negative);
+            self.translate_failure_check(failed, span, "__moo_division_by_zero", None)?;
+        }
+
+        // negative powers round toward zero, leaving the powers of 1, and of -1 for signed bases,
+        // which start the loop below with an exponent of zero
+        let odd = self.translate_low_bit(exponent);
+        let zero = self.builder.ins().iconst(self.int, 0);
+        let one = self.builder.ins().iconst(self.int, 1);
+        let minus_one = self.builder.ins().iconst(self.int, -1);
+        let power_of_minus_one = self.builder.ins().select(odd, minus_one, one);
+        let is_one = self.builder.ins().icmp_imm(IntCC::Equal, base, 1);
+        let negative_power = match op {
+            BinaryOp::Pow => {
+                let is_minus_one = self.builder.ins().icmp_imm(IntCC::Equal, base, -1);
+                let power = self.builder.ins().select(is_minus_one, power_of_minus_one, zero);
+                self.builder.ins().select(is_one, one, power)
+            }
+            _ => self.builder.ins().select(is_one, one, zero),
+        };
+        let result = self.builder.ins().select(negative, negative_power, one);
+        let remaining = self.builder.ins().select(negative, zero, exponent);
+
+        // header(result, base, remaining exponent, and whether it overflowed when checked)
         let header_block = self.builder.create_block();
         let body_block = self.builder.create_block();
         let exit_block = self.builder.create_block();
+        let mut params = vec![self.int, self.int, self.int];
+        let mut args = vec![result, base, remaining];
+        if checked.is_some() {
+            params.push(types::I8);
+            args.push(self.builder.ins().iconst(types::I8, 0));
+        }
+        for param in params.iter() {
+            self.builder.append_block_param(header_block, *param);
+        }
+        self.builder.append_block_param(exit_block, self.int);
+        if checked.is_some() {
+            self.builder.append_block_param(exit_block, types::I8);
+        }
+        self.builder.ins().jump(header_block, &args);
 
-        self.builder.ins().jump(header_block, &[]);
         self.builder.switch_to_block(header_block);
+        let header = self.builder.block_params(header_block).to_vec();
+        let mut exit_args = vec![header[0]];
+        exit_args.extend(header.get(3).copied());
+        self.builder.ins().brif(header[2], body_block, &[], exit_block, &exit_args);
 
-        let condition_value = self.translate_expr(condition);
-        self.builder
-            .ins()
-            .brif(condition_value, body_block, &[], exit_block, &[]);
-
+        // the base is squared only for the bits of the exponent left, so it overflows only if the power does
         self.builder.switch_to_block(body_block);
         self.builder.seal_block(body_block);
-
-        for expr in loop_body {
-            self.translate_expr(expr);
+        let (result, squared, remaining) = (header[0], header[1], header[2]);
+        let bit = self.translate_low_bit(remaining);
+        let (product, product_overflowed) = self.translate_multiply(checked.map(|(_, int)| int), result, squared);
+        let result = self.builder.ins().select(bit, product, result);
+        let remaining = self.builder.ins().ushr_imm(remaining, 1);
+        let (square, square_overflowed) = self.translate_multiply(checked.map(|(_, int)| int), squared, squared);
+        let mut args = vec![result, square, remaining];
+        if let (Some(overflowed), Some(product_overflowed), Some(square_overflowed)) = (header.get(3), product_overflowed, square_overflowed) {
+            let product_overflowed = self.builder.ins().band(bit, product_overflowed);
+            let more = self.builder.ins().icmp_imm(IntCC::NotEqual, remaining, 0);
+            let square_overflowed = self.builder.ins().band(more, square_overflowed);
+            let overflowed = self.builder.ins().bor(*overflowed, product_overflowed);
+            args.push(self.builder.ins().bor(overflowed, square_overflowed));
         }
-        self.builder.ins().jump(header_block, &[]);
+        self.builder.ins().jump(header_block, &args);
+        self.builder.seal_block(header_block);
 
         self.builder.switch_to_block(exit_block);
-
-        // We've reached the bottom of the loop, so there will be no
-        // more backedges to the header to exits to the bottom.
-        self.builder.seal_block(header_block);
         self.builder.seal_block(exit_block);
+        let exit = self.builder.block_params(exit_block).to_vec();
+        let Some((overflow, int)) = checked else {
+            return Ok(exit[0]);
+        };
+        let (result, overflowed) = (exit[0], exit[1]);
+        if overflow != Overflow::Saturate {
+            self.translate_failure_check(overflowed, span, "__moo_overflow", Some(&ir::overflow_message(op, int)))?;
+            return Ok(result);
+        }
+        // the power is below the range of the type if the base is negative and the exponent odd
+        let below = match op {
+            BinaryOp::Pow => {
+                let negative_base = self.builder.ins().icmp_imm(IntCC::SignedLessThan, base, 0);
+                self.builder.ins().band(negative_base, odd)
+            }
+            _ => self.builder.ins().iconst(types::I8, 0),
+        };
+        let (min, max) = ir::bounds(int);
+        let min = self.builder.ins().iconst(self.int, min as i64);
+        let max = self.builder.ins().iconst(self.int, max as i64);
+        let bound = self.builder.ins().select(below, min, max);
+        Ok(self.builder.ins().select(overflowed, bound, result))
+    }
 
-        // Just return 0 for now.
-        self.builder.ins().iconst(self.int, 0)
+    /// Whether the lowest bit of a value is set
+    fn translate_low_bit(&mut self, value: Value) -> Value {
+        let bit = self.builder.ins().band_imm(value, 1);
+        self.builder.ins().icmp_imm(IntCC::NotEqual, bit, 0)
     }
 
-    fn translate_call(&mut self, name: String, args: Vec<AST>) -> Value {
+    /// Multiplies two values, of an integer type, `int` for `None`, with whether the product overflows it,
+    /// when `checked` is given
+    fn translate_multiply(&mut self, checked: Option<Option<IntTy>>, lhs: Value, rhs: Value) -> (Value, Option<Value>) {
+        let ins = self.builder.ins();
+        let (product, overflowed) = match checked {
+            None => return (ins.imul(lhs, rhs), None),
+            Some(None) => ins.smul_overflow(lhs, rhs),
+            Some(Some(IntTy::U64)) => ins.umul_overflow(lhs, rhs),
+            // the exact product of narrower types fits on 64 bits
+            Some(Some(narrow)) => {
+                let product = ins.imul(lhs, rhs);
+                let wrapped = self.translate_wrap(product, narrow);
+                (product, self.builder.ins().icmp(IntCC::NotEqual, wrapped, product))
+            }
+        };
+        (product, Some(overflowed))
+    }
+
+    /// Calls the function `name` of the runtime if `failed` is nonzero, which reports an error at `span`
+    /// then exits. It takes the location of the code, see `runtime::encode_location`,
+    /// followed by the message of the error if given, the runtime knowing it otherwise
+    fn translate_failure_check(&mut self, failed: Value, span: Span, name: &str, message: Option<&Message>) -> Result<(), LocalizedError> {
+        let error_block = self.builder.create_block();
+        let body_block = self.builder.create_block();
+        self.builder.set_cold_block(error_block);
+        self.builder.ins().brif(failed, error_block, &[], body_block, &[]);
+
+        self.builder.switch_to_block(error_block);
+        self.builder.seal_block(error_block);
+        let mut args = vec![self.translate_data(runtime::encode_location(span, self.source))?];
+        if let Some(message) = message {
+            args.push(self.translate_string(&message.to_string())?);
+            args.push(self.translate_string(message.code().unwrap_or_default())?);
+        }
         let mut sig = self.module.make_signature();
+        sig.params.extend(args.iter().map(|_| AbiParam::new(self.int)));
+        let callee = self
+            .module
+            .declare_function(name, Linkage::Import, &sig)
+            .expect("runtime function declared with a different signature");
+        let local_callee = self.module.declare_func_in_func(callee, self.builder.func);
+        self.builder.ins().call(local_callee, &args);
+        // the runtime exits the process
+        self.builder.ins().trap(UNREACHABLE);
+
+        self.builder.switch_to_block(body_block);
+        self.builder.seal_block(body_block);
+        Ok(())
+    }
+
+    /// Strings are pointers to them, laid out by `runtime::encode` in the data of the module,
+    /// after the header of an object which is never freed, see `runtime::HEADER`
+    fn translate_string(&mut self, string: &str) -> Result<Value, LocalizedError> {
+        let encoded = runtime::encode(string);
+        let mut bytes = runtime::static_header(encoded.len());
+        bytes.extend(encoded);
+        let object = self.translate_data(bytes)?;
+        Ok(self.builder.ins().iadd_imm(object, runtime::HEADER as i64))
+    }
+
+    /// A pointer to `bytes`, in the data of the module
+    fn translate_data(&mut self, bytes: Vec<u8>) -> Result<Value, LocalizedError> {
+        let mut data = DataDescription::new();
+        data.define(bytes.into_boxed_slice());
+        data.set_align(SLOT as u64);
+        let id = self.module
+            .declare_anonymous_data(false, false)
+            .map_err(|e| codegen_error_at(e, self.span))?;
+        self.module.define_data(id, &data).map_err(|e| codegen_error_at(e, self.span))?;
+        let string = self.module.declare_data_in_func(id, self.builder.func);
+        Ok(self.builder.ins().symbol_value(self.int, string))
+    }
 
-        // Add a parameter for each argument.
-        for _arg in &args {
-            sig.params.push(AbiParam::new(self.int));
+    /// Pushes a frame for the roots of the function onto the shadow stack of the tracing collector,
+    /// laid out as `runtime::__moo_enter` reads it, unless none of its values are objects.
+    /// The roots are 0 until the values are computed and stored into them
+    fn translate_enter(&mut self) -> Option<Frame> {
+        let mut count = 0;
+        let roots = self.function.managed
+            .iter()
+            .map(|managed| managed.then(|| {
+                count += 1;
+                SLOT * (count + 1)
+            }))
+            .collect::<Vec<_>>();
+        if count == 0 {
+            return None;
+        }
+        let slot = self.builder.create_sized_stack_slot(StackSlotData::new(StackSlotKind::ExplicitSlot, (SLOT * (count + 2)) as u32, 3));
+        let count_value = self.builder.ins().iconst(self.int, count as i64);
+        self.builder.ins().stack_store(count_value, slot, SLOT);
+        let zero = self.builder.ins().iconst(self.int, 0);
+        for root in 0..count {
+            self.builder.ins().stack_store(zero, slot, SLOT * (root + 2));
         }
+        let address = self.builder.ins().stack_addr(self.int, slot, 0);
+        self.translate_runtime_call("__moo_enter", &[address]);
+        Some(Frame { slot, roots })
+    }
 
-        // For simplicity for now, just make all calls return a single I64.
-        sig.returns.push(AbiParam::new(self.int));
+    /// Allocates an object of `size` bytes on the heap through the runtime, holding `children` objects,
+    /// see `runtime::HEADER`
+    fn translate_new(&mut self, size: i64, children: usize) -> Value {
+        let size = self.builder.ins().iconst(self.int, size);
+        let children = self.builder.ins().iconst(self.int, children as i64);
+        self.translate_runtime_call("__moo_new", &[size, children])
+    }
 
-        // TODO: Streamline the API here?
+    /// Calls the function `name` of the C library on floats, kept as their bits, e.g. `pow`
+    fn translate_libm_call(&mut self, name: &str, args: &[Value]) -> Value {
+        let mut sig = self.module.make_signature();
+        sig.params.extend(args.iter().map(|_| AbiParam::new(types::F64)));
+        sig.returns.push(AbiParam::new(types::F64));
         let callee = self
             .module
-            .declare_function(&name, Linkage::Import, &sig)
-            .expect("problem declaring function");
+            .declare_function(name, Linkage::Import, &sig)
+            .expect("C library function declared with a different signature");
         let local_callee = self.module.declare_func_in_func(callee, self.builder.func);
+        let args = args.iter().map(|arg| self.builder.ins().bitcast(types::F64, MemFlags::new(), *arg)).collect::<Vec<_>>();
+        let call = self.builder.ins().call(local_callee, &args);
+        let result = self.builder.inst_results(call)[0];
+        self.builder.ins().bitcast(self.int, MemFlags::new(), result)
+    }
 
-        let mut arg_values = Vec::new();
-        for arg in args {
-            arg_values.push(self.translate_expr(arg))
-        }
-        let call = self.builder.ins().call(local_callee, &arg_values);
+    /// Calls the function `name` of the runtime, which takes `args` and returns a value
+    fn translate_runtime_call(&mut self, name: &str, args: &[Value]) -> Value {
+        let mut sig = self.module.make_signature();
+        sig.params.extend(args.iter().map(|_| AbiParam::new(self.int)));
+        sig.returns.push(AbiParam::new(self.int));
+        let callee = self
+            .module
+            .declare_function(name, Linkage::Import, &sig)
+            .expect("runtime function declared with a different signature");
+        let local_callee = self.module.declare_func_in_func(callee, self.builder.func);
+        let call = self.builder.ins().call(local_callee, args);
         self.builder.inst_results(call)[0]
     }
+}
 
-    fn translate_global_data_addr(&mut self, name: String) -> Value {
-        let sym = self
-            .module
-            .declare_data(&name, Linkage::Export, true, false)
-            .expect("problem declaring data object");
-        let local_id = self.module.declare_data_in_func(sym, self.builder.func);
+/// The Cranelift type of an integer type of explicit width
+fn int_type(int: IntTy) -> types::Type {
+    match int.bits() {
+        8 => types::I8,
+        16 => types::I16,
+        32 => types::I32,
+        _ => types::I64,
+    }
+}
 
-        let pointer = self.module.target_config().pointer_type();
-        self.builder.ins().symbol_value(pointer, local_id)
+fn signature(module: &dyn Module, arity: usize) -> Signature {
+    let int = module.target_config().pointer_type();
+    let mut sig = module.make_signature();
+    sig.params.push(AbiParam::new(int));
+    for _ in 0..arity {
+        sig.params.push(AbiParam::new(int));
     }
+    sig.returns.push(AbiParam::new(int));
+    sig
 }
 
-fn declare_variables(
-    int: types::Type,
-    builder: &mut FunctionBuilder,
-    params: &[String],
-    the_return: &str,
-    stmts: &[AST],
-    entry_block: Block,
-) -> HashMap<String, Variable> {
-    let mut variables = HashMap::new();
-    let mut index = 0;
-
-    for (i, name) in params.iter().enumerate() {
-        // TODO: cranelift_frontend should really have an API to make it easy to set
-        // up param variables.
-        let val = builder.block_params(entry_block)[i];
-        let var = declare_variable(int, builder, &mut variables, &mut index, name);
-        builder.def_var(var, val);
-    }
-    let zero = builder.ins().iconst(int, 0);
-    let return_variable = declare_variable(int, builder, &mut variables, &mut index, the_return);
-    builder.def_var(return_variable, zero);
-    for expr in stmts {
-        declare_variables_in_stmt(int, builder, &mut variables, &mut index, expr);
-    }
-
-    variables
+/// The instructions of the compiled code of a function which can trap, at the span of the instruction
+/// of the IR they come from, or of the function for the code of no instruction
+fn trap_sites(function: &ir::Function, code: &codegen::CompiledCode) -> Vec<TrapSite> {
+    let srclocs = code.buffer.get_srclocs_sorted();
+    code.buffer
+        .traps()
+        .iter()
+        .map(|trap| {
+            let span = srclocs.iter()
+                .find(|srcloc| (srcloc.start..srcloc.end).contains(&trap.offset) && !srcloc.loc.is_default())
+                .map_or(function.span, |srcloc| function.spans[srcloc.loc.bits() as usize]);
+            TrapSite { offset: trap.offset, code: trap.code, span }
+        })
+        .collect()
 }
 
-/// Recursively descend through the AST, translating all implicit
-/// variable declarations.
-fn declare_variables_in_stmt(
-    int: types::Type,
-    builder: &mut FunctionBuilder,
-    variables: &mut HashMap<String, Variable>,
-    index: &mut usize,
-    expr: &AST,
-) {
-    match *expr {
-        AST::Assign(ref name, _) => {
-            declare_variable(int, builder, variables, index, name);
-        }
-        AST::IfElse(ref _condition, ref then_body, ref else_body) => {
-            for stmt in then_body {
-                declare_variables_in_stmt(int, builder, variables, index, stmt);
-            }
-            for stmt in else_body {
-                declare_variables_in_stmt(int, builder, variables, index, stmt);
-            }
-        }
-        AST::WhileLoop(ref _condition, ref loop_body) => {
-            for stmt in loop_body {
-                declare_variables_in_stmt(int, builder, variables, index, stmt);
-            }
-        }
-        _ => (),
-    }
+pub fn codegen_error(error: impl fmt::Display, ast: &AST) -> LocalizedError {
+    codegen_error_at(error, ast.span())
 }
 
-/// Declare a single variable declaration.
-fn declare_variable(
-    int: types::Type,
-    builder: &mut FunctionBuilder,
-    variables: &mut HashMap<String, Variable>,
-    index: &mut usize,
-    name: &str,
-) -> Variable {
-    let var = Variable::new(*index);
-    if !variables.contains_key(name) {
-        variables.insert(name.into(), var);
-        builder.declare_var(var, int);
-        *index += 1;
-    }
-    var
-}
\ No newline at end of file
+fn codegen_error_at(error: impl fmt::Display, span: Span) -> LocalizedError {
+    CodegenError {
+        message: error.to_string(),
+    }.with_span(span)
+}
@@ -0,0 +1,45 @@
+//! moolang as a library: the compiler the `moolang` command line is a thin layer over,
+//! and `Engine`, to embed the language into Rust programs as a scripting language.
+
+#![allow(clippy::upper_case_acronyms)]
+
+mod cache;
+#[cfg(feature = "jit")]
+mod capi;
+pub mod color;
+#[cfg(feature = "aot")]
+mod debuginfo;
+mod diagnostics;
+#[cfg(feature = "jit")]
+mod engine;
+pub mod gc;
+pub mod interrupt;
+pub mod explain;
+pub mod lint;
+#[cfg(feature = "lsp")]
+pub mod lsp;
+mod interp;
+pub mod bytecode;
+mod ir;
+pub mod manifest;
+pub mod selftest;
+#[cfg(feature = "jit")]
+mod jit;
+#[cfg(feature = "aot")]
+mod object;
+#[cfg(feature = "repl")]
+pub mod repl;
+#[cfg(feature = "jit")]
+mod runtime;
+#[cfg(feature = "jit")]
+pub mod traps;
+mod messages;
+mod sources;
+
+pub mod frontend;
+pub mod compile;
+pub mod errors;
+
+#[cfg(feature = "jit")]
+pub use engine::{Engine, EngineError, Function, Signature, Value};
+pub use messages::{set_catalog, Catalog, ENGLISH};
@@ -0,0 +1,60 @@
+// The lints: the kinds of warnings the compiler reports, each allowed, warned about or denied.
+// An allowed lint isn't reported, a denied one is reported as an error, failing the compilation
+// once its phase is done, see `errors::warn`. `warnings` stands for every lint, e.g. `-D warnings`
+// denies them all, but a lint set on its own takes precedence, so `-D warnings -A deprecated` still allows
+// the deprecated constructs. A lint set to several levels takes the strictest.
+
+use core::fmt;
+use std::sync::RwLock;
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    /// Every lint
+    Warnings,
+    /// Constructs deprecated in the edition of the sources, see `edition`
+    Deprecated,
+    /// Variables bound by `let` and never used
+    UnusedVariables,
+    /// Module level functions `main` never calls, in programs with a `main`
+    UnusedFunctions,
+    /// What the plugins report, see `frontend::plugin`
+    Plugins,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Lint::Warnings => write!(f, "warnings"),
+            Lint::Deprecated => write!(f, "deprecated"),
+            Lint::UnusedVariables => write!(f, "unused-variables"),
+            Lint::UnusedFunctions => write!(f, "unused-functions"),
+            Lint::Plugins => write!(f, "plugins"),
+        }
+    }
+}
+
+/// What is done with a lint
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Not reported
+    Allow,
+    /// Reported as a warning
+    #[default]
+    Warn,
+    /// Reported as an error
+    Deny,
+}
+
+static LEVELS: RwLock<Vec<(Lint, Level)>> = RwLock::new(Vec::new());
+
+/// Sets `lint`, or every lint for `Lint::Warnings`, to `level` from now on
+pub fn set(lint: Lint, level: Level) {
+    LEVELS.write().unwrap().push((lint, level));
+}
+
+/// The level of `lint`, set on its own, else for every lint, warning about it unless set otherwise
+pub fn level(lint: Lint) -> Level {
+    let levels = LEVELS.read().unwrap();
+    let set = |lint: Lint| levels.iter().filter(|(set, _)| *set == lint).map(|(_, level)| *level).max();
+    set(lint).or_else(|| set(Lint::Warnings)).unwrap_or_default()
+}
@@ -0,0 +1,135 @@
+// The language server, `moolang lsp`, for editors to show the errors and warnings of the code as it's written.
+// It speaks the Language Server Protocol over stdin and stdout: every document opened or changed is checked
+// like `check` checks a file, and the diagnostics found in it are published, see `errors::to_json`.
+// The text of an open document is registered in the source map, see `sources`, so it is what gets checked,
+// by the programs importing it too, rather than the file last saved.
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use lsp_server::{Connection, ErrorCode, Message, Notification, Response};
+use lsp_types::notification::{DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _, PublishDiagnostics};
+use lsp_types::{
+    Diagnostic, DiagnosticSeverity, DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+    NumberOrString, Position, PublishDiagnosticsParams, Range, ServerCapabilities, TextDocumentSyncCapability,
+    TextDocumentSyncKind, Url,
+};
+use serde_json::Value;
+
+use crate::compile::{check_files, report_warnings};
+use crate::errors;
+use crate::sources::{self, SourceFile};
+
+/// Serves the editor on stdin and stdout, until it asks the server to shut down
+pub fn run() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (connection, threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        ..Default::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    break;
+                }
+                // the server only publishes diagnostics
+                let response = Response::new_err(request.id, ErrorCode::MethodNotFound as i32, format!("`{}` isn't supported", request.method));
+                connection.sender.send(response.into())?;
+            }
+            Message::Notification(notification) => {
+                let (uri, text) = match notification.method.as_str() {
+                    DidOpenTextDocument::METHOD => {
+                        let params = serde_json::from_value::<DidOpenTextDocumentParams>(notification.params)?;
+                        (params.text_document.uri, Some(params.text_document.text))
+                    }
+                    // the whole text is sent on every change, see `TextDocumentSyncKind::FULL`
+                    DidChangeTextDocument::METHOD => {
+                        let mut params = serde_json::from_value::<DidChangeTextDocumentParams>(notification.params)?;
+                        let Some(change) = params.content_changes.pop() else { continue };
+                        (params.text_document.uri, Some(change.text))
+                    }
+                    DidCloseTextDocument::METHOD => {
+                        let params = serde_json::from_value::<DidCloseTextDocumentParams>(notification.params)?;
+                        (params.text_document.uri, None)
+                    }
+                    _ => continue,
+                };
+                let params = PublishDiagnosticsParams { diagnostics: update(&uri, text), uri, version: None };
+                connection.sender.send(Notification::new(PublishDiagnostics::METHOD.to_owned(), params).into())?;
+            }
+            Message::Response(_) => (),
+        }
+    }
+    // the thread writing to stdout ends once the connection, and its sender, is dropped
+    drop(connection);
+    threads.join()?;
+    Ok(())
+}
+
+/// Registers the `text` of the document at `uri`, or forgets it once closed, returning the diagnostics to show in it
+fn update(uri: &Url, text: Option<String>) -> Vec<Diagnostic> {
+    let Ok(path) = uri.to_file_path() else {
+        return Vec::new();
+    };
+    match text {
+        Some(text) => {
+            sources::register(&path, text);
+            check(path)
+        }
+        // closed documents show no diagnostics
+        None => {
+            sources::unregister(&path);
+            Vec::new()
+        }
+    }
+}
+
+/// The errors and warnings of checking the program whose entry file is at `path`, those in the file
+fn check(path: PathBuf) -> Vec<Diagnostic> {
+    let paths = [path];
+    let ((), reported) = errors::collect_diagnostics(|| {
+        let checked = check_files(&paths);
+        // like `main`, the warnings of a check which failed are left to report, before its errors
+        report_warnings();
+        if let Err(errors) = checked {
+            errors::report(&errors);
+        }
+    });
+    let Ok(source) = sources::load(&paths[0]) else {
+        return Vec::new();
+    };
+    reported.iter()
+        .filter(|object| object["file"].as_str().map(Path::new) == Some(paths[0].as_path()))
+        .map(|object| diagnostic(object, &source))
+        .collect()
+}
+
+/// The diagnostic of an error or a warning, from its JSON object, see `errors::to_json`
+fn diagnostic(object: &Value, source: &SourceFile) -> Diagnostic {
+    // positions are in UTF-16 code units, by default
+    let position = |position: &Value| {
+        let line = position["line"].as_u64().unwrap_or(1) as usize;
+        let column = position["column"].as_u64().unwrap_or(0) as usize;
+        let text = source.line(line);
+        let character = text.get(..column).map_or(column, |before| before.encode_utf16().count());
+        Position::new(line.saturating_sub(1) as u32, character as u32)
+    };
+    let span = &object["span"];
+    let range = match span.is_null() {
+        true => Range::default(),
+        false => Range::new(position(&span["start"]), position(&span["end"])),
+    };
+    Diagnostic {
+        range,
+        severity: Some(match object["severity"].as_str() {
+            Some("warning") => DiagnosticSeverity::WARNING,
+            _ => DiagnosticSeverity::ERROR,
+        }),
+        code: object["code"].as_str().map(|code| NumberOrString::String(code.to_owned())),
+        source: Some("moolang".to_owned()),
+        message: object["message"].as_str().unwrap_or_default().to_owned(),
+        ..Default::default()
+    }
+}
@@ -1,53 +1,491 @@
 
-mod codegen;
-mod jit;
-
-mod frontend;
-mod compile;
-mod errors;
-
 use std::error::Error;
+use std::ffi::OsString;
 
-use std::fs::File;
-use std::io::{BufReader, BufRead};
-
-use clap::Parser;
-use compile::compile_lines;
-use errors::{LocalizedSourcedError, LocalizableError};
-use frontend::tokenizer::Location;
+use clap::{CommandFactory, Parser, Subcommand};
+use tracing::Level;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::Layer;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use moolang::{bytecode, color, compile, errors, explain, frontend, interrupt, manifest, selftest};
+use moolang::color::Color;
+use moolang::errors::ErrorFormat;
+use moolang::lint::{self, Lint};
+use moolang::gc::{self, Gc};
+#[cfg(feature = "lsp")]
+use moolang::lsp;
+#[cfg(feature = "repl")]
+use moolang::repl;
+#[cfg(feature = "jit")]
+use moolang::traps;
+use moolang::frontend::edition::{self, Edition};
+use moolang::frontend::optimize::{self, OptLevel};
+use moolang::frontend::overflow::{self, Overflow};
+#[cfg(feature = "fmt")]
+use moolang::compile::format_files;
+use moolang::compile::{build_files, check_files, compile_files, fix_files, emit_ast_json, emit_deps, emit_grammar, emit_source, emit_tokens, emit_types, eval_source, report_warnings, take_timings, Artifact, Backend, Format};
 
 /// LOL
+///
+/// Options can also be given in the `MOO_FLAGS` environment variable, separated by whitespace,
+/// which those on the command line override.
+/// `MOO_LOG` selects what the compiler logs to stderr, e.g. `moolang=debug` for the phases and the functions compiled.
+/// `MOO_HOME` is where moolang is installed: modules not found next to the importing file
+/// are looked up in its `std` directory, then in the standard library built into the compiler.
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, args_override_self = true)]
 struct Args {
-    /// The path to the file to read
-    #[arg(short, long)]
-    path: std::path::PathBuf,
+    #[command(subcommand)]
+    command: Command,
+
+    /// Log every production of the grammar the parser enters and leaves, to stderr
+    #[arg(long, global = true)]
+    explain_parse: bool,
+
+    /// Print more about what was done: a summary line once compiled, and the time each phase took
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Print the time each phase of compiling and running the program took, to stderr
+    #[arg(long, global = true)]
+    time_phases: bool,
+
+    /// The backend generating and running the code
+    #[arg(long, global = true, value_enum, env = "MOO_BACKEND", default_value_t)]
+    backend: Backend,
+
+    /// How much to optimize the programs: not at all, for speed, or for speed and size.
+    /// Optimizing takes longer to compile
+    #[arg(short = 'O', global = true, value_enum, default_value_t)]
+    opt_level: OptLevel,
+
+    /// What integer arithmetic does when its result doesn't fit its type: wrap around,
+    /// stop the program with an error where it overflows, or saturate to the bounds of the type
+    #[arg(long, global = true, value_enum, default_value_t)]
+    overflow: Overflow,
+
+    /// Whether to color the errors and warnings: when stderr is a terminal and `NO_COLOR` isn't set, always or never
+    #[arg(long, global = true, value_enum, env = "MOO_COLOR", default_value_t)]
+    color: Color,
+
+    /// How the errors and warnings are reported on stderr: with a snippet of the code they are in,
+    /// or as JSON objects, one per line, with their severity, code, message, file and span
+    #[arg(long, global = true, value_enum, default_value_t)]
+    error_format: ErrorFormat,
+
+    /// The number of threads generating the machine code of the functions, one per core by default
+    #[arg(short, long, global = true, env = "MOO_JOBS", default_value_t = 0, hide_default_value = true)]
+    jobs: usize,
+
+    /// Don't report the warnings of a lint, e.g. `-A unused-variables`, or of every lint, `-A warnings`
+    #[arg(short = 'A', long = "allow", global = true, value_enum, value_name = "LINT")]
+    allow: Vec<Lint>,
+
+    /// Report the warnings of a lint, as by default
+    #[arg(short = 'W', long = "warn", global = true, value_enum, value_name = "LINT")]
+    warn: Vec<Lint>,
+
+    /// Report the warnings of a lint as errors, failing the compilation, e.g. `--deny warnings` for every lint.
+    /// A lint set on its own takes precedence over `warnings`, and the strictest level over the others
+    #[arg(short = 'D', long = "deny", global = true, value_enum, value_name = "LINT")]
+    deny: Vec<Lint>,
+
+    /// The edition of the language the sources are written in.
+    /// Constructs deprecated in an edition are errors in the next one
+    #[arg(long, global = true, value_enum, default_value_t)]
+    edition: Edition,
 }
 
-fn run(args: Args) -> Result<(), Box<dyn Error>> {
-    
-    let file = File::open(args.path.clone())
-        .map_err(|err| err
-            .with_location(Location::default())
-            .with_source(args.path.clone()))?;
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Compile a program and run it: call its `main` function and print the value it returns
+    ///
+    /// A `main` taking the arguments of the program, `fn(args: [string]): int`, gets those after `--`,
+    /// and the value it returns is the exit code instead.
+    /// A bytecode file, written by `build --emit bytecode`, is run by the stack machine without compiling it.
+    Run {
+        /// The source files to compile together, or directories of source files, or a bytecode file.
+        /// The entry point of the project the current directory is in by default, see `new`
+        paths: Vec<std::path::PathBuf>,
+
+        /// Run this code instead of source files, e.g. `-e "print(1 + 2 * 3);"`.
+        /// Without a `main` function, its statements are run in order, with the arguments of the program as `args`
+        #[arg(short = 'e', long, conflicts_with = "paths")]
+        eval: Option<String>,
+
+        /// The arguments of the program, after `--`
+        #[arg(last = true)]
+        args: Vec<String>,
+
+        /// How compiled code manages the memory of strings, arrays and closures: by counting references to them,
+        /// or by tracing those reachable, which frees those referencing each other in cycles too.
+        /// The interpreter and the stack machine leave it to Rust
+        #[arg(long, value_enum, default_value_t)]
+        gc: Gc,
+    },
+    /// Compile a program ahead of time into an executable, or an object file to link into other programs
+    ///
+    /// Executables call the `main` function of the program and print the value it returns, like `run`,
+    /// or pass it their arguments and exit with the value it returns.
+    /// They are linked by the C compiler in `CC`, `cc` by default, with a runtime written in C.
+    /// In object files, module level functions are exported as `moo_<name>` symbols, taking a closure
+    /// environment pointer first. The runtime functions the code calls are left for the linked program to provide.
+    /// In a project, what is generated is cached in its `target/cache`: executables are linked from an object
+    /// per module, so building again only generates the code of the modules which changed, and links.
+    Build {
+        /// The source files to compile together, or directories of source files.
+        /// The entry point of the project the current directory is in by default, see `new`
+        paths: Vec<std::path::PathBuf>,
+
+        /// What to write
+        #[arg(long, value_enum, default_value_t)]
+        emit: Artifact,
+
+        /// The target triple of the machine to compile for, e.g. `aarch64-apple-darwin`, the host by default.
+        /// Executables for another machine are linked by a cross compiler in `CC`
+        #[arg(long)]
+        target: Option<String>,
 
-    let lines = BufReader::new(file)
-        .lines()
-        .map(Result::unwrap);
+        /// The file to write, by default named after the first source file, in the current directory,
+        /// or stdout for text. For a project, the output of its manifest, or named after it at its root
+        #[arg(short, long)]
+        output: Option<std::path::PathBuf>,
 
-    compile_lines(lines)
-        .map_err(|err| err.with_source(args.path))?;
+        /// Write debug information into executables and object files, for debuggers like gdb and lldb
+        /// to step through the source and show the values of variables
+        #[arg(short = 'g', long)]
+        debug_info: bool,
 
+        /// How the executable manages the memory of strings, arrays and closures, see `run --gc`
+        #[arg(long, value_enum, default_value_t)]
+        gc: Gc,
+    },
+    /// Check a program for errors without generating its code nor running it, e.g. for editors to check on save
+    ///
+    /// Reports the errors and warnings of compiling the program, and exits with 1 if there are errors.
+    Check {
+        /// The source files to check together, or directories of source files.
+        /// The entry point of the project the current directory is in by default, see `new`
+        paths: Vec<std::path::PathBuf>,
+    },
+    /// Apply the fixes the compiler is sure of to the source files of a program, then check it
+    ///
+    /// The fixes are those of the errors and warnings with a machine-applicable suggestion, e.g. a missing `;`
+    /// at the end of a line, an unused variable prefixed with `_`, or a deprecated construct replaced.
+    /// Guesses, like the name meant by a misspelled one, are left for people to apply.
+    /// Reports what is left to fix like `check`.
+    Fix {
+        /// The source files to fix together, or directories of source files.
+        /// The entry point of the project the current directory is in by default, see `new`
+        paths: Vec<std::path::PathBuf>,
+    },
+    /// Format source files in the canonical layout, keeping their comments
+    ///
+    /// Statements go on lines of their own, blocks are indented by four spaces unless they hold a single statement,
+    /// operators are surrounded by spaces, and only the parentheses the precedence of the operators requires are kept.
+    /// Single blank lines between statements are kept, and deprecated constructs are written as what replaces them.
+    /// Files with errors are left as they are.
+    Fmt {
+        /// The source files to format, or directories of source files.
+        /// The source files next to the entry point of the project the current directory is in by default, see `new`
+        paths: Vec<std::path::PathBuf>,
+
+        /// Write nothing, list the files which aren't formatted instead and exit with 1 if there are any, e.g. for CI
+        #[arg(long)]
+        check: bool,
+    },
+    /// Create a project in a new directory: its manifest, `moo.toml`, and a `src/main.moo` to start from
+    ///
+    /// `run`, `build` and `check` compile the project the current directory is in when given no source file.
+    /// The manifest names the package, and can set the `entry` source file, `src/main.moo` by default,
+    /// and the `output` file `build` writes. Other projects are imported by the names they're given in `[deps]`,
+    /// e.g. `mathlib = "../mathlib"`.
+    New {
+        /// The directory to create, the name of the project
+        path: std::path::PathBuf,
+    },
+    /// Stop compiling a program after a stage of the frontend and print its output
+    Emit {
+        /// The stage to stop after
+        #[arg(value_enum)]
+        stage: Stage,
+
+        /// The source files to compile together, or directories of source files.
+        /// The grammar is the same for every program, and needs none
+        paths: Vec<std::path::PathBuf>,
+
+        /// The format of the output, for the stages supporting several
+        #[arg(long, value_enum, default_value_t)]
+        format: Format,
+    },
+    /// Serve an editor over stdin and stdout with the Language Server Protocol
+    ///
+    /// Every document opened or changed is checked like `check` checks its file, with the text being edited,
+    /// and the errors and warnings found in it shown in the editor.
+    Lsp,
+    /// Read moolang code from stdin and run it an input at a time, printing the value of expressions
+    ///
+    /// The functions and values defined by an input can be used by the next ones.
+    Repl,
+    /// Run programs on every backend and report those whose results differ, to test the compiler
+    ///
+    /// The programs are the source files given, each run on its own, and programs generated at random.
+    /// Files which don't compile, or without a `main` function returning an integer, are skipped.
+    Selftest {
+        /// Source files, or directories of source files
+        paths: Vec<std::path::PathBuf>,
+
+        /// How many programs to generate
+        #[arg(long, default_value_t = 100)]
+        generated: usize,
+
+        /// The seed to generate programs from, the current time by default
+        #[arg(long)]
+        seed: Option<u64>,
+    },
+    /// Print the explanation of the error or warning with a code, e.g. `explain E0102`, and an example reproducing it
+    Explain {
+        /// The code of the error or warning, shown in brackets after its kind, e.g. `ResolveError[E0102]`
+        code: String,
+    },
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum Stage {
+    /// The token stream, with the location of every token, see `--format`
+    Tokens,
+    /// Every source file parsed and printed back in the canonical layout
+    Source,
+    /// The syntax tree of every source file, as JSON
+    AstJson,
+    /// The operator precedence table and the grammar of the language
+    Grammar,
+    /// The declared types of the module level bindings
+    Types,
+    /// The files the program is read from, as a Makefile rule or JSON with `--format json`
+    Deps,
+}
+
+/// Logs what the compiler does to stderr: the events and the spans of the phases and of the functions
+/// selected by `MOO_LOG`, e.g. `moolang=debug` or `moolang::jit=trace,cranelift=debug`, and the productions the parser enters
+/// and leaves with `--explain-parse`
+fn init_logging(explain_parse: bool) -> Result<(), Box<dyn Error>> {
+    let log = match std::env::var("MOO_LOG") {
+        Ok(log) => Some(log.parse::<Targets>().map_err(|error| format!("Invalid `MOO_LOG`: {}", error))?),
+        Err(_) => None,
+    };
+    if log.is_none() && !explain_parse {
+        return Ok(());
+    }
+    let log = log.map(|targets| tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_ansi(color::enabled())
+        // with the time spent in the spans of the phases and the functions
+        .with_span_events(FmtSpan::CLOSE)
+        .with_filter(targets));
+    let explain_parse = explain_parse.then(|| tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_target(false)
+        .with_level(false)
+        .without_time()
+        .with_filter(Targets::new().with_target(frontend::ast::TRACE_TARGET, Level::TRACE)));
+    tracing_subscriber::registry()
+        .with(log)
+        .with(explain_parse)
+        .init();
     Ok(())
 }
 
+/// Runs the command, returning the exit code of the program it ran, if any, 0 otherwise
+fn run(args: Args) -> Result<i32, Box<dyn Error>> {
+    init_logging(args.explain_parse)?;
+
+    errors::set_format(args.error_format);
+    edition::set(args.edition);
+    optimize::set(args.opt_level);
+    overflow::set(args.overflow);
+    compile::set_jobs(args.jobs);
+    for (lints, level) in [(&args.allow, lint::Level::Allow), (&args.warn, lint::Level::Warn), (&args.deny, lint::Level::Deny)] {
+        lints.iter().for_each(|lint| lint::set(*lint, level));
+    }
+
+    match args.command {
+        Command::Run { paths, eval, args: program_args, gc } => {
+            gc::set(gc);
+            if let Some(source) = eval {
+                let summary = eval_source(&source, &program_args, args.backend)?;
+                if args.verbose > 0 {
+                    anstream::eprintln!("{}", summary);
+                }
+                return Ok(summary.exit_code);
+            }
+            let paths = match paths.is_empty() {
+                true => vec![manifest::discover(&std::env::current_dir()?)?.entry],
+                false => paths,
+            };
+            if let [path] = &paths[..] {
+                if path.extension().is_some_and(|extension| extension == bytecode::EXTENSION) {
+                    return compile::run_bytecode(path, &program_args);
+                }
+            }
+            let summary = compile_files(&paths, Some(&program_args), args.backend)?;
+            if args.verbose > 0 {
+                anstream::eprintln!("{}", summary);
+            }
+            Ok(summary.exit_code)
+        }
+        Command::Build { paths, emit, target, output, debug_info, gc } => {
+            gc::set(gc);
+            let (paths, output) = match paths.is_empty() {
+                true => {
+                    let project = manifest::discover(&std::env::current_dir()?)?;
+                    let output = output
+                        .or(project.output)
+                        .or_else(|| emit.extension().map(|extension| project.root.join(&project.name).with_extension(extension)));
+                    (vec![project.entry], output)
+                }
+                false => (paths, output),
+            };
+            let summary = build_files(&paths, emit, target.as_deref(), output.as_deref(), debug_info)?;
+            if args.verbose > 0 {
+                anstream::eprintln!("{}", summary);
+            }
+            Ok(0)
+        }
+        Command::Check { paths } => {
+            let paths = match paths.is_empty() {
+                true => vec![manifest::discover(&std::env::current_dir()?)?.entry],
+                false => paths,
+            };
+            let summary = check_files(&paths)?;
+            if args.verbose > 0 {
+                anstream::eprintln!("{}", summary);
+            }
+            Ok(0)
+        }
+        Command::Fix { paths } => {
+            let paths = match paths.is_empty() {
+                true => vec![manifest::discover(&std::env::current_dir()?)?.entry],
+                false => paths,
+            };
+            for (path, count) in fix_files(&paths)? {
+                anstream::eprintln!("fixed {} problem{} in '{}'", count, if count == 1 { "" } else { "s" }, path.display());
+            }
+            let summary = check_files(&paths)?;
+            if args.verbose > 0 {
+                anstream::eprintln!("{}", summary);
+            }
+            Ok(0)
+        }
+        Command::Explain { code } => {
+            print!("{}", explain::explain(&code)?);
+            Ok(0)
+        }
+        #[cfg(feature = "fmt")]
+        Command::Fmt { paths, check } => {
+            let paths = match paths.is_empty() {
+                // the modules of the project are imported from next to its entry file
+                true => {
+                    let entry = manifest::discover(&std::env::current_dir()?)?.entry;
+                    vec![entry.parent().map(std::path::Path::to_path_buf).unwrap_or(entry)]
+                }
+                false => paths,
+            };
+            let changed = format_files(&paths, check)?;
+            for (path, line) in &changed {
+                match check {
+                    true => anstream::eprintln!("'{}' isn't formatted, from line {}", path.display(), line),
+                    false if args.verbose > 0 => anstream::eprintln!("formatted '{}'", path.display()),
+                    false => (),
+                }
+            }
+            Ok(if check && !changed.is_empty() { 1 } else { 0 })
+        }
+        #[cfg(not(feature = "fmt"))]
+        Command::Fmt { .. } => Err(Box::new(compile::feature_required("fmt", "fmt"))),
+        Command::New { path } => {
+            let project = manifest::new_project(&path)?;
+            if args.verbose > 0 {
+                anstream::eprintln!("created `{}` at '{}'", project.name, project.root.display());
+            }
+            Ok(0)
+        }
+        Command::Emit { stage: Stage::Grammar, .. } => {
+            emit_grammar();
+            Ok(0)
+        }
+        Command::Emit { paths, .. } if paths.is_empty() => {
+            Args::command()
+                .error(clap::error::ErrorKind::MissingRequiredArgument, "the following required arguments were not provided: <PATHS>...")
+                .exit();
+        }
+        Command::Emit { stage, paths, format } => {
+            match stage {
+                Stage::Tokens => emit_tokens(&paths, format)?,
+                Stage::Source => emit_source(&paths)?,
+                Stage::AstJson => emit_ast_json(&paths)?,
+                Stage::Types => emit_types(&paths)?,
+                Stage::Deps => emit_deps(&paths, format)?,
+                Stage::Grammar => unreachable!("emitted without a source file"),
+            }
+            Ok(0)
+        }
+        #[cfg(feature = "lsp")]
+        Command::Lsp => lsp::run().map(|()| 0).map_err(|error| error as Box<dyn Error>),
+        #[cfg(not(feature = "lsp"))]
+        Command::Lsp => Err(Box::new(compile::feature_required("lsp", "lsp"))),
+        #[cfg(feature = "repl")]
+        Command::Repl => repl::run().map(|()| 0),
+        #[cfg(not(feature = "repl"))]
+        Command::Repl => Err(Box::new(compile::feature_required("repl", "repl"))),
+        Command::Selftest { paths, generated, seed } => selftest::run(&paths, generated, seed).map(|()| 0),
+    }
+}
+
+/// Parses the options in `MOO_FLAGS` followed by the command line arguments, so the latter win.
+/// The options go after the name of the subcommand, so they can be those of the subcommand too, e.g. `--gc tracing`
+fn parse_args() -> Args {
+    let mut args = std::env::args_os().collect::<Vec<_>>();
+    let flags = std::env::var("MOO_FLAGS").unwrap_or_default();
+    let command = Args::command();
+    // the name of the program comes first
+    let after_subcommand = args.iter()
+        .skip(1)
+        .position(|arg| command.get_subcommands().any(|subcommand| arg.to_str() == Some(subcommand.get_name())))
+        .map_or(1.min(args.len()), |subcommand| subcommand + 2);
+    args.splice(after_subcommand..after_subcommand, flags.split_whitespace().map(OsString::from));
+    Args::parse_from(args)
+}
 
 fn main() {
-    let args = Args::parse();
+    let args = parse_args();
+    color::set(args.color);
+    interrupt::install();
+    #[cfg(feature = "jit")]
+    traps::install();
 
-    if let Err(e) = run(args) {
-        eprintln!("{}", e);
-        std::process::exit(1);
+    let time_phases = args.time_phases || args.verbose > 0;
+    let result = run(args);
+    report_warnings();
+    if let Err(e) = &result {
+        errors::report(&**e);
+        if let (Some(summary), ErrorFormat::Human) = (errors::summary(), errors::format()) {
+            // without errors of their own, e.g. for denied warnings, the blank line after the warnings separates the summary
+            let separated = e.downcast_ref::<errors::Diagnostics>().is_some_and(|diagnostics| diagnostics.0.is_empty());
+            anstream::eprintln!("{}{}", if separated { "" } else { "\n" }, summary);
+        }
+    }
+    if time_phases {
+        anstream::eprintln!("{}", take_timings());
+    }
+    if interrupt::interrupted() {
+        interrupt::exit();
+    }
+    match result {
+        Ok(0) => (),
+        Ok(exit_code) => std::process::exit(exit_code),
+        Err(_) => std::process::exit(1),
     }
 }
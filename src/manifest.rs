@@ -0,0 +1,244 @@
+// The manifest of a project, `moo.toml`, at its root, which `run`, `build` and `check` read when given no source file:
+// ```toml
+// [package]
+// name = "hello"
+// # the source file compiled, along with the modules it imports, `src/main.moo` by default
+// entry = "src/main.moo"
+//
+// [build]
+// # the executable `build` writes, named after the package at the root by default
+// output = "hello"
+//
+// [deps]
+// # the packages imported by name, `import mathlib` loading the entry module of the package at `../mathlib`
+// mathlib = "../mathlib"
+// ```
+// Paths are relative to the root. The manifest is written in the subset of TOML made of tables,
+// dotted keys and string values, see `parse`.
+
+use core::fmt;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::errors::{Coded, LocalizableError, LocalizedError, LocalizedSourcedError};
+use crate::frontend::tokenizer::Location;
+use crate::messages::{message, Message};
+
+/// The file name of manifests
+pub const FILE_NAME: &str = "moo.toml";
+
+#[derive(Debug)]
+pub struct ManifestError {
+    message: Message,
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ManifestError: {}", self.message)
+    }
+}
+
+impl Error for ManifestError {}
+
+impl Coded for ManifestError {
+    fn code(&self) -> Option<&'static str> {
+        self.message.code()
+    }
+}
+
+/// A project, as described by its manifest
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifest {
+    /// The directory of the manifest
+    pub root: PathBuf,
+    pub name: String,
+    /// The source file compiled, along with the modules it imports
+    pub entry: PathBuf,
+    /// The file `build` writes, if set
+    pub output: Option<PathBuf>,
+    /// The roots of the packages depended on, by the names they're imported by
+    pub deps: Vec<(String, PathBuf)>,
+}
+
+/// The keys of a manifest, with whether they are required
+const KEYS: &[(&str, bool)] = &[("package.name", true), ("package.entry", false), ("build.output", false)];
+
+/// The table of the dependencies, whose keys are the names of the packages
+const DEPS: &str = "deps";
+
+/// Reads the manifest at `path`
+pub fn load(path: &Path) -> Result<Manifest, LocalizedSourcedError> {
+    let source = fs::read_to_string(path).map_err(|error| ManifestError {
+        message: message!("E0501", path = path.display(), error = error),
+    }.with_location(Location::default()).with_source(path))?;
+    let entries = parse(&source).map_err(|error| error.with_source(path))?;
+
+    let root = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let mut values = vec![None; KEYS.len()];
+    let mut deps = Vec::new();
+    for (key, value, location) in entries {
+        if let Some(name) = key.strip_prefix(DEPS).and_then(|key| key.strip_prefix('.')) {
+            if !is_module_name(name) {
+                return Err(ManifestError {
+                    message: message!("E0509", name = name),
+                }.with_location(location).with_source(path));
+            }
+            deps.push((name.to_owned(), root.join(value)));
+            continue;
+        }
+        let Some(index) = KEYS.iter().position(|(known, _)| *known == key) else {
+            let expected = KEYS.iter().map(|(key, _)| format!("`{}`", key))
+                .chain([format!("`{}.<name>`", DEPS)])
+                .collect::<Vec<_>>().join(", ");
+            return Err(ManifestError {
+                message: message!("E0503", key = key, expected = expected),
+            }.with_location(location).with_source(path));
+        };
+        values[index] = Some(value);
+    }
+    for ((key, required), value) in KEYS.iter().zip(values.iter()) {
+        if *required && value.is_none() {
+            return Err(ManifestError {
+                message: message!("E0504", key = key),
+            }.with_location(Location::default()).with_source(path));
+        }
+    }
+
+    let mut values = values.into_iter();
+    let mut next = || values.next().expect("a value for every key");
+    let name = next().expect("a required key");
+    let entry = root.join(next().unwrap_or_else(|| "src/main.moo".to_owned()));
+    let output = next().map(|output| root.join(output));
+    Ok(Manifest { root, name, entry, output, deps })
+}
+
+/// The path of the manifest of the project `dir` is in, looked for in `dir` then in its ancestors
+pub fn find(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors().map(|dir| dir.join(FILE_NAME)).find(|path| path.is_file())
+}
+
+/// Looks for the manifest of the project `dir` is in, see `find`, and reads it
+pub fn discover(dir: &Path) -> Result<Manifest, Box<dyn Error>> {
+    match find(dir) {
+        Some(path) => Ok(load(&path)?),
+        None => Err(Box::new(ManifestError {
+            message: message!("E0502", file = FILE_NAME, directory = dir.display()),
+        })),
+    }
+}
+
+/// Parses a manifest into its entries: the keys, dotted with the table they are in, e.g. `package.name`,
+/// the values, and the locations of the keys.
+/// Comments, from `#` to the end of the line, and blank lines are skipped
+fn parse(source: &str) -> Result<Vec<(String, String, Location)>, LocalizedError> {
+    let mut entries = Vec::<(String, String, Location)>::new();
+    let mut table = String::new();
+    let mut offset = 0;
+    for (i, line) in source.split('\n').enumerate() {
+        let start = offset;
+        offset += line.len() + 1;
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let content = line.trim_start();
+        let location = Location { line: i + 1, column: line.len() - content.len(), offset: start + line.len() - content.len(), ..Location::default() };
+        let invalid = |message: Message| ManifestError { message }.with_location(location);
+        let content = strip_comment(content).trim_end();
+        if content.is_empty() {
+            continue;
+        }
+        if let Some(name) = content.strip_prefix('[') {
+            let name = name.strip_suffix(']').ok_or_else(|| invalid(message!("E0505", expected = "`]`", found = content)))?;
+            table = key(name).ok_or_else(|| invalid(message!("E0505", expected = "a table name", found = name)))?;
+            continue;
+        }
+        let (name, value) = content.split_once('=').ok_or_else(|| invalid(message!("E0505", expected = "`key = \"value\"`", found = content)))?;
+        let name = key(name).ok_or_else(|| invalid(message!("E0505", expected = "a key", found = name.trim())))?;
+        let value = string(value.trim()).ok_or_else(|| {
+            let column = location.column + content.len() - value.trim_start().len();
+            let at = Location { column, offset: start + column, ..location };
+            ManifestError { message: message!("E0505", expected = "a string", found = value.trim()) }.with_location(at)
+        })?;
+        let name = match table.is_empty() {
+            true => name,
+            false => format!("{}.{}", table, name),
+        };
+        if entries.iter().any(|(key, _, _)| *key == name) {
+            return Err(invalid(message!("E0506", key = name)));
+        }
+        entries.push((name, value, location));
+    }
+    Ok(entries)
+}
+
+/// The line without its comment, if any, `#` in strings not starting one
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '\\' if quoted => escaped = !escaped,
+            '"' if !escaped => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => escaped = false,
+        }
+    }
+    line
+}
+
+/// A dotted key, e.g. `package.name`, its parts being made of letters, digits, `_` and `-`
+fn key(text: &str) -> Option<String> {
+    let parts = text.split('.').map(str::trim).collect::<Vec<_>>();
+    parts.iter()
+        .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+        .then(|| parts.join("."))
+}
+
+/// Whether a dependency can be imported by `name`: letters, digits and `_`, not starting with a digit
+fn is_module_name(name: &str) -> bool {
+    name.chars().next().is_some_and(|c| !c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The value of a basic string, e.g. `"src/main.moo"`, with the escapes `\"` and `\\`
+fn string(text: &str) -> Option<String> {
+    let inner = text.strip_prefix('"')?.strip_suffix('"')?;
+    let mut value = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next()? {
+                c @ ('"' | '\\') => value.push(c),
+                _ => return None,
+            },
+            '"' => return None,
+            c => value.push(c),
+        }
+    }
+    Some(value)
+}
+
+/// Creates a project in the new directory `path`, named after it: its manifest,
+/// and a `src/main.moo` printing a greeting
+pub fn new_project(path: &Path) -> Result<Manifest, Box<dyn Error>> {
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    if key(&name).is_none_or(|key| key.contains('.')) {
+        return Err(Box::new(ManifestError {
+            message: message!("E0507", name = name),
+        }));
+    }
+    if path.exists() {
+        return Err(Box::new(ManifestError {
+            message: message!("E0508", path = path.display()),
+        }));
+    }
+    let written = |file: &Path, error: std::io::Error| ManifestError {
+        message: message!("E0409", path = file.display(), error = error),
+    };
+    let src = path.join("src");
+    fs::create_dir_all(&src).map_err(|error| written(&src, error))?;
+    let manifest = path.join(FILE_NAME);
+    fs::write(&manifest, format!("[package]\nname = \"{}\"\n", name)).map_err(|error| written(&manifest, error))?;
+    let main = src.join("main.moo");
+    fs::write(&main, "let main = fn(args: [string]) -> int {\n    println(\"Hello, world!\");\n    0\n};\n").map_err(|error| written(&main, error))?;
+    Ok(load(&manifest)?)
+}
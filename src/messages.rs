@@ -0,0 +1,214 @@
+// The text of the diagnostics, in a catalog keyed by the code of the error.
+// Messages are templates whose `{name}` placeholders are filled in with the arguments
+// the error site gives, so translating the messages only takes another catalog,
+// and the wording of all of them can be reviewed in one place.
+
+use std::fmt::{self, Display};
+use std::sync::RwLock;
+
+/// The messages by their key: the code of an error, or the name of some other text of the diagnostics
+pub type Catalog = &'static [(&'static str, &'static str)];
+
+/// The messages in English, which other catalogs fall back to
+pub const ENGLISH: Catalog = &[
+    // tokens and syntax
+    ("E0001", "Expected {expected}, found {found}"),
+    ("E0002", "Expected {expected}, found end of input"),
+    ("E0003", "Invalid token: {token}"),
+    ("E0004", "Unterminated string literal: {literal}"),
+    ("E0005", "Unterminated block comment, expected `*/`"),
+    ("E0006", "Unary `+` was removed in edition {edition}, remove it"),
+    ("E0007", "Invalid suffix on numeric literal `{literal}`, expected one of {suffixes}"),
+    ("E0008", "Unsupported character `{character}` ({codepoint}), outside of comments and strings non-ASCII characters may only be part of identifiers"),
+    ("E0009", "Unclosed interpolation in string literal, expected `}`, or escape the brace opening it with `\\`"),
+    // names
+    ("E0101", "`{name}` is already defined in this scope"),
+    ("E0102", "Cannot find `{name}` in this scope"),
+    ("E0103", "Type parameter `{param}` is declared twice"),
+    ("E0104", "Module `{module}` has not been loaded"),
+    ("E0105", "Expected a name to bind, found {found}"),
+    // modules
+    ("E0201", "`{name}` is already defined in '{file}'"),
+    ("E0202", "No .{extension} files in directory '{directory}'"),
+    ("E0203", "Module `{module}` is already imported"),
+    ("E0204", "Cyclic import: {cycle}"),
+    ("E0205", "Cannot find module `{module}`, expected it at '{path}'"),
+    // types
+    ("E0301", "Too many instances of generic functions, last one was `{instance}`"),
+    ("E0302", "Cannot infer the type parameters of `{name}` when used as a value, call it instead"),
+    ("E0303", "Literal `{literal}` is out of range for `{ty}`"),
+    ("E0304", "Cannot apply `{operator}` to a value of generic type `{ty}`, type parameters have no constraints"),
+    ("E0305", "Cannot apply `{operator}` to a value of type `unit`, the operand has no value"),
+    ("E0306", "Cannot apply `{operator}` to a value of type `float`, floating point arithmetic other than `**` isn't supported yet"),
+    ("E0307", "Cannot apply `{operator}` to `{lhs}` and `{rhs}`, integers of different widths don't mix without a cast"),
+    ("E0308", "Cannot call a value of generic type `{ty}`, type parameters have no constraints"),
+    ("E0309", "Generic functions are only supported at module level"),
+    ("E0310", "Cannot cast a value of generic type `{ty}`, type parameters have no constraints"),
+    ("E0311", "Cannot cast a value of type `{from}` to `{to}`, only numbers can be cast"),
+    ("E0312", "`{name}` takes {expected} arguments but {given} were given"),
+    ("E0313", "Cannot infer type parameter `{param}` of `{name}`"),
+    // `diff` starts with a space, or with a line break when the types span several lines
+    ("E0314", "Mismatched return type:{diff}"),
+    ("E0315", "Type parameter `{param}` is bound to two different types:{diff}"),
+    ("E0316", "Mismatched types for argument {index}:{diff}"),
+    ("E0317", "Cannot format a value of type `{ty}` into a string, only numbers and strings can be"),
+    ("E0318", "Cannot apply `{operator}` to a value of type `string`, interpolate the strings to join them"),
+    ("E0319", "Cannot raise a value of type `{ty}` to a `float` power, cast it to `float` first"),
+    ("E0320", "`{name}` is a builtin function, which can only be called"),
+    ("E0321", "`{name}` takes an array, found a value of type `{ty}`"),
+    ("E0322", "`{name}` is an extern function, which can only be called"),
+    ("E0323", "Extern functions take numbers and strings and return numbers or `unit`, not `{ty}`"),
+    // code generation and running
+    ("E0401", "Expected a module"),
+    ("E0402", "Only function definitions are supported at module level"),
+    ("E0403", "Unsupported literal {literal}"),
+    ("E0404", "Unsupported expression {expression}"),
+    ("E0405", "Running programs with the JIT requires the `jit` feature, run them with `--backend interp` instead"),
+    ("E0406", "`main` must be a function without arguments, or taking the arguments of the program, `fn(args: [string]) -> int`"),
+    ("E0407", "No `main` function to run"),
+    ("E0408", "Building executables, object files and listings requires the `aot` feature, emit `ir` or `bytecode` instead"),
+    ("E0409", "Cannot write '{path}': {error}"),
+    ("E0410", "Linking with `{linker}` failed: {error}"),
+    ("E0411", "Cannot compile for `{target}`: {error}"),
+    ("E0412", "The IR of the program is malformed, {error}"),
+    ("E0413", "Division by zero"),
+    ("E0414", "Dividing {lhs} by {rhs} overflows"),
+    ("E0415", "Not a moolang bytecode file, it doesn't start with the right magic bytes"),
+    ("E0416", "Bytecode version {version} isn't supported, only version {supported} is, compile the program again"),
+    ("E0417", "Malformed bytecode, {error}"),
+    ("E0418", "Stack overflow, calls are nested more than {depth} deep"),
+    ("E0419", "Expected {expected} on the stack, found `{found}`"),
+    ("E0420", "Cannot read '{path}': {error}"),
+    ("E0421", "{count} programs ran differently on the backends, run `selftest --seed {seed}` to reproduce them"),
+    ("E0422", "The program trapped: {trap}"),
+    ("E0423", "The result of `{operator}` overflows `{ty}`"),
+    ("E0424", "Expected an integer on the standard input, found `{found}`"),
+    ("E0425", "Expected an integer on the standard input, found its end"),
+    ("E0426", "Index {index} is out of bounds of an array of length {len}"),
+    ("E0427", "`{name}` is an extern function, which only compiled code can call, run the program with the JIT"),
+    ("E0428", "No function `{name}` was compiled"),
+    ("E0429", "`{name}` is a `{found}`, not a `{expected}`"),
+    ("E0430", "`moo_run` passes at most {max} arguments, not {count}"),
+    ("E0431", "The {what} isn't valid UTF-8: {error}"),
+    ("E0432", "No error has the code `{code}`, codes are like `E0102`"),
+    ("E0433", "Unsupported cast from `{from}` to `{to}`"),
+    ("E0434", "Cannot cast a value of unknown type to `{to}`, annotate its type"),
+    ("E0435", "The `{command}` command requires the `{feature}` feature, build `moolang` with it"),
+    // projects
+    ("E0501", "Cannot read the manifest '{path}': {error}"),
+    ("E0502", "No source files given, and no `{file}` in '{directory}' or its parents"),
+    ("E0503", "Unknown key `{key}` in the manifest, expected one of {expected}"),
+    ("E0504", "The manifest has no `{key}`"),
+    ("E0505", "Expected {expected} in the manifest, found `{found}`"),
+    ("E0506", "`{key}` is set twice in the manifest"),
+    ("E0507", "`{name}` isn't a valid package name, only letters, digits, `_` and `-` are allowed"),
+    ("E0508", "Cannot create a project at '{path}', it already exists"),
+    ("E0509", "`{name}` isn't a valid dependency name, it must be importable: only letters, digits and `_`, not starting with a digit"),
+    ("E0510", "Cannot find the package `{package}`, expected its `{file}` at '{path}'"),
+    ("E0511", "Cyclic dependency: {cycle}"),
+    // warnings
+    ("W0001", "Unary `+` is deprecated and an error from edition {edition} on, remove it"),
+    ("W0002", "`:` before the return type of a function is deprecated, write `->` instead"),
+    ("W0003", "Unused variable `{name}`, prefix it with `_` if that's intended"),
+    ("W0004", "Unused function `{name}`, which `main` never calls, prefix it with `_` if that's intended"),
+    // the text around the messages
+    ("one-of", "one of {alternatives} or {last}"),
+    ("inside-file", "Inside file '{path}':"),
+    ("inside-source", "Inside {name}:"),
+    ("help", "help:"),
+    ("first-defined", "first defined here"),
+    ("label-at", "{message} at {location}"),
+    ("did-you-mean", "did you mean `{replacement}`?"),
+    ("instead-of", "did you mean `{replacement}` instead of `{code}`?"),
+    ("change", "change `{code}` to `{replacement}`"),
+    ("insert", "insert `{code}` at the end of line {line}"),
+    ("remove", "remove `{code}`"),
+    ("no-snippet", "Couldn't show snippet, error opening file: {error}"),
+    ("plugin-message", "{message} [{plugin}]"),
+    ("one-error", "1 error"),
+    ("errors", "{count} errors"),
+    ("one-warning", "1 warning"),
+    ("warnings", "{count} warnings"),
+];
+
+static CATALOG: RwLock<Catalog> = RwLock::new(ENGLISH);
+
+/// Makes the messages come from `catalog`, those it lacks still coming from `ENGLISH`
+pub fn set_catalog(catalog: Catalog) {
+    *CATALOG.write().unwrap() = catalog;
+}
+
+/// A message of the catalog, formatted, which keeps its key: the code of the error or warning it is the message of, if any
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    key: &'static str,
+    text: String,
+}
+
+impl Message {
+    /// The code of the error or warning the message is that of, e.g. `E0102`, whatever the catalog
+    pub fn code(&self) -> Option<&'static str> {
+        let key = self.key;
+        (key.starts_with(['E', 'W']) && key.len() > 1 && key[1..].bytes().all(|byte| byte.is_ascii_digit())).then_some(key)
+    }
+    /// The message of the error or warning `code` of the catalog, already formatted, e.g. by compiled code reporting it
+    pub fn formatted(code: &str, text: String) -> Self {
+        let key = ENGLISH.iter().map(|(key, _)| *key).find(|key| *key == code).unwrap_or_default();
+        Self { key, text }
+    }
+}
+
+impl Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl From<Message> for String {
+    fn from(message: Message) -> Self {
+        message.text
+    }
+}
+
+/// Returns the message `key` of the catalog, its placeholders replaced by the `args` of the same name.
+/// Debug builds check that the placeholders and the arguments match, so a message can't silently
+/// lose a detail, nor show a placeholder.
+pub fn text(key: &'static str, args: &[(&str, &dyn Display)]) -> Message {
+    let lookup = |catalog: Catalog| catalog.iter().find(|(k, _)| *k == key).map(|(_, template)| *template);
+    let template = lookup(*CATALOG.read().unwrap())
+        .or_else(|| lookup(ENGLISH))
+        .unwrap_or_else(|| panic!("no message `{}` in the catalog", key));
+
+    let mut message = String::new();
+    let mut used = vec![false; args.len()];
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}').map(|end| start + end) else {
+            break;
+        };
+        message.push_str(&rest[..start]);
+        let name = &rest[start + 1..end];
+        match args.iter().position(|(arg, _)| *arg == name) {
+            Some(i) => {
+                message.push_str(&args[i].1.to_string());
+                used[i] = true;
+            }
+            None => {
+                debug_assert!(false, "message `{}` has no argument for `{{{}}}`", key, name);
+                message.push_str(&rest[start..=end]);
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    message.push_str(rest);
+    debug_assert!(used.iter().all(|used| *used), "message `{}` doesn't use all of its arguments", key);
+    Message { key, text: message }
+}
+
+/// Formats a message of the catalog, e.g. `message!("E0102", name = "x")`
+macro_rules! message {
+    ($key:literal $(, $name:ident = $value:expr)* $(,)?) => {
+        $crate::messages::text($key, &[$((stringify!($name), &$value as &dyn std::fmt::Display)),*])
+    };
+}
+pub(crate) use message;
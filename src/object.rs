@@ -0,0 +1,144 @@
+// Ahead of time compilation: the code of a program is generated into a relocatable object file,
+// for the host machine or another one, to be linked into other programs.
+// Module level functions are exported under their `jit::symbol`, and take the environment
+// of a closure first like every function, e.g. `int64_t moo_square(void *env, int64_t x)` in C.
+// The runtime functions the code calls, see `runtime`, are left for the linked program to provide,
+// or `link` links the objects of a program, one per module, with a runtime written in C into an executable.
+
+use core::fmt;
+use std::error::Error;
+use std::path::Path;
+use std::process::Command;
+
+use cranelift_module::default_libcall_names;
+use cranelift_object::{ObjectBuilder, ObjectModule};
+
+use crate::debuginfo::Dwarf;
+use crate::errors::{Coded, LocalizedError};
+use crate::frontend::ast::AST;
+use crate::frontend::symbol::Symbol;
+use crate::frontend::types::Ty;
+use crate::gc::{self, Gc};
+use crate::ir::Printed;
+use crate::jit::{codegen_error, isa, target_isa, Codegen, Listing};
+use crate::messages::{message, Message};
+
+/// The runtime executables are linked with, which calls their `main` function
+const SHIM: &str = include_str!("shim.c");
+
+#[derive(Debug)]
+pub struct LinkError {
+    message: Message,
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "LinkError: {}", self.message)
+    }
+}
+
+impl Error for LinkError {}
+
+impl Coded for LinkError {
+    fn code(&self) -> Option<&'static str> {
+        self.message.code()
+    }
+}
+
+/// Compiles modules to machine code in an object file
+pub type AOT = Codegen<ObjectModule>;
+
+/// A code generator into an object file
+/// * `name` - the name of the object, e.g. the name of the entry file
+/// * `target` - the target triple of the machine to compile for, the host by default
+fn codegen(module: &AST, name: &str, target: Option<&str>) -> Result<AOT, Vec<LocalizedError>> {
+    // executables are linked position independent
+    let isa = match target {
+        Some(target) => target_isa(target, true)
+            .map_err(|error| vec![codegen_error(message!("E0411", target = target, error = error), module)])?,
+        None => isa(true),
+    };
+    let builder = ObjectBuilder::new(isa, name, default_libcall_names())
+        .map_err(|e| vec![codegen_error(e, module)])?;
+    // Ctrl-C is left to the default handler of the program
+    Ok(Codegen::new(ObjectModule::new(builder), false))
+}
+
+/// Compiles a module into the bytes of an object file, with the number of functions it defines
+/// * `source` - the source file the module was read from, which the errors of the code running are reported in
+/// * `imports` - the functions of the other modules of the program it calls, in the objects it is linked with
+/// * `debug_info` - whether to describe the code against the source in debug information
+///   written into the object, see `debuginfo`
+pub fn compile_object(module: &AST, source: &Path, imports: &[(Symbol, Ty)], name: &str, target: Option<&str>, debug_info: bool) -> Result<(Vec<u8>, usize), Vec<LocalizedError>> {
+    let mut codegen = codegen(module, name, target)?;
+    if debug_info {
+        codegen.record_debug_info();
+    }
+    codegen.import(imports).map_err(|e| vec![codegen_error(e, module)])?;
+    codegen.define(module, source)?;
+    let functions = codegen.defined();
+    let debug_functions = codegen.take_debug_info();
+    let dwarf = debug_info
+        .then(|| Dwarf::new(&debug_functions, source, codegen.isa()))
+        .transpose()
+        .map_err(|e| vec![codegen_error(e, module)])?;
+    let mut product = codegen.into_module().finish();
+    if let Some(dwarf) = dwarf {
+        dwarf.write(&mut product).map_err(|e| vec![codegen_error(e, module)])?;
+    }
+    let bytes = product
+        .emit()
+        .map_err(|e| vec![codegen_error(e, module)])?;
+    Ok((bytes, functions))
+}
+
+/// Compiles a module like `compile_object`, returning the code generated for every function
+pub fn compile_listings(module: &AST, source: &Path, name: &str, target: Option<&str>) -> Result<Vec<Listing>, Vec<LocalizedError>> {
+    let mut codegen = codegen(module, name, target)?;
+    codegen.record_listings();
+    codegen.define(module, source)?;
+    Ok(codegen.take_listings())
+}
+
+/// Links object files, compiled from the modules of a program with a `main` function, into an executable at `output`.
+/// The C compiler in `CC`, or `cc` by default, compiles the runtime and drives the system linker,
+/// e.g. `CC="cc -fuse-ld=lld"` links with `lld`, or a cross compiler for objects compiled for another machine.
+/// * `objects` - the bytes of the objects, see `compile_object`
+/// * `main_args` - whether `main` takes the arguments of the program, see `compile::check_entry_point`
+/// * `returns` - how the value a `main` without arguments returns is printed
+/// * `leak_check` - whether the executable reports the objects the program leaked, for debug builds
+pub fn link(objects: &[Vec<u8>], name: &str, output: &Path, main_args: bool, returns: Printed, leak_check: bool) -> Result<(), LinkError> {
+    let compiler = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let mut words = compiler.split_whitespace();
+    let program = words.next().unwrap_or("cc");
+    let failed = |error: &dyn fmt::Display| LinkError {
+        message: message!("E0410", linker = compiler, error = error),
+    };
+
+    // the inputs of the linker, in a directory of their own
+    let dir = std::env::temp_dir().join(format!("moolang-{}-{}", name, std::process::id()));
+    let object_paths = (0..objects.len()).map(|index| dir.join(format!("{}-{}.o", name, index))).collect::<Vec<_>>();
+    let shim_path = dir.join("shim.c");
+    let status = std::fs::create_dir_all(&dir)
+        .and_then(|()| object_paths.iter().zip(objects).try_for_each(|(path, object)| std::fs::write(path, object)))
+        .and_then(|()| std::fs::write(&shim_path, SHIM))
+        .and_then(|()| Command::new(program)
+            .args(words)
+            .arg("-o")
+            .arg(output)
+            .args(&object_paths)
+            .arg(&shim_path)
+            .args(main_args.then_some("-DMOO_MAIN_ARGS"))
+            .arg(format!("-DMOO_MAIN_RETURNS_{}", returns.as_str().to_uppercase()))
+            .args(leak_check.then_some("-DMOO_LEAK_CHECK"))
+            .args((gc::current() == Gc::Tracing).then_some("-DMOO_GC_TRACING"))
+            // the C library's math functions, e.g. `pow`
+            .arg("-lm")
+            .status());
+    let _ = std::fs::remove_dir_all(&dir);
+    // the linker reports why it failed on stderr
+    match status.map_err(|error| failed(&error))? {
+        status if status.success() => Ok(()),
+        status => Err(failed(&status)),
+    }
+}
@@ -0,0 +1,218 @@
+// The interactive mode, `moolang repl`.
+// Every input is compiled into the same JIT, so the functions and values it defines
+// stay alive for the inputs after it. The frontend checks an input along with the
+// definitions of the inputs before it, and the JIT only compiles what is new.
+
+use std::error::Error;
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use anstream::{eprintln, print, println};
+
+use crate::compile::report_warnings;
+use crate::errors::{self, Diagnostics};
+use crate::frontend::arena::Node;
+use crate::frontend::ast::{parse_module, ParseError, Type, TypeName, AST};
+use crate::frontend::generics::monomorphize_with_types;
+use crate::frontend::modules::{load_imports, parse_file};
+use crate::frontend::plugin::run_plugins;
+use crate::frontend::resolve::{binding_name, resolve};
+use crate::frontend::symbol::Symbol;
+use crate::frontend::tokenizer::{tokenize, Operator};
+use crate::frontend::types::{IntTy, Ty};
+use crate::jit::JIT;
+use crate::{interrupt, runtime, sources};
+
+/// Printed before the first line of an input, when reading from a terminal
+const PROMPT: &str = "moo> ";
+/// Printed before the next lines of an input
+const CONTINUATION: &str = "...> ";
+
+/// Reads inputs from stdin until its end, printing the value of every input ending with an expression.
+/// An input spans several lines while it's missing its end, e.g. the `}` of a block,
+/// or until an empty line.
+pub fn run() -> Result<(), Box<dyn Error>> {
+    let mut repl = Repl::default();
+    let stdin = io::stdin();
+    let interactive = stdin.is_terminal();
+    let mut input = String::new();
+    loop {
+        if interactive {
+            print!("{}", if input.is_empty() { PROMPT } else { CONTINUATION });
+            io::stdout().flush()?;
+        }
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            break;
+        }
+        if interrupt::interrupted() {
+            interrupt::exit();
+        }
+        let blank = line.trim().is_empty();
+        if blank && input.is_empty() {
+            continue;
+        }
+        input.push_str(&line);
+        let source = match complete(&input) {
+            Some(source) => source,
+            None if !blank => continue,
+            None => input.clone(),
+        };
+        input.clear();
+
+        let value = repl.eval(&source);
+        report_warnings();
+        match value {
+            Ok(Some(value)) => println!("{}", value),
+            Ok(None) => (),
+            Err(errors) => eprintln!("{}", errors),
+        }
+    }
+    Ok(())
+}
+
+/// The source to evaluate once `input` is a whole input, `None` while lines are missing from it.
+/// The `;` after the last statement is optional.
+fn complete(input: &str) -> Option<String> {
+    let terminated = format!("{};", input.trim_end());
+    match (parse(input), parse(&terminated)) {
+        (Some(Ok(_)), _) => Some(input.to_owned()),
+        (_, Some(Ok(_))) => Some(terminated),
+        (Some(Err(ParseError::UnexpectedEof { .. })), _) => None,
+        // left to `eval` to report
+        _ => Some(input.to_owned()),
+    }
+}
+
+/// Parses `source` on its own, `None` if it has an invalid token
+fn parse(source: &str) -> Option<Result<AST, ParseError>> {
+    let tokens = tokenize(source.lines()).collect::<Result<Vec<_>, _>>().ok()?;
+    Some(parse_module(&mut tokens.into_iter().peekable()))
+}
+
+#[derive(Default)]
+struct Repl {
+    jit: JIT,
+    /// The module level statements of the inputs so far, before monomorphization
+    definitions: Vec<AST>,
+    /// The number of inputs so far, to name them
+    inputs: usize,
+}
+
+impl Repl {
+    /// Compiles the input `source` and runs it, returning the value of its last statement
+    /// if it's an expression with a value
+    fn eval(&mut self, source: &str) -> Result<Option<String>, Diagnostics> {
+        self.inputs += 1;
+        let path = sources::repl_name(self.inputs);
+        sources::register(&path, source);
+        let module = load_imports(parse_file(&path)?, &path)?;
+        let location = *module.location();
+
+        // the value of an expression is bound to a name of its own, and computed like other values
+        let mut statements = Vec::new();
+        let mut expressions = Vec::new();
+        let mut last = None;
+        let module_statements = match module.type_() {
+            Type::Module(statements) => statements,
+            _ => unreachable!("parse returns a module"),
+        };
+        for (i, statement) in module_statements.into_iter().enumerate() {
+            if let Type::Expression(Operator::Let, ..) = *statement {
+                statements.push(statement);
+                last = None;
+                continue;
+            }
+            let name = Symbol::intern(&format!("{}:{}", path.display(), i));
+            let span = statement.span();
+            statements.push(Type::Expression(
+                Operator::Let,
+                Node::new(Type::Literal(name).wrap_span(span)),
+                Node::new(statement),
+            ).wrap_span(span));
+            expressions.push(name);
+            last = Some(name);
+        }
+
+        let module = Type::Module(self.definitions.iter().cloned().chain(statements.iter().cloned()).collect()).wrap(location);
+        resolve(&module).map_err(|errors| errors.into_iter().map(|error| error.with_source(&path)).collect::<Diagnostics>())?;
+        let (module, types) = monomorphize_with_types(module).map_err(|error| error.with_source(&path))?;
+
+        // what the inputs before defined is compiled already
+        let mut fresh = Vec::new();
+        let mut values = Vec::new();
+        let module_statements = match module.type_() {
+            Type::Module(statements) => statements,
+            _ => unreachable!("monomorphize returns a module"),
+        };
+        for statement in module_statements {
+            let span = statement.span();
+            let (name, value) = match &*statement {
+                Type::Expression(Operator::Let, name, value) => (binding_name(name).expect("resolved binding"), value),
+                _ => unreachable!("statements are bindings"),
+            };
+            if self.jit.is_defined(name) {
+                continue;
+            }
+            if let Type::Lambda(..) = ***value {
+                fresh.push(statement);
+                continue;
+            }
+            // a value is computed by a function of its own, called once compiled,
+            // and read from where it's stored by the code using it
+            let slot = Box::into_raw(Box::new(0i64));
+            let init = Symbol::intern(&format!("{}:{}", name, values.len()));
+            let return_type = TypeName::Named(types.get(&name).map_or_else(|| "int".to_owned(), Ty::to_string));
+            fresh.push(Type::Expression(
+                Operator::Let,
+                Node::new(Type::Literal(init).wrap_span(span)),
+                Node::new(Type::Lambda(return_type, Vec::new(), value.clone()).wrap_span(span)),
+            ).wrap_span(span));
+            self.jit.define_value(name, slot, types.get(&name).cloned().unwrap_or(Ty::Int));
+            values.push((name, init, slot));
+        }
+
+        let fresh = Type::Module(fresh).wrap(location);
+        let lints = run_plugins(&fresh);
+        lints.warnings.into_iter().for_each(|warning| errors::warn(warning.with_source(&path)));
+        let functions = match lints.errors.is_empty() {
+            true => self.jit.compile(&fresh, &path),
+            false => Err(lints.errors),
+        };
+        let functions = match functions {
+            Ok(functions) => functions,
+            Err(errors) => {
+                values.iter().for_each(|(name, _, _)| self.jit.undefine_value(*name));
+                return Err(errors.into_iter().map(|error| error.with_source(&path)).collect());
+            }
+        };
+        // only the definitions which compiled are kept, the values of expressions can't be referred to
+        self.definitions.extend(statements.into_iter().filter(|statement| match &**statement {
+            Type::Expression(Operator::Let, name, _) => binding_name(name).is_some_and(|name| !expressions.contains(&name)),
+            _ => false,
+        }));
+
+        let mut value = None;
+        for (name, init, slot) in values {
+            let init: extern "C" fn(*const u8) -> i64 = unsafe { std::mem::transmute(functions[init.as_str()]) };
+            let computed = init(std::ptr::null());
+            unsafe { *slot = computed };
+            if Some(name) == last {
+                value = show(computed, types.get(&name));
+            }
+        }
+        Ok(value)
+    }
+}
+
+/// Shows a value as it would be written in the source, given its type.
+/// Values of type `unit` aren't shown.
+fn show(value: i64, ty: Option<&Ty>) -> Option<String> {
+    Some(match ty {
+        Some(Ty::Unit) => return None,
+        Some(Ty::Sized(IntTy::U64)) => (value as u64).to_string(),
+        Some(Ty::Float) => format!("{:?}", f64::from_bits(value as u64)),
+        Some(Ty::String) => format!("{:?}", unsafe { runtime::read(value as *const u8) }),
+        Some(ty @ (Ty::Function(..) | Ty::AnyFunction)) => format!("<{}>", ty),
+        _ => value.to_string(),
+    })
+}
@@ -0,0 +1,447 @@
+// Functions the generated code calls into.
+// They are registered as symbols of the `JITModule`, see `symbols`.
+// Object files import them by the same names, for the runtime they are linked with to provide.
+//
+// The memory of strings, arrays and closure environments is reference counted: each is allocated
+// by `__moo_new` after a header holding its count, and freed by the `__moo_release` dropping its count to 0,
+// along with the objects it holds. The code compiled retains and releases them, see `ir::rc`.
+// The objects alive are counted, for debug builds to check that programs don't leak them, see `live`.
+// With the tracing collector, the objects aren't counted but traced from the roots of the functions running instead,
+// see `gc` and `collect`.
+
+use std::alloc::{alloc, dealloc, Layout};
+use std::cell::{Cell, RefCell};
+use std::path::Path;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use crate::frontend::builtins;
+use crate::gc::{self, Gc};
+use crate::frontend::tokenizer::{Location, Span};
+use crate::messages::{message, Message};
+use crate::traps;
+
+/// The size of the header before the objects whose memory is reference counted: 3 fields of 8 bytes,
+/// the size of the object, the number of objects it holds, then its reference count.
+/// An object holds the objects in the 8 bytes slots after its first slot, e.g. the captures of a closure environment
+/// after its function pointer, whose number is given, or else all the elements of an array, see `ELEMENTS`.
+/// The objects in the data of the code, e.g. string literals, are never freed, their count is negative.
+/// C code reads it as a `struct { int64_t size, children, count; }`.
+/// With the tracing collector, the count is whether the object is marked, 1, or not, 0.
+pub const HEADER: usize = 3 * SIZE;
+
+/// The number of the objects held by an array whose elements are objects, whatever its length
+pub const ELEMENTS: i64 = -1;
+
+/// The number of the objects alive, those allocated and not freed yet
+static LIVE: AtomicI64 = AtomicI64::new(0);
+
+/// The fewest allocations between two collections of the tracing collector
+const COLLECTION_INTERVAL: usize = 1024;
+
+thread_local! {
+    /// The frame of the innermost function running with the tracing collector, see `__moo_enter`
+    static FRAMES: Cell<*const i64> = const { Cell::new(std::ptr::null()) };
+    /// The objects allocated with the tracing collector, which it frees once unreachable
+    static OBJECTS: RefCell<Vec<*mut u8>> = const { RefCell::new(Vec::new()) };
+    /// The allocations left before the next collection
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(COLLECTION_INTERVAL) };
+}
+
+/// Allocates an object of `size` bytes, 8 byte aligned, holding `children` objects, see `HEADER`,
+/// with a count of 1 for the reference returned.
+/// With the tracing collector, the objects unreachable are collected first every so many allocations,
+/// at every one in debug builds, for the objects the compiled code doesn't root to be found early
+pub extern "C" fn __moo_new(size: i64, children: i64) -> *mut u8 {
+    let tracing = gc::current() == Gc::Tracing;
+    if tracing {
+        let left = ALLOCATIONS.get();
+        match left {
+            0 => collect(),
+            _ if cfg!(debug_assertions) => collect(),
+            _ => ALLOCATIONS.set(left - 1),
+        }
+    }
+    let layout = Layout::from_size_align(HEADER + size as usize, 8)
+        .expect("invalid allocation size");
+    LIVE.fetch_add(1, Ordering::Relaxed);
+    let object = unsafe {
+        let header = alloc(layout).cast::<i64>();
+        header.write(size);
+        header.add(1).write(children);
+        header.add(2).write(if tracing { 0 } else { 1 });
+        header.add(3).cast()
+    };
+    if tracing {
+        OBJECTS.with_borrow_mut(|objects| objects.push(object));
+    }
+    object
+}
+
+/// Pushes the frame of a function starting to run onto the shadow stack of the tracing collector,
+/// returning `unit` as 0. A frame is the frame of the function calling it on 8 bytes, then the number of roots,
+/// followed by the roots: the objects the values of the function are, or 0 until they are computed.
+/// The code compiled lays the frames out, see `jit::FunctionTranslator::translate_enter`.
+///
+/// # Safety
+/// The frame is laid out as above, and popped by `__moo_leave` before the function returns
+pub unsafe extern "C" fn __moo_enter(frame: *mut i64) -> i64 {
+    frame.write(FRAMES.get() as i64);
+    FRAMES.set(frame);
+    0
+}
+
+/// Pops the frame of a function returning off the shadow stack, returning `unit` as 0
+///
+/// # Safety
+/// The frame is the last one pushed by `__moo_enter`
+pub unsafe extern "C" fn __moo_leave(frame: *const i64) -> i64 {
+    FRAMES.set(frame.read() as *const i64);
+    0
+}
+
+/// Frees the objects the tracing collector allocated which the roots of the functions running don't reach.
+/// Once the program returned, there are no roots left, and it frees every object
+pub fn collect() {
+    let mut marking = Vec::new();
+    let mut frame = FRAMES.get();
+    while !frame.is_null() {
+        unsafe {
+            let roots = frame.add(1).read() as usize;
+            marking.extend((0..roots).map(|root| frame.add(2 + root).read()).filter(|root| *root != 0));
+            frame = frame.read() as *const i64;
+        }
+    }
+    while let Some(object) = marking.pop() {
+        unsafe {
+            let header = (object as *mut u8).sub(HEADER).cast::<i64>();
+            // the objects marked already, and those in the data of the code, which hold none
+            if header.add(2).read() != 0 {
+                continue;
+            }
+            header.add(2).write(1);
+            marking.extend((1..=children(header)).map(|slot| (object as *const i64).add(slot).read()));
+        }
+    }
+    let survivors = OBJECTS.with_borrow_mut(|objects| {
+        objects.retain(|object| unsafe {
+            let count = object.sub(SIZE).cast::<i64>();
+            let marked = count.read() == 1;
+            match marked {
+                true => count.write(0),
+                false => free(*object),
+            }
+            marked
+        });
+        objects.len()
+    });
+    ALLOCATIONS.set(survivors.max(COLLECTION_INTERVAL));
+}
+
+/// Takes a reference to an object, returning `unit` as 0
+///
+/// # Safety
+/// The object is allocated by `__moo_new`, or in the data of the code
+pub unsafe extern "C" fn __moo_retain(object: *mut u8) -> i64 {
+    let count = object.sub(SIZE).cast::<i64>();
+    if *count >= 0 {
+        *count += 1;
+    }
+    0
+}
+
+/// Drops a reference to an object, freeing it and releasing the objects it holds if it was the last one,
+/// returning `unit` as 0
+///
+/// # Safety
+/// The object is allocated by `__moo_new`, or in the data of the code, and isn't used after by the caller
+pub unsafe extern "C" fn __moo_release(object: *mut u8) -> i64 {
+    let header = object.sub(HEADER).cast::<i64>();
+    let count = header.add(2);
+    if *count < 0 {
+        return 0;
+    }
+    *count -= 1;
+    if *count > 0 {
+        return 0;
+    }
+    let slots = object.cast::<i64>();
+    for slot in 1..=children(header) {
+        __moo_release(slots.add(slot).read() as *mut u8);
+    }
+    free(object);
+    0
+}
+
+/// The number of objects an object holds, after its first slot, from its header
+unsafe fn children(header: *const i64) -> usize {
+    match header.add(1).read() {
+        ELEMENTS => header.add(HEADER / SIZE).read() as usize,
+        children => children as usize,
+    }
+}
+
+/// Frees the memory of an object, leaving the objects it holds be
+unsafe fn free(object: *mut u8) {
+    let header = object.sub(HEADER);
+    let layout = Layout::from_size_align(HEADER + header.cast::<i64>().read() as usize, 8).expect("valid allocation size");
+    dealloc(header, layout);
+    LIVE.fetch_sub(1, Ordering::Relaxed);
+}
+
+/// The number of the objects alive, which is 0 again once a program released the objects it allocated
+pub fn live() -> i64 {
+    LIVE.load(Ordering::Relaxed)
+}
+
+/// The header of an object in the data of the code, see `HEADER`, which comes before its bytes
+pub fn static_header(size: usize) -> Vec<u8> {
+    [size as i64, 0, -1].iter().flat_map(|field| field.to_ne_bytes()).collect()
+}
+
+/// Lays a string out the way compiled code passes it around a pointer to:
+/// its length in bytes on 8 bytes, followed by its UTF-8 bytes, then a null byte which the length doesn't count,
+/// so that extern functions can take the bytes as a C string.
+/// C code reads it as a `struct { int64_t len; char bytes[]; }`.
+pub fn encode(value: &str) -> Vec<u8> {
+    let mut bytes = (value.len() as i64).to_ne_bytes().to_vec();
+    bytes.extend_from_slice(value.as_bytes());
+    bytes.push(0);
+    bytes
+}
+
+/// Moves a string to the heap for compiled code, see `encode`, with a count of 1, see `__moo_new`
+pub fn string(value: &str) -> *const u8 {
+    let bytes = encode(value);
+    let string = __moo_new(bytes.len() as i64, 0);
+    unsafe { string.copy_from_nonoverlapping(bytes.as_ptr(), bytes.len()) };
+    string
+}
+
+/// Reads a string passed around by compiled code
+///
+/// # Safety
+/// The string is laid out by `encode`, and lives as long as `'a`
+pub unsafe fn read<'a>(string: *const u8) -> &'a str {
+    let len = string.cast::<i64>().read_unaligned() as usize;
+    std::str::from_utf8_unchecked(std::slice::from_raw_parts(string.add(SIZE), len))
+}
+
+/// Moves an array to the heap for compiled code: its length on 8 bytes, followed by its elements,
+/// each a value of compiled code on 8 bytes, e.g. a pointer to a string.
+/// C code reads it as a `struct { int64_t len; int64_t elements[]; }`.
+/// The array takes the references to its elements if they are objects, `managed`, releasing them once freed.
+pub fn array(elements: &[i64], managed: bool) -> *const u8 {
+    let bytes = std::iter::once(elements.len() as i64)
+        .chain(elements.iter().copied())
+        .flat_map(i64::to_ne_bytes)
+        .collect::<Vec<_>>();
+    let array = __moo_new(bytes.len() as i64, if managed { ELEMENTS } else { 0 });
+    unsafe { array.copy_from_nonoverlapping(bytes.as_ptr(), bytes.len()) };
+    array
+}
+
+/// Moves the arguments of a program to the heap for its `main`, an array of strings, see `array`.
+/// The strings are rooted while the others are allocated, for the tracing collector not to free them
+pub fn arguments(args: &[String]) -> *const u8 {
+    let mut roots = vec![0i64; 2 + args.len()];
+    let frame = roots.as_mut_ptr();
+    unsafe {
+        frame.add(1).write(args.len() as i64);
+        __moo_enter(frame);
+        for (i, arg) in args.iter().enumerate() {
+            frame.add(2 + i).write(string(arg) as i64);
+        }
+        let arguments = array(std::slice::from_raw_parts(frame.add(2), args.len()), true);
+        __moo_leave(frame);
+        arguments
+    }
+}
+
+/// The size of the length of a string, before its bytes
+const SIZE: usize = std::mem::size_of::<i64>();
+
+/// Formats a signed integer, or an unsigned one narrower than 64 bits
+pub extern "C" fn __moo_format_int(value: i64) -> *const u8 {
+    string(&value.to_string())
+}
+
+/// Formats an unsigned 64 bits integer, passed as its bits
+pub extern "C" fn __moo_format_uint(value: i64) -> *const u8 {
+    string(&(value as u64).to_string())
+}
+
+/// Formats a float, passed as its bits
+pub extern "C" fn __moo_format_float(bits: i64) -> *const u8 {
+    string(&f64::from_bits(bits as u64).to_string())
+}
+
+/// Concatenates two strings into a new one
+///
+/// # Safety
+/// Both strings are laid out by `encode`
+pub unsafe extern "C" fn __moo_concat(lhs: *const u8, rhs: *const u8) -> *const u8 {
+    string(&format!("{}{}", read(lhs), read(rhs)))
+}
+
+/// The builtin `print`, returning `unit` as 0
+///
+/// # Safety
+/// The string is laid out by `encode`
+pub unsafe extern "C" fn __moo_print(string: *const u8) -> i64 {
+    builtins::print(read(string), false);
+    0
+}
+
+/// The builtin `println`, see `__moo_print`
+///
+/// # Safety
+/// The string is laid out by `encode`
+pub unsafe extern "C" fn __moo_println(string: *const u8) -> i64 {
+    builtins::print(read(string), true);
+    0
+}
+
+/// The builtin `read_int`, reporting at the call if there is no integer to read, and exiting
+///
+/// # Safety
+/// The location is laid out by `encode_location`
+pub unsafe extern "C" fn __moo_read_int(location: *const u8) -> i64 {
+    builtins::read_int().unwrap_or_else(|message| {
+        let (span, source) = read_location(location);
+        traps::exit(message, span, Path::new(source))
+    })
+}
+
+/// The builtin `read_line`
+pub extern "C" fn __moo_read_line() -> *const u8 {
+    string(&builtins::read_line())
+}
+
+/// The builtin `len`
+///
+/// # Safety
+/// The array is laid out by `array`
+pub unsafe extern "C" fn __moo_len(array: *const u8) -> i64 {
+    array.cast::<i64>().read_unaligned()
+}
+
+/// The builtin `at`, reporting at the call if the index is out of bounds, and exiting.
+/// An element which is an object is retained, the caller getting a reference to it, unless with the tracing collector
+///
+/// # Safety
+/// The location is laid out by `encode_location`, and the array by `array`
+pub unsafe extern "C" fn __moo_at(location: *const u8, array: *const u8, index: i64) -> i64 {
+    let len = __moo_len(array) as usize;
+    match builtins::index(index, len) {
+        Ok(offset) => {
+            let element = array.cast::<i64>().add(1 + offset).read_unaligned();
+            if gc::current() == Gc::Rc && array.sub(HEADER - SIZE).cast::<i64>().read() == ELEMENTS {
+                __moo_retain(element as *mut u8);
+            }
+            element
+        }
+        Err(message) => {
+            let (span, source) = read_location(location);
+            traps::exit(message, span, Path::new(source))
+        }
+    }
+}
+
+/// Lays the location of some code out for the runtime functions reporting errors at it:
+/// the line, column and offset of its start, then of its end, on 8 bytes each,
+/// followed by the path of its source, laid out by `encode`.
+/// C code reads it as a `struct { int64_t line, column, offset, end_line, end_column, end_offset, path_len; char path[]; }`.
+pub fn encode_location(span: Span, source: &Path) -> Vec<u8> {
+    let mut bytes = [span.start, span.end]
+        .iter()
+        .flat_map(|location| [location.line, location.column, location.offset])
+        .flat_map(|field| (field as i64).to_ne_bytes())
+        .collect::<Vec<_>>();
+    bytes.extend(encode(&source.to_string_lossy()));
+    bytes
+}
+
+/// Reads a location laid out by `encode_location`
+///
+/// # Safety
+/// The location lives as long as `'a`
+unsafe fn read_location<'a>(location: *const u8) -> (Span, &'a str) {
+    let field = |index: usize| location.cast::<i64>().add(index).read_unaligned() as usize;
+    let start = Location { line: field(0), column: field(1), offset: field(2), ..Location::default() };
+    let end = Location { line: field(3), column: field(4), offset: field(5), ..Location::default() };
+    (Span::new(start, end), read(location.add(6 * SIZE)))
+}
+
+/// Called by compiled code about to divide by zero, to report it at the division and exit
+///
+/// # Safety
+/// The location is laid out by `encode_location`
+pub unsafe extern "C" fn __moo_division_by_zero(location: *const u8) {
+    let (span, source) = read_location(location);
+    traps::exit(message!("E0413"), span, Path::new(source))
+}
+
+/// Called by compiled code whose checked arithmetic overflows, to report it at the arithmetic and exit
+///
+/// # Safety
+/// The location is laid out by `encode_location`, and the message and its code by `encode`
+pub unsafe extern "C" fn __moo_overflow(location: *const u8, message: *const u8, code: *const u8) {
+    let (span, source) = read_location(location);
+    traps::exit(Message::formatted(read(code), read(message).to_owned()), span, Path::new(source))
+}
+
+/// The power function of the C library, which compiled code calls for `**` on floats
+extern "C" fn pow(base: f64, exponent: f64) -> f64 {
+    base.powf(exponent)
+}
+
+/// The functions of the C library the math builtins call, see `Builtin::symbol`
+extern "C" fn sin(x: f64) -> f64 {
+    x.sin()
+}
+
+extern "C" fn cos(x: f64) -> f64 {
+    x.cos()
+}
+
+extern "C" fn fmin(x: f64, y: f64) -> f64 {
+    x.min(y)
+}
+
+extern "C" fn fmax(x: f64, y: f64) -> f64 {
+    x.max(y)
+}
+
+/// Called by compiled code which found the Ctrl-C flag set, see `interrupt`
+pub extern "C" fn __moo_interrupted() {
+    crate::interrupt::exit()
+}
+
+/// The runtime symbols every compiled module can link against
+pub fn symbols() -> Vec<(&'static str, *const u8)> {
+    vec![
+        ("__moo_new", __moo_new as *const u8),
+        ("__moo_retain", __moo_retain as *const u8),
+        ("__moo_release", __moo_release as *const u8),
+        ("__moo_enter", __moo_enter as *const u8),
+        ("__moo_leave", __moo_leave as *const u8),
+        ("__moo_interrupted", __moo_interrupted as *const u8),
+        ("__moo_division_by_zero", __moo_division_by_zero as *const u8),
+        ("__moo_overflow", __moo_overflow as *const u8),
+        ("__moo_format_int", __moo_format_int as *const u8),
+        ("__moo_format_uint", __moo_format_uint as *const u8),
+        ("__moo_format_float", __moo_format_float as *const u8),
+        ("__moo_concat", __moo_concat as *const u8),
+        ("__moo_print", __moo_print as *const u8),
+        ("__moo_println", __moo_println as *const u8),
+        ("__moo_read_int", __moo_read_int as *const u8),
+        ("__moo_read_line", __moo_read_line as *const u8),
+        ("__moo_len", __moo_len as *const u8),
+        ("__moo_at", __moo_at as *const u8),
+        ("pow", pow as *const u8),
+        ("sin", sin as *const u8),
+        ("cos", cos as *const u8),
+        ("fmin", fmin as *const u8),
+        ("fmax", fmax as *const u8),
+    ]
+}
@@ -0,0 +1,368 @@
+// Differential testing of the backends: `moo selftest` runs programs on every backend,
+// at several optimization levels, and reports the programs whose results differ.
+// The programs are the source files given, each run on its own, and programs generated
+// from a seed, which only use arithmetic that can't trap, the JIT running in process.
+// They overflow all the time though, so they wrap around rather than trap with `--overflow trap`.
+
+use core::fmt;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::slice;
+use std::time::{SystemTime, UNIX_EPOCH};
+use anstream::println;
+
+use crate::bytecode::{self, vm};
+use crate::compile::{check_entry_point, check_module, load_program, Backend, Summary};
+use crate::errors::{Coded, Diagnostics, LocalizedError};
+use crate::frontend::ast::{Type, AST};
+use crate::frontend::modules::{merge, parse_file, source_files};
+use crate::frontend::optimize::{self, OptLevel};
+use crate::frontend::overflow::{self, Overflow};
+use crate::frontend::tokenizer::Operator;
+use crate::frontend::types::IntTy;
+use crate::interp::{Interpreter, Value};
+use crate::ir;
+use crate::messages::{message, Message};
+use crate::sources;
+#[cfg(feature = "jit")]
+use crate::jit::JIT;
+
+#[derive(Debug)]
+pub struct SelftestError {
+    message: Message,
+}
+
+impl fmt::Display for SelftestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SelftestError: {}", self.message)
+    }
+}
+
+impl Error for SelftestError {}
+
+impl Coded for SelftestError {
+    fn code(&self) -> Option<&'static str> {
+        self.message.code()
+    }
+}
+
+/// The backends the programs are run on, with the optimization level they are run at
+const BACKENDS: &[(&str, Backend, OptLevel)] = &[
+    ("interp", Backend::Interp, OptLevel::O0),
+    ("vm", Backend::Vm, OptLevel::O0),
+    ("vm -O1", Backend::Vm, OptLevel::O1),
+    #[cfg(feature = "jit")]
+    ("jit", Backend::Jit, OptLevel::O0),
+    #[cfg(feature = "jit")]
+    ("jit -O2", Backend::Jit, OptLevel::O2),
+];
+
+/// The value `main` returns, or the message of the error running it
+type Outcome = Result<String, String>;
+
+/// What running a program on the backends found
+enum Comparison {
+    Same,
+    /// The program has no `main` function to run, or it doesn't return an integer
+    Skipped,
+    /// The outcome on every backend, some of them different
+    Diverged(Vec<(&'static str, Outcome)>),
+}
+
+/// Runs the programs at `paths`, and `generated` programs generated from `seed`, on every backend,
+/// printing the programs which run differently on some of them, and a summary.
+/// The seed is the current time by default, and is printed to reproduce the programs.
+pub fn run(paths: &[PathBuf], generated: usize, seed: Option<u64>) -> Result<(), Box<dyn Error>> {
+    let seed = seed.unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).map_or(1, |time| time.as_nanos() as u64));
+    let mut programs = 0;
+    let mut skipped = 0;
+    let mut divergences = 0;
+
+    // files which don't compile are skipped, they are the business of the frontend
+    for path in source_files(paths)? {
+        programs += 1;
+        let comparison = load_program(slice::from_ref(&path))
+            .ok()
+            .and_then(|(ast, _)| compare(ast, &path).ok());
+        match comparison {
+            Some(Comparison::Same) => (),
+            Some(Comparison::Diverged(outcomes)) => {
+                report(&path, &outcomes);
+                divergences += 1;
+            }
+            Some(Comparison::Skipped) | None => skipped += 1,
+        }
+    }
+
+    // generated programs always compile, or the generator is wrong
+    let mut generator = Generator::new(seed);
+    let saved = overflow::current();
+    if saved == Overflow::Trap {
+        overflow::set(Overflow::Wrap);
+    }
+    for index in 1..=generated {
+        programs += 1;
+        let path = PathBuf::from(format!("<selftest:{}>", index));
+        let source = generator.program();
+        sources::register(&path, source.as_str());
+        let ast = merge(vec![(path.clone(), parse_file(&path)?)])?;
+        let comparison = compare(ast, &path).map_err(|errors| errors
+            .into_iter()
+            .map(|err| err.with_source(&path))
+            .collect::<Diagnostics>())?;
+        match comparison {
+            Comparison::Same => (),
+            Comparison::Diverged(outcomes) => {
+                report(&path, &outcomes);
+                println!("{}", source);
+                divergences += 1;
+            }
+            Comparison::Skipped => skipped += 1,
+        }
+    }
+    overflow::set(saved);
+
+    println!("selftest: {} programs on {} backends, {} skipped, {} divergent, seed {}",
+        programs, BACKENDS.len(), skipped, divergences, seed);
+    match divergences {
+        0 => Ok(()),
+        count => Err(Box::new(SelftestError {
+            message: message!("E0421", count = count, seed = seed),
+        })),
+    }
+}
+
+/// Prints the outcomes of a program which runs differently on some backends
+fn report(path: &Path, outcomes: &[(&str, Outcome)]) {
+    println!("'{}' runs differently on the backends:", path.display());
+    for (backend, outcome) in outcomes {
+        match outcome {
+            Ok(value) => println!("    {}: {}", backend, value),
+            Err(message) => println!("    {}: error: {}", backend, message),
+        }
+    }
+}
+
+/// Whether a module declares extern functions, which are bound at its top level
+fn declares_externs(ast: &AST) -> bool {
+    let (Type::Block(statements) | Type::Module(statements)) = &**ast else {
+        return false;
+    };
+    statements.iter().any(|statement| {
+        matches!(&**statement, Type::Expression(Operator::Let, _, function) if matches!(***function, Type::Extern(..)))
+    })
+}
+
+/// Checks a module whose imports have been loaded, then runs it on every backend
+fn compare(ast: AST, path: &Path) -> Result<Comparison, Vec<LocalizedError>> {
+    let ast = check_module(ast, path, &mut Summary::default())?;
+    // the exit code of a `main` taking the arguments of the program isn't printed to compare
+    if !matches!(check_entry_point(&ast), Ok(false)) {
+        return Ok(Comparison::Skipped);
+    }
+    // only compiled code calls extern functions
+    if declares_externs(&ast) {
+        return Ok(Comparison::Skipped);
+    }
+
+    let saved = optimize::current();
+    let mut outcomes = Vec::new();
+    for (name, backend, level) in BACKENDS {
+        optimize::set(*level);
+        let outcome = match backend {
+            Backend::Interp => run_interp(&ast),
+            Backend::Vm => run_vm(&ast),
+            Backend::Jit => run_jit(&ast, path),
+        };
+        match outcome {
+            Some(outcome) => outcomes.push((*name, outcome)),
+            // compiled code returns strings and functions as pointers, which can't be compared
+            None => {
+                optimize::set(saved);
+                return Ok(Comparison::Skipped);
+            }
+        }
+    }
+    optimize::set(saved);
+
+    match outcomes.iter().all(|(_, outcome)| *outcome == outcomes[0].1) {
+        true => Ok(Comparison::Same),
+        false => Ok(Comparison::Diverged(outcomes)),
+    }
+}
+
+/// The message of an error, without the name of its type which differs between the backends
+fn message(error: &dyn Error) -> String {
+    let error = error.source().unwrap_or(error).to_string();
+    match error.split_once(": ") {
+        Some((_, message)) => message.to_owned(),
+        None => error,
+    }
+}
+
+fn run_interp(ast: &AST) -> Option<Outcome> {
+    let interpreter = match Interpreter::new(ast) {
+        Ok(interpreter) => interpreter,
+        Err(err) => return Some(Err(message(&err))),
+    };
+    match interpreter.call("main", Vec::new()) {
+        Ok(Value::Int(value)) => Some(Ok(value.to_string())),
+        Ok(_) => None,
+        Err(err) => Some(Err(message(&err))),
+    }
+}
+
+fn run_vm(ast: &AST) -> Option<Outcome> {
+    let ir = match ir::compile(ast, &HashMap::new(), &HashMap::new()) {
+        Ok(ir) => ir,
+        Err(errors) => return Some(Err(message(&errors[0]))),
+    };
+    let program = bytecode::compile(&ir);
+    let main = program.function("main").expect("an entry point");
+    match vm::run(&program, main, Vec::new()) {
+        Ok(vm::Value::Int(value)) => Some(Ok(value.to_string())),
+        Ok(_) => None,
+        Err(err) => Some(Err(message(&err))),
+    }
+}
+
+#[cfg(feature = "jit")]
+fn run_jit(ast: &AST, path: &Path) -> Option<Outcome> {
+    // the compiled functions live as long as the JIT
+    let mut jit = JIT::default();
+    let functions = match jit.compile(ast, path) {
+        Ok(functions) => functions,
+        Err(errors) => return Some(Err(message(&errors[0]))),
+    };
+    let main: extern "C" fn(*const u8) -> i64 = unsafe { std::mem::transmute(functions["main"]) };
+    Some(Ok(main(std::ptr::null()).to_string()))
+}
+
+#[cfg(not(feature = "jit"))]
+fn run_jit(_: &AST, _: &Path) -> Option<Outcome> {
+    unreachable!("the JIT is only among the backends with the `jit` feature")
+}
+
+/// Generates programs from a seed: module level functions of integers, then a `main` function,
+/// each a block of `let` bindings and an expression
+struct Generator {
+    /// The state of a xorshift generator, never 0
+    state: u64,
+    /// The number of arguments of the functions generated so far in the program
+    functions: Vec<usize>,
+    /// The number of names bound so far in the program, to give each a new one
+    names: usize,
+}
+
+/// How deep expressions nest
+const MAX_DEPTH: u32 = 4;
+
+impl Generator {
+    fn new(seed: u64) -> Self {
+        Self { state: seed.max(1), functions: Vec::new(), names: 0 }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    /// A number in `0..n`
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn name(&mut self, prefix: &str) -> String {
+        self.names += 1;
+        format!("_{}{}", prefix, self.names)
+    }
+
+    fn program(&mut self) -> String {
+        self.functions.clear();
+        self.names = 0;
+        let mut source = String::new();
+        for index in 0..self.below(4) {
+            let mut params = (0..1 + self.below(3)).map(|_| self.name("a")).collect::<Vec<_>>();
+            let signature = params.iter().map(|param| format!("{}: int", param)).collect::<Vec<_>>().join(", ");
+            let arity = params.len();
+            let body = self.block(&mut params);
+            source.push_str(&format!("let f{} = fn({}) -> int {{\n{}\n}};\n", index, signature, body));
+            self.functions.push(arity);
+        }
+        let body = self.block(&mut Vec::new());
+        source.push_str(&format!("let main = fn() -> int {{\n{}\n}};\n", body));
+        source
+    }
+
+    /// Bindings of the variables in scope followed by an expression, a line each
+    fn block(&mut self, vars: &mut Vec<String>) -> String {
+        let mut lines = Vec::new();
+        for _ in 0..self.below(4) {
+            let value = self.expr(vars, MAX_DEPTH);
+            let name = self.name("v");
+            lines.push(format!("    let {} = {};", name, value));
+            vars.push(name);
+        }
+        lines.push(format!("    {}", self.expr(vars, MAX_DEPTH)));
+        lines.join("\n")
+    }
+
+    fn expr(&mut self, vars: &[String], depth: u32) -> String {
+        if depth == 0 || self.below(4) == 0 {
+            return match vars.is_empty() || self.below(2) == 0 {
+                true => self.literal(),
+                false => vars[self.below(vars.len() as u64) as usize].clone(),
+            };
+        }
+        let depth = depth - 1;
+        match self.below(9) {
+            0..=2 => {
+                let op = ["+", "-", "*"][self.below(3) as usize];
+                format!("({} {} {})", self.expr(vars, depth), op, self.expr(vars, depth))
+            }
+            // dividing by zero traps, or by -1 for the smallest integer, and so does zero to a negative power
+            3 => match self.below(3) {
+                0 => format!("({} ** {})", self.expr(vars, depth), self.below(70)),
+                op => format!("({} {} {})", self.expr(vars, depth), ["/", "%"][op as usize - 1], 2 + self.below(98)),
+            },
+            4 => format!("(0 - {})", self.expr(vars, depth)),
+            5 => {
+                let int = IntTy::ALL[self.below(IntTy::ALL.len() as u64) as usize].name();
+                match self.below(3) {
+                    0 => format!("((({}) as {}) as int)", self.expr(vars, depth), int),
+                    1 => {
+                        let op = ["+", "-", "*"][self.below(3) as usize];
+                        format!("(((({}) as {int}) {} (({}) as {int})) as int)", self.expr(vars, depth), op, self.expr(vars, depth))
+                    }
+                    _ => {
+                        let op = ["/", "%"][self.below(2) as usize];
+                        format!("(((({}) as {int}) {} {}{int}) as int)", self.expr(vars, depth), op, 2 + self.below(98))
+                    }
+                }
+            }
+            6 => format!("((({}) as float) as int)", self.expr(vars, depth)),
+            7 if !self.functions.is_empty() => {
+                let function = self.below(self.functions.len() as u64) as usize;
+                let args = (0..self.functions[function]).map(|_| self.expr(vars, depth)).collect::<Vec<_>>();
+                format!("f{}({})", function, args.join(", "))
+            }
+            _ => {
+                let arg = self.expr(vars, depth);
+                let param = self.name("y");
+                let mut scope = vars.to_vec();
+                scope.push(param.clone());
+                format!("(fn({}: int) -> int {{ {} }})({})", param, self.expr(&scope, depth), arg)
+            }
+        }
+    }
+
+    fn literal(&mut self) -> String {
+        match self.below(8) {
+            0 => (self.next() >> 1).to_string(),
+            _ => self.below(100).to_string(),
+        }
+    }
+}
@@ -0,0 +1,471 @@
+// The runtime compiled programs are linked with by `moo build`, see `object::link`.
+// It provides the functions of `runtime.rs` to the generated code, behaving the same,
+// and a `main` which calls the `main` function of the program and prints the value it returns,
+// like `moo run`, as the type `MOO_MAIN_RETURNS_<TYPE>` is defined for, e.g. `MOO_MAIN_RETURNS_FLOAT`.
+// Built with `MOO_MAIN_ARGS` defined, for a `main` taking the arguments of the program,
+// it passes them instead and exits with the value returned.
+// Built with `MOO_LEAK_CHECK` defined, for programs built with debug information, it counts the objects alive,
+// and reports those the program didn't release by the time its `main` returned.
+// Built with `MOO_GC_TRACING` defined, for `--gc tracing`, it collects the objects unreachable from the frames
+// of the functions running instead, like `runtime::collect`, at every allocation with `MOO_LEAK_CHECK`.
+
+#include <ctype.h>
+#include <errno.h>
+#include <inttypes.h>
+#include <math.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+// laid out like `runtime::HEADER`, before every object
+typedef struct {
+    int64_t size, children, count;
+} moo_header;
+
+// like `runtime::ELEMENTS`
+#define ELEMENTS (-1)
+
+// laid out like `runtime::encode`, the bytes followed by a null byte
+typedef struct {
+    int64_t len;
+    char bytes[];
+} moo_string;
+
+// laid out like `runtime::encode_location`
+typedef struct {
+    int64_t line, column, offset, end_line, end_column, end_offset;
+    // the path, laid out like a `moo_string`
+    int64_t path_len;
+    char path[];
+} moo_location;
+
+// laid out like `runtime::array`
+typedef struct {
+    int64_t len;
+    int64_t elements[];
+} moo_array;
+
+#ifdef MOO_MAIN_ARGS
+int64_t moo_main(void *env, const moo_array *args);
+#else
+int64_t moo_main(void *env);
+#endif
+
+#ifdef MOO_LEAK_CHECK
+static int64_t live;
+#endif
+
+static void *allocate(size_t size) {
+    void *memory = malloc(size);
+    if (memory == NULL) {
+        fputs("out of memory\n", stderr);
+        abort();
+    }
+    return memory;
+}
+
+// the number of objects an object holds after its first slot
+static int64_t children(const moo_header *header) {
+    return header->children == ELEMENTS ? ((const int64_t *)(header + 1))[0] : header->children;
+}
+
+static void free_object(moo_header *header) {
+    free(header);
+#ifdef MOO_LEAK_CHECK
+    live--;
+#endif
+}
+
+// laid out like `runtime::__moo_enter`
+typedef struct moo_frame {
+    struct moo_frame *caller;
+    int64_t roots;
+    int64_t root[];
+} moo_frame;
+
+#ifdef MOO_GC_TRACING
+// like `runtime::COLLECTION_INTERVAL`
+#define COLLECTION_INTERVAL 1024
+
+static moo_frame *frames;
+// the objects allocated, a count of 1 marking those reachable while collecting
+static moo_header **objects;
+static size_t object_count, object_capacity;
+static size_t allocations = COLLECTION_INTERVAL;
+
+int64_t __moo_enter(moo_frame *frame) {
+    frame->caller = frames;
+    frames = frame;
+    return 0;
+}
+
+int64_t __moo_leave(moo_frame *frame) {
+    frames = frame->caller;
+    return 0;
+}
+
+// Frees the objects the roots of the frames don't reach, like `runtime::collect`
+static void collect(void) {
+    size_t marking_count = 0, marking_capacity = 64;
+    int64_t *marking = allocate(marking_capacity * sizeof(int64_t));
+    for (moo_frame *frame = frames; frame != NULL; frame = frame->caller) {
+        for (int64_t root = 0; root < frame->roots; root++) {
+            if (frame->root[root] == 0) {
+                continue;
+            }
+            if (marking_count == marking_capacity) {
+                marking_capacity *= 2;
+                marking = realloc(marking, marking_capacity * sizeof(int64_t));
+            }
+            marking[marking_count++] = frame->root[root];
+        }
+    }
+    while (marking_count > 0) {
+        int64_t *object = (int64_t *)(intptr_t)marking[--marking_count];
+        moo_header *header = (moo_header *)object - 1;
+        // the objects marked already, and those in the data of the code, which hold none
+        if (header->count != 0) {
+            continue;
+        }
+        header->count = 1;
+        for (int64_t slot = 1; slot <= children(header); slot++) {
+            if (marking_count == marking_capacity) {
+                marking_capacity *= 2;
+                marking = realloc(marking, marking_capacity * sizeof(int64_t));
+            }
+            marking[marking_count++] = object[slot];
+        }
+    }
+    free(marking);
+
+    size_t survivors = 0;
+    for (size_t i = 0; i < object_count; i++) {
+        if (objects[i]->count == 0) {
+            free_object(objects[i]);
+        } else {
+            objects[i]->count = 0;
+            objects[survivors++] = objects[i];
+        }
+    }
+    object_count = survivors;
+    allocations = survivors > COLLECTION_INTERVAL ? survivors : COLLECTION_INTERVAL;
+}
+#endif
+
+void *__moo_new(int64_t size, int64_t children) {
+#ifdef MOO_GC_TRACING
+#ifdef MOO_LEAK_CHECK
+    collect();
+#else
+    if (allocations-- == 0) {
+        collect();
+    }
+#endif
+#endif
+    moo_header *header = allocate(sizeof(moo_header) + size);
+    header->size = size;
+    header->children = children;
+#ifdef MOO_LEAK_CHECK
+    live++;
+#endif
+#ifdef MOO_GC_TRACING
+    header->count = 0;
+    if (object_count == object_capacity) {
+        object_capacity = object_capacity == 0 ? 64 : object_capacity * 2;
+        objects = realloc(objects, object_capacity * sizeof(moo_header *));
+    }
+    objects[object_count++] = header;
+#else
+    header->count = 1;
+#endif
+    return header + 1;
+}
+
+// objects with a negative count, in the data of the code, are never freed
+int64_t __moo_retain(void *object) {
+    moo_header *header = (moo_header *)object - 1;
+    if (header->count >= 0) {
+        header->count++;
+    }
+    return 0;
+}
+
+int64_t __moo_release(void *object) {
+    moo_header *header = (moo_header *)object - 1;
+    if (header->count < 0 || --header->count > 0) {
+        return 0;
+    }
+    int64_t *slots = object;
+    for (int64_t slot = 1; slot <= children(header); slot++) {
+        __moo_release((void *)(intptr_t)slots[slot]);
+    }
+    free_object(header);
+    return 0;
+}
+
+static moo_string *string(const char *bytes, size_t len) {
+    moo_string *string = __moo_new(sizeof(moo_string) + len + 1, 0);
+    string->len = (int64_t)len;
+    memcpy(string->bytes, bytes, len);
+    string->bytes[len] = '\0';
+    return string;
+}
+
+moo_string *__moo_format_int(int64_t value) {
+    char buffer[32];
+    int len = snprintf(buffer, sizeof buffer, "%" PRId64, value);
+    return string(buffer, len);
+}
+
+moo_string *__moo_format_uint(int64_t value) {
+    char buffer[32];
+    int len = snprintf(buffer, sizeof buffer, "%" PRIu64, (uint64_t)value);
+    return string(buffer, len);
+}
+
+// Like the `Display` of `f64` in Rust: the shortest digits reading back as the same value,
+// never in scientific notation
+moo_string *__moo_format_float(int64_t bits) {
+    double value;
+    memcpy(&value, &bits, sizeof value);
+    if (isnan(value)) {
+        return string("NaN", 3);
+    }
+    if (isinf(value)) {
+        return value < 0 ? string("-inf", 4) : string("inf", 3);
+    }
+
+    // the shortest `d.ddde±x` which reads back as `value`
+    char scientific[32];
+    for (int precision = 0; precision < 17; precision++) {
+        snprintf(scientific, sizeof scientific, "%.*e", precision, value);
+        if (strtod(scientific, NULL) == value) {
+            break;
+        }
+    }
+    char *exponent = strchr(scientific, 'e');
+    int power = atoi(exponent + 1);
+    *exponent = '\0';
+
+    char *mantissa = scientific;
+    char digits[32];
+    size_t count = 0;
+    int negative = *mantissa == '-';
+    for (char *c = mantissa + negative; *c != '\0'; c++) {
+        if (*c != '.') {
+            digits[count++] = *c;
+        }
+    }
+    while (count > 1 && digits[count - 1] == '0') {
+        count--;
+    }
+
+    // the digits, placed around the decimal point
+    char buffer[400];
+    size_t len = 0;
+    if (negative) {
+        buffer[len++] = '-';
+    }
+    if (power < 0) {
+        buffer[len++] = '0';
+        buffer[len++] = '.';
+        for (int zero = 1; zero < -power; zero++) {
+            buffer[len++] = '0';
+        }
+        memcpy(buffer + len, digits, count);
+        len += count;
+    } else {
+        for (int i = 0; i <= power || (size_t)i < count; i++) {
+            if (i == power + 1) {
+                buffer[len++] = '.';
+            }
+            buffer[len++] = (size_t)i < count ? digits[i] : '0';
+        }
+    }
+    return string(buffer, len);
+}
+
+moo_string *__moo_concat(const moo_string *lhs, const moo_string *rhs) {
+    moo_string *string = __moo_new(sizeof(moo_string) + lhs->len + rhs->len + 1, 0);
+    string->len = lhs->len + rhs->len;
+    memcpy(string->bytes, lhs->bytes, lhs->len);
+    memcpy(string->bytes + lhs->len, rhs->bytes, rhs->len);
+    string->bytes[string->len] = '\0';
+    return string;
+}
+
+// The builtins `print` and `println`, returning `unit` as 0
+int64_t __moo_print(const moo_string *string) {
+    fwrite(string->bytes, 1, string->len, stdout);
+    return 0;
+}
+
+int64_t __moo_println(const moo_string *string) {
+    fwrite(string->bytes, 1, string->len, stdout);
+    putchar('\n');
+    return 0;
+}
+
+// Reports an error at `location` and exits, like `traps::exit` without the snippet
+static void fail(const moo_location *location, const char *message, int64_t len) {
+    fflush(stdout);
+    fprintf(stderr, "TrapError: %.*s\nInside file '%.*s' at [line:%" PRId64 ",column:%" PRId64 "]\n",
+        (int)len, message, (int)location->path_len, location->path, location->line, location->column);
+    exit(1);
+}
+
+void __moo_division_by_zero(const moo_location *location) {
+    fail(location, "Division by zero", 16);
+}
+
+// the message is formatted by the compiler, which passes its code too, only reported by the compiler's runtime
+void __moo_overflow(const moo_location *location, const moo_string *message, const moo_string *code) {
+    (void)code;
+    fail(location, message->bytes, message->len);
+}
+
+// The builtin `read_int`, like `builtins::read_int`
+int64_t __moo_read_int(const moo_location *location) {
+    fflush(stdout);
+    int c = getchar();
+    while (c != EOF && isspace(c)) {
+        c = getchar();
+    }
+    // longer tokens are no integer that fits, unless padded with zeros
+    char token[64];
+    size_t len = 0;
+    int truncated = 0;
+    for (; c != EOF && !isspace(c); c = getchar()) {
+        if (len < sizeof token - 1) {
+            token[len++] = (char)c;
+        } else {
+            truncated = 1;
+        }
+    }
+    if (c != EOF) {
+        ungetc(c, stdin);
+    }
+    if (len == 0) {
+        const char *message = "Expected an integer on the standard input, found its end";
+        fail(location, message, (int64_t)strlen(message));
+    }
+    token[len] = '\0';
+
+    char *end;
+    errno = 0;
+    long long value = strtoll(token, &end, 10);
+    if (errno != 0 || *end != '\0' || truncated) {
+        char message[128];
+        int message_len = snprintf(message, sizeof message, "Expected an integer on the standard input, found `%s`", token);
+        fail(location, message, message_len < (int)sizeof message ? message_len : (int)sizeof message - 1);
+    }
+    return value;
+}
+
+// The builtin `read_line`, like `builtins::read_line`
+moo_string *__moo_read_line(void) {
+    fflush(stdout);
+    char *line = NULL;
+    size_t capacity = 0;
+    ssize_t len = getline(&line, &capacity, stdin);
+    if (len < 0) {
+        free(line);
+        return string("", 0);
+    }
+    if (len > 0 && line[len - 1] == '\n') {
+        len--;
+        if (len > 0 && line[len - 1] == '\r') {
+            len--;
+        }
+    }
+    moo_string *result = string(line, (size_t)len);
+    free(line);
+    return result;
+}
+
+// The builtin `len`
+int64_t __moo_len(const moo_array *array) {
+    return array->len;
+}
+
+// The builtin `at`, like `builtins::index`, retaining the element if it is an object but with the tracing collector
+int64_t __moo_at(const moo_location *location, const moo_array *array, int64_t index) {
+    if (index < 0 || index >= array->len) {
+        char message[128];
+        int message_len = snprintf(message, sizeof message, "Index %" PRId64 " is out of bounds of an array of length %" PRId64, index, array->len);
+        fail(location, message, message_len < (int)sizeof message ? message_len : (int)sizeof message - 1);
+    }
+    int64_t element = array->elements[index];
+#ifndef MOO_GC_TRACING
+    if (((const moo_header *)array - 1)->children == ELEMENTS) {
+        __moo_retain((void *)(intptr_t)element);
+    }
+#endif
+    return element;
+}
+
+// Reports the objects the program didn't release, returning whether there were any.
+// Those of the tracing collector are all unreachable once `main` returned
+static int leaked(void) {
+#ifdef MOO_GC_TRACING
+    collect();
+#endif
+#ifdef MOO_LEAK_CHECK
+    if (live != 0) {
+        fflush(stdout);
+        fprintf(stderr, "the program leaked %" PRId64 " objects\n", live);
+        return 1;
+    }
+#endif
+    return 0;
+}
+
+#ifdef MOO_MAIN_ARGS
+// the arguments after the name of the program, like `moo run` passes those after `--`
+int main(int argc, char **argv) {
+    // the strings are rooted while the others are allocated, like `runtime::arguments`
+    moo_frame *frame = allocate(sizeof(moo_frame) + (argc - 1) * sizeof(int64_t));
+    frame->roots = argc - 1;
+    memset(frame->root, 0, (argc - 1) * sizeof(int64_t));
+#ifdef MOO_GC_TRACING
+    __moo_enter(frame);
+#endif
+    for (int i = 1; i < argc; i++) {
+        frame->root[i - 1] = (int64_t)(intptr_t)string(argv[i], strlen(argv[i]));
+    }
+    moo_array *args = __moo_new(sizeof(moo_array) + (argc - 1) * sizeof(int64_t), ELEMENTS);
+    args->len = argc - 1;
+    memcpy(args->elements, frame->root, (argc - 1) * sizeof(int64_t));
+#ifdef MOO_GC_TRACING
+    __moo_leave(frame);
+#endif
+    free(frame);
+    int code = (int)moo_main(NULL, args);
+#ifndef MOO_GC_TRACING
+    __moo_release(args);
+#endif
+    return leaked() ? 1 : code;
+}
+#else
+int main(void) {
+    int64_t value = moo_main(NULL);
+#if defined(MOO_MAIN_RETURNS_STRING)
+    // `moo_main` returned its reference to the string
+    __moo_println((const moo_string *)(intptr_t)value);
+#ifndef MOO_GC_TRACING
+    __moo_release((void *)(intptr_t)value);
+#endif
+#elif defined(MOO_MAIN_RETURNS_FLOAT)
+    moo_string *string = __moo_format_float(value);
+    __moo_println(string);
+#ifndef MOO_GC_TRACING
+    __moo_release(string);
+#endif
+#elif defined(MOO_MAIN_RETURNS_UINT)
+    printf("%" PRIu64 "\n", (uint64_t)value);
+#else
+    printf("%" PRId64 "\n", value);
+#endif
+    return leaked();
+}
+#endif
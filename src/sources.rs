@@ -0,0 +1,186 @@
+// The source map: the text of every source compiled, read once and shared by every phase, from the tokenizer
+// to the rendering of diagnostics, which show the code that was compiled rather than reading the file again.
+// Each source gets a `FileId`, which the locations of its tokens carry, so the diagnostics of a program merged
+// from several files are reported in the file their code is from.
+//
+// Sources are files, read the first time they are asked for, or code which doesn't live in a file, e.g. the inputs
+// of the REPL or the string given to `-e`, registered under a name standing for their path.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::iter::once;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// The id of a source in the source map, the default one standing for code of no source, e.g. generated code
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FileId(u32);
+
+impl FileId {
+    /// The id of code of no source
+    pub const NONE: FileId = FileId(0);
+}
+
+/// A source in the source map
+#[derive(Debug)]
+pub struct SourceFile {
+    pub id: FileId,
+    /// The path of the file, or the name standing for it, e.g. `<eval>`
+    pub name: PathBuf,
+    pub text: Arc<str>,
+    /// Whether the source was registered rather than read from a file
+    pub registered: bool,
+    /// The byte ranges of the lines in `text`, without their line breaks
+    lines: Vec<Range<usize>>,
+    index: LineIndex,
+}
+
+impl SourceFile {
+    fn new(id: FileId, name: &Path, text: Arc<str>, registered: bool) -> Self {
+        let lines = text.lines()
+            .map(|line| {
+                let start = line.as_ptr() as usize - text.as_ptr() as usize;
+                start..start + line.len()
+            })
+            .collect::<Vec<_>>();
+        let index = LineIndex::new(&lines.iter().map(|line| &text[line.clone()]).collect::<Vec<_>>());
+        Self { id, name: name.to_path_buf(), text, registered, lines, index }
+    }
+
+    /// The lines of the source, without their line breaks
+    pub fn lines(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().map(|line| &self.text[line.clone()])
+    }
+
+    /// The number of lines of the source
+    pub fn line_count(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// The line numbered `number`, counting from 1, empty past the end of the source
+    pub fn line(&self, number: usize) -> &str {
+        number.checked_sub(1).and_then(|i| self.lines.get(i)).map_or("", |line| &self.text[line.clone()])
+    }
+
+    /// Returns the line of `offset`, counting from 1, and its byte offset in that line, see `LineIndex::position`
+    pub fn position(&self, offset: usize) -> (usize, usize) {
+        self.index.position(offset)
+    }
+
+    /// The offset in `text` of the byte at `column` in the line numbered `line`, counting from 1,
+    /// the end of `text` past its last line
+    pub fn offset(&self, line: usize, column: usize) -> usize {
+        line.checked_sub(1).and_then(|i| self.lines.get(i)).map_or(self.text.len(), |line| line.start + column)
+    }
+}
+
+/// The sources compiled so far, by id, the first id standing for none
+struct SourceMap {
+    files: Vec<Arc<SourceFile>>,
+    by_name: BTreeMap<PathBuf, FileId>,
+}
+
+static SOURCE_MAP: Mutex<SourceMap> = Mutex::new(SourceMap { files: Vec::new(), by_name: BTreeMap::new() });
+
+impl SourceMap {
+    /// Adds a source, replacing any source of the same name for the code compiled from now on
+    fn add(&mut self, name: &Path, text: Arc<str>, registered: bool) -> Arc<SourceFile> {
+        let id = FileId(self.files.len() as u32 + 1);
+        let file = Arc::new(SourceFile::new(id, name, text, registered));
+        self.files.push(file.clone());
+        self.by_name.insert(name.to_path_buf(), id);
+        file
+    }
+
+    fn get(&self, name: &Path) -> Option<Arc<SourceFile>> {
+        self.by_name.get(name).and_then(|id| self.file(*id))
+    }
+
+    fn file(&self, id: FileId) -> Option<Arc<SourceFile>> {
+        id.0.checked_sub(1).and_then(|i| self.files.get(i as usize)).cloned()
+    }
+}
+
+/// The name of the source given on the command line with `-e`
+pub fn eval_name() -> PathBuf {
+    PathBuf::from("<eval>")
+}
+
+/// The name of the `source`th source compiled by an `Engine`, counting from 1
+#[cfg(feature = "jit")]
+pub fn engine_name(source: usize) -> PathBuf {
+    PathBuf::from(format!("<engine:{}>", source))
+}
+
+/// The name of the `input`th input of the REPL, counting from 1
+#[cfg(feature = "repl")]
+pub fn repl_name(input: usize) -> PathBuf {
+    PathBuf::from(format!("<repl:{}>", input))
+}
+
+/// Registers `source` under `name`, replacing any source registered under it before
+pub fn register(name: &Path, source: impl Into<Arc<str>>) -> FileId {
+    SOURCE_MAP.lock().unwrap().add(name, source.into(), true).id
+}
+
+/// The source of `name`: a registered one, or the file at `name`, read the first time
+pub fn load(name: &Path) -> io::Result<Arc<SourceFile>> {
+    if let Some(file) = SOURCE_MAP.lock().unwrap().get(name) {
+        return Ok(file);
+    }
+    let text = fs::read_to_string(name)?;
+    Ok(SOURCE_MAP.lock().unwrap().add(name, text.into(), false))
+}
+
+/// Reads the file at `path` again the next time it's loaded, e.g. once it's been rewritten
+pub fn reload(path: &Path) {
+    let mut map = SOURCE_MAP.lock().unwrap();
+    if map.get(path).is_some_and(|file| !file.registered) {
+        map.by_name.remove(path);
+    }
+}
+
+/// Forgets the source registered under `name`, the file at `name` being read the next time it's loaded,
+/// e.g. once the editor which had it open closed it
+#[cfg(feature = "lsp")]
+pub fn unregister(name: &Path) {
+    SOURCE_MAP.lock().unwrap().by_name.remove(name);
+}
+
+/// The source of `id`, if it's that of a source
+pub fn file(id: FileId) -> Option<Arc<SourceFile>> {
+    SOURCE_MAP.lock().unwrap().file(id)
+}
+
+/// Whether `name` stands for a registered source rather than a file
+pub fn is_registered(name: &Path) -> bool {
+    SOURCE_MAP.lock().unwrap().get(name).is_some_and(|file| file.registered)
+}
+
+/// The offsets the lines of a source start at, to find the line and column of an offset
+#[derive(Debug)]
+pub struct LineIndex {
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    /// * `lines` - the lines of the source without their line breaks, which count as one byte,
+    ///   as for the offsets of the tokenizer
+    pub fn new(lines: &[impl AsRef<str>]) -> Self {
+        let starts = once(0)
+            .chain(lines.iter().scan(0, |start, line| {
+                *start += line.as_ref().len() + 1;
+                Some(*start)
+            }))
+            .collect();
+        Self { starts }
+    }
+
+    /// Returns the line of `offset`, counting from 1, and its byte offset in that line
+    pub fn position(&self, offset: usize) -> (usize, usize) {
+        let line = self.starts.partition_point(|start| *start <= offset).max(1);
+        (line, offset - self.starts[line - 1])
+    }
+}
@@ -0,0 +1,162 @@
+// Traps of the code the JIT runs in the compiler's process, e.g. dividing the smallest `int` by -1.
+// Codegen records where the instructions which can trap are in their function, with the span
+// of the source they come from, see `Codegen::record_traps`. Once the JIT has placed the functions,
+// they are registered here by address, and the handler of the signals trapping raises looks
+// the faulting instruction up, to report the error at its location like any other, snippet included.
+// The process then exits, the program can't go on. Signals raised elsewhere, or on platforms where
+// the faulting instruction isn't known, kill the process like before.
+
+use core::fmt;
+use std::error::Error;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use cranelift::prelude::TrapCode;
+
+use crate::errors::{self, Coded, ErrorFormat, LocalizableError};
+use crate::frontend::tokenizer::Span;
+use crate::messages::{message, Message};
+
+#[derive(Debug)]
+pub struct TrapError {
+    message: Message,
+}
+
+impl fmt::Display for TrapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TrapError: {}", self.message)
+    }
+}
+
+impl Error for TrapError {}
+
+impl Coded for TrapError {
+    fn code(&self) -> Option<&'static str> {
+        self.message.code()
+    }
+}
+
+/// An instruction of a function which can trap, by its offset in the machine code of the function
+#[derive(Debug, Clone, Copy)]
+pub struct TrapSite {
+    pub offset: u32,
+    pub code: TrapCode,
+    /// Where the code of the instruction comes from
+    pub span: Span,
+}
+
+/// An instruction of the code in memory which can trap
+struct Trap {
+    address: usize,
+    /// The address of the function the instruction is in
+    function: usize,
+    code: TrapCode,
+    span: Span,
+    /// The source the code was compiled from
+    source: PathBuf,
+}
+
+static TRAPS: Mutex<Vec<Trap>> = Mutex::new(Vec::new());
+
+/// Registers the instructions of a function placed at `function` which can trap
+/// * `source` - the source the function was compiled from
+pub fn register(function: *const u8, sites: &[TrapSite], source: &Path) {
+    let mut traps = TRAPS.lock().unwrap();
+    traps.extend(sites.iter().map(|site| Trap {
+        address: function as usize + site.offset as usize,
+        function: function as usize,
+        code: site.code,
+        span: site.span,
+        source: source.to_path_buf(),
+    }));
+}
+
+/// Unregisters the instructions of the functions placed at `functions`, once their code is freed
+pub fn unregister(functions: &[*const u8]) {
+    TRAPS.lock().unwrap().retain(|trap| !functions.contains(&(trap.function as *const u8)));
+}
+
+/// The message of the error a trap stands for
+fn message(code: TrapCode) -> Message {
+    // sized integers are divided on 64 bits, so only the smallest `int` overflows, divided by -1
+    match code {
+        TrapCode::INTEGER_DIVISION_BY_ZERO => message!("E0413"),
+        TrapCode::INTEGER_OVERFLOW => message!("E0414", lhs = i64::MIN, rhs = -1),
+        code => message!("E0422", trap = code),
+    }
+}
+
+/// Installs the handler of the signals trapping instructions raise
+pub fn install() {
+    #[cfg(unix)]
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_trap as extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void) as libc::sighandler_t;
+        action.sa_flags = libc::SA_SIGINFO;
+        libc::sigemptyset(&mut action.sa_mask);
+        for signal in [libc::SIGFPE, libc::SIGILL, libc::SIGTRAP] {
+            libc::sigaction(signal, &action, std::ptr::null_mut());
+        }
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn on_trap(signal: libc::c_int, _: *mut libc::siginfo_t, context: *mut libc::c_void) {
+    // the trap is in compiled code, not in the middle of the allocator or of some output,
+    // so the error can be reported like any other before exiting
+    let address = unsafe { program_counter(context) };
+    let traps = TRAPS.try_lock();
+    let trap = address.zip(traps.as_ref().ok()).and_then(|(address, traps)| traps.iter().find(|trap| trap.address == address));
+    let Some(trap) = trap else {
+        // returning runs the instruction again, which kills the process this time
+        unsafe { libc::signal(signal, libc::SIG_DFL) };
+        return;
+    };
+    exit(message(trap.code), trap.span, &trap.source)
+}
+
+/// Reports an error at `span` of `source`, followed by the summary `main` ends with, then exits,
+/// the program can't go on. Also called by the runtime for the errors compiled code checks for,
+/// e.g. `runtime::__moo_division_by_zero`
+pub fn exit(message: Message, span: Span, source: &Path) -> ! {
+    let _ = std::io::stdout().flush();
+    errors::report(&TrapError { message }.with_span(span).with_source(source));
+    if let (Some(summary), ErrorFormat::Human) = (errors::summary(), errors::format()) {
+        anstream::eprintln!("\n{}", summary);
+    }
+    std::process::exit(1);
+}
+
+/// The address of the instruction which raised a signal, from the context of the thread it interrupted
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+unsafe fn program_counter(context: *mut libc::c_void) -> Option<usize> {
+    let context = &*(context as *const libc::ucontext_t);
+    Some(context.uc_mcontext.gregs[libc::REG_RIP as usize] as usize)
+}
+
+#[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+unsafe fn program_counter(context: *mut libc::c_void) -> Option<usize> {
+    let context = &*(context as *const libc::ucontext_t);
+    Some(context.uc_mcontext.pc as usize)
+}
+
+#[cfg(all(target_os = "macos", target_arch = "x86_64"))]
+unsafe fn program_counter(context: *mut libc::c_void) -> Option<usize> {
+    let context = &*(context as *const libc::ucontext_t);
+    Some((*context.uc_mcontext).__ss.__rip as usize)
+}
+
+#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+unsafe fn program_counter(context: *mut libc::c_void) -> Option<usize> {
+    let context = &*(context as *const libc::ucontext_t);
+    Some((*context.uc_mcontext).__ss.__pc as usize)
+}
+
+#[cfg(all(unix, not(any(
+    all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")),
+    all(target_os = "macos", any(target_arch = "x86_64", target_arch = "aarch64")),
+))))]
+unsafe fn program_counter(_: *mut libc::c_void) -> Option<usize> {
+    None
+}
@@ -0,0 +1,99 @@
+//! Tests of the C API: a C program compiling moolang code through the shared library and calling it.
+
+#![cfg(all(feature = "jit", unix))]
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn c_programs_compile_and_run_code() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("capi");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("main.c"), r#"
+        #include <stdio.h>
+        #include "moolang.h"
+
+        int main(void) {
+            moo_engine *engine = moo_engine_new();
+            if (moo_compile(engine, "let add = fn(a: int, b: int) -> int { a + b };") != 0) {
+                fprintf(stderr, "%s\n", moo_last_error());
+                return 1;
+            }
+            int64_t args[] = {40, 2};
+            int64_t result = 0;
+            if (moo_run(engine, "add", args, 2, &result) != 0) {
+                fprintf(stderr, "%s\n", moo_last_error());
+                return 1;
+            }
+            printf("%lld\n", (long long)result);
+            // the error of a failing call is kept
+            int failed = moo_run(engine, "add", args, 1, &result);
+            printf("%d %s\n", failed, moo_last_error());
+            printf("%d\n", moo_compile(engine, "let broken = fn() -> int { missing };"));
+            moo_engine_free(engine);
+            return 0;
+        }
+    "#).unwrap();
+
+    // `cargo test` only builds the library for Rust, the shared library is built next to the executable
+    let built = Command::new(env!("CARGO"))
+        .args(["build", "--lib"])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .status()
+        .unwrap();
+    assert!(built.success());
+    let lib = Path::new(env!("CARGO_BIN_EXE_moolang")).parent().unwrap();
+    let include = Path::new(env!("CARGO_MANIFEST_DIR")).join("include");
+    let linked = Command::new("cc")
+        .current_dir(&dir)
+        .arg("main.c")
+        .arg("-I").arg(&include)
+        .arg("-L").arg(lib)
+        .args(["-lmoolang", "-o", "main"])
+        .status()
+        .unwrap();
+    assert!(linked.success());
+    let output = Command::new(dir.join("main"))
+        .env("LD_LIBRARY_PATH", lib)
+        .env("DYLD_LIBRARY_PATH", lib)
+        .env("MOO_COLOR", "never")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "42\n-1 EngineError: `add` is a `fn(int, int) -> int`, not a `fn(int) -> int`\n-1\n",
+    );
+}
+
+/// The functions a source declares with `prefix`, e.g. `extern "C" fn `, by name, with their number of parameters
+fn declared(source: &str, prefix: &str) -> BTreeMap<String, usize> {
+    source
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("//"))
+        .filter_map(|line| {
+            let start = line.find(&format!("{}moo_", prefix))? + prefix.len();
+            let (name, rest) = line[start..].split_once('(')?;
+            let parameters = &rest[..rest.find(')')?];
+            let count = match parameters.trim() {
+                "" | "void" => 0,
+                parameters => parameters.split(',').count(),
+            };
+            Some((name.trim().to_string(), count))
+        })
+        .collect()
+}
+
+#[test]
+fn the_header_declares_the_functions_the_library_exports() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let header = fs::read_to_string(root.join("include/moolang.h")).unwrap();
+    let capi = fs::read_to_string(root.join("src/capi.rs")).unwrap();
+    // a C declaration names the function after its return type, e.g. `int moo_compile(` or `moo_engine *moo_engine_new(`
+    let exported = declared(&capi, "extern \"C\" fn ");
+    assert!(!exported.is_empty());
+    let header = declared(&header.replace('*', " "), " ");
+    assert_eq!(header, exported);
+}
@@ -0,0 +1,56 @@
+//! Tests of embedding moolang into a Rust program through `Engine`.
+
+#![cfg(feature = "jit")]
+
+use moolang::Engine;
+
+#[test]
+fn functions_are_called_with_their_signature() {
+    let mut engine = Engine::new();
+    engine.compile_str("\
+        import math;\n\
+        let add = fn(a: int, b: int) -> int { a + b };\n\
+        let narrow = fn(x: i8) -> i8 { x + 1 };\n\
+        let greet = fn(name: string) -> string { \"hello, {name}\" };\n\
+        let nothing = fn() -> unit { 0; };\n").unwrap();
+
+    assert_eq!(engine.get_function::<fn(i64, i64) -> i64>("add").unwrap().call(40, 2), 42);
+    assert_eq!(engine.get_function::<fn(i8) -> i8>("narrow").unwrap().call(127), -128);
+    assert_eq!(engine.get_function::<fn(String) -> String>("greet").unwrap().call("moo".to_owned()), "hello, moo");
+    assert_eq!(engine.get_function::<fn(f64) -> f64>("math.sqrt").unwrap().call(2.25), 1.5);
+    engine.get_function::<fn()>("nothing").unwrap().call();
+}
+
+#[test]
+fn signatures_are_checked() {
+    let mut engine = Engine::new();
+    engine.compile_str("let add = fn(a: int, b: int) -> int { a + b };").unwrap();
+
+    let error = engine.get_function::<fn(i64) -> i64>("add").err().unwrap();
+    assert_eq!(error.to_string(), "EngineError: `add` is a `fn(int, int) -> int`, not a `fn(int) -> int`");
+    let error = engine.get_function::<fn(i64) -> i64>("sub").err().unwrap();
+    assert_eq!(error.to_string(), "EngineError: No function `sub` was compiled");
+}
+
+#[test]
+fn sources_compile_into_the_same_engine() {
+    let mut engine = Engine::new();
+    engine.compile_str("let one = fn() -> int { 1 };").unwrap();
+    engine.compile_str("let two = fn() -> int { 2 };").unwrap();
+    assert_eq!(engine.get_function::<fn() -> i64>("one").unwrap().call(), 1);
+    assert_eq!(engine.get_function::<fn() -> i64>("two").unwrap().call(), 2);
+
+    let errors = engine.compile_str("let three = fn() -> int { four };").err().unwrap();
+    let errors = errors.to_string();
+    assert!(errors.contains("Cannot find `four` in this scope") && errors.contains("<engine:3>"), "got:\n{}", errors);
+}
+
+#[test]
+fn engines_free_their_code_when_dropped() {
+    // the code of each engine is freed along with it, so the memory used doesn't grow with the engines created
+    for i in 0..200 {
+        let mut engine = Engine::new();
+        engine.compile_str(&format!("let id = fn() -> int {{ {} / 1 }};", i)).unwrap();
+        assert_eq!(engine.get_function::<fn() -> i64>("id").unwrap().call(), i);
+    }
+}
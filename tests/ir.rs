@@ -0,0 +1,70 @@
+//! Golden file tests of the IR: the lowering of programs, and its optimizations,
+//! constant folding and dead code elimination.
+//!
+//! Compiles every `tests/ir/*.moo` fixture with `-O1` and compares the IR of the program,
+//! printed by `build --emit ir`, with the `.ir` file next to it.
+//! Run with `BLESS=1` to write the current output to the `.ir` files instead,
+//! then review the changes to them like any other change:
+//! ```text
+//! BLESS=1 cargo test --test ir
+//! ```
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The fixtures, in a stable order
+fn fixtures(dir: &Path) -> Vec<PathBuf> {
+    let mut fixtures = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "moo"))
+        .collect::<Vec<_>>();
+    fixtures.sort();
+    fixtures
+}
+
+/// Compiles the fixture and returns its IR
+fn lower(fixture: &Path) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_moolang"))
+        // the defaults of the environment would change the output
+        .env_remove("MOO_FLAGS")
+        .env_remove("MOO_BACKEND")
+        .env_remove("MOO_COLOR")
+        .env_remove("MOO_HOME")
+        .args(["-O1", "build", "--emit", "ir"])
+        .arg(fixture)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{} doesn't compile:\n{}", fixture.display(), String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn ir_fixtures() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/ir");
+    let bless = std::env::var_os("BLESS").is_some();
+
+    let mut failures = Vec::new();
+    for fixture in fixtures(&dir) {
+        let actual = lower(&fixture);
+        let expected_path = fixture.with_extension("ir");
+        let expected = fs::read_to_string(&expected_path).unwrap_or_default();
+
+        if bless {
+            fs::write(&expected_path, &actual).unwrap();
+        } else if actual != expected {
+            failures.push(format!(
+                "{}\n--- expected\n{}\n--- actual\n{}",
+                fixture.display(), expected, actual,
+            ));
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} fixtures don't match their .ir file, rerun with BLESS=1 to update them:\n\n{}",
+        failures.len(),
+        failures.join("\n\n"),
+    );
+}
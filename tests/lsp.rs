@@ -0,0 +1,85 @@
+//! Tests of the language server: an editor talking to `moolang lsp` over its stdin and stdout.
+
+#![cfg(feature = "lsp")]
+
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::process::{ChildStdin, ChildStdout, Command, Stdio};
+
+use serde_json::{json, Value};
+
+/// Sends a message of the protocol, framed by its length
+fn send(stdin: &mut ChildStdin, message: Value) {
+    let body = message.to_string();
+    write!(stdin, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+    stdin.flush().unwrap();
+}
+
+/// Reads messages until one is a notification of `method`, returning its parameters
+fn receive(stdout: &mut BufReader<ChildStdout>, method: &str) -> Value {
+    loop {
+        let mut length = 0;
+        loop {
+            let mut header = String::new();
+            stdout.read_line(&mut header).unwrap();
+            match header.trim_end() {
+                "" => break,
+                header => if let Some(value) = header.strip_prefix("Content-Length: ") {
+                    length = value.parse().unwrap();
+                },
+            }
+        }
+        let mut body = vec![0; length];
+        stdout.read_exact(&mut body).unwrap();
+        let message = serde_json::from_slice::<Value>(&body).unwrap();
+        if message["method"] == method {
+            return message["params"].clone();
+        }
+    }
+}
+
+#[test]
+fn the_server_publishes_the_diagnostics_of_the_documents_being_edited() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("lsp");
+    fs::create_dir_all(&dir).unwrap();
+    let path = fs::canonicalize(&dir).unwrap().join("main.moo");
+    // what is checked is the text being edited, not the file saved
+    fs::write(&path, "let main = fn() -> int { 1 };\n").unwrap();
+    let uri = format!("file://{}", path.display());
+
+    let mut server = Command::new(env!("CARGO_BIN_EXE_moolang"))
+        .arg("lsp")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = server.stdin.take().unwrap();
+    let mut stdout = BufReader::new(server.stdout.take().unwrap());
+    send(&mut stdin, json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": { "capabilities": {} } }));
+    send(&mut stdin, json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }));
+
+    send(&mut stdin, json!({ "jsonrpc": "2.0", "method": "textDocument/didOpen", "params": { "textDocument": {
+        "uri": uri, "languageId": "moolang", "version": 1,
+        "text": "let main = fn() -> int {\n    let é = 1;\n    \"é\" + 1\n};\n",
+    } } }));
+    let params = receive(&mut stdout, "textDocument/publishDiagnostics");
+    assert_eq!(params["uri"], uri);
+    let diagnostics = params["diagnostics"].as_array().unwrap();
+    let error = diagnostics.iter().find(|diagnostic| diagnostic["severity"] == 1).expect("an error");
+    assert_eq!(error["code"], "E0318", "{}", error);
+    assert_eq!(error["source"], "moolang");
+    // lines count from 0, and characters in UTF-16 code units, `é` being one
+    assert_eq!(error["range"]["start"], json!({ "line": 2, "character": 4 }), "{}", error);
+    assert_eq!(error["range"]["end"], json!({ "line": 2, "character": 11 }), "{}", error);
+
+    send(&mut stdin, json!({ "jsonrpc": "2.0", "method": "textDocument/didChange", "params": {
+        "textDocument": { "uri": uri, "version": 2 },
+        "contentChanges": [{ "text": "let main = fn() -> int { 2 };\n" }],
+    } }));
+    assert_eq!(receive(&mut stdout, "textDocument/publishDiagnostics")["diagnostics"], json!([]));
+
+    send(&mut stdin, json!({ "jsonrpc": "2.0", "id": 2, "method": "shutdown", "params": null }));
+    send(&mut stdin, json!({ "jsonrpc": "2.0", "method": "exit", "params": null }));
+    assert!(server.wait().unwrap().success());
+}
@@ -0,0 +1,1452 @@
+//! End to end tests of running programs.
+//!
+//! Runs every `tests/run/*.moo` fixture under each backend and checks what it prints
+//! and its exit code against the annotations in its comments:
+//! ```text
+//! // expect: 42        a line the program prints, in order
+//! // expect-exit: 1    the exit code, 0 by default
+//! ```
+//! Files without annotations are modules imported by the fixtures, and aren't run on their own.
+
+#![cfg(feature = "jit")]
+
+use std::collections::HashSet;
+use std::fs;
+#[cfg(any(feature = "aot", feature = "repl"))]
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The backends to run the fixtures under, with the arguments selecting them
+const BACKENDS: &[(&str, &[&str])] = &[
+    ("jit", &[]),
+    ("jit -O2", &["-O2"]),
+    // collecting at every allocation in debug builds of the compiler
+    ("jit --gc tracing", &["--gc", "tracing"]),
+    ("interp", &["--backend", "interp"]),
+    ("vm", &["--backend", "vm"]),
+];
+
+/// The `moolang` command, with none of the variables of the environment which change what it does
+fn moo() -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_moolang"));
+    for variable in ["MOO_FLAGS", "MOO_BACKEND", "MOO_COLOR", "MOO_HOME"] {
+        command.env_remove(variable);
+    }
+    command
+}
+
+/// What a fixture is expected to do, from its annotations
+#[derive(Debug, PartialEq)]
+struct Expectation {
+    stdout: String,
+    exit_code: i32,
+}
+
+/// Reads the annotations of the fixture, if it has any
+fn expectation(source: &str) -> Option<Expectation> {
+    let mut stdout = String::new();
+    let mut exit_code = None;
+    let mut annotated = false;
+    for line in source.lines() {
+        let Some(comment) = line.split_once("//").map(|(_, comment)| comment.trim()) else {
+            continue;
+        };
+        if let Some(expected) = comment.strip_prefix("expect:") {
+            stdout.push_str(expected.trim());
+            stdout.push('\n');
+            annotated = true;
+        } else if let Some(code) = comment.strip_prefix("expect-exit:") {
+            exit_code = Some(code.trim().parse().expect("an integer exit code"));
+            annotated = true;
+        }
+    }
+    annotated.then_some(Expectation { stdout, exit_code: exit_code.unwrap_or(0) })
+}
+
+/// The fixtures with their expectations, in a stable order
+fn fixtures(dir: &Path) -> Vec<(PathBuf, Expectation)> {
+    let mut fixtures = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "moo"))
+        .filter_map(|path| expectation(&fs::read_to_string(&path).unwrap()).map(|expectation| (path, expectation)))
+        .collect::<Vec<_>>();
+    fixtures.sort_by(|(lhs, _), (rhs, _)| lhs.cmp(rhs));
+    fixtures
+}
+
+fn run(fixture: &Path, backend: &[&str]) -> (Expectation, String) {
+    let output = moo()
+        .arg("run")
+        // the defaults of the environment would change the output
+        .args(backend)
+        .arg(fixture)
+        .output()
+        .unwrap();
+    let actual = Expectation {
+        stdout: String::from_utf8(output.stdout).unwrap(),
+        exit_code: output.status.code().expect("an exit code"),
+    };
+    (actual, String::from_utf8(output.stderr).unwrap())
+}
+
+#[test]
+fn run_fixtures() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/run");
+    let fixtures = fixtures(&dir);
+    assert!(!fixtures.is_empty(), "no annotated fixtures in {}", dir.display());
+
+    let mut failures = Vec::new();
+    for (fixture, expected) in fixtures.iter() {
+        for (backend, args) in BACKENDS {
+            let (actual, stderr) = run(fixture, args);
+            if actual != *expected {
+                failures.push(format!(
+                    "{} ({})\n--- expected, exit code {}\n{}--- actual, exit code {}\n{}--- stderr\n{}",
+                    fixture.display(), backend,
+                    expected.exit_code, expected.stdout,
+                    actual.exit_code, actual.stdout,
+                    stderr,
+                ));
+            }
+        }
+    }
+
+    assert!(failures.is_empty(), "{} runs didn't go as expected:\n\n{}", failures.len(), failures.join("\n\n"));
+}
+
+#[test]
+#[cfg(feature = "aot")]
+fn build_compiles_for_other_targets() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("build_target");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("square.moo"), "let square = fn(x: int) -> int { x * x };\n").unwrap();
+
+    let build = |target: &str| moo()
+        .args(["build", "--emit", "obj", "--target", target, "-o"])
+        .arg(dir.join(target))
+        .arg(dir.join("square.moo"))
+        .output()
+        .unwrap();
+
+    let output = build("aarch64-unknown-linux-gnu");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    // an ELF file for the `EM_AARCH64` machine
+    let object = fs::read(dir.join("aarch64-unknown-linux-gnu")).unwrap();
+    assert_eq!(&object[..4], b"\x7fELF");
+    assert_eq!(u16::from_le_bytes([object[18], object[19]]), 183);
+
+    let output = build("aarch64-apple-darwin");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    // a Mach-O file for the `CPU_TYPE_ARM64` machine
+    let object = fs::read(dir.join("aarch64-apple-darwin")).unwrap();
+    assert_eq!(&object[..4], &0xfeedfacf_u32.to_le_bytes());
+    assert_eq!(u32::from_le_bytes([object[4], object[5], object[6], object[7]]), 0x0100000c);
+
+    let output = build("wasm32-unknown-unknown");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Cannot compile for `wasm32-unknown-unknown`"), "expected an unsupported target, got:\n{}", stderr);
+}
+
+#[test]
+fn check_reports_errors_without_running() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("check");
+    fs::create_dir_all(&dir).unwrap();
+    let check = |source: &str| {
+        let path = dir.join("checked.moo");
+        fs::write(&path, source).unwrap();
+        moo()
+            .arg("check")
+            .arg(&path)
+            .output()
+            .unwrap()
+    };
+
+    let output = check("let main = fn() -> int {\n    println(\"ran\");\n    1\n};\n");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "");
+
+    // found by the type checker, then lowering the calls
+    let output = check("let main = fn() -> int { \"one\" };\n");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("GenericsError"));
+    let output = check("let sq = fn(x: int) -> int { x * x };\nlet main = fn() -> int { sq(1, 2) };\n");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("`sq` takes 1 arguments but 2 were given"));
+}
+
+#[test]
+fn fix_applies_the_fixes_the_compiler_is_sure_of() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("fix");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("fixed.moo");
+    // the misspelled name is only a guess, and its error hides the unused variable, found by the checks after resolving
+    fs::write(&path, "let f = fn(x: int): int {\n    let y = 2\n    x\n};\nlet main = fn() -> int { f(+1) + cout };\nlet count = 1;\n").unwrap();
+    let output = moo()
+        .args(["fix", "--edition", "2024"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(output.status.code(), Some(1), "{}", stderr);
+    assert!(stderr.contains("fixed 3 problems in"), "{}", stderr);
+    assert!(stderr.contains("help: did you mean `count`?"), "{}", stderr);
+    assert_eq!(fs::read_to_string(&path).unwrap(),
+        "let f = fn(x: int) -> int {\n    let y = 2;\n    x\n};\nlet main = fn() -> int { f(1) + cout };\nlet count = 1;\n");
+}
+
+#[test]
+fn fix_only_guesses_what_is_missing_at_the_end_of_the_input() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("fix_eof");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("open.moo");
+    let source = "let main = fn() -> int {\n    let y = 1;\n    y\n";
+    fs::write(&path, source).unwrap();
+    let output = moo()
+        .arg("fix")
+        .arg(&path)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(output.status.code(), Some(1), "{}", stderr);
+    assert!(stderr.contains("Expected one of `}`"), "{}", stderr);
+    assert!(stderr.contains("help: insert `}` at the end of line 3"), "{}", stderr);
+    assert!(!stderr.contains("fixed"), "{}", stderr);
+    assert_eq!(fs::read_to_string(&path).unwrap(), source);
+}
+
+#[test]
+fn fix_applies_the_fixes_to_files_with_crlf_line_breaks() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("fix_crlf");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("fixed.moo");
+    fs::write(&path, "let f = fn(x: int): int {\r\n    let y = 2\r\n    x\r\n};\r\nlet main = fn() -> int { f(+1) };\r\n").unwrap();
+    let output = moo()
+        .args(["fix", "--edition", "2024"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(output.status.success(), "{}", stderr);
+    assert!(stderr.contains("fixed 4 problems in"), "{}", stderr);
+    assert_eq!(fs::read_to_string(&path).unwrap(),
+        "let f = fn(x: int) -> int {\r\n    let _y = 2;\r\n    x\r\n};\r\nlet main = fn() -> int { f(1) };\r\n");
+}
+
+#[test]
+fn check_reports_errors_in_the_file_they_are_in() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("check_files");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("main.moo"), "let main = fn() -> int { helper() };\n").unwrap();
+    fs::write(dir.join("helper.moo"), "let helper = fn() -> int {\n    1 + \"a\"\n};\n").unwrap();
+    let output = moo()
+        .args(["check", "--error-format", "json"])
+        .arg(dir.join("main.moo"))
+        .arg(dir.join("helper.moo"))
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let diagnostic = serde_json::from_str::<serde_json::Value>(stderr.lines().next().unwrap()).unwrap();
+    assert_eq!(diagnostic["code"], "E0318");
+    assert_eq!(diagnostic["file"], dir.join("helper.moo").display().to_string());
+    assert_eq!(diagnostic["span"]["start"]["line"], 2);
+}
+
+#[test]
+fn eval_runs_code_given_on_the_command_line() {
+    for (name, flags) in BACKENDS {
+        let eval = |source: &str, args: &[&str]| moo()
+            .arg("run")
+            .args(*flags)
+            .args(["-e", source, "--"])
+            .args(args)
+            .output()
+            .unwrap();
+
+        let output = eval("print(1+2*3);", &[]);
+        assert!(output.status.success(), "{}: {}", name, String::from_utf8_lossy(&output.stderr));
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "7", "{}", name);
+
+        let output = eval("let sq = fn(x: int) -> int { x * x }; println(sq(len(args)));", &["a", "b"]);
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "4\n", "{}", name);
+
+        let output = eval("print(1 + \"a\");", &[]);
+        assert_eq!(output.status.code(), Some(1), "{}", name);
+        assert!(String::from_utf8_lossy(&output.stderr).contains("Inside <eval>:"), "{}", name);
+    }
+}
+
+#[test]
+fn emit_prints_a_stage_of_the_frontend() {
+    let emit = |args: &[&str]| moo()
+        .arg("emit")
+        .args(args)
+        .output()
+        .unwrap();
+    let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/run/closures.moo");
+
+    let output = emit(&["types", fixture.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let types = String::from_utf8(output.stdout).unwrap();
+    assert!(types.contains("sq: fn(int) -> int"), "expected the type of `sq` in:\n{}", types);
+
+    // the grammar is the only stage without sources
+    let output = emit(&["grammar"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let output = emit(&["tokens"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("<PATHS>"));
+}
+
+#[test]
+fn an_example_of_the_grammar_enters_each_of_its_productions() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("grammar");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("util.moo"), "let neg = fn(x: int) -> int { 0 - x };\n").unwrap();
+    fs::write(dir.join("example.moo"), "\
+        import util;\n\
+        extern fn strlen(s: string) -> int;\n\
+        let twice = fn<T>(f: fn(T) -> T, x: T) -> T { f(f(x)) };\n\
+        let main = fn(args: [string]) -> int {\n\
+            let n: int = -(1 + 2) * 3 % 4 ** 2 - util.neg(len(args)) as i64 as int;\n\
+            println(\"n = {n}\");\n\
+            { twice(fn(x: int) -> int { x + 1 }, n) }\n\
+        };\n").unwrap();
+
+    let output = moo()
+        .args(["emit", "source", "--explain-parse"])
+        .arg(dir.join("example.moo"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let trace = String::from_utf8(output.stderr).unwrap();
+    let mut entered = trace.lines()
+        .filter_map(|line| line.trim_start().strip_prefix("> "))
+        .filter_map(|line| line.split(' ').next())
+        .collect::<HashSet<_>>();
+
+    let output = moo().args(["emit", "grammar"]).output().unwrap();
+    let grammar = String::from_utf8(output.stdout).unwrap();
+    let productions = grammar.lines()
+        .skip_while(|line| *line != "Grammar:")
+        .skip(1)
+        .filter_map(|line| line.split_once(" = ").map(|(name, _)| name.trim()))
+        .collect::<Vec<_>>();
+    assert!(productions.contains(&"type"), "no productions in:\n{}", grammar);
+    // `binary` only names the loosest level
+    if entered.contains("binary_1") {
+        entered.insert("binary");
+    }
+    let missing = productions.iter()
+        .filter(|production| !entered.contains(*production))
+        .collect::<Vec<_>>();
+    assert!(missing.is_empty(), "the parser never entered {:?} of the grammar, in:\n{}", missing, trace);
+}
+
+#[test]
+#[cfg(feature = "aot")]
+fn build_emits_clif_in_source_order() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("build_clif");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("square.moo"), "\
+        let square = fn(x: int) -> int { x * x };\n\
+        let main = fn() -> int { square(3) };\n").unwrap();
+
+    let output = moo()
+        .args(["build", "--emit", "clif"])
+        .arg(dir.join("square.moo"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    // text is printed rather than written next to the source
+    let clif = String::from_utf8(output.stdout).unwrap();
+    let headers = clif.lines().filter(|line| line.starts_with("; ")).collect::<Vec<_>>();
+    assert_eq!(headers, ["; square", "; square, optimized", "; main", "; main, optimized"]);
+    assert!(clif.contains("imul"), "expected a multiplication in:\n{}", clif);
+}
+
+#[test]
+#[cfg(feature = "aot")]
+fn build_emits_asm_for_every_function() {
+    let output = moo()
+        .args(["build", "--emit", "asm"])
+        .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/run/closures.moo"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let asm = String::from_utf8(output.stdout).unwrap();
+    let functions = asm.lines().filter(|line| line.starts_with("; ")).count();
+    // `add`, `twice`, `sq` and `main`, and the 3 lambdas
+    assert_eq!(functions, 7, "expected 7 functions in:\n{}", asm);
+    assert_eq!(asm.matches("ret").count(), 7, "expected every function to return in:\n{}", asm);
+}
+
+#[test]
+#[cfg(feature = "aot")]
+fn build_generates_the_same_code_on_any_number_of_threads() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("build_jobs");
+    fs::create_dir_all(&dir).unwrap();
+    let build = |jobs: &str| {
+        let output = dir.join(format!("closures-{}.o", jobs));
+        let status = moo()
+            .args(["build", "--emit", "obj", "--jobs", jobs, "-o"])
+            .arg(&output)
+            .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/run/closures.moo"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+        fs::read(output).unwrap()
+    };
+    assert_eq!(build("1"), build("4"));
+}
+
+#[test]
+fn interp_reports_traps_as_errors() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("interp_traps");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("div.moo"), "\
+        let div = fn(a: int, b: int) -> int { a / b };\n\
+        let main = fn() -> int { div(1, 0) };\n").unwrap();
+
+    let output = moo()
+        .args(["run", "--backend", "interp"])
+        .arg(dir.join("div.moo"))
+        .output()
+        .unwrap();
+    // like compiled code, see jit_reports_traps_at_their_location
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Division by zero"), "expected a division by zero, got:\n{}", stderr);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn interp_memoizes_the_calls_of_pure_functions() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("interp_memo");
+    fs::create_dir_all(&dir).unwrap();
+    // each function calls the one before it twice, 2^60 calls without memoization
+    let mut source = "let twice0 = fn(x: int) -> int { x + 1 };\n".to_owned();
+    for i in 1..=60 {
+        source += &format!("let twice{} = fn(x: int) -> int {{ twice{}(x) + twice{}(x) }};\n", i, i - 1, i - 1);
+    }
+    // printing is an effect, done at every call
+    source += "let shout = fn(x: int) -> int { print(\"moo \"); x };\n";
+    source += "let main = fn() -> int { shout(1) + shout(1) + twice60(0) };\n";
+    fs::write(dir.join("memo.moo"), source).unwrap();
+
+    let mut child = moo()
+        .args(["run", "--backend", "interp"])
+        .arg(dir.join("memo.moo"))
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let started = Instant::now();
+    while child.try_wait().unwrap().is_none() {
+        if started.elapsed() > Duration::from_secs(30) {
+            child.kill().unwrap();
+            panic!("the calls of the pure functions weren't memoized");
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), format!("moo moo {}\n", (1u64 << 60) + 2));
+}
+
+#[test]
+fn jit_reports_traps_at_their_location() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("jit_traps");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("div.moo"), "\
+        let div = fn(a: int, b: int) -> int {\n\
+            let q = a / b;\n\
+            q\n\
+        };\n\
+        let main = fn() -> int { div(1, 0) };\n").unwrap();
+    fs::write(dir.join("overflow.moo"), "\
+        let div = fn(a: int, b: int) -> int { a / b };\n\
+        let main = fn() -> int { div(0 - 9223372036854775807 - 1, 0 - 1) };\n").unwrap();
+
+    for opt_level in ["-O0", "-O2"] {
+        for (fixture, message, line) in [("div.moo", "Division by zero", "let q = a / b;"), ("overflow.moo", "overflows", "{ a / b }")] {
+            let output = moo()
+                .args([opt_level, "run"])
+                .arg(dir.join(fixture))
+                .output()
+                .unwrap();
+            // reported with a snippet rather than killed by the signal
+            let stderr = String::from_utf8(output.stderr).unwrap();
+            assert!(stderr.contains(message) && stderr.contains(line), "expected `{}` at `{}` with {}, got:\n{}", message, line, opt_level, stderr);
+            assert_eq!(output.status.code(), Some(1));
+        }
+    }
+}
+
+#[test]
+fn vm_reports_traps_at_the_code_of_their_instruction() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("vm_traps");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("div.moo"), "\
+        let div = fn(a: int, b: int) -> int {\n\
+            let q = a / b;\n\
+            q\n\
+        };\n\
+        let main = fn() -> int { div(1, 0) };\n").unwrap();
+
+    let trap = |backend: &str| {
+        let output = moo()
+            .args(["run", "--error-format", "json", "--backend", backend])
+            .arg(dir.join("div.moo"))
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(1));
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        let diagnostic = serde_json::from_str::<serde_json::Value>(stderr.lines().next().unwrap()).unwrap();
+        assert_eq!(diagnostic["code"], "E0413", "{}", stderr);
+        diagnostic["span"].clone()
+    };
+    // the division rather than the whole function, like compiled code
+    let span = trap("vm");
+    assert_eq!(span["start"]["line"], 2);
+    assert_eq!(span["end"]["line"], 2);
+    assert_eq!(span, trap("jit"));
+}
+
+#[test]
+fn selftest_finds_no_divergence_between_the_backends() {
+    let output = moo()
+        .args(["selftest", "--seed", "1", "--generated", "100"])
+        .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/run"))
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(output.status.success(), "{}{}", stdout, String::from_utf8_lossy(&output.stderr));
+    // the fixtures without a `main` function are skipped
+    assert!(stdout.ends_with("0 divergent, seed 1\n"), "expected a summary, got:\n{}", stdout);
+}
+
+/// Builds the fixture into an executable in `dir` and runs it, or returns how building it failed
+#[cfg(feature = "aot")]
+fn build_and_run(fixture: &Path, dir: &Path) -> (Expectation, String) {
+    let executable = dir.join(fixture.file_stem().unwrap());
+    let output = moo()
+        .arg("build")
+        .arg("-o")
+        .arg(&executable)
+        .arg(fixture)
+        .output()
+        .unwrap();
+    let output = match output.status.success() {
+        true => Command::new(&executable).output().unwrap(),
+        false => output,
+    };
+    let actual = Expectation {
+        stdout: String::from_utf8(output.stdout).unwrap(),
+        exit_code: output.status.code().expect("an exit code"),
+    };
+    (actual, String::from_utf8(output.stderr).unwrap())
+}
+
+#[test]
+#[cfg(feature = "aot")]
+fn built_executables_run_like_the_jit() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("build_exe");
+    fs::create_dir_all(&dir).unwrap();
+
+    let mut failures = Vec::new();
+    for (fixture, expected) in fixtures(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/run")) {
+        let (actual, stderr) = build_and_run(&fixture, &dir);
+        if actual != expected {
+            failures.push(format!(
+                "{}\n--- expected, exit code {}\n{}--- actual, exit code {}\n{}--- stderr\n{}",
+                fixture.display(),
+                expected.exit_code, expected.stdout,
+                actual.exit_code, actual.stdout,
+                stderr,
+            ));
+        }
+    }
+
+    assert!(failures.is_empty(), "{} executables didn't run as expected:\n\n{}", failures.len(), failures.join("\n\n"));
+}
+
+#[test]
+#[cfg(feature = "aot")]
+fn built_debug_executables_release_every_object() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("build_leaks");
+    fs::create_dir_all(&dir).unwrap();
+    let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/run/objects.moo");
+
+    // with debug information, the runtime reports the objects leaked and fails,
+    // and the tracing collector collects at every allocation
+    for gc in ["rc", "tracing"] {
+        let executable = dir.join(format!("objects-{}", gc));
+        let output = moo()
+            .args(["build", "-g", "--gc", gc, "-o"])
+            .arg(&executable)
+            .arg(&fixture)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let output = Command::new(&executable).args(["a", "b"]).output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stderr), "", "with --gc {}", gc);
+        assert_eq!(output.status.code(), Some(7), "with --gc {}", gc);
+        assert!(String::from_utf8(output.stdout).unwrap().ends_with("2 hello, world\n"), "with --gc {}", gc);
+    }
+}
+
+#[test]
+#[cfg(feature = "aot")]
+fn built_executables_report_division_by_zero() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("build_division");
+    fs::create_dir_all(&dir).unwrap();
+    let fixture = dir.join("div.moo");
+    fs::write(&fixture, "\
+        let div = fn(a: int, b: int) -> int {\n\
+            let q = a % b;\n\
+            q\n\
+        };\n\
+        let main = fn() -> int { div(1, 0) };\n").unwrap();
+
+    let (actual, stderr) = build_and_run(&fixture, &dir);
+    // at the division, which the runtime is given the location of
+    assert!(stderr.contains("Division by zero") && stderr.contains("[line:2,column:8]"), "expected a division by zero at the division, got:\n{}", stderr);
+    assert_eq!(actual.exit_code, 1);
+}
+
+#[test]
+fn overflow_modes_agree_on_every_backend() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("overflow_modes");
+    fs::create_dir_all(&dir).unwrap();
+    let fixture = dir.join("overflow.moo");
+    fs::write(&fixture, "\
+        let add = fn(a: u8, b: u8) -> u8 { a + b };\n\
+        let mul = fn(a: int, b: int) -> int { a * b };\n\
+        let main = fn() -> int { (add(200u8, 100u8) as int) + mul(4611686018427387904, 4) };\n").unwrap();
+
+    // 255 + the largest `int`, itself saturating
+    let saturated = Expectation { stdout: "9223372036854775807\n".to_string(), exit_code: 0 };
+    for (backend, args) in BACKENDS {
+        let (actual, stderr) = run(&fixture, &[&["--overflow", "saturate"], *args].concat());
+        assert_eq!(actual, saturated, "saturating with {}, stderr:\n{}", backend, stderr);
+
+        let (actual, stderr) = run(&fixture, &[&["--overflow", "trap"], *args].concat());
+        assert!(stderr.contains("overflows `u8`") && stderr.contains("{ a + b }"), "expected the overflow of `a + b` with {}, got:\n{}", backend, stderr);
+        // compiled code passes the code of the error to the runtime along with its message
+        assert!(stderr.contains("[E0423]: "), "expected the code of the error with {}, got:\n{}", backend, stderr);
+        assert_eq!(actual.exit_code, 1);
+    }
+}
+
+#[test]
+#[cfg(feature = "aot")]
+fn math_module_runs_alike_on_every_backend() {
+    // out of `tests/run`, whose own `math` module shadows the one of the standard library
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("math_module");
+    fs::create_dir_all(&dir).unwrap();
+    let fixture = dir.join("geometry.moo");
+    fs::write(&fixture, "\
+        import math;\n\
+        let main = fn() -> int {\n\
+            println(\"{math.sqrt(2.0)} {math.floor(2.75)} {math.abs((0 - 3) as float)}\");\n\
+            println(\"{math.min(1.5, 0.5)} {math.max(1.5, 0.5)} {math.sin(0.0)} {math.cos(0.0)}\");\n\
+            math.sqrt(1764.0) as int\n\
+        };\n").unwrap();
+
+    let expected = Expectation { stdout: "1.4142135623730951 2 3\n0.5 1.5 0 1\n42\n".to_string(), exit_code: 0 };
+    for (backend, args) in BACKENDS {
+        let (actual, stderr) = run(&fixture, args);
+        assert_eq!(actual, expected, "running with {}, stderr:\n{}", backend, stderr);
+    }
+    let (actual, stderr) = build_and_run(&fixture, &dir);
+    assert_eq!(actual, expected, "running the executable, stderr:\n{}", stderr);
+}
+
+/// Runs `command` with `input` on its standard input
+#[cfg(feature = "aot")]
+fn run_with_input(command: &mut Command, input: &str) -> (Expectation, String) {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    let actual = Expectation {
+        stdout: String::from_utf8(output.stdout).unwrap(),
+        exit_code: output.status.code().expect("an exit code"),
+    };
+    (actual, String::from_utf8(output.stderr).unwrap())
+}
+
+#[test]
+#[cfg(feature = "aot")]
+fn read_builtins_read_the_standard_input_on_every_backend() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("read_input");
+    fs::create_dir_all(&dir).unwrap();
+    let fixture = dir.join("sum.moo");
+    fs::write(&fixture, "\
+        let main = fn() -> int {\n\
+            let n = read_int();\n\
+            let m = read_int();\n\
+            let rest = read_line();\n\
+            println(\"{n + m}, '{rest}', '{read_line()}'\");\n\
+            read_int()\n\
+        };\n").unwrap();
+    let executable = dir.join("sum");
+    let output = moo()
+        .arg("build")
+        .arg("-o")
+        .arg(&executable)
+        .arg(&fixture)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "building failed:\n{}", String::from_utf8_lossy(&output.stderr));
+
+    // integers are separated by any whitespace, and lines lose their line break
+    let input = "  12\n\n-30 tail\r\nsecond line\n 7";
+    let expected = Expectation { stdout: "-18, ' tail', 'second line'\n7\n".to_string(), exit_code: 0 };
+    for (backend, args) in BACKENDS {
+        let mut command = moo();
+        command.arg("run").args(*args).arg(&fixture);
+        let (actual, stderr) = run_with_input(&mut command, input);
+        assert_eq!(actual, expected, "running with {}, stderr:\n{}", backend, stderr);
+
+        let (actual, stderr) = run_with_input(&mut command, "1 2\n\nx7");
+        assert!(stderr.contains("found `x7`"), "expected no integer with {}, got:\n{}", backend, stderr);
+        assert_eq!(actual.exit_code, 1);
+    }
+    let (actual, stderr) = run_with_input(&mut Command::new(&executable), input);
+    assert_eq!(actual, expected, "running the executable, stderr:\n{}", stderr);
+    let (_, stderr) = run_with_input(&mut Command::new(&executable), "1 2\n");
+    assert!(stderr.contains("found its end") && stderr.contains("[line:6,column:"), "expected the end of the input at the call, got:\n{}", stderr);
+}
+
+#[test]
+#[cfg(feature = "aot")]
+fn main_gets_the_arguments_after_the_separator_and_returns_the_exit_code() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("main_args");
+    fs::create_dir_all(&dir).unwrap();
+    let fixture = dir.join("echo.moo");
+    fs::write(&fixture, "\
+        let main = fn(args: [string]) -> int {\n\
+            println(\"{at(args, 0)}, {at(args, 1)}\");\n\
+            len(args)\n\
+        };\n").unwrap();
+    let executable = dir.join("echo");
+    let output = moo()
+        .arg("build")
+        .arg("-o")
+        .arg(&executable)
+        .arg(&fixture)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "building failed:\n{}", String::from_utf8_lossy(&output.stderr));
+
+    // the arguments are passed as they are, options of the compiler included
+    let expected = Expectation { stdout: "first one, -O2\n".to_string(), exit_code: 3 };
+    for (backend, args) in BACKENDS {
+        let mut command = moo();
+        command.arg("run").args(*args).arg(&fixture)
+            .args(["--", "first one", "-O2", "--"]);
+        let (actual, stderr) = run_with_input(&mut command, "");
+        assert_eq!(actual, expected, "running with {}, stderr:\n{}", backend, stderr);
+
+        let mut command = moo();
+        command.arg("run").args(*args).arg(&fixture).args(["--", "alone"]);
+        let (actual, stderr) = run_with_input(&mut command, "");
+        assert!(stderr.contains("Index 1 is out of bounds of an array of length 1"), "expected indexing to fail with {}, got:\n{}", backend, stderr);
+        assert_eq!(actual.exit_code, 1);
+    }
+    let (actual, stderr) = run_with_input(Command::new(&executable).args(["first one", "-O2", "--"]), "");
+    assert_eq!(actual, expected, "running the executable, stderr:\n{}", stderr);
+    let (_, stderr) = run_with_input(Command::new(&executable).arg("alone"), "");
+    assert!(stderr.contains("out of bounds") && stderr.contains("[line:2,column:"), "expected indexing to fail at the call, got:\n{}", stderr);
+}
+
+#[test]
+#[cfg(feature = "aot")]
+fn extern_functions_call_the_c_library_from_compiled_code() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("externs");
+    fs::create_dir_all(&dir).unwrap();
+    let fixture = dir.join("libc.moo");
+    fs::write(&fixture, "\
+        extern fn strlen(s: string) -> int;\n\
+        extern fn abs(x: i32) -> i32;\n\
+        extern fn floor(x: float) -> float;\n\
+        let main = fn() -> int {\n\
+            let hello = \"hello\";\n\
+            println(\"{strlen(hello)} {abs(0i32 - 7i32)} {floor(2.5)}\");\n\
+            0\n\
+        };\n").unwrap();
+    let expected = Expectation { stdout: "5 7 2\n0\n".to_string(), exit_code: 0 };
+    for (backend, args) in &BACKENDS[..3] {
+        let (actual, stderr) = run(&fixture, args);
+        assert_eq!(actual, expected, "running with {}, stderr:\n{}", backend, stderr);
+    }
+    let (actual, stderr) = build_and_run(&fixture, &dir);
+    assert_eq!(actual, expected, "running the executable, stderr:\n{}", stderr);
+
+    // the other backends can't call them
+    for (backend, args) in &BACKENDS[3..] {
+        let (actual, stderr) = run(&fixture, args);
+        assert!(stderr.contains("`strlen` is an extern function"), "expected {} to refuse the program, got:\n{}", backend, stderr);
+        assert_eq!(actual.exit_code, 1);
+    }
+}
+
+/// Builds the fixture into a bytecode file in `dir` and runs it, or returns how building it failed
+fn build_and_run_bytecode(fixture: &Path, dir: &Path) -> (Expectation, String) {
+    let program = dir.join(fixture.file_stem().unwrap()).with_extension("moob");
+    let moolang = |args: &[&std::ffi::OsStr]| moo()
+        .args(args)
+        .output()
+        .unwrap();
+    let output = moolang(&["build".as_ref(), "--emit".as_ref(), "bytecode".as_ref(), "-o".as_ref(), program.as_ref(), fixture.as_ref()]);
+    let output = match output.status.success() {
+        true => moolang(&["run".as_ref(), program.as_ref()]),
+        false => output,
+    };
+    let actual = Expectation {
+        stdout: String::from_utf8(output.stdout).unwrap(),
+        exit_code: output.status.code().expect("an exit code"),
+    };
+    (actual, String::from_utf8(output.stderr).unwrap())
+}
+
+#[test]
+fn built_bytecode_runs_like_the_jit() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("build_bytecode");
+    fs::create_dir_all(&dir).unwrap();
+
+    let mut failures = Vec::new();
+    for (fixture, expected) in fixtures(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/run")) {
+        let (actual, stderr) = build_and_run_bytecode(&fixture, &dir);
+        if actual != expected {
+            failures.push(format!(
+                "{}\n--- expected, exit code {}\n{}--- actual, exit code {}\n{}--- stderr\n{}",
+                fixture.display(),
+                expected.exit_code, expected.stdout,
+                actual.exit_code, actual.stdout,
+                stderr,
+            ));
+        }
+    }
+
+    assert!(failures.is_empty(), "{} bytecode files didn't run as expected:\n\n{}", failures.len(), failures.join("\n\n"));
+
+    // files which aren't bytecode are refused rather than run
+    fs::write(dir.join("garbage.moob"), [&moolang::bytecode::MAGIC[..], &[moolang::bytecode::VERSION, 0xff]].concat()).unwrap();
+    let output = moo()
+        .arg("run")
+        .arg(dir.join("garbage.moob"))
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Malformed bytecode"), "expected malformed bytecode, got:\n{}", stderr);
+}
+
+#[test]
+fn moo_home_overrides_the_embedded_stdlib() {
+    let home = Path::new(env!("CARGO_TARGET_TMPDIR")).join("moo_home");
+    fs::create_dir_all(home.join("std")).unwrap();
+    fs::write(home.join("std/ops.moo"), "let square = fn(x: int): int { x + x };\n").unwrap();
+
+    let output = moo()
+        .arg("run")
+        .env("MOO_HOME", &home)
+        .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/run/stdlib.moo"))
+        .output()
+        .unwrap();
+    // `rem` isn't defined by the installed `ops`, which replaces the embedded one as a whole
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("ops.rem"), "expected an unresolved `ops.rem`, got:\n{}", stderr);
+}
+
+#[test]
+#[cfg(feature = "repl")]
+fn repl_keeps_definitions_across_inputs() {
+    let mut repl = moo()
+        .arg("repl")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // the `;` of the last statement is optional, and an input runs over lines until its block is closed
+    let inputs = "\
+        let double = fn(x: int) -> int { x * 2 }\n\
+        let offset = 5;\n\
+        let shifted = fn(x: int) -> int {\n\
+            double(x) + offset\n\
+        }\n\
+        shifted(10)\n\
+        missing\n\
+        \"offset = {offset}\"\n";
+    repl.stdin.take().unwrap().write_all(inputs.as_bytes()).unwrap();
+    let output = repl.wait_with_output().unwrap();
+
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "25\n\"offset = 5\"\n");
+    // an input which doesn't compile doesn't end the session
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Cannot find `missing`"), "expected an unresolved `missing`, got:\n{}", stderr);
+    assert!(output.status.success());
+}
+
+#[test]
+#[cfg(feature = "aot")]
+fn built_objects_link_into_c_programs() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("build_obj");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("square.moo"), "let square = fn(x: int) -> int { x * x };\n").unwrap();
+    fs::write(dir.join("main.c"), "\
+        #include <stdint.h>\n\
+        #include <stdio.h>\n\
+        int64_t moo_square(void *env, int64_t x);\n\
+        int main(void) { printf(\"%lld\\n\", (long long)moo_square(0, 9)); return 0; }\n").unwrap();
+
+    let output = moo()
+        .args(["build", "--emit", "obj"])
+        .current_dir(&dir)
+        .arg("square.moo")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    // the object is named after the source file by default
+    let linked = Command::new("cc")
+        .current_dir(&dir)
+        .args(["main.c", "square.o", "-o", "square"])
+        .status()
+        .unwrap();
+    assert!(linked.success());
+    let output = Command::new(dir.join("square")).output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "81\n");
+}
+
+#[test]
+#[cfg(all(feature = "aot", target_os = "linux"))]
+fn built_executables_map_their_code_to_the_source() {
+    use cranelift_codegen::gimli::{self, EndianSlice, LittleEndian};
+    use cranelift_object::object::read::elf::ElfFile64;
+    use cranelift_object::object::{Object, ObjectSection};
+
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("build_debug_info");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("square.moo"), "\
+        let square = fn(x: int) -> int {\n\
+            let y = x * x;\n\
+            y + 1\n\
+        };\n\
+        let main = fn() -> int {\n\
+            let a = 20;\n\
+            square(a) / 3\n\
+        };\n").unwrap();
+
+    let output = moo()
+        .args(["build", "-g"])
+        .current_dir(&dir)
+        .arg("square.moo")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let output = Command::new(dir.join("square")).output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "133\n");
+
+    let bytes = fs::read(dir.join("square")).unwrap();
+    let elf = ElfFile64::<cranelift_object::object::LittleEndian>::parse(&*bytes).unwrap();
+    let dwarf = gimli::Dwarf::load(|id| -> Result<_, gimli::Error> {
+        let data = elf.section_by_name(id.name()).map(|section| section.data().unwrap()).unwrap_or_default();
+        Ok(EndianSlice::new(data, LittleEndian))
+    }).unwrap();
+
+    // the units of the C runtime aside
+    let mut units = dwarf.units();
+    let mut names = Vec::new();
+    let mut lines = Vec::new();
+    while let Some(header) = units.next().unwrap() {
+        let unit = dwarf.unit(header).unwrap();
+        let name = unit.name.map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        if name != "square.moo" {
+            continue;
+        }
+        let mut entries = unit.entries();
+        while let Some((_, entry)) = entries.next_dfs().unwrap() {
+            if let Some(name) = entry.attr_value(gimli::DW_AT_name).unwrap() {
+                names.push(dwarf.attr_string(&unit, name).unwrap().to_string_lossy().into_owned());
+            }
+        }
+        let mut rows = unit.line_program.clone().unwrap().rows();
+        while let Some((_, row)) = rows.next_row().unwrap() {
+            if let Some(line) = row.line() {
+                lines.push(line.get());
+            }
+        }
+    }
+    for name in ["square", "y", "main", "a"] {
+        assert!(names.iter().any(|found| found == name), "expected an entry named `{}` in {:?}", name, names);
+    }
+    for line in [2, 3, 6, 7] {
+        assert!(lines.contains(&line), "expected code from line {} in {:?}", line, lines);
+    }
+}
+
+#[test]
+fn error_format_json_reports_a_json_object_per_diagnostic() {
+    let output = moo()
+        .args(["run", "--error-format", "json", "-e", "let f = fn() -> int { let x = 1; 2 };\nprint(f() + \"a\");"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let diagnostics = String::from_utf8(output.stderr).unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(diagnostics, [
+        serde_json::json!({
+            "severity": "warning",
+            "kind": "Warning",
+            "code": "W0003",
+            "message": "Unused variable `x`, prefix it with `_` if that's intended",
+            "file": "<eval>",
+            "span": {"start": {"line": 1, "column": 26, "offset": 26}, "end": {"line": 1, "column": 27, "offset": 27}},
+            "labels": [],
+            "suggestions": [{
+                "message": "change `x` to `_x`",
+                "replacement": "_x",
+                "applicability": "machine-applicable",
+                "span": {"start": {"line": 1, "column": 26, "offset": 26}, "end": {"line": 1, "column": 27, "offset": 27}},
+            }],
+        }),
+        serde_json::json!({
+            "severity": "error",
+            "kind": "GenericsError",
+            "code": "E0318",
+            "message": "Cannot apply `+` to a value of type `string`, interpolate the strings to join them",
+            "file": "<eval>",
+            "span": {"start": {"line": 2, "column": 6, "offset": 44}, "end": {"line": 2, "column": 15, "offset": 53}},
+            "labels": [],
+            "suggestions": [],
+        }),
+    ]);
+}
+
+#[test]
+fn error_format_json_suggests_the_names_closest_to_unknown_ones() {
+    let output = moo()
+        .args(["run", "--error-format", "json", "-e", "let count = 1;\nprintln(cout);"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let diagnostic = serde_json::from_str::<serde_json::Value>(stderr.lines().next().unwrap()).unwrap();
+    assert_eq!(diagnostic["code"], "E0102");
+    assert_eq!(diagnostic["suggestions"], serde_json::json!([{
+        "message": "did you mean `count`?",
+        "replacement": "count",
+        "applicability": "maybe-incorrect",
+        "span": {"start": {"line": 2, "column": 8, "offset": 23}, "end": {"line": 2, "column": 12, "offset": 27}},
+    }]));
+}
+
+#[test]
+fn error_format_json_reports_the_labels_of_diagnostics() {
+    let output = moo()
+        .args(["run", "--error-format", "json", "-e", "let f = fn() -> int {\n    let x = 1;\n    let x = 2;\n    x\n};"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let diagnostic = serde_json::from_str::<serde_json::Value>(stderr.lines().next().unwrap()).unwrap();
+    assert_eq!(diagnostic["code"], "E0101");
+    assert_eq!(diagnostic["labels"], serde_json::json!([{
+        "message": "first defined here",
+        "span": {"start": {"line": 2, "column": 8, "offset": 30}, "end": {"line": 2, "column": 9, "offset": 31}},
+    }]));
+}
+
+#[test]
+fn error_format_json_reports_the_byte_offsets_of_files_with_crlf_line_breaks() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("json_crlf");
+    fs::create_dir_all(&dir).unwrap();
+    let source = "let main = fn() -> int {\r\n    \"a\" + 1\r\n};\r\n";
+    fs::write(dir.join("main.moo"), source).unwrap();
+    let output = moo()
+        .current_dir(&dir)
+        .args(["check", "--error-format", "json", "main.moo"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let diagnostic = serde_json::from_str::<serde_json::Value>(stderr.lines().next().unwrap()).unwrap();
+    assert_eq!(diagnostic["code"], "E0318");
+    let start = source.find("\"a\"").unwrap();
+    assert_eq!(diagnostic["span"], serde_json::json!({
+        "start": {"line": 2, "column": 4, "offset": start},
+        "end": {"line": 2, "column": 11, "offset": start + 7},
+    }));
+}
+
+#[test]
+fn color_colors_diagnostics_as_asked() {
+    let stderr = |flags: &[&str], env: Option<&str>| {
+        let mut command = moo();
+        command.args(["run", "-e", "print(1 + \"a\");"])
+            .args(flags);
+        if let Some(color) = env {
+            command.env("MOO_COLOR", color);
+        }
+        String::from_utf8(command.output().unwrap().stderr).unwrap()
+    };
+
+    let red = "\x1b[31mGenericsError";
+    assert!(stderr(&["--color", "always"], None).starts_with(red));
+    assert!(stderr(&[], Some("always")).starts_with(red));
+    // stderr isn't a terminal
+    assert!(stderr(&[], None).starts_with("GenericsError"));
+    assert!(stderr(&["--color", "never"], Some("always")).starts_with("GenericsError"));
+}
+
+#[test]
+fn time_phases_reports_the_time_of_each_phase() {
+    for (name, flags) in BACKENDS {
+        let output = moo()
+            .arg("run")
+            .args(*flags)
+            .args(["--time-phases", "-e", "print(1 + 2 * 3);"])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}: {}", name, String::from_utf8_lossy(&output.stderr));
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        let phases = stderr.lines().map(|line| line.split_whitespace().next().unwrap()).collect::<Vec<_>>();
+        let expected = match *name {
+            "interp" | "vm" => &["tokenize", "parse", "check", "codegen", "run", "total"][..],
+            _ => &["tokenize", "parse", "check", "codegen", "finalize", "run", "total"][..],
+        };
+        assert_eq!(phases, expected, "{}", name);
+        assert!(stderr.lines().all(|line| line.ends_with("ms")), "{}: {}", name, stderr);
+    }
+}
+
+#[test]
+fn moo_log_logs_the_phases_and_the_functions_compiled() {
+    let output = moo()
+        .args(["run", "-e", "let sq = fn(x: int) -> int { x * x };\nprint(sq(3));"])
+        .env("MOO_LOG", "moolang=debug")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "9");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    for logged in ["phase{name=\"parse\"}", "phase{name=\"check\"}", "phase{name=\"codegen\"}:function{name=sq}: moolang::jit: compiled", "phase{name=\"run\"}"] {
+        assert!(stderr.contains(logged), "expected `{}` in:\n{}", logged, stderr);
+    }
+
+    let output = moo()
+        .args(["run", "-e", "print(1);"])
+        .env("MOO_LOG", "moolang=loud")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr).unwrap().starts_with("Invalid `MOO_LOG`"));
+}
+
+#[test]
+#[cfg(feature = "aot")]
+fn new_creates_a_project_which_commands_without_paths_compile() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("new");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let moolang = |dir: &Path, args: &[&str]| moo()
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .unwrap();
+
+    let output = moolang(&dir, &["new", "hello"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let project = dir.join("hello");
+    assert_eq!(fs::read_to_string(project.join("moo.toml")).unwrap(), "[package]\nname = \"hello\"\n");
+    let output = moolang(&dir, &["new", "hello"]);
+    assert!(String::from_utf8(output.stderr).unwrap().contains("Cannot create a project at 'hello', it already exists"));
+
+    // found from any directory of the project
+    let output = moolang(&project.join("src"), &["run"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "Hello, world!\n");
+    let output = moolang(&project.join("src"), &["build"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let output = Command::new(project.join("hello").with_extension(std::env::consts::EXE_EXTENSION)).output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "Hello, world!\n");
+
+    fs::write(project.join("moo.toml"), "[package]\nname = \"hello\"\nentry = \"src/greet.moo\" # moved\n\n[build]\noutput = \"bin/greet\"\n").unwrap();
+    fs::rename(project.join("src/main.moo"), project.join("src/greet.moo")).unwrap();
+    fs::create_dir_all(project.join("bin")).unwrap();
+    let output = moolang(&project, &["build"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(project.join("bin/greet").with_extension(std::env::consts::EXE_EXTENSION).is_file());
+
+    fs::write(project.join("moo.toml"), "[package]\nname = \"hello\"\nversion = \"1\"\n").unwrap();
+    let output = moolang(&project, &["check"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr).unwrap().starts_with("ManifestError[E0503]: Unknown key `package.version` in the manifest"));
+    let output = moolang(&dir, &["run"]);
+    assert!(String::from_utf8(output.stderr).unwrap().contains("No source files given, and no `moo.toml`"));
+}
+
+#[test]
+fn deps_are_imported_by_name_from_their_packages() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("deps");
+    let _ = fs::remove_dir_all(&dir);
+    let write = |path: &str, source: &str| {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, source).unwrap();
+    };
+    let run = |project: &str| {
+        let output = moo()
+            .current_dir(dir.join(project))
+            .arg("run")
+            .output()
+            .unwrap();
+        (String::from_utf8(output.stdout).unwrap(), String::from_utf8(output.stderr).unwrap())
+    };
+
+    write("app/moo.toml", "[package]\nname = \"app\"\n\n[deps]\nmathlib = \"../mathlib\"\n");
+    write("app/src/main.moo", "import mathlib;\nimport util;\nlet main = fn(args: [string]) -> int { println(\"{mathlib.double(util.three())}\"); 0 };\n");
+    write("app/src/util.moo", "let three = fn() -> int { 3 };\n");
+    // the modules of a dependency are looked up next to its entry, not next to the importer
+    write("mathlib/moo.toml", "[package]\nname = \"mathlib\"\nentry = \"lib.moo\"\n");
+    write("mathlib/lib.moo", "import util;\nlet double = fn(x: int) -> int { util.twice(x) };\n");
+    write("mathlib/util.moo", "let twice = fn(x: int) -> int { x * 2 };\n");
+    assert_eq!(run("app"), ("6\n".to_owned(), String::new()));
+
+    write("mathlib/moo.toml", "[package]\nname = \"mathlib\"\nentry = \"lib.moo\"\n[deps]\napp = \"../app\"\n");
+    write("mathlib/lib.moo", "import app;\nlet double = fn(x: int) -> int { x * 2 };\n");
+    assert!(run("app").1.contains("Cyclic dependency: app -> mathlib -> app"));
+
+    fs::remove_dir_all(dir.join("mathlib")).unwrap();
+    let (_, stderr) = run("app");
+    assert!(stderr.contains("Cannot find the package `mathlib`, expected its `moo.toml` at '"), "{}", stderr);
+    assert!(stderr.contains(" 1 │ import mathlib;\n   │ ^^^^^^^^^^^^^^"), "{}", stderr);
+}
+
+#[test]
+fn build_caches_what_it_generates_until_a_module_changes() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("cache");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("src")).unwrap();
+    fs::write(dir.join("moo.toml"), "[package]\nname = \"cached\"\n").unwrap();
+    fs::write(dir.join("src/main.moo"), "import greeting;\nlet main = fn() -> string { greeting.hello() };\n").unwrap();
+    fs::write(dir.join("src/greeting.moo"), "let hello = fn() -> string { \"hello\" };\n").unwrap();
+    let build = || {
+        let output = moo()
+            .current_dir(&dir)
+            .args(["build", "--emit", "bytecode"])
+            .env("MOO_LOG", "moolang=debug")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let run = moo().arg("run").arg(dir.join("cached.moob")).output().unwrap();
+        (String::from_utf8(output.stderr).unwrap().contains("cache hit"), String::from_utf8(run.stdout).unwrap())
+    };
+
+    assert_eq!(build(), (false, "hello\n".to_owned()));
+    assert!(dir.join("target/cache").is_dir());
+    assert_eq!(build(), (true, "hello\n".to_owned()));
+    // changing an imported module builds the program again
+    fs::write(dir.join("src/greeting.moo"), "let hello = fn() -> string { \"bonjour\" };\n").unwrap();
+    assert_eq!(build(), (false, "bonjour\n".to_owned()));
+    assert_eq!(build(), (true, "bonjour\n".to_owned()));
+}
+
+#[test]
+#[cfg(feature = "aot")]
+fn build_generates_the_objects_of_the_modules_which_changed_only() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("cache_objects");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("src")).unwrap();
+    fs::write(dir.join("moo.toml"), "[package]\nname = \"cached\"\n").unwrap();
+    fs::write(dir.join("src/main.moo"), "import greeting;\nlet main = fn() -> string { greeting.first(greeting.hello(), \"\") };\n").unwrap();
+    let greeting = |word: &str| fs::write(dir.join("src/greeting.moo"),
+        format!("let hello = fn() -> string {{ \"{}\" }};\nlet first = fn<T>(x: T, y: T) -> T {{ x }};\n", word)).unwrap();
+    // the modules whose object was loaded from the cache, and what the executable prints
+    let build = || {
+        let output = moo()
+            .current_dir(&dir)
+            .arg("build")
+            .env("MOO_LOG", "moolang=debug")
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(output.status.success(), "{}", stderr);
+        let run = Command::new(dir.join("cached")).output().unwrap();
+        let hits = ["main.moo", "greeting.moo"].into_iter()
+            .filter(|module| stderr.lines().any(|line| line.contains("cache hit") && line.ends_with(module)))
+            .collect::<Vec<_>>();
+        (hits, String::from_utf8(run.stdout).unwrap())
+    };
+
+    greeting("hello");
+    assert_eq!(build(), (vec![], "hello\n".to_owned()));
+    assert_eq!(build(), (vec!["main.moo", "greeting.moo"], "hello\n".to_owned()));
+    // the importer calls the same functions, with the same types
+    greeting("bonjour");
+    assert_eq!(build(), (vec!["main.moo"], "bonjour\n".to_owned()));
+    fs::write(dir.join("src/main.moo"), "import greeting;\nlet main = fn() -> int { greeting.first(4, 2) };\n").unwrap();
+    // the instantiation of the generic function for `int` is in the module defining it
+    assert_eq!(build(), (vec![], "4\n".to_owned()));
+}
+
+#[test]
+fn every_diagnostic_code_is_explained() {
+    let codes = moolang::explain::codes().collect::<Vec<_>>();
+    let explained = moolang::explain::explained().collect::<Vec<_>>();
+    assert_eq!(explained, codes);
+}
+
+#[test]
+fn explained_examples_report_their_code() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("explain");
+    fs::create_dir_all(&dir).unwrap();
+    for code in moolang::explain::codes() {
+        let explanation = moolang::explain::explain(code).unwrap();
+        // examples which aren't a program are `text` blocks
+        let Some((_, example)) = explanation.split_once("```moo\n") else { continue };
+        let example = &example[..example.find("```").unwrap()];
+        let flags = example.lines()
+            .next()
+            .and_then(|line| line.strip_prefix("// compile-flags:"))
+            .map(|flags| flags.split_whitespace().collect::<Vec<_>>())
+            .unwrap_or_default();
+        let path = dir.join(format!("{}.moo", code));
+        fs::write(&path, example).unwrap();
+        let output = moo()
+            .args(["run", "--error-format", "json"])
+            .args(&flags)
+            .arg(&path)
+            .stdin(Stdio::null())
+            .output()
+            .unwrap();
+        let reported = String::from_utf8(output.stderr).unwrap()
+            .lines()
+            .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+            .any(|diagnostic| diagnostic["code"] == code);
+        assert!(reported, "the example of {} doesn't report it", code);
+    }
+}
+
+#[test]
+fn explain_prints_the_explanation_of_a_code() {
+    let explain = |code: &str| moo()
+        .args(["explain", code])
+        .output()
+        .unwrap();
+    let output = explain("e0102");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), moolang::explain::explain("E0102").unwrap());
+
+    let output = explain("E9999");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("No error has the code `E9999`"));
+}
+
+#[test]
+#[cfg(feature = "fmt")]
+fn fmt_formats_sources_keeping_their_comments() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("fmt");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("main.moo");
+    let unformatted = "// squares\nlet   sq=fn(x:int):int{x*x};   // of ints\n\n\n\nlet main = fn() -> int {\n  let a = -1;  /* one */\n  let f = fn(y: int) -> int {\n     y + (1) // inside\n  };\n  f(sq(\n     2, // moved\n  )) + -(a)\n  // at the end\n};\n";
+    fs::write(&path, unformatted).unwrap();
+    let fmt = |args: &[&str]| moo()
+        .arg("fmt")
+        .args(args)
+        .arg(&path)
+        .output()
+        .unwrap();
+
+    let output = fmt(&["--check"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("isn't formatted, from line 2"));
+    assert_eq!(fs::read_to_string(&path).unwrap(), unformatted);
+
+    assert_eq!(fmt(&[]).status.code(), Some(0));
+    assert_eq!(fs::read_to_string(&path).unwrap(),
+        "// squares\nlet sq = fn(x: int) -> int { x * x }; // of ints\n\nlet main = fn() -> int {\n    let a = -1; /* one */\n    let f = fn(y: int) -> int {\n        y + 1 // inside\n    };\n    // moved\n    f(sq(2)) + -a\n    // at the end\n};\n");
+    assert_eq!(fmt(&["--check"]).status.code(), Some(0));
+}
+
+#[test]
+#[cfg(feature = "fmt")]
+fn fmt_keeps_the_meaning_of_the_fixtures() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("fmt_fixtures");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let moolang = |args: &[&str], path: &Path| moo()
+        .args(args)
+        .arg(path)
+        .output()
+        .unwrap();
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/run");
+    for entry in fs::read_dir(&fixtures).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|extension| extension == "moo") {
+            fs::copy(&path, dir.join(path.file_name().unwrap())).unwrap();
+        }
+    }
+    let output = moolang(&["fmt"], &dir);
+    assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+    // the canonical layout of the trees is the same
+    assert_eq!(String::from_utf8(moolang(&["emit", "source"], &dir).stdout).unwrap(),
+        String::from_utf8(moolang(&["emit", "source"], &fixtures).stdout).unwrap().replace(&fixtures.display().to_string(), &dir.display().to_string()));
+    assert_eq!(moolang(&["fmt", "--check"], &dir).status.code(), Some(0));
+}
+
+#[test]
+#[cfg(not(all(feature = "fmt", feature = "aot", feature = "lsp")))]
+fn commands_of_missing_features_report_the_feature_they_require() {
+    if !cfg!(feature = "fmt") {
+        let output = moo().args(["fmt", "--check"]).output().unwrap();
+        assert_eq!(output.status.code(), Some(1));
+        assert!(String::from_utf8(output.stderr).unwrap().contains("The `fmt` command requires the `fmt` feature"));
+    }
+    if !cfg!(feature = "lsp") {
+        let output = moo().arg("lsp").output().unwrap();
+        assert_eq!(output.status.code(), Some(1));
+        assert!(String::from_utf8(output.stderr).unwrap().contains("The `lsp` command requires the `lsp` feature"));
+    }
+    if !cfg!(feature = "aot") {
+        let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("missing_features");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("main.moo"), "let main = fn() -> int { 1 };\n").unwrap();
+        let output = moo().current_dir(&dir).args(["build", "--emit", "obj", "main.moo"]).output().unwrap();
+        assert_eq!(output.status.code(), Some(1));
+        assert!(String::from_utf8(output.stderr).unwrap().contains("[E0408]: Building executables, object files and listings requires the `aot` feature"));
+    }
+}
+
+#[test]
+fn moo_flags_can_hold_options_of_the_subcommand() {
+    let output = moo()
+        .args(["run", "-e", "print(6 * 7);"])
+        .env("MOO_FLAGS", "--gc tracing -O2")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "42");
+
+    // those on the command line still win: only the JIT calls extern functions
+    let output = moo()
+        .args(["run", "--backend", "jit", "-e", "extern fn strlen(s: string) -> int; print(strlen(\"moo\"));"])
+        .env("MOO_FLAGS", "--backend vm")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "3");
+}
@@ -0,0 +1,74 @@
+//! Round trip tests of printing programs back as source, see `emit source`.
+//!
+//! Prints every `tests/run/*.moo` fixture, then checks that printing the printed source
+//! gives it back unchanged, and that the printed programs run like the fixtures.
+
+#![cfg(feature = "jit")]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn moolang(args: &[&str], path: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_moolang"))
+        .env_remove("MOO_FLAGS")
+        .env_remove("MOO_BACKEND")
+        .env_remove("MOO_COLOR")
+        .env_remove("MOO_HOME")
+        .args(args)
+        .arg(path)
+        .output()
+        .unwrap()
+}
+
+fn emit_source(path: &Path) -> String {
+    let output = moolang(&["emit", "source"], path);
+    assert!(output.status.success(), "{} doesn't parse:\n{}", path.display(), String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// The fixtures, in a stable order
+fn fixtures(dir: &Path) -> Vec<PathBuf> {
+    let mut fixtures = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "moo"))
+        .collect::<Vec<_>>();
+    fixtures.sort();
+    fixtures
+}
+
+#[test]
+fn round_trip() {
+    let fixtures = fixtures(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/run"));
+    // the printed fixtures are written side by side, for their imports to resolve
+    let printed_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("source");
+    fs::create_dir_all(&printed_dir).unwrap();
+
+    let mut failures = Vec::new();
+    let mut printed = Vec::new();
+    for fixture in fixtures.iter() {
+        let source = emit_source(fixture);
+        let path = printed_dir.join(fixture.file_name().unwrap());
+        fs::write(&path, &source).unwrap();
+        printed.push((fixture, path, source));
+    }
+
+    for (fixture, path, source) in printed.iter() {
+        let reprinted = emit_source(path);
+        if reprinted != *source {
+            failures.push(format!("{} isn't printed back unchanged\n--- printed\n{}--- reprinted\n{}", fixture.display(), source, reprinted));
+            continue;
+        }
+        let expected = moolang(&["run"], fixture);
+        let actual = moolang(&["run"], path);
+        if (&actual.stdout, actual.status.code()) != (&expected.stdout, expected.status.code()) {
+            failures.push(format!(
+                "{} runs differently once printed\n--- expected\n{}--- actual\n{}",
+                fixture.display(), String::from_utf8_lossy(&expected.stdout), String::from_utf8_lossy(&actual.stdout),
+            ));
+        }
+    }
+
+    assert!(failures.is_empty(), "{} fixtures don't round trip:\n\n{}", failures.len(), failures.join("\n\n"));
+}
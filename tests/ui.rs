@@ -0,0 +1,90 @@
+//! Golden file tests of the diagnostics.
+//!
+//! Compiles every `tests/ui/*.moo` fixture and compares what the compiler writes to stderr
+//! with the `.stderr` file next to it, a missing file standing for no output.
+//! A fixture starting with a `// compile-flags: ...` line is compiled with those options,
+//! e.g. `// compile-flags: --edition 2024`.
+//! Run with `BLESS=1` to write the current output to the `.stderr` files instead,
+//! then review the changes to them like any other change:
+//! ```text
+//! BLESS=1 cargo test --test ui
+//! ```
+
+// some fixtures are codegen errors
+#![cfg(feature = "jit")]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The fixtures, in a stable order
+fn fixtures(dir: &Path) -> Vec<PathBuf> {
+    let mut fixtures = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "moo"))
+        .collect::<Vec<_>>();
+    fixtures.sort();
+    fixtures
+}
+
+/// The options given by the `// compile-flags:` first line of the fixture, if any
+fn compile_flags(fixture: &Path) -> Vec<String> {
+    let source = fs::read_to_string(fixture).unwrap();
+    source.lines()
+        .next()
+        .and_then(|line| line.strip_prefix("// compile-flags:"))
+        .map(|flags| flags.split_whitespace().map(str::to_owned).collect())
+        .unwrap_or_default()
+}
+
+/// Compiles the fixture and returns its stderr, with the paths made independent of the checkout
+fn compile(root: &Path, fixture: &Path) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_moolang"))
+        .current_dir(root)
+        // the defaults of the environment would change the output
+        .env_remove("MOO_FLAGS")
+        .env_remove("MOO_BACKEND")
+        .env_remove("MOO_COLOR")
+        .env_remove("MOO_HOME")
+        .args(compile_flags(fixture))
+        .arg("run")
+        .arg(fixture.strip_prefix(root).unwrap())
+        .output()
+        .unwrap();
+    String::from_utf8(output.stderr)
+        .unwrap()
+        .replace(&*fs::canonicalize(root).unwrap().to_string_lossy(), "$DIR")
+}
+
+#[test]
+fn ui() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let bless = std::env::var_os("BLESS").is_some();
+
+    let mut failures = Vec::new();
+    for fixture in fixtures(&root.join("tests/ui")) {
+        let actual = compile(root, &fixture);
+        let expected_path = fixture.with_extension("stderr");
+        let expected = fs::read_to_string(&expected_path).unwrap_or_default();
+
+        if bless {
+            match actual.is_empty() {
+                true => { let _ = fs::remove_file(&expected_path); }
+                false => fs::write(&expected_path, &actual).unwrap(),
+            }
+        } else if actual != expected {
+            failures.push(format!(
+                "{}\n--- expected\n{}\n--- actual\n{}",
+                fixture.display(), expected, actual,
+            ));
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} fixtures don't match their .stderr file, rerun with BLESS=1 to update them:\n\n{}",
+        failures.len(),
+        failures.join("\n\n"),
+    );
+}